@@ -5,7 +5,7 @@ Current version: {{version}}
 ## apiclient binary
 
 The `apiclient` binary provides high-level methods to interact with the Bottlerocket API.
-There's a [set](#set-mode) subcommand for changing settings, an [update](#update-mode) subcommand for updating the host, and an [exec](#exec-mode) subcommand for running commands in host containers.
+There's a [set](#set-mode) subcommand for changing settings, an [unset](#unset-mode) subcommand for removing them, an [update](#update-mode) subcommand for updating the host, and an [exec](#exec-mode) subcommand for running commands in host containers.
 There's also a low-level [raw](#raw-mode) subcommand for direct interaction with the HTTP API.
 
 It talks to the Bottlerocket socket by default.
@@ -27,6 +27,26 @@ Or, request some specific settings:
 apiclient get settings.motd settings.kernel.lockdown
 ```
 
+`get` prints JSON by default, but it can also print settings in the forms taken by other subcommands, so they can be given right back to apiclient.
+`--format toml` prints the TOML format taken by [apply](#apply-mode) and used for user data, `--format flat` prints a `KEY=VALUE` line for each setting, as taken by [set](#set-mode), and `--format env` prints a shell variable assignment for each setting, like `SETTINGS_MOTD='hi'`:
+```shell
+apiclient get settings.kubernetes --format toml > kubernetes.toml
+apiclient apply kubernetes.toml
+apiclient get settings.motd --format flat | xargs -d '\n' apiclient set
+```
+
+`--filter` selects part of the output by its path, like a simple `jq` filter.
+Names with dots in them are quoted, and list items are selected by index:
+```shell
+apiclient get settings.kubernetes --filter '.settings.kubernetes.node-labels."my.label"'
+apiclient get settings.ntp --filter '.settings.ntp.time-servers[0]'
+```
+
+With JSON, only the selected value is printed.
+With the other formats, settings keep their full names, so a filter can't select a list item.
+The flat format shows lists in JSON form, and quotes strings that would otherwise be read as another type, like `"42"`, so `set` reads each value back unchanged.
+It can't show values with more than one line, so settings like those are best copied with TOML.
+
 ### Set mode
 
 This allows you to change settings on the system.
@@ -36,8 +56,8 @@ For example, if you change an NTP setting, the NTP configuration will be updated
 
 #### Key=value input
 
-There are two input methods.
-The simpler method looks like this:
+There are a few input methods.
+The simplest looks like this:
 
 ```shell
 apiclient set settings.x.y.z=VALUE
@@ -56,6 +76,14 @@ If you're changing a setting whose name requires quoting, please quote the whole
 apiclient set 'kubernetes.node-labels."my.label"=hello'
 ```
 
+Values can also be given in JSON form, such as a list, or a string that looks like a different type:
+
+```shell
+apiclient set 'ntp.time-servers=["a.example.com","b.example.com"]' 'motd="42"'
+```
+
+The API's key=value form doesn't accept JSON values, so when any value is in JSON form, apiclient sends all of the given settings as JSON input instead, described below.
+
 #### JSON input
 
 This simpler key=value form is convenient for most changes, but sometimes you'll want to specify input in JSON form.
@@ -75,6 +103,165 @@ You can use JSON form to set it:
 apiclient set --json '{"motd": "42"}'
 ```
 
+#### Patch input
+
+The forms above set values, but can't remove entries from list or map settings.
+To edit those element-wise, you can give an [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON Patch, with paths relative to the settings:
+
+```shell
+apiclient set --json-patch '[{"op": "remove", "path": "/kubernetes/node-taints/dedicated"}]'
+```
+
+A `test` operation makes the change fail, without changing anything, unless a setting has the value you expect:
+
+```shell
+apiclient set --json-patch '[{"op": "test", "path": "/motd", "value": "hi"}, {"op": "replace", "path": "/motd", "value": "bye"}]'
+```
+
+You can also give an [RFC 7396](https://www.rfc-editor.org/rfc/rfc7396) merge patch, in which `null` removes a setting:
+
+```shell
+apiclient set --merge-patch '{"kubernetes": {"node-labels": {"old-label": null, "new-label": "hello"}}}'
+```
+
+#### Dry runs
+
+To see what a change would do before making it, add `--dry-run`:
+
+```shell
+apiclient set --dry-run motd="hi there"
+```
+
+Nothing on the system is changed.
+Instead, apiclient shows the settings that would change, the services that would be restarted, and a diff of each configuration file that would be rewritten.
+`apiclient apply --dry-run` works the same way for settings files.
+
+### Apply mode
+
+This applies settings from TOML or JSON files, like the ones `apiclient get` shows, and commits them.
+
+```shell
+apiclient apply file:///local/settings.toml https://example.com/settings.json
+```
+
+If no URI is given, or "-" is given, settings are read from stdin.
+
+#### Verifying input
+
+Input fetched from elsewhere can be checked before it's applied.
+`--sha256` gives the expected SHA-256 digest, in hex, of the URI before it:
+
+```shell
+apiclient apply https://example.com/settings.toml --sha256 0123...cdef
+```
+
+`--signature` gives the URI of a detached Ed25519 signature of the URI before it, which must be made with the key given by `--trusted-key`.
+The key can be PEM, like the output of `openssl pkey -pubout`, and the signature can be raw or base64, like the output of `openssl pkeyutl -sign -rawin`.
+Once a trusted key is given, every URI must have a signature.
+Signatures can only be checked if apiclient is built with the `tls` feature, which is on by default.
+
+```shell
+apiclient apply --trusted-key /local/settings-key.pem \
+   https://example.com/settings.toml --signature https://example.com/settings.toml.sig
+```
+
+If any check fails, nothing is changed.
+
+#### Other URI schemes
+
+file, http, and https URIs are fetched by apiclient.
+For other schemes, like s3, apiclient runs a helper program with the URI as its only argument, and reads the contents from its stdout.
+By default, the helper is `apiclient-fetch-SCHEME` in PATH; `--fetcher SCHEME=PROGRAM` chooses another.
+For example, a helper for s3 could be:
+
+```shell
+#!/bin/sh
+exec aws s3 cp "$1" -
+```
+
+### Unset mode
+
+This allows you to remove settings from the system, along with any settings under them.
+Like `set`, the changes are committed and applied, and the "settings." prefix is optional.
+
+```shell
+apiclient unset kubernetes.node-labels.my-label
+```
+
+### Diff mode
+
+This shows how settings would change, without changing anything.
+Given settings files, in the same TOML or JSON format as `apiclient apply`, it compares them with live settings:
+
+```shell
+apiclient diff file:///tmp/settings.toml
+```
+
+Added settings are marked with `+`, removed settings with `-`, and changed settings with `~`, and are shown in dotted form like `settings.motd`.
+Add `--tx NAME` to compare the files with the settings as they'd be after a pending transaction is committed.
+With no files, it compares live settings with the pending transaction, or the "default" transaction if none is given:
+
+```shell
+apiclient diff --tx FOO
+```
+
+Output is colored when it's shown on a terminal.
+For other tools, add `--format json` to get an object mapping each changed setting to its `old` and `new` values.
+
+### Snapshot mode
+
+This allows you to save all of the system's settings, along with their metadata, to a single file, and to restore them later, for example on a replacement host.
+
+```shell
+apiclient snapshot export > settings-snapshot.json
+apiclient snapshot export --format toml > settings-snapshot.toml
+```
+
+Settings made by a setting generator, like the hostname, are specific to the host, so they're left out unless you add `--include-generated`.
+
+Restoring takes a file path, or `-` for stdin, in either format.
+The snapshot is checked against the current settings model before anything is changed, then its settings are committed and applied in a single transaction.
+Settings that aren't in the snapshot are removed, so the host's settings match it, except for generated settings, which the host keeps if the snapshot doesn't have them.
+Metadata isn't restored, since it comes from the OS image, so a snapshot can be restored onto a different OS version as long as its settings fit that version's model.
+
+```shell
+apiclient snapshot import settings-snapshot.toml
+```
+
+### Watch mode
+
+This follows changes to settings, printing a line with a timestamp for each setting that's added, removed, or changed, in the same form as [diff mode](#diff-mode).
+It takes prefixes, like `apiclient get`, and shows the current values when it starts:
+
+```shell
+apiclient watch settings.motd settings.kubernetes
+```
+
+Changes are streamed from the API as they're committed; if the connection is lost, apiclient reconnects after `--interval` seconds, by default 2.
+Prefixes have to be under `settings`, since other data isn't streamed.
+It runs until interrupted, or until every `--until NAME=VALUE` condition is met, which is useful in scripts:
+
+```shell
+apiclient watch settings.motd --until settings.motd=ready --timeout 300
+```
+
+String values are compared without quotes.
+With `--timeout`, apiclient fails if the conditions aren't met in time.
+
+### Describe mode
+
+This explains settings: for each setting under the given prefixes, it shows its type, any constraints on its value, and its current value, along with the metadata that governs how it's used, such as the services restarted when it changes, the setting generator that sets it, and the template its value is rendered from.
+Metadata given for a prefix, like the services affected by `settings.kubernetes`, is shown for each setting under it.
+The "settings." prefix is optional, and all settings are described if no prefix is given.
+
+```shell
+apiclient describe kubernetes.node-labels motd
+apiclient describe --format json settings.network
+```
+
+Types and constraints come from the settings schema the API publishes at `/settings/schema`, so the settings it covers are described whether or not they're set.
+That schema is partial: sections it leaves open, like `settings.kubernetes`, are described from the live settings under them, with the type of their current value.
+
 ### Update mode
 
 To start, you can check what updates are available:
@@ -102,6 +289,37 @@ apiclient update apply --check --reboot
 
 > Note that available updates are controlled by your settings under `settings.updates`; see [README](../../../README.md#updates-settings) for details.
 
+Hosts without access to the update repository can update from a local copy of it, for example on an attached disk.
+Give `--source` with the path on the host of a directory holding the repository, with `metadata` and `targets` subdirectories, or of a block device whose filesystem holds it:
+
+```shell
+apiclient update apply --check --source /dev/nvme1n1
+```
+
+The repository is verified against the same trusted root as the configured repository.
+
+If updog's config file gives maintenance windows, the update is only made active inside them; add `--force` to `apiclient update apply` to make it active anyway.
+
+If a new version isn't working out, you can go back to the previous version, still in the alternate partition set:
+
+```shell
+apiclient update rollback --reboot
+```
+
+This is only allowed if the alternate partition set holds an older version that has booted successfully, and not an update you've applied since.
+Without `--reboot`, the previous version takes effect the next time you reboot, and `apiclient update cancel` undoes the rollback until then.
+
+You can also follow the update status, with the same options as watch mode.
+The status isn't streamed, so apiclient checks it every `--interval` seconds.
+For example, to wait for an update to be ready:
+
+```shell
+apiclient update watch --until update_state=Ready
+```
+
+While the update is being prepared, the status includes `download_progress`, with the bytes of the update's images downloaded so far.
+If the download is interrupted, preparing the update again continues where it stopped.
+
 ### Reboot mode
 
 This will reboot the system.
@@ -124,16 +342,93 @@ For example, if you used SSM to get into the control container and want to acces
 apiclient exec admin bash
 ```
 
-You can also run noninteractive commands, and redirect output, so you can use it to copy files between containers:
+You can also run noninteractive commands, and redirect output:
 ```shell
 apiclient exec admin cat /file > file
 ```
+To copy files, though, [copy mode](#copy-mode) is a better fit.
 
 This works OK because apiclient detects if you have a TTY by checking if stdout and stdin are connected to TTYs.
 If that doesn't work for your use case, you can pass `-t`/`--tty` to specifically request a TTY, or `-T`/`--no-tty` to request no TTY.
 
+apiclient exits with the exit code of the command, or, if the command was killed by a signal, 128 plus the signal number, like a shell.
+If apiclient receives SIGINT, SIGTERM, or SIGQUIT, it passes the signal on to the command and waits for it to exit; a second signal stops apiclient right away.
+This makes exec usable in scripts:
+```shell
+apiclient exec admin test -f /file || echo "no file"
+```
+
 See the [exec documentation](../api-exec.md) for more detail on how this feature works.
 
+### Copy mode
+
+This mode copies a file to or from a host container, using the same channel as exec mode.
+The container side is given as the container name and an absolute path, separated by a colon.
+
+To copy a file out of the admin container:
+```shell
+apiclient cp admin:/home/ec2-user/capture.pcap ./capture.pcap
+```
+
+And to copy a file into it:
+```shell
+apiclient cp ./tool admin:/home/ec2-user/
+```
+
+If the destination is a local directory, or a container path ending in `/`, the file keeps its name.
+The file keeps its permissions, apart from setuid, setgid, and sticky bits.
+
+Binary files are copied exactly.
+apiclient and the server each compute the size and SHA-256 digest of the data, and the copy is only kept if they match; the file is written next to its destination and moved into place, so a failed copy doesn't leave part of a file behind.
+Progress is shown when stderr is a terminal; pass `-q`/`--quiet` to hide it.
+
+### Shell completion
+
+apiclient can generate completion scripts for bash, zsh, and fish, which complete subcommands, options, and setting names, from the settings schema and the host's current settings:
+
+```shell
+source <(apiclient completion bash)
+source <(apiclient completion zsh)
+apiclient completion fish | source
+```
+
+The scripts call apiclient each time they complete a word, so setting names are fetched from the API, using the socket given with `--socket-path`, if any.
+
+### Remote hosts
+
+apiclient can talk to the API on another host through ssh, for example from a workstation or another node, using `--host`:
+
+```shell
+apiclient --host admin@node1 get settings.motd
+```
+
+ssh forwards a local socket to the API socket on the remote host, so every subcommand works this way, and `--socket-path` gives the path of the socket on the remote host.
+ssh runs with your usual configuration, so keys, ports, and jump hosts can be set up in `~/.ssh/config`; the host can also be given as a URI, like `ssh://admin@node1:2222`.
+The remote user needs access to the API socket, as the admin container has.
+
+To manage a handful of hosts at once, list them in a file, one per line, and give it with `--hosts-file`.
+Blank lines and lines starting with `#` are ignored.
+`get`, `set`, `update check`, and `report` can be run this way; they run on each host in turn, and then apiclient prints a JSON object keyed by host, holding the output from each host, or the error it failed with:
+
+```shell
+apiclient --hosts-file nodes.txt set motd="maintenance tonight"
+apiclient --hosts-file nodes.txt get settings.motd --filter .settings.motd
+```
+```json
+{
+  "admin@node1": {
+    "output": "maintenance tonight"
+  },
+  "admin@node2": {
+    "error": "Failed to connect through ssh: ssh to 'admin@node2' exited before forwarding the API socket: exit status: 255"
+  }
+}
+```
+
+Output that's JSON, like that of `get` in its default format, is included as JSON, and other output as a string; `set` has no output, so it's shown as `null`.
+ssh runs in batch mode, so hosts that would prompt for a password or to confirm their host key fail rather than waiting; hosts that can't be reached fail after a timeout.
+If the subcommand fails on any host, apiclient keeps going with the others, then fails, listing the hosts that failed.
+
 ### Raw mode
 
 Raw mode lets you make HTTP requests to a UNIX socket.
@@ -1,29 +1,121 @@
 //! This module allows application of settings from URIs or stdin.  The inputs are expected to be
 //! TOML settings files, in the same format as user data, or the JSON equivalent.  The inputs are
 //! pulled and applied to the API server in a single transaction.
+//!
+//! Inputs can be checked against a SHA-256 digest or a detached signature before they're used;
+//! see the 'verify' module.  They're retrieved by the fetchers in the 'fetch' module, which can be
+//! extended to support other URI schemes.
 
-use crate::rando;
-use futures::future::{join, ready, TryFutureExt};
+use crate::{plan, rando};
+use futures::future::{join, ready};
 use futures::stream::{self, StreamExt};
-use reqwest::Url;
+use model::plan::TransactionPlan;
 use serde::de::{Deserialize, IntoDeserializer};
-use snafu::{futures::try_future::TryFutureExt as SnafuTryFutureExt, OptionExt, ResultExt};
-use std::path::Path;
-use tokio::io::AsyncReadExt;
+use snafu::{OptionExt, ResultExt};
+use std::path::{Path, PathBuf};
+
+mod fetch;
+mod verify;
+pub use fetch::{CommandFetcher, FetchError, Fetcher, Fetchers, FileFetcher, HttpFetcher};
+
+/// Input describes a settings file to apply, and how to check that it's the file you expect.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Input {
+    /// The URI of the file, or "-" for stdin.
+    pub source: String,
+    /// The expected SHA-256 digest of the file, in hex.
+    pub sha256: Option<String>,
+    /// The URI of a detached signature of the file, made with the Loader's trusted key.
+    pub signature: Option<String>,
+}
+
+impl From<String> for Input {
+    fn from(source: String) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+}
+
+/// Loader retrieves and checks inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Loader {
+    /// The fetchers used to retrieve inputs and their signatures.
+    pub fetchers: Fetchers,
+    /// The path to the public key that input signatures must be made with.  If given, every input
+    /// must have a signature.
+    pub trusted_key: Option<PathBuf>,
+}
+
+impl Loader {
+    /// Retrieves the given input, checks its digest and signature, if given, and returns its
+    /// contents.
+    pub async fn load(&self, input: &Input) -> Result<String> {
+        let input_source = input.source.as_str();
+        let data = self.fetchers.fetch(input_source).await?;
+
+        if let Some(sha256) = &input.sha256 {
+            verify::check_sha256(&data, sha256, input_source)?;
+        }
+
+        match (&input.signature, &self.trusted_key) {
+            (Some(signature), Some(trusted_key)) => {
+                let signature = self.fetchers.fetch(signature).await?;
+                verify::check_signature(&data, &signature, trusted_key, input_source).await?;
+            }
+            (Some(_), None) => return error::MissingTrustedKeySnafu { input_source }.fail(),
+            (None, Some(_)) => return error::MissingSignatureSnafu { input_source }.fail(),
+            (None, None) => {}
+        }
+
+        String::from_utf8(data).context(error::NonUtf8InputSnafu { input_source })
+    }
+}
 
 /// Reads settings in TOML or JSON format from files at the requested URIs (or from stdin, if given
 /// "-"), then commits them in a single transaction and applies them to the system.
-pub async fn apply<P>(socket_path: P, input_sources: Vec<String>) -> Result<()>
+pub async fn apply<P>(socket_path: P, inputs: Vec<Input>, loader: &Loader) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, inputs, loader).await?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::CommitApplySnafu { uri })?;
+
+    Ok(())
+}
+
+/// Like `apply`, but rather than committing and applying the settings, returns what doing so would
+/// change, and then discards them.
+pub async fn plan<P>(socket_path: P, inputs: Vec<Input>, loader: &Loader) -> Result<TransactionPlan>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, inputs, loader).await?;
+    plan::plan(&socket_path, &transaction)
+        .await
+        .context(error::PlanSnafu)
+}
+
+/// Reads settings from the requested URIs and sends them to the API in a new transaction, and
+/// returns the name of the transaction.
+async fn stage<P>(socket_path: P, inputs: Vec<Input>, loader: &Loader) -> Result<String>
 where
     P: AsRef<Path>,
 {
     // We want to retrieve URIs in parallel because they're arbitrary and could be slow.  First, we
     // build a list of request futures, and we store the source of the data with the future for
     // inclusion in later error messages.
-    let mut get_requests = Vec::with_capacity(input_sources.len());
-    for input_source in &input_sources {
-        let get_future = get(input_source);
-        let info_future = ready(input_source);
+    let mut get_requests = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        let get_future = loader.load(input);
+        let info_future = ready(&input.source);
         get_requests.push(join(info_future, get_future));
     }
 
@@ -56,62 +148,12 @@ where
             })?;
     }
 
-    // Commit the transaction and apply it to the system.
-    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
-    let method = "POST";
-    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
-        .await
-        .context(error::CommitApplySnafu { uri })?;
-
-    Ok(())
-}
-
-/// Retrieves the given source location and returns the result in a String.
-async fn get<S>(input_source: S) -> Result<String>
-where
-    S: Into<String>,
-{
-    let input_source = input_source.into();
-
-    // Read from stdin if "-" was given.
-    if input_source == "-" {
-        let mut output = String::new();
-        tokio::io::stdin()
-            .read_to_string(&mut output)
-            .context(error::StdinReadSnafu)
-            .await?;
-        return Ok(output);
-    }
-
-    // Otherwise, the input should be a URI; parse it to know what kind.
-    // Until reqwest handles file:// URIs: https://github.com/seanmonstar/reqwest/issues/178
-    let uri = Url::parse(&input_source).context(error::UriSnafu {
-        input_source: &input_source,
-    })?;
-    if uri.scheme() == "file" {
-        // Turn the URI to a file path, and return a future that reads it.
-        let path = uri.to_file_path().ok().context(error::FileUriSnafu {
-            input_source: &input_source,
-        })?;
-        tokio::fs::read_to_string(path)
-            .context(error::FileReadSnafu { input_source })
-            .await
-    } else {
-        // Return a future that contains the text of the (non-file) URI.
-        reqwest::get(uri)
-            .and_then(|response| ready(response.error_for_status()))
-            .and_then(|response| response.text())
-            .context(error::ReqwestSnafu {
-                uri: input_source,
-                method: "GET",
-            })
-            .await
-    }
+    Ok(transaction)
 }
 
 /// Takes a string of TOML or JSON settings data and reserializes
 /// it to JSON for sending to the API.
-fn format_change(input: &str, input_source: &str) -> Result<String> {
+pub(crate) fn format_change(input: &str, input_source: &str) -> Result<String> {
     // Try to parse the input as (arbitrary) TOML.  If that fails, try to parse it as JSON.
     let mut json_val = match toml::from_str::<toml::Value>(input) {
         Ok(toml_val) => {
@@ -144,10 +186,14 @@ fn format_change(input: &str, input_source: &str) -> Result<String> {
 
 mod error {
     use snafu::Snafu;
+    use std::path::PathBuf;
 
     #[derive(Debug, Snafu)]
     #[snafu(visibility(pub(super)))]
     pub enum Error {
+        #[snafu(display("Signature of '{}' is not valid for the trusted key", input_source))]
+        BadSignature { input_source: String },
+
         #[snafu(display("Failed to commit combined settings to '{}': {}", uri, source))]
         CommitApply {
             uri: String,
@@ -155,14 +201,23 @@ mod error {
             source: Box<crate::Error>,
         },
 
-        #[snafu(display("Failed to read given file '{}': {}", input_source, source))]
-        FileRead {
+        #[snafu(display(
+            "SHA-256 digest of '{}' is {}, expected {}",
+            input_source,
+            actual,
+            expected
+        ))]
+        DigestMismatch {
             input_source: String,
-            source: std::io::Error,
+            expected: String,
+            actual: String,
         },
 
-        #[snafu(display("Given invalid file URI '{}'", input_source))]
-        FileUri { input_source: String },
+        #[snafu(display("Failed to fetch '{}': {}", input_source, source))]
+        Fetch {
+            input_source: String,
+            source: super::FetchError,
+        },
 
         #[snafu(display(
             "Input '{}' is not valid TOML or JSON.  (TOML error: {})  (JSON error: {})",
@@ -177,6 +232,15 @@ mod error {
             source: Box<serde_json::Error>,
         },
 
+        #[snafu(display(
+            "Signature of '{}' is not a raw or base64 Ed25519 signature",
+            input_source
+        ))]
+        InvalidSignature { input_source: String },
+
+        #[snafu(display("Trusted key at '{}' is not an Ed25519 public key", path.display()))]
+        InvalidTrustedKey { path: PathBuf },
+
         #[snafu(display(
             "Failed to serialize settings from '{}' to JSON: {}",
             input_source,
@@ -193,9 +257,27 @@ mod error {
         ))]
         MissingSettings { input_source: String },
 
+        #[snafu(display(
+            "No signature given for '{}', but a trusted key was given, so all input must be signed",
+            input_source
+        ))]
+        MissingSignature { input_source: String },
+
+        #[snafu(display(
+            "Signature given for '{}', but no trusted key to check it with",
+            input_source
+        ))]
+        MissingTrustedKey { input_source: String },
+
         #[snafu(display("Settings from '{}' are not a TOML table / JSON object", input_source))]
         ModelType { input_source: String },
 
+        #[snafu(display("Settings from '{}' are not valid UTF-8: {}", input_source, source))]
+        NonUtf8Input {
+            input_source: String,
+            source: std::string::FromUtf8Error,
+        },
+
         #[snafu(display(
             "Failed to {} settings from '{}' to '{}': {}",
             method,
@@ -211,12 +293,14 @@ mod error {
             source: Box<crate::Error>,
         },
 
-        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
-        Reqwest {
-            method: String,
-            uri: String,
-            source: reqwest::Error,
-        },
+        #[snafu(display("Unable to plan changes: {}", source))]
+        Plan { source: crate::plan::Error },
+
+        #[snafu(display(
+            "Can't check signature of '{}': apiclient was built without the 'tls' feature",
+            input_source
+        ))]
+        SignatureUnsupported { input_source: String },
 
         #[snafu(display("Failed to read standard input: {}", source))]
         StdinRead { source: std::io::Error },
@@ -231,6 +315,19 @@ mod error {
             source: toml::de::Error,
         },
 
+        #[snafu(display("Trusted key '{}' could not be read: {}", path.display(), source))]
+        TrustedKeyRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display(
+            "No fetcher for '{}' URIs, and no helper program '{}' in PATH",
+            scheme,
+            helper
+        ))]
+        UnsupportedScheme { scheme: String, helper: String },
+
         #[snafu(display("Given invalid URI '{}': {}", input_source, source))]
         Uri {
             input_source: String,
@@ -0,0 +1,178 @@
+//! The 'fetch' module retrieves settings input from URIs.  Each URI scheme is handled by a Fetcher;
+//! file, http, and https are built in, and others, like s3, can be added by library users, or on
+//! the command line by naming a helper program.  For schemes without a fetcher, we look for a
+//! helper named "apiclient-fetch-SCHEME" in PATH.
+//!
+//! A helper program is given the URI as its only argument, and should write the contents to
+//! stdout and exit 0.  For example, a helper for s3 could run `aws s3 cp "$1" -`.
+
+use super::{error, Result};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::Url;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+/// The error type returned by fetchers; anything goes, since fetchers can come from outside.
+pub type FetchError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A Fetcher retrieves the contents of URIs with a particular scheme.
+pub trait Fetcher: Send + Sync {
+    /// Returns the contents of the given URI.
+    fn fetch<'a>(&'a self, uri: &'a Url)
+        -> BoxFuture<'a, std::result::Result<Vec<u8>, FetchError>>;
+}
+
+/// Reads file:// URIs from the local filesystem.
+#[derive(Debug, Default)]
+pub struct FileFetcher;
+
+impl Fetcher for FileFetcher {
+    fn fetch<'a>(
+        &'a self,
+        uri: &'a Url,
+    ) -> BoxFuture<'a, std::result::Result<Vec<u8>, FetchError>> {
+        async move {
+            let path = uri.to_file_path().map_err(|()| {
+                io::Error::new(io::ErrorKind::InvalidInput, "not a valid file path")
+            })?;
+            Ok(tokio::fs::read(path).await?)
+        }
+        .boxed()
+    }
+}
+
+/// Fetches http:// and https:// URIs.
+#[derive(Debug, Default)]
+pub struct HttpFetcher;
+
+impl Fetcher for HttpFetcher {
+    fn fetch<'a>(
+        &'a self,
+        uri: &'a Url,
+    ) -> BoxFuture<'a, std::result::Result<Vec<u8>, FetchError>> {
+        async move {
+            let response = reqwest::get(uri.clone()).await?.error_for_status()?;
+            Ok(response.bytes().await?.to_vec())
+        }
+        .boxed()
+    }
+}
+
+/// Runs a helper program to fetch URIs, passing the URI as its only argument and reading the
+/// contents from its stdout.
+#[derive(Debug, Clone)]
+pub struct CommandFetcher {
+    program: PathBuf,
+}
+
+impl CommandFetcher {
+    pub fn new<P: Into<PathBuf>>(program: P) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+}
+
+impl Fetcher for CommandFetcher {
+    fn fetch<'a>(
+        &'a self,
+        uri: &'a Url,
+    ) -> BoxFuture<'a, std::result::Result<Vec<u8>, FetchError>> {
+        async move {
+            let output = tokio::process::Command::new(&self.program)
+                .arg(uri.as_str())
+                .output()
+                .await?;
+            if !output.status.success() {
+                return Err(format!(
+                    "{} failed ({}): {}",
+                    self.program.display(),
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into());
+            }
+            Ok(output.stdout)
+        }
+        .boxed()
+    }
+}
+
+/// Fetchers maps URI schemes to the Fetchers that handle them.
+#[derive(Clone)]
+pub struct Fetchers {
+    fetchers: HashMap<String, Arc<dyn Fetcher>>,
+}
+
+impl Default for Fetchers {
+    /// Returns the built-in fetchers, for file, http, and https.
+    fn default() -> Self {
+        let mut fetchers = Self {
+            fetchers: HashMap::new(),
+        };
+        fetchers.register("file", FileFetcher);
+        fetchers.register("http", HttpFetcher);
+        fetchers.register("https", HttpFetcher);
+        fetchers
+    }
+}
+
+impl fmt::Debug for Fetchers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut schemes: Vec<_> = self.fetchers.keys().collect();
+        schemes.sort();
+        f.debug_struct("Fetchers")
+            .field("schemes", &schemes)
+            .finish()
+    }
+}
+
+impl Fetchers {
+    /// Uses the given Fetcher for URIs with the given scheme, replacing any previous one.
+    pub fn register<S, F>(&mut self, scheme: S, fetcher: F)
+    where
+        S: Into<String>,
+        F: Fetcher + 'static,
+    {
+        self.fetchers.insert(scheme.into(), Arc::new(fetcher));
+    }
+
+    /// Returns the contents of the given input source, which is a URI, or "-" for stdin.
+    pub async fn fetch(&self, input_source: &str) -> Result<Vec<u8>> {
+        if input_source == "-" {
+            let mut output = Vec::new();
+            tokio::io::stdin()
+                .read_to_end(&mut output)
+                .await
+                .context(error::StdinReadSnafu)?;
+            return Ok(output);
+        }
+
+        let uri = Url::parse(input_source).context(error::UriSnafu { input_source })?;
+        let scheme = uri.scheme();
+        if let Some(fetcher) = self.fetchers.get(scheme) {
+            return fetcher
+                .fetch(&uri)
+                .await
+                .context(error::FetchSnafu { input_source });
+        }
+
+        // Fall back to a helper program in PATH, if there is one.
+        let helper = format!("apiclient-fetch-{}", scheme);
+        match CommandFetcher::new(&helper).fetch(&uri).await {
+            Ok(contents) => Ok(contents),
+            Err(e)
+                if e.downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == io::ErrorKind::NotFound) =>
+            {
+                error::UnsupportedSchemeSnafu { scheme, helper }.fail()
+            }
+            Err(e) => Err(e).context(error::FetchSnafu { input_source }),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! The 'verify' module checks that settings input is what the user expects, either by its SHA-256
+//! digest or by a detached Ed25519 signature made with a key the host trusts, so the input can be
+//! fetched over a transport that isn't trusted.
+//!
+//! Trusted keys are Ed25519 public keys, either PEM-encoded, like the output of
+//! `openssl pkey -pubout`, or the raw 32-byte key in base64.  Signatures are the raw 64 bytes, like
+//! the output of `openssl pkeyutl -sign -rawin`, or the same in base64.
+//!
+//! Signatures are checked with aws-lc-rs, which comes with the 'tls' feature; without it, inputs
+//! can still be checked by digest, but not by signature.
+
+use super::{error, Result};
+#[cfg(feature = "tls")]
+use aws_lc_rs::signature::{UnparsedPublicKey, ED25519};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::path::Path;
+
+/// The DER encoding of an Ed25519 SubjectPublicKeyInfo, up to the key itself.
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+const ED25519_KEY_LEN: usize = 32;
+const ED25519_SIGNATURE_LEN: usize = 64;
+
+/// Returns the lowercase hex SHA-256 digest of the given data.
+pub(super) fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Checks that the given data has the expected SHA-256 digest, given in hex.
+pub(super) fn check_sha256(data: &[u8], expected: &str, input_source: &str) -> Result<()> {
+    let actual = sha256_hex(data);
+    ensure!(
+        actual.eq_ignore_ascii_case(expected.trim()),
+        error::DigestMismatchSnafu {
+            input_source,
+            expected,
+            actual,
+        }
+    );
+    Ok(())
+}
+
+/// Checks that the given signature of the data was made with the trusted key at the given path.
+pub(super) async fn check_signature(
+    data: &[u8],
+    signature: &[u8],
+    trusted_key: &Path,
+    input_source: &str,
+) -> Result<()> {
+    let key_text = tokio::fs::read_to_string(trusted_key)
+        .await
+        .context(error::TrustedKeyReadSnafu { path: trusted_key })?;
+    let key = parse_public_key(&key_text, trusted_key)?;
+    let signature = parse_signature(signature, input_source)?;
+    verify_ed25519(&key, data, &signature, input_source)
+}
+
+/// Checks that the given Ed25519 signature of the data was made with the given public key.
+#[cfg(feature = "tls")]
+fn verify_ed25519(key: &[u8], data: &[u8], signature: &[u8], input_source: &str) -> Result<()> {
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(data, signature)
+        .ok()
+        .context(error::BadSignatureSnafu { input_source })
+}
+
+/// Without the 'tls' feature, there's nothing to check signatures with.
+#[cfg(not(feature = "tls"))]
+fn verify_ed25519(_key: &[u8], _data: &[u8], _signature: &[u8], input_source: &str) -> Result<()> {
+    error::SignatureUnsupportedSnafu { input_source }.fail()
+}
+
+/// Parses an Ed25519 public key from PEM or base64 text.
+fn parse_public_key(text: &str, path: &Path) -> Result<Vec<u8>> {
+    // Leave out PEM armor lines; what's left is base64.
+    let encoded: String = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let decoded = STANDARD
+        .decode(encoded)
+        .ok()
+        .context(error::InvalidTrustedKeySnafu { path })?;
+    if decoded.len() == ED25519_KEY_LEN {
+        return Ok(decoded);
+    }
+    let key = decoded
+        .strip_prefix(ED25519_SPKI_PREFIX)
+        .filter(|key| key.len() == ED25519_KEY_LEN)
+        .context(error::InvalidTrustedKeySnafu { path })?;
+    Ok(key.to_vec())
+}
+
+/// Parses a raw or base64-encoded Ed25519 signature.
+fn parse_signature(signature: &[u8], input_source: &str) -> Result<Vec<u8>> {
+    if signature.len() == ED25519_SIGNATURE_LEN {
+        return Ok(signature.to_vec());
+    }
+    std::str::from_utf8(signature)
+        .ok()
+        .and_then(|text| STANDARD.decode(text.trim()).ok())
+        .filter(|decoded| decoded.len() == ED25519_SIGNATURE_LEN)
+        .context(error::InvalidSignatureSnafu { input_source })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "tls")]
+    use aws_lc_rs::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    #[test]
+    fn digest() {
+        let data = b"[settings]\nmotd = \"hi\"\n";
+        let expected = sha256_hex(data);
+        assert!(check_sha256(data, &expected.to_uppercase(), "-").is_ok());
+        assert!(matches!(
+            check_sha256(b"other", &expected, "-"),
+            Err(error::Error::DigestMismatch { .. })
+        ));
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn signature() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let data = b"[settings]\nmotd = \"hi\"\n";
+        let signature = pair.sign(data);
+
+        // Write the key as PEM, as openssl would.
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend_from_slice(pair.public_key().as_ref());
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            STANDARD.encode(&der)
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("trusted.pem");
+        std::fs::write(&key_path, pem).unwrap();
+
+        check_signature(data, signature.as_ref(), &key_path, "-")
+            .await
+            .unwrap();
+        let encoded = STANDARD.encode(signature.as_ref());
+        check_signature(data, encoded.as_bytes(), &key_path, "-")
+            .await
+            .unwrap();
+        assert!(matches!(
+            check_signature(b"other", signature.as_ref(), &key_path, "-").await,
+            Err(error::Error::BadSignature { .. })
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//! The client module provides a typed async client for the API, for programs that would otherwise
+//! build URIs, make requests with `raw_request`, and parse the responses themselves.  Responses
+//! are deserialized into the caller's types, and error statuses are mapped to variants of Error,
+//! so callers can handle the ones they expect, like committing a transaction with no changes.
+
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Client makes requests to the API server listening on a Unix-domain socket.
+#[derive(Debug, Clone)]
+pub struct Client {
+    socket_path: PathBuf,
+}
+
+impl Client {
+    /// Returns a client for the API server listening on the given socket, usually
+    /// constants::API_SOCKET.
+    pub fn new<P: Into<PathBuf>>(socket_path: P) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    // Settings and transactions
+
+    /// Returns the live settings.
+    pub async fn get_settings<T: DeserializeOwned>(&self) -> Result<T> {
+        self.get("/settings".to_string()).await
+    }
+
+    /// Returns the settings and other data, like "os", whose names start with the given prefix,
+    /// like "settings.kubernetes".  The response starts at the top of the model, so settings are
+    /// under "settings".
+    pub async fn get_prefix<T: DeserializeOwned>(&self, prefix: &str) -> Result<T> {
+        self.get(format!("/?prefix={}", encode(prefix))).await
+    }
+
+    /// Returns the pending settings in the given transaction.
+    pub async fn get_pending_settings<T: DeserializeOwned>(&self, transaction: &str) -> Result<T> {
+        self.get(format!("/tx?tx={}", encode(transaction))).await
+    }
+
+    /// Adds the given settings to the given transaction.  They don't take effect until the
+    /// transaction is committed.
+    pub async fn patch_settings<T: Serialize>(
+        &self,
+        settings: &T,
+        transaction: &str,
+    ) -> Result<()> {
+        let uri = format!("/settings?tx={}", encode(transaction));
+        let data = serde_json::to_string(settings).context(error::SerializeSnafu)?;
+        self.request("PATCH", uri, Some(data)).await?;
+        Ok(())
+    }
+
+    /// Returns the names of transactions with pending settings.
+    pub async fn list_transactions(&self) -> Result<Vec<String>> {
+        let mut transactions: Vec<String> = self.get("/tx/list".to_string()).await?;
+        transactions.sort();
+        Ok(transactions)
+    }
+
+    /// Deletes the given transaction, returning the names of the settings it had.
+    pub async fn delete_transaction(&self, transaction: &str) -> Result<Vec<String>> {
+        let uri = format!("/tx?tx={}", encode(transaction));
+        self.request_json("DELETE", uri, None).await
+    }
+
+    /// Commits the given transaction, returning the names of the settings that changed, without
+    /// applying the changes to the system.  Fails with Error::Unprocessable if the transaction has
+    /// no changes.
+    pub async fn commit(&self, transaction: &str) -> Result<Vec<String>> {
+        let uri = format!("/tx/commit?tx={}", encode(transaction));
+        self.request_json("POST", uri, None).await
+    }
+
+    /// Commits the given transaction and applies the changes to the system, returning the names
+    /// of the settings that changed.  Fails with Error::Unprocessable if the transaction has no
+    /// changes.
+    pub async fn commit_and_apply(&self, transaction: &str) -> Result<Vec<String>> {
+        let uri = format!("/tx/commit_and_apply?tx={}", encode(transaction));
+        self.request_json("POST", uri, None).await
+    }
+
+    // Metadata
+
+    /// Returns the services affected by each of the given settings, or prefixes of settings, that
+    /// has any.
+    pub async fn affected_services(&self, keys: &[&str]) -> Result<HashMap<String, Vec<String>>> {
+        self.get(format!(
+            "/metadata/affected-services?keys={}",
+            encode(&keys.join(","))
+        ))
+        .await
+    }
+
+    /// Returns the setting generator of each setting that has one.  A generator is usually a
+    /// command, but can be a map with the command and options.
+    pub async fn setting_generators(&self) -> Result<HashMap<String, Value>> {
+        self.get("/metadata/setting-generators".to_string()).await
+    }
+
+    /// Returns the template of each of the given settings that has one.
+    pub async fn templates(&self, keys: &[&str]) -> Result<HashMap<String, String>> {
+        self.get(format!(
+            "/metadata/templates?keys={}",
+            encode(&keys.join(","))
+        ))
+        .await
+    }
+
+    // Updates and other actions
+
+    /// Returns the update status.  Fails with Error::NotFound if no update command has been run,
+    /// and with Error::Locked while one is running.
+    pub async fn update_status<T: DeserializeOwned>(&self) -> Result<T> {
+        self.get("/updates/status".to_string()).await
+    }
+
+    /// Starts refreshing the list of available updates.  Like the other update actions, this only
+    /// starts the command; check update_status to see when it's done.
+    pub async fn refresh_updates(&self) -> Result<()> {
+        self.action("refresh-updates").await
+    }
+
+    /// Starts downloading the chosen update to the inactive partitions.
+    pub async fn prepare_update(&self) -> Result<()> {
+        self.action("prepare-update").await
+    }
+
+    /// Starts marking the prepared update to be used on the next boot.
+    pub async fn activate_update(&self) -> Result<()> {
+        self.action("activate-update").await
+    }
+
+    /// Starts undoing activate_update, so the next boot uses the current partitions.
+    pub async fn deactivate_update(&self) -> Result<()> {
+        self.action("deactivate-update").await
+    }
+
+    /// Starts rolling back to the previous version, by marking the inactive partitions for boot.
+    /// The API only allows this if they hold an older version that has booted successfully.
+    pub async fn rollback(&self) -> Result<()> {
+        self.action("rollback").await
+    }
+
+    /// Reboots the host.
+    pub async fn reboot(&self) -> Result<()> {
+        self.action("reboot").await
+    }
+
+    // Requests
+
+    async fn action(&self, name: &str) -> Result<()> {
+        self.request("POST", format!("/actions/{}", name), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, uri: String) -> Result<T> {
+        self.request_json("GET", uri, None).await
+    }
+
+    async fn request_json<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        uri: String,
+        data: Option<String>,
+    ) -> Result<T> {
+        let body = self.request(method, uri.clone(), data).await?;
+        serde_json::from_str(&body).context(error::ResponseJsonSnafu { method, uri })
+    }
+
+    /// Makes a request, returning the response body if the status shows success.
+    async fn request(&self, method: &str, uri: String, data: Option<String>) -> Result<String> {
+        let (code, body) = crate::raw_request_unchecked(&self.socket_path, &uri, method, data)
+            .await
+            .context(error::RequestSnafu { method, uri: &uri })?;
+        if code.is_success() {
+            Ok(body)
+        } else {
+            Err(status_error(method, uri, code, body))
+        }
+    }
+}
+
+/// Encodes a value for a query parameter, since setting names can have characters like '"' and
+/// '&' in their quoted segments.
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// Maps an error status from the API to an Error.
+fn status_error(method: &str, uri: String, code: StatusCode, body: String) -> Error {
+    let method = method.to_string();
+    match code {
+        StatusCode::BAD_REQUEST => Error::BadRequest { method, uri, body },
+        StatusCode::FORBIDDEN => Error::Forbidden { method, uri, body },
+        StatusCode::NOT_FOUND => Error::NotFound { method, uri, body },
+        StatusCode::CONFLICT => Error::Conflict { method, uri, body },
+        StatusCode::UNPROCESSABLE_ENTITY => Error::Unprocessable { method, uri, body },
+        StatusCode::LOCKED => Error::Locked { method, uri, body },
+        code if code.is_server_error() => Error::Server {
+            method,
+            uri,
+            code,
+            body,
+        },
+        code => Error::Status {
+            method,
+            uri,
+            code,
+            body,
+        },
+    }
+}
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        /// The request was invalid, for example because of a setting's value.
+        #[snafu(display("Invalid {} request to '{}': {}", method, uri, body))]
+        BadRequest {
+            method: String,
+            uri: String,
+            body: String,
+        },
+
+        /// The request conflicts with the state of the system, for example because settings
+        /// changed since the revision the caller gave.
+        #[snafu(display(
+            "{} request to '{}' conflicts with current state: {}",
+            method,
+            uri,
+            body
+        ))]
+        Conflict {
+            method: String,
+            uri: String,
+            body: String,
+        },
+
+        /// The server's policy doesn't allow this client to make the request.
+        #[snafu(display("{} request to '{}' was denied: {}", method, uri, body))]
+        Forbidden {
+            method: String,
+            uri: String,
+            body: String,
+        },
+
+        /// An update command holds the update lock.
+        #[snafu(display("{} request to '{}' is locked: {}", method, uri, body))]
+        Locked {
+            method: String,
+            uri: String,
+            body: String,
+        },
+
+        /// What was requested doesn't exist, like an update status before any update command.
+        #[snafu(display("Nothing found for {} request to '{}': {}", method, uri, body))]
+        NotFound {
+            method: String,
+            uri: String,
+            body: String,
+        },
+
+        /// We couldn't talk to the server.
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display(
+            "Response to {} request to '{}' was not valid JSON: {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: String,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to serialize request: {}", source))]
+        Serialize { source: serde_json::Error },
+
+        #[snafu(display("Server error {} for {} request to '{}': {}", code, method, uri, body))]
+        Server {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            body: String,
+        },
+
+        #[snafu(display("{} request to '{}' returned {}: {}", method, uri, code, body))]
+        Status {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            body: String,
+        },
+
+        /// The request was valid but there was nothing to do, like committing a transaction with
+        /// no changes.
+        #[snafu(display("Unable to process {} request to '{}': {}", method, uri, body))]
+        Unprocessable {
+            method: String,
+            uri: String,
+            body: String,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn status_errors() {
+        let error = |code| status_error("POST", "/tx/commit".to_string(), code, "no".to_string());
+        assert!(matches!(
+            error(StatusCode::UNPROCESSABLE_ENTITY),
+            Error::Unprocessable { .. }
+        ));
+        assert!(matches!(error(StatusCode::LOCKED), Error::Locked { .. }));
+        assert!(matches!(
+            error(StatusCode::SERVICE_UNAVAILABLE),
+            Error::Server { .. }
+        ));
+        assert!(matches!(
+            error(StatusCode::METHOD_NOT_ALLOWED),
+            Error::Status { .. }
+        ));
+        assert_eq!(
+            error(StatusCode::CONFLICT).to_string(),
+            "POST request to '/tx/commit' conflicts with current state: no"
+        );
+    }
+
+    #[test]
+    fn query_encoding() {
+        assert_eq!(
+            encode(r#"settings.kubernetes.node-labels."a&b=c""#),
+            "settings.kubernetes.node-labels.%22a%26b%3Dc%22"
+        );
+        assert_eq!(
+            encode("settings.motd,settings.ntp"),
+            "settings.motd%2Csettings.ntp"
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! The completion module helps shells complete apiclient command lines.  The scripts it generates
+//! call back into apiclient with the words typed so far, so completions follow the subcommands and
+//! options apiclient accepts, and setting names come from the settings schema and the live
+//! settings on the host.
+
+use crate::schema;
+use datastore::serialization::to_pairs_with_prefix;
+use snafu::ResultExt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The shells we can generate completion scripts for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Fish,
+    Zsh,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(shell: &str) -> Result<Self> {
+        match shell {
+            "bash" => Ok(Self::Bash),
+            "fish" => Ok(Self::Fish),
+            "zsh" => Ok(Self::Zsh),
+            _ => error::ShellSnafu { shell }.fail(),
+        }
+    }
+}
+
+/// Cli describes the command line to complete.
+#[derive(Debug)]
+pub struct Cli {
+    /// Options accepted with any subcommand.
+    pub options: &'static [&'static str],
+    /// Global options that take a value.
+    pub value_options: &'static [&'static str],
+    pub commands: &'static [Command],
+}
+
+/// Command describes a subcommand for completion.
+#[derive(Debug)]
+pub struct Command {
+    /// The words that select the subcommand, like "update check".
+    pub name: &'static str,
+    /// Options that don't take a value.
+    pub options: &'static [&'static str],
+    /// Options that take a value.
+    pub value_options: &'static [&'static str],
+    /// Whether the subcommand takes setting names, like 'get'.
+    pub settings: bool,
+    /// Whether the subcommand takes local files, like 'apply'.
+    pub files: bool,
+}
+
+/// Completion holds what could complete the last word of a command line.
+#[derive(Debug, Default, PartialEq)]
+pub struct Completion {
+    /// Subcommands or options that start with the word.
+    pub words: Vec<String>,
+    /// Whether names of settings that start with the word could also complete it.
+    pub settings: bool,
+    /// The word being completed.
+    pub current: String,
+    /// The socket path given on the command line, for fetching setting names.
+    pub socket_path: Option<String>,
+}
+
+/// Returns what could complete the last of the given words, which are the words of a command line
+/// after "apiclient".  The last word is the one being completed, and may be empty.
+pub fn complete(cli: &Cli, words: &[String]) -> Completion {
+    let (current, before) = match words.split_last() {
+        Some((current, before)) => (current.clone(), before),
+        None => (String::new(), words),
+    };
+    let mut completion = Completion {
+        current,
+        ..Default::default()
+    };
+
+    // Find the subcommand, skipping option values, which could look like anything.
+    let mut path = String::new();
+    let mut positional = false;
+    let mut takes_value = false;
+    let mut value_option = "";
+    for word in before {
+        if takes_value {
+            if matches!(value_option, "-s" | "--socket-path") {
+                completion.socket_path = Some(word.clone());
+            }
+            takes_value = false;
+            continue;
+        }
+        if word.starts_with('-') {
+            takes_value = cli.value_options.contains(&word.as_str())
+                || find(cli, &path).is_some_and(|c| c.value_options.contains(&word.as_str()));
+            value_option = word;
+            continue;
+        }
+        let longer = if path.is_empty() {
+            word.clone()
+        } else {
+            format!("{} {}", path, word)
+        };
+        if !positional && cli.commands.iter().any(|c| selects(c.name, &longer)) {
+            path = longer;
+        } else {
+            positional = true;
+        }
+    }
+    // We can't know the possible values of options, so let the shell complete them as files.
+    if takes_value {
+        return completion;
+    }
+
+    let command = find(cli, &path);
+    if completion.current.starts_with('-') {
+        let mut options: Vec<&str> = cli.options.to_vec();
+        options.extend(cli.value_options);
+        if let Some(command) = command {
+            options.extend(command.options);
+            options.extend(command.value_options);
+        }
+        completion.words = matching(options, &completion.current);
+    } else if let Some(command) = command {
+        completion.settings = command.settings;
+    } else if !positional {
+        // Offer the next word of any subcommand that starts with what we've seen.
+        let next = cli.commands.iter().filter_map(|c| {
+            let rest = if path.is_empty() {
+                c.name
+            } else {
+                c.name.strip_prefix(&path)?.strip_prefix(' ')?
+            };
+            rest.split(' ').next()
+        });
+        completion.words = matching(next, &completion.current);
+    }
+    completion
+}
+
+/// Returns the subcommand with the given name, if any.
+fn find<'a>(cli: &'a Cli, name: &str) -> Option<&'a Command> {
+    cli.commands.iter().find(|c| c.name == name)
+}
+
+/// Returns whether the given words are the start of the name of a subcommand, like "update" for
+/// "update check".
+fn selects(name: &str, words: &str) -> bool {
+    name == words
+        || name
+            .strip_prefix(words)
+            .is_some_and(|rest| rest.starts_with(' '))
+}
+
+/// Returns the given words that start with the given prefix, sorted, without duplicates.
+fn matching<'a, I>(words: I, prefix: &str) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut words: Vec<String> = words
+        .into_iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(String::from)
+        .collect();
+    words.sort();
+    words.dedup();
+    words
+}
+
+/// Returns the names of all settings that start with the given prefix, like "settings.motd": those
+/// described by the settings schema, whether or not they're set, and any others that are set.
+pub async fn setting_names<P>(socket_path: P, prefix: &str) -> Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings";
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+    let settings = serde_json::from_str(&body).context(error::ResponseJsonSnafu { body })?;
+    let pairs = to_pairs_with_prefix("settings", &settings).context(error::FlattenSnafu)?;
+    let schema = schema::get_schema(&socket_path)
+        .await
+        .context(error::SchemaSnafu)?;
+    let schemas = schema::settings(&schema).context(error::SchemaSnafu)?;
+
+    let mut names: Vec<String> = pairs
+        .into_keys()
+        .map(|key| key.name().clone())
+        .chain(schemas.into_keys())
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Returns a script that sets up completion of apiclient command lines in the given shell.
+pub fn script(cli: &Cli, shell: Shell) -> String {
+    match shell {
+        Shell::Bash => BASH.to_string(),
+        Shell::Zsh => ZSH.to_string(),
+        Shell::Fish => {
+            // fish can't fall back to files only when we have nothing to offer, so we tell it
+            // which subcommands take files.
+            let files: Vec<&str> = cli
+                .commands
+                .iter()
+                .filter(|c| c.files)
+                .filter_map(|c| c.name.split(' ').next_back())
+                .collect();
+            format!("{}{}'\n", FISH, files.join(" "))
+        }
+    }
+}
+
+const BASH: &str = r#"# bash completion for apiclient; load it with: source <(apiclient completion bash)
+_apiclient() {
+    local IFS=$'\n'
+    COMPREPLY=($(apiclient completion --complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F _apiclient apiclient
+"#;
+
+const ZSH: &str = r#"#compdef apiclient
+# zsh completion for apiclient; load it with: source <(apiclient completion zsh)
+_apiclient() {
+    local -a candidates
+    candidates=("${(@f)$(apiclient completion --complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    if [[ -n "${candidates[1]}" ]]; then
+        compadd -a candidates
+    else
+        _files
+    fi
+}
+compdef _apiclient apiclient
+"#;
+
+const FISH: &str = r#"# fish completion for apiclient; load it with: apiclient completion fish | source
+function __apiclient_complete
+    set -l words (commandline -opc)
+    set -e words[1]
+    set -l current (commandline -ct)
+    apiclient completion --complete -- $words "$current" 2>/dev/null
+end
+complete -c apiclient -f -a '(__apiclient_complete)'
+complete -c apiclient -F -n '__fish_seen_subcommand_from "#;
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Unable to flatten settings: {}", source))]
+        Flatten {
+            source: datastore::serialization::Error,
+        },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid JSON '{}' - {}", body, source))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to get settings schema: {}", source))]
+        Schema { source: crate::schema::Error },
+
+        #[snafu(display("Unknown shell '{}', expected bash, fish, or zsh", shell))]
+        Shell { shell: String },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static CLI: Cli = Cli {
+        options: &["--verbose"],
+        value_options: &["--socket-path"],
+        commands: &[
+            Command {
+                name: "get",
+                options: &[],
+                value_options: &[],
+                settings: true,
+                files: false,
+            },
+            Command {
+                name: "update check",
+                options: &[],
+                value_options: &[],
+                settings: false,
+                files: false,
+            },
+            Command {
+                name: "update watch",
+                options: &[],
+                value_options: &["--interval", "--until"],
+                settings: false,
+                files: false,
+            },
+        ],
+    };
+
+    fn complete_words(words: &[&str]) -> Completion {
+        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+        complete(&CLI, &words)
+    }
+
+    #[test]
+    fn completions() {
+        assert_eq!(complete_words(&[]).words, vec!["get", "update"]);
+        assert_eq!(complete_words(&["u"]).words, vec!["update"]);
+        assert_eq!(
+            complete_words(&["--verbose", "update", ""]).words,
+            vec!["check", "watch"]
+        );
+        assert_eq!(
+            complete_words(&["update", "watch", "--"]).words,
+            vec!["--interval", "--socket-path", "--until", "--verbose"]
+        );
+        // Option values aren't completed, and aren't taken for subcommands.
+        assert_eq!(
+            complete_words(&["--socket-path", ""]),
+            Completion::default()
+        );
+        let completion = complete_words(&["--socket-path", "get", "get", "settings.m"]);
+        assert!(completion.words.is_empty());
+        assert!(completion.settings);
+        assert_eq!(completion.current, "settings.m");
+        assert_eq!(completion.socket_path.as_deref(), Some("get"));
+        // Nothing follows a complete subcommand that doesn't take settings.
+        assert_eq!(
+            complete_words(&["update", "check", ""]),
+            Completion::default()
+        );
+    }
+}
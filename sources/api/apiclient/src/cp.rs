@@ -0,0 +1,606 @@
+//! The 'cp' module copies files to and from host containers through the apiserver.  It uses the
+//! same WebSocket as 'exec', but rather than running a command, it asks the server to stream a
+//! file, keeping error output apart from the file data.  Both sides track the size and SHA-256
+//! digest of the data, and a copy is only accepted if they agree.
+//!
+//! A file copied from a container is written next to its destination and moved into place once
+//! it's checked, so a failed copy doesn't leave a partial file behind.  The server does the same
+//! for a file copied to a container.
+
+use crate::exec::connect::{self, websocket_connect};
+use futures::{SinkExt, StreamExt};
+use futures_channel::mpsc;
+use log::{debug, trace};
+use model::exec::{
+    Capacity, ClientMessage, Copy, CopyDirection, CopyResult, Exit, FileInfo, ServerMessage,
+};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs::Permissions;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::{protocol::CloseFrame, Message};
+
+/// We ping the server regularly so it knows we're still here during long copies.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// File data is sent in messages of this size; it matches 'exec', which the server expects.
+const BUFFER_SIZE: usize = 4096;
+
+/// Copied describes a file that was copied successfully.
+#[derive(Debug, Clone)]
+pub struct Copied {
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The SHA-256 digest of the file, in hex.
+    pub sha256: String,
+}
+
+/// Copies the file at `container_path` in the `target` container to `local_path`.  The local
+/// file gets the same permissions, apart from setuid, setgid, and sticky bits.  If `progress` is
+/// true, shows progress on stderr.
+pub async fn copy_from_container<P>(
+    socket_path: P,
+    target: &str,
+    container_path: &Path,
+    local_path: &Path,
+    progress: bool,
+) -> Result<Copied>
+where
+    P: AsRef<Path>,
+{
+    ensure!(
+        container_path.is_absolute(),
+        error::RelativePathSnafu {
+            path: container_path
+        }
+    );
+    let copy = Copy {
+        target: target.to_string(),
+        path: container_path.into(),
+        direction: CopyDirection::FromContainer,
+    };
+    let (ws_tx, mut read) = start(socket_path, copy).await?;
+    // We don't send the server any file data.
+    send(&ws_tx, ClientMessage::ContentComplete)?;
+
+    let mut server = ServerState::default();
+    let mut transfer = Transfer::new();
+    let mut progress = Progress::new(progress);
+    let mut file: Option<(TempFile, tokio::fs::File)> = None;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    while !server.closed {
+        tokio::select! {
+            msg = read.next() => {
+                let Some(msg) = msg else { break };
+                let Some(data) = server.receive(msg.context(error::ReadWebSocketSnafu)?)? else {
+                    // The server sends FileInfo before any data; that's when we start the file.
+                    if let (Some(info), None) = (server.info, &file) {
+                        debug!("Copying {} bytes to {}", info.size, local_path.display());
+                        progress.total = Some(info.size);
+                        file = Some(TempFile::create(local_path).await?);
+                    }
+                    continue;
+                };
+                let (temp, file) = file.as_mut().context(error::NoFileInfoSnafu)?;
+                file.write_all(&data)
+                    .await
+                    .context(error::WriteFileSnafu { path: &temp.path })?;
+                transfer.update(&data);
+                progress.update(transfer.size);
+            }
+            _ = heartbeat.tick() => {
+                let _ = ws_tx.unbounded_send(Message::Ping(vec![]));
+            }
+        }
+    }
+    progress.finish();
+
+    let info = server.info;
+    let copied = server.check(transfer)?;
+    let (temp, mut file) = file.context(error::NoFileInfoSnafu)?;
+    let info = info.context(error::NoFileInfoSnafu)?;
+    ensure!(
+        copied.size == info.size,
+        error::SizeMismatchSnafu {
+            expected: info.size,
+            actual: copied.size,
+        }
+    );
+    file.flush()
+        .await
+        .context(error::WriteFileSnafu { path: &temp.path })?;
+    drop(file);
+    temp.persist(local_path, info.mode & 0o777).await?;
+    Ok(copied)
+}
+
+/// Copies the file at `local_path` to `container_path` in the `target` container.  The container
+/// file gets the same permissions, apart from setuid, setgid, and sticky bits.  If `progress` is
+/// true, shows progress on stderr.
+pub async fn copy_to_container<P>(
+    socket_path: P,
+    local_path: &Path,
+    target: &str,
+    container_path: &Path,
+    progress: bool,
+) -> Result<Copied>
+where
+    P: AsRef<Path>,
+{
+    ensure!(
+        container_path.is_absolute(),
+        error::RelativePathSnafu {
+            path: container_path
+        }
+    );
+    let mut file = tokio::fs::File::open(local_path)
+        .await
+        .context(error::ReadFileSnafu { path: local_path })?;
+    let metadata = file
+        .metadata()
+        .await
+        .context(error::ReadFileSnafu { path: local_path })?;
+    ensure!(metadata.is_file(), error::NotFileSnafu { path: local_path });
+    let info = FileInfo {
+        mode: metadata.permissions().mode() & 0o777,
+        size: metadata.len(),
+    };
+
+    let copy = Copy {
+        target: target.to_string(),
+        path: container_path.into(),
+        direction: CopyDirection::ToContainer(info),
+    };
+    let (ws_tx, mut read) = start(socket_path, copy).await?;
+
+    let mut server = ServerState::default();
+    let mut transfer = Transfer::new();
+    let mut progress = Progress::new(progress);
+    progress.total = Some(info.size);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut messages_sent = 0u64;
+    let mut sending = true;
+
+    while !server.closed {
+        // Only read more of the file when the server has room for it; it disconnects clients that
+        // send more than it allows.
+        let has_capacity = sending && server.has_capacity(messages_sent);
+        tokio::select! {
+            msg = read.next() => {
+                let Some(msg) = msg else { break };
+                if server.receive(msg.context(error::ReadWebSocketSnafu)?)?.is_some() {
+                    trace!("Ignoring unexpected output from server");
+                }
+            }
+            n = file.read(&mut buf), if has_capacity => {
+                let n = n.context(error::ReadFileSnafu { path: local_path })?;
+                if n == 0 {
+                    debug!("Finished sending {} bytes", transfer.size);
+                    send(&ws_tx, ClientMessage::ContentComplete)?;
+                    sending = false;
+                    continue;
+                }
+                transfer.update(&buf[..n]);
+                ws_tx
+                    .unbounded_send(Message::Binary(buf[..n].to_vec()))
+                    .ok()
+                    .context(error::SendMessageSnafu { kind: "file data" })?;
+                messages_sent += 1;
+                progress.update(transfer.size);
+            }
+            _ = heartbeat.tick() => {
+                let _ = ws_tx.unbounded_send(Message::Ping(vec![]));
+            }
+        }
+    }
+    progress.finish();
+
+    // The file could have changed while we read it.
+    let copied = server.check(transfer)?;
+    ensure!(
+        copied.size == info.size,
+        error::SizeMismatchSnafu {
+            expected: info.size,
+            actual: copied.size,
+        }
+    );
+    Ok(copied)
+}
+
+/// Connects to the server and asks it to start the given copy.  Returns a channel for sending
+/// messages to the server, and the stream of messages from the server.
+async fn start<P>(
+    socket_path: P,
+    copy: Copy,
+) -> Result<(
+    mpsc::UnboundedSender<Message>,
+    impl futures::Stream<Item = std::result::Result<Message, tokio_tungstenite::tungstenite::Error>>,
+)>
+where
+    P: AsRef<Path>,
+{
+    let ws_stream = websocket_connect(socket_path, "/exec")
+        .await
+        .context(error::ConnectSnafu)?;
+    let (mut write, read) = ws_stream.split();
+
+    // Forward anything sent to the channel to the WebSocket, as 'exec' does.
+    let (ws_tx, mut ws_rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        while let Some(msg) = ws_rx.next().await {
+            if let Err(e) = write.send(msg).await {
+                debug!("Failed to send message to server: {}", e);
+                break;
+            }
+        }
+    });
+
+    debug!(
+        "Sending copy request for target '{}' and path {:?}",
+        copy.target, copy.path
+    );
+    send(&ws_tx, ClientMessage::Copy(copy))?;
+    Ok((ws_tx, read))
+}
+
+/// Sends a control message to the server.
+fn send(ws_tx: &mpsc::UnboundedSender<Message>, msg: ClientMessage) -> Result<()> {
+    let msg = serde_json::to_string(&msg).context(error::SerializeSnafu)?;
+    ws_tx
+        .unbounded_send(Message::Text(msg))
+        .ok()
+        .context(error::SendMessageSnafu { kind: "control" })
+}
+
+/// ServerState records what the server has told us about the copy.
+#[derive(Debug, Default)]
+struct ServerState {
+    capacity: Option<Capacity>,
+    info: Option<FileInfo>,
+    result: Option<CopyResult>,
+    exit: Option<Exit>,
+    close: Option<CloseFrame<'static>>,
+    closed: bool,
+}
+
+impl ServerState {
+    /// Records a message from the server, returning file data, if it was file data.
+    fn receive(&mut self, msg: Message) -> Result<Option<Vec<u8>>> {
+        match msg {
+            Message::Binary(data) => return Ok(Some(data)),
+            Message::Text(raw) => {
+                match serde_json::from_str(&raw).context(error::DeserializeSnafu)? {
+                    ServerMessage::Capacity(capacity) => self.capacity = Some(capacity),
+                    ServerMessage::FileInfo(info) => self.info = Some(info),
+                    ServerMessage::CopyResult(result) => self.result = Some(result),
+                    ServerMessage::Exit(exit) => self.exit = Some(exit),
+                }
+            }
+            Message::Close(frame) => {
+                self.close = frame;
+                self.closed = true;
+            }
+            // tokio-tungstenite answers pings for us, and the server doesn't use frames.
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+        }
+        Ok(None)
+    }
+
+    /// Returns whether the server will accept another message of file data, given how many we've
+    /// sent.
+    fn has_capacity(&self, messages_sent: u64) -> bool {
+        self.capacity.as_ref().is_some_and(|capacity| {
+            messages_sent.saturating_sub(capacity.messages_written)
+                < capacity.max_messages_outstanding
+        })
+    }
+
+    /// Checks that the server finished the copy successfully, and transferred the same data we
+    /// did.
+    fn check(self, transfer: Transfer) -> Result<Copied> {
+        let Some(exit) = self.exit else {
+            let reason = self
+                .close
+                .map(|frame| frame.reason.into_owned())
+                .filter(|reason| !reason.is_empty())
+                .unwrap_or_else(|| "connection closed".to_string());
+            return error::IncompleteSnafu { reason }.fail();
+        };
+        let result = self.result.context(error::NoResultSnafu)?;
+        ensure!(
+            exit.code == Some(0),
+            error::CopyFailedSnafu {
+                status: match (exit.code, exit.signal) {
+                    (Some(code), _) => format!("exit code {}", code),
+                    (None, Some(signal)) => format!("signal {}", signal),
+                    (None, None) => "unknown status".to_string(),
+                },
+                stderr: result.stderr.trim(),
+            }
+        );
+
+        let copied = transfer.finish();
+        ensure!(
+            result.size == copied.size,
+            error::SizeMismatchSnafu {
+                expected: result.size,
+                actual: copied.size,
+            }
+        );
+        ensure!(
+            result.sha256.eq_ignore_ascii_case(&copied.sha256),
+            error::DigestMismatchSnafu {
+                expected: result.sha256,
+                actual: copied.sha256,
+            }
+        );
+        Ok(copied)
+    }
+}
+
+/// Transfer tracks the file data we've sent or received.
+struct Transfer {
+    size: u64,
+    digest: Sha256,
+}
+
+impl Transfer {
+    fn new() -> Self {
+        Self {
+            size: 0,
+            digest: Sha256::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.size += data.len() as u64;
+        self.digest.update(data);
+    }
+
+    fn finish(self) -> Copied {
+        let sha256 = self
+            .digest
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Copied {
+            size: self.size,
+            sha256,
+        }
+    }
+}
+
+/// TempFile is a file being written next to its destination; it's removed when dropped unless
+/// it's moved into place.
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    async fn create(destination: &Path) -> Result<(Self, tokio::fs::File)> {
+        let mut name = destination.as_os_str().to_owned();
+        name.push(format!(".apiclient-cp.{}", std::process::id()));
+        let path = PathBuf::from(name);
+        let file = tokio::fs::File::create(&path)
+            .await
+            .context(error::WriteFileSnafu { path: &path })?;
+        Ok((
+            Self {
+                path,
+                persisted: false,
+            },
+            file,
+        ))
+    }
+
+    /// Sets the permissions of the file and moves it to its destination.
+    async fn persist(mut self, destination: &Path, mode: u32) -> Result<()> {
+        tokio::fs::set_permissions(&self.path, Permissions::from_mode(mode))
+            .await
+            .context(error::WriteFileSnafu { path: &self.path })?;
+        tokio::fs::rename(&self.path, destination)
+            .await
+            .context(error::WriteFileSnafu { path: destination })?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Progress shows how much of a file has been copied, if requested.
+struct Progress {
+    enabled: bool,
+    total: Option<u64>,
+    /// The last percentage shown, so we only redraw when it changes.
+    shown: Option<u64>,
+}
+
+impl Progress {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            total: None,
+            shown: None,
+        }
+    }
+
+    fn update(&mut self, size: u64) {
+        let Some(total) = self.total.filter(|_| self.enabled) else {
+            return;
+        };
+        let percent = (size * 100).checked_div(total).unwrap_or(100);
+        if self.shown == Some(percent) {
+            return;
+        }
+        self.shown = Some(percent);
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{} / {} bytes ({}%)", size, total, percent);
+        let _ = stderr.flush();
+    }
+
+    fn finish(&self) {
+        if self.shown.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+mod error {
+    use super::connect;
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        // This is from the exec module, which includes enough context.
+        #[snafu(display("{}", source))]
+        Connect {
+            #[snafu(source(from(connect::Error, Box::new)))]
+            source: Box<connect::Error>,
+        },
+
+        #[snafu(display("Copy failed with {}: {}", status, stderr))]
+        CopyFailed { status: String, stderr: String },
+
+        #[snafu(display("Failed to deserialize message from server: {}", source))]
+        Deserialize { source: serde_json::Error },
+
+        #[snafu(display(
+            "Copied data has SHA-256 digest {}, but server reported {}",
+            actual,
+            expected
+        ))]
+        DigestMismatch { expected: String, actual: String },
+
+        #[snafu(display("Copy did not complete: {}", reason))]
+        Incomplete { reason: String },
+
+        #[snafu(display("Server sent file data without describing the file"))]
+        NoFileInfo,
+
+        #[snafu(display("Server did not report the result of the copy"))]
+        NoResult,
+
+        #[snafu(display("'{}' is not a regular file", path.display()))]
+        NotFile { path: PathBuf },
+
+        #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+        ReadFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to read from WebSocket: {}", source))]
+        ReadWebSocket {
+            #[snafu(source(from(tokio_tungstenite::tungstenite::Error, Box::new)))]
+            source: Box<tokio_tungstenite::tungstenite::Error>,
+        },
+
+        #[snafu(display("Container path '{}' must be absolute", path.display()))]
+        RelativePath { path: PathBuf },
+
+        #[snafu(display("Failed to send {} message to server", kind))]
+        SendMessage { kind: String },
+
+        #[snafu(display("Failed to serialize message to server: {}", source))]
+        Serialize { source: serde_json::Error },
+
+        #[snafu(display("Copied {} bytes, but expected {}", actual, expected))]
+        SizeMismatch { expected: u64, actual: u64 },
+
+        #[snafu(display("Failed to write '{}': {}", path.display(), source))]
+        WriteFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(msg: ServerMessage) -> Message {
+        Message::Text(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn result(data: &[u8], code: i32) -> ServerState {
+        let mut transfer = Transfer::new();
+        transfer.update(data);
+        let copied = transfer.finish();
+        let mut server = ServerState::default();
+        for msg in [
+            text(ServerMessage::CopyResult(CopyResult {
+                size: copied.size,
+                sha256: copied.sha256,
+                stderr: "oops\n".to_string(),
+            })),
+            text(ServerMessage::Exit(Exit {
+                code: Some(code),
+                signal: None,
+            })),
+        ] {
+            assert!(server.receive(msg).unwrap().is_none());
+        }
+        server
+    }
+
+    #[test]
+    fn check() {
+        let data = b"hello\n";
+        let mut transfer = Transfer::new();
+        transfer.update(data);
+        let copied = result(data, 0).check(transfer).unwrap();
+        assert_eq!(copied.size, 6);
+
+        let mut transfer = Transfer::new();
+        transfer.update(b"hellO\n");
+        assert!(matches!(
+            result(data, 0).check(transfer),
+            Err(Error::DigestMismatch { .. })
+        ));
+
+        let mut transfer = Transfer::new();
+        transfer.update(b"hello");
+        assert!(matches!(
+            result(data, 0).check(transfer),
+            Err(Error::SizeMismatch { .. })
+        ));
+
+        let err = result(data, 1).check(Transfer::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Copy failed with exit code 1: oops");
+    }
+
+    #[test]
+    fn capacity() {
+        let mut server = ServerState::default();
+        assert!(!server.has_capacity(0));
+        let capacity = Capacity {
+            max_messages_outstanding: 2,
+            messages_written: 0,
+        };
+        server
+            .receive(text(ServerMessage::Capacity(capacity)))
+            .unwrap();
+        assert!(server.has_capacity(1));
+        assert!(!server.has_capacity(2));
+    }
+}
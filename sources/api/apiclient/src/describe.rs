@@ -0,0 +1,409 @@
+//! The describe module explains settings: the type of each setting under the given prefixes and
+//! the constraints on its value, from the settings schema published by the API, and the metadata
+//! that constrains how it's used, like the services it affects, the setting generator that sets
+//! it, and the template it's rendered from.
+//!
+//! Settings the schema describes are described whether or not they're set.  The schema is partial,
+//! so settings that are set but aren't in it, like those in the open "settings.kubernetes"
+//! section, are described too, with the type of the map's values if the schema gives one, and
+//! otherwise the type of the current value.
+
+use crate::schema::{self, SettingSchemas};
+use crate::{diff, get};
+use datastore::{Key, KeyType};
+use serde::Serialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// How many keys we ask about in each metadata request, to keep URIs short.
+const KEYS_PER_REQUEST: usize = 50;
+
+/// SettingDescription describes one setting.  Metadata set on a prefix of the setting, like
+/// affected services on "settings.kubernetes", applies to the setting too.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SettingDescription {
+    #[serde(rename = "type")]
+    pub value_type: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub constraints: BTreeMap<String, Value>,
+    /// The current value, if the setting is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affected_services: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setting_generator: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<Value>,
+}
+
+/// Maps the dotted name of each setting, like "settings.motd", to its description.
+pub type Descriptions = BTreeMap<String, SettingDescription>;
+
+/// Describes the settings under the given prefixes, whether or not they're set.  The "settings."
+/// prefix is optional.
+pub async fn describe<P>(socket_path: P, prefixes: Vec<String>) -> Result<Descriptions>
+where
+    P: AsRef<Path>,
+{
+    let prefixes: Vec<String> = prefixes
+        .into_iter()
+        .map(|prefix| {
+            if prefix == "settings" || prefix.starts_with("settings.") {
+                prefix
+            } else {
+                format!("settings.{}", prefix)
+            }
+        })
+        .collect();
+    let schema = schema::get_schema(&socket_path)
+        .await
+        .context(error::SchemaSnafu)?;
+    let schemas = schema::settings(&schema).context(error::SchemaSnafu)?;
+    let value = get::get_prefixes(&socket_path, prefixes.clone())
+        .await
+        .context(error::GetSnafu)?;
+    let mut settings = diff::flatten_value(&value).context(error::FlattenSnafu)?;
+
+    let mut names: BTreeSet<String> = schemas
+        .keys()
+        .filter(|name| prefixes.iter().any(|prefix| name.starts_with(prefix)))
+        .cloned()
+        .collect();
+    names.extend(settings.keys().cloned());
+
+    // Ask about every setting and every prefix of it, since metadata can be set on either.
+    let mut metadata_names = Vec::new();
+    for name in &names {
+        for prefix in prefixes_of(name)? {
+            if !metadata_names.contains(&prefix) {
+                metadata_names.push(prefix);
+            }
+        }
+    }
+    let services = get_metadata(&socket_path, "affected-services", &metadata_names).await?;
+    let templates = get_metadata(&socket_path, "templates", &metadata_names).await?;
+    let generators = get_all_metadata(&socket_path, "setting-generators").await?;
+
+    names
+        .into_iter()
+        .map(|name| {
+            let prefixes = prefixes_of(&name)?;
+            let value = settings.remove(&name);
+            let (value_type, constraints) = setting_type(&schemas, &prefixes, value.as_ref());
+            let description = SettingDescription {
+                value_type,
+                constraints,
+                value,
+                affected_services: nearest(&services, &prefixes),
+                setting_generator: nearest(&generators, &prefixes),
+                template: nearest(&templates, &prefixes),
+            };
+            Ok((name, description))
+        })
+        .collect()
+}
+
+/// Returns the type of a setting and the constraints on its value, given the setting's name and
+/// the names of its prefixes, longest first.  The type comes from the schema of the setting, or of
+/// the map it's in; failing that, it's the type of the current value.
+fn setting_type(
+    schemas: &SettingSchemas,
+    names: &[String],
+    value: Option<&Value>,
+) -> (String, BTreeMap<String, Value>) {
+    if let Some(schema) = names.first().and_then(|name| schemas.get(name)) {
+        return (schema.value_type.clone(), schema.constraints.clone());
+    }
+    let map_type = names
+        .iter()
+        .skip(1)
+        .find_map(|name| schemas.get(name))
+        .and_then(|schema| schema.value_type.strip_prefix("map of "));
+    let value_type = match (map_type, value) {
+        (Some(map_type), _) => map_type.to_string(),
+        (None, Some(value)) => value_type(value),
+        (None, None) => "any".to_string(),
+    };
+    (value_type, BTreeMap::new())
+}
+
+/// Returns the given setting name and the names of its prefixes, longest first, skipping the
+/// top-level "settings".
+fn prefixes_of(name: &str) -> Result<Vec<String>> {
+    let key = Key::new(KeyType::Data, name).context(error::KeySnafu { name })?;
+    let segments = key.segments();
+    (2..=segments.len())
+        .rev()
+        .map(|len| {
+            Key::from_segments(KeyType::Data, &segments[..len])
+                .map(|prefix| prefix.name().clone())
+                .context(error::KeySnafu { name })
+        })
+        .collect()
+}
+
+/// Returns the metadata of the first of the given names that has any.
+fn nearest(metadata: &HashMap<String, Value>, names: &[String]) -> Option<Value> {
+    names.iter().find_map(|name| metadata.get(name).cloned())
+}
+
+/// Fetches the metadata of the given kind, like "affected-services", for the given data keys.
+async fn get_metadata<P>(
+    socket_path: P,
+    kind: &str,
+    names: &[String],
+) -> Result<HashMap<String, Value>>
+where
+    P: AsRef<Path>,
+{
+    let mut metadata = HashMap::new();
+    for names in names.chunks(KEYS_PER_REQUEST) {
+        let keys: String =
+            url::form_urlencoded::byte_serialize(names.join(",").as_bytes()).collect();
+        let uri = format!("/metadata/{}?keys={}", kind, keys);
+        metadata.extend(get_uri(&socket_path, uri).await?);
+    }
+    Ok(metadata)
+}
+
+/// Fetches metadata of the given kind, like "setting-generators", for all data keys that have it.
+async fn get_all_metadata<P>(socket_path: P, kind: &str) -> Result<HashMap<String, Value>>
+where
+    P: AsRef<Path>,
+{
+    get_uri(socket_path, format!("/metadata/{}", kind)).await
+}
+
+/// Fetches a map of data keys to metadata from the given URI.
+async fn get_uri<P>(socket_path: P, uri: String) -> Result<HashMap<String, Value>>
+where
+    P: AsRef<Path>,
+{
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+    serde_json::from_str(&body).context(error::ResponseJsonSnafu { body })
+}
+
+/// Returns the type of a setting's value, like "string" or "list of integer".
+fn value_type(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "boolean".to_string(),
+        Value::Number(n) if n.is_f64() => "number".to_string(),
+        Value::Number(_) => "integer".to_string(),
+        Value::String(_) => "string".to_string(),
+        Value::Object(_) => "map".to_string(),
+        Value::Array(items) => {
+            let mut types: Vec<String> = items.iter().map(value_type).collect();
+            types.dedup();
+            match types.as_slice() {
+                [item_type] => format!("list of {}", item_type),
+                _ => "list".to_string(),
+            }
+        }
+    }
+}
+
+/// Formats descriptions; `format` may be "text" (the default) or "json".
+pub fn format_descriptions(descriptions: &Descriptions, format: Option<&str>) -> Result<String> {
+    match format {
+        None | Some("text") => Ok(format_text(descriptions)),
+        Some("json") => {
+            serde_json::to_string_pretty(descriptions).context(error::JsonSerializeSnafu)
+        }
+        Some(format) => error::FormatSnafu { format }.fail(),
+    }
+}
+
+fn format_text(descriptions: &Descriptions) -> String {
+    let mut output = String::new();
+    for (name, description) in descriptions {
+        output.push_str(&format!("{}\n", name));
+        output.push_str(&format!("    type: {}\n", description.value_type));
+        for (keyword, value) in &description.constraints {
+            output.push_str(&format!("    {}: {}\n", keyword, value));
+        }
+        match &description.value {
+            Some(value) => output.push_str(&format!("    value: {}\n", value)),
+            None => output.push_str("    value: not set\n"),
+        }
+        let metadata = [
+            ("affected services", &description.affected_services),
+            ("setting generator", &description.setting_generator),
+            ("template", &description.template),
+        ];
+        for (label, value) in metadata {
+            if let Some(value) = value {
+                output.push_str(&format!("    {}: {}\n", label, format_metadata(value)));
+            }
+        }
+    }
+    output
+}
+
+/// Shows strings without quotes, and lists of strings separated by commas; other metadata is
+/// shown as JSON.
+fn format_metadata(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) if items.iter().all(Value::is_string) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    }
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("{}", source))]
+        Flatten { source: crate::diff::Error },
+
+        #[snafu(display("Unknown describe format '{}', expected 'text' or 'json'", format))]
+        Format { format: String },
+
+        #[snafu(display("{}", source))]
+        Get { source: crate::get::Error },
+
+        #[snafu(display("Unable to serialize descriptions: {}", source))]
+        JsonSerialize { source: serde_json::Error },
+
+        #[snafu(display("Invalid setting name '{}': {}", name, source))]
+        Key {
+            name: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid JSON '{}' - {}", body, source))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to get settings schema: {}", source))]
+        Schema { source: crate::schema::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn describe_settings() {
+        assert_eq!(
+            prefixes_of("settings.kubernetes.node-labels.\"my.label\"").unwrap(),
+            vec![
+                "settings.kubernetes.node-labels.\"my.label\"",
+                "settings.kubernetes.node-labels",
+                "settings.kubernetes",
+            ]
+        );
+        assert_eq!(value_type(&json!(["a", "b"])), "list of string");
+        assert_eq!(value_type(&json!([1, "b"])), "list");
+        assert_eq!(value_type(&json!(1.5)), "number");
+
+        // Types come from the schema, even for settings that aren't set, or from the map the
+        // setting is in.
+        let schemas = schema::settings(&json!({
+            "type": "object",
+            "properties": {
+                "kernel": {
+                    "type": "object",
+                    "properties": {"lockdown": {"type": "string", "enum": ["none"]}},
+                },
+                "labels": {"type": "object", "additionalProperties": {"type": "string"}},
+                "kubernetes": {"type": "object"},
+            },
+        }))
+        .unwrap();
+        let lockdown = prefixes_of("settings.kernel.lockdown").unwrap();
+        assert_eq!(
+            setting_type(&schemas, &lockdown, None),
+            (
+                "string".to_string(),
+                BTreeMap::from([("enum".to_string(), json!(["none"]))])
+            )
+        );
+        let label = prefixes_of("settings.labels.a").unwrap();
+        assert_eq!(setting_type(&schemas, &label, Some(&json!(1))).0, "string");
+        let max_pods = prefixes_of("settings.kubernetes.max-pods").unwrap();
+        assert_eq!(
+            setting_type(&schemas, &max_pods, Some(&json!(110))).0,
+            "integer"
+        );
+
+        let services = HashMap::from([("settings.kubernetes".to_string(), json!(["kubelet"]))]);
+        let descriptions = Descriptions::from([
+            (
+                "settings.kernel.lockdown".to_string(),
+                SettingDescription {
+                    value_type: "string".to_string(),
+                    constraints: BTreeMap::from([("enum".to_string(), json!(["none"]))]),
+                    value: None,
+                    affected_services: None,
+                    setting_generator: None,
+                    template: None,
+                },
+            ),
+            (
+                "settings.kubernetes.max-pods".to_string(),
+                SettingDescription {
+                    value_type: "integer".to_string(),
+                    constraints: BTreeMap::new(),
+                    value: Some(json!(110)),
+                    affected_services: nearest(&services, &max_pods),
+                    setting_generator: Some(json!("pluto max-pods")),
+                    template: None,
+                },
+            ),
+        ]);
+        assert_eq!(
+            format_descriptions(&descriptions, None).unwrap(),
+            "settings.kernel.lockdown\n    \
+             type: string\n    \
+             enum: [\"none\"]\n    \
+             value: not set\n\
+             settings.kubernetes.max-pods\n    \
+             type: integer\n    \
+             value: 110\n    \
+             affected services: kubelet\n    \
+             setting generator: pluto max-pods\n"
+        );
+        assert_eq!(
+            serde_json::to_value(&descriptions["settings.kubernetes.max-pods"]).unwrap(),
+            json!({
+                "type": "integer",
+                "value": 110,
+                "affected-services": ["kubelet"],
+                "setting-generator": "pluto max-pods",
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&descriptions["settings.kernel.lockdown"]).unwrap(),
+            json!({"type": "string", "constraints": {"enum": ["none"]}})
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! The diff module shows how settings would change: how a pending transaction differs from live
+//! settings, or how settings files, in the same TOML or JSON format accepted by 'apply', differ
+//! from live settings or from a pending transaction.  Nothing on the system is changed.
+
+use crate::apply::{self, Input, Loader};
+use crate::get;
+use datastore::serialization::{to_pairs, to_pairs_with_prefix};
+use datastore::{deserialize_scalar, Key, ScalarError};
+use serde::Serialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// SettingChange holds the value of a setting before and after a change; None means it isn't set.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettingChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+}
+
+/// Maps the dotted name of each setting that differs, like "settings.motd", to its change.
+pub type SettingsDiff = BTreeMap<String, SettingChange>;
+
+/// Compares settings.  If no inputs are given, compares live settings with the settings as they'd
+/// be after the given transaction, or the "default" transaction, is committed.  Otherwise, reads
+/// settings from the given inputs with the given Loader, and compares live settings, or the
+/// settings as they'd be after the given transaction, with the result of applying the inputs on
+/// top of them.
+pub async fn diff<P>(
+    socket_path: P,
+    transaction: Option<&str>,
+    inputs: Vec<Input>,
+    loader: &Loader,
+) -> Result<SettingsDiff>
+where
+    P: AsRef<Path>,
+{
+    if inputs.is_empty() {
+        let old = get_settings(&socket_path, None).await?;
+        let new = get_settings(&socket_path, Some(transaction.unwrap_or("default"))).await?;
+        return compare(&old, &new);
+    }
+
+    let old = get_settings(&socket_path, transaction).await?;
+    let mut new = old.clone();
+    for input in &inputs {
+        let data = loader.load(input).await.context(error::InputSnafu)?;
+        let change = apply::format_change(&data, &input.source).context(error::InputSnafu)?;
+        let change = serde_json::from_str(&change).context(error::InputJsonSnafu)?;
+        get::merge_json(&mut new, change);
+    }
+    compare(&old, &new)
+}
+
+/// Fetches live settings, or the settings as they'd be after the given transaction is committed.
+async fn get_settings<P>(socket_path: P, transaction: Option<&str>) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    let uri = match transaction {
+        Some(transaction) => format!("/tx/settings?tx={}", transaction),
+        None => "/settings".to_string(),
+    };
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+    serde_json::from_str(&body).context(error::ResponseJsonSnafu { body })
+}
+
+/// Compares two sets of settings key by key.  Maps are compared by their inner keys; lists and
+/// other values are compared whole.
+fn compare(old: &Value, new: &Value) -> Result<SettingsDiff> {
+    Ok(changes(flatten(old)?, flatten(new)?))
+}
+
+/// Compares two maps of dotted names to values, like those returned by flatten_value.
+pub(crate) fn changes(
+    old: BTreeMap<String, Value>,
+    mut new: BTreeMap<String, Value>,
+) -> SettingsDiff {
+    let mut diff = BTreeMap::new();
+    for (name, old) in old {
+        match new.remove(&name) {
+            Some(new) if new == old => {}
+            new => {
+                diff.insert(
+                    name,
+                    SettingChange {
+                        old: Some(old),
+                        new,
+                    },
+                );
+            }
+        }
+    }
+    for (name, new) in new {
+        diff.insert(
+            name,
+            SettingChange {
+                old: None,
+                new: Some(new),
+            },
+        );
+    }
+    diff
+}
+
+/// Turns settings into a map of dotted setting names to values, using the data store's naming, so
+/// names with dots in them are quoted.
+fn flatten(settings: &Value) -> Result<BTreeMap<String, Value>> {
+    let pairs = to_pairs_with_prefix("settings", settings).context(error::FlattenSnafu)?;
+    deserialize_pairs(pairs)
+}
+
+/// Like flatten, but for any API response, like one including "settings" and "os" at the top
+/// level, or the update status.
+pub(crate) fn flatten_value(value: &Value) -> Result<BTreeMap<String, Value>> {
+    let pairs = to_pairs(value).context(error::FlattenSnafu)?;
+    deserialize_pairs(pairs)
+}
+
+fn deserialize_pairs(pairs: HashMap<Key, String>) -> Result<BTreeMap<String, Value>> {
+    pairs
+        .into_iter()
+        .map(|(key, value)| {
+            let value = deserialize_scalar::<Value, ScalarError>(&value)
+                .context(error::ValueSnafu { key: key.name() })?;
+            Ok((key.name().clone(), value))
+        })
+        .collect()
+}
+
+/// Formats a diff; `format` may be "text" (the default) or "json".  In text form, added settings
+/// are marked with "+", removed settings with "-", and changed settings with "~", and are colored
+/// if `color` is true.
+pub fn format_diff(diff: &SettingsDiff, format: Option<&str>, color: bool) -> Result<String> {
+    match format {
+        None | Some("text") => Ok(format_text(diff, color)),
+        Some("json") => serde_json::to_string_pretty(diff).context(error::JsonSerializeSnafu),
+        Some(format) => error::FormatSnafu { format }.fail(),
+    }
+}
+
+pub(crate) fn format_text(diff: &SettingsDiff, color: bool) -> String {
+    let mut output = String::new();
+    for (name, change) in diff {
+        let (mark, start, values) = match (&change.old, &change.new) {
+            (None, Some(new)) => ("+", GREEN, new.to_string()),
+            (Some(old), None) => ("-", RED, old.to_string()),
+            (Some(old), Some(new)) => ("~", YELLOW, format!("{} -> {}", old, new)),
+            (None, None) => continue,
+        };
+        let line = format!("{} {} = {}", mark, name, values);
+        if color {
+            output.push_str(&format!("{}{}{}\n", start, line, RESET));
+        } else {
+            output.push_str(&format!("{}\n", line));
+        }
+    }
+    output
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Unable to flatten settings: {}", source))]
+        Flatten {
+            source: datastore::serialization::Error,
+        },
+
+        #[snafu(display("Unknown diff format '{}', expected 'text' or 'json'", format))]
+        Format { format: String },
+
+        #[snafu(display("{}", source))]
+        Input {
+            #[snafu(source(from(crate::apply::Error, Box::new)))]
+            source: Box<crate::apply::Error>,
+        },
+
+        #[snafu(display("Settings input is not valid JSON: {}", source))]
+        InputJson { source: serde_json::Error },
+
+        #[snafu(display("Unable to serialize diff: {}", source))]
+        JsonSerialize { source: serde_json::Error },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid JSON '{}' - {}", body, source))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Invalid value for '{}': {}", key, source))]
+        Value {
+            key: String,
+            source: serde_json::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn compare_settings() {
+        let old = json!({
+            "motd": "hi",
+            "ntp": {"time-servers": ["a"]},
+            "kubernetes": {"node-labels": {"my.label": "x", "gone": "y"}},
+        });
+        let new = json!({
+            "motd": "hi",
+            "ntp": {"time-servers": ["a", "b"]},
+            "kubernetes": {"node-labels": {"my.label": "z", "new": "w"}},
+        });
+        let diff = compare(&old, &new).unwrap();
+        assert_eq!(
+            diff.keys().collect::<Vec<_>>(),
+            vec![
+                "settings.kubernetes.node-labels.\"my.label\"",
+                "settings.kubernetes.node-labels.gone",
+                "settings.kubernetes.node-labels.new",
+                "settings.ntp.time-servers",
+            ]
+        );
+        assert_eq!(
+            format_diff(&diff, None, false).unwrap(),
+            "~ settings.kubernetes.node-labels.\"my.label\" = \"x\" -> \"z\"\n\
+             - settings.kubernetes.node-labels.gone = \"y\"\n\
+             + settings.kubernetes.node-labels.new = \"w\"\n\
+             ~ settings.ntp.time-servers = [\"a\"] -> [\"a\",\"b\"]\n"
+        );
+        assert_eq!(
+            serde_json::to_value(&diff["settings.kubernetes.node-labels.new"]).unwrap(),
+            json!({"new": "w"})
+        );
+    }
+}
@@ -17,7 +17,8 @@ use futures::{Future, FutureExt, Stream, StreamExt, TryStream, TryStreamExt};
 use futures_channel::{mpsc, oneshot};
 use libc::{ioctl, winsize as WinSize, STDOUT_FILENO, TIOCGWINSZ as GetWinSize};
 use log::{debug, error, trace, warn};
-use model::exec::{ClientMessage, Initialize, ServerMessage, Size};
+use model::exec::{ClientMessage, Exit, Initialize, ServerMessage, Size};
+use nix::sys::signal::Signal;
 use retry_read::RetryRead;
 use signal_hook::{consts::signal, iterator::Signals};
 use snafu::{OptionExt, ResultExt};
@@ -39,7 +40,7 @@ use tokio_tungstenite::tungstenite::{
     Error as WsError,
 };
 
-mod connect;
+pub(crate) mod connect;
 mod terminal;
 use connect::websocket_connect;
 use terminal::Terminal;
@@ -101,6 +102,7 @@ where
         command,
         target,
         tty: terminal.tty().clone(),
+        exit_status: true,
     };
     // Control messages go to the server in a text channel, so we serialize to JSON before sending.
     let msg =
@@ -114,8 +116,9 @@ where
     // Now that the server knows what we want, we set up helper threads to manage communication.
     // First, a heartbeat type that regularly pings the server and keeps track of responses.
     let mut heartbeat = Heartbeat::new(ws_tx.clone());
-    // Next, a type that watches for signals to the local process, and either forwards them to the
-    // server (e.g. if you change your window size) or ends communication (e.g. for SIGTERM).
+    // Next, a type that watches for signals to the local process and forwards them to the server,
+    // either as window size changes, or as signals for the process (e.g. SIGTERM).  If it gets a
+    // second signal like SIGTERM, it ends communication.
     let mut signal_handler = HandleSignals::new(ws_tx.clone())?;
 
     // We don't want to overload the server with our process input.  It sends us capacity updates
@@ -161,7 +164,8 @@ where
             Err(err)?;
         }
 
-        // Stop if we receive a terminal signal.
+        // Stop if we receive a terminal signal that we couldn't forward to the process, or a
+        // second one after forwarding the first.
         signal = &mut signal_handler.signal_rx => {
             drop(terminal);
             debug!("Received signal: {:?}", signal);
@@ -177,12 +181,23 @@ where
 
     // Determine how to exit based on the information we got back from the server, or from a
     // local signal.
-    if let Some(Some(ret)) = read_from_server.ret_rx.next().now_or_never() {
+    if let Some(Some(exit)) = read_from_server.exit_rx.next().now_or_never() {
+        // This is the normal case where the server tells us how the process exited.
+        match (exit.code, exit.signal) {
+            (Some(code), _) => process::exit(code),
+            // Use shell-style return codes for signals.
+            (None, Some(signal)) => process::exit(128 + signal),
+            (None, None) => {
+                warn!("Server didn't know how the process exited");
+                process::exit(1)
+            }
+        }
+    } else if let Some(Some(ret)) = read_from_server.ret_rx.next().now_or_never() {
         match ret.code {
             // The connection is closing normally, we expect the process exit code in the reason message.
             CloseCode::Normal => {
                 if !ret.reason.is_empty() {
-                    // This is the normal case where the server gives us the exit code of the process.
+                    // Older servers give us the exit code of the process this way.
                     if let Ok(exit_code) = ret.reason.parse::<u16>() {
                         process::exit(i32::from(exit_code))
                     }
@@ -218,6 +233,8 @@ struct ReadFromServer {
     /// If the server sends us a reason for closing the connection (which normally includes the
     /// return code of the command) we'll forward it on this channel.
     ret_rx: mpsc::UnboundedReceiver<CloseFrame<'static>>,
+    /// If the server tells us how the process exited, we'll forward it on this channel.
+    exit_rx: mpsc::UnboundedReceiver<Exit>,
 }
 
 impl ReadFromServer {
@@ -234,18 +251,24 @@ impl ReadFromServer {
         heartbeat_setter: Arc<Mutex<Instant>>,
         capacity: Arc<AtomicCapacity>,
     ) -> Self {
-        // Create a channel we use to tell the caller if we get a return value from the server.
+        // Create channels we use to tell the caller if we get a return value from the server.
         let (ret_tx, ret_rx) = mpsc::unbounded();
+        let (exit_tx, exit_rx) = mpsc::unbounded();
 
-        let future = Self::read_from_server(read, heartbeat_setter, ret_tx, capacity);
+        let future = Self::read_from_server(read, heartbeat_setter, ret_tx, exit_tx, capacity);
 
-        Self { future, ret_rx }
+        Self {
+            future,
+            ret_rx,
+            exit_rx,
+        }
     }
 
     fn read_from_server(
         read: impl TryStream<Ok = Message, Error = WsError> + 'static,
         heartbeat_setter: Arc<Mutex<Instant>>,
         ret_tx: mpsc::UnboundedSender<CloseFrame<'static>>,
+        exit_tx: mpsc::UnboundedSender<Exit>,
         capacity: Arc<AtomicCapacity>,
     ) -> Pin<Box<dyn Future<Output = Result<()>>>> {
         // Turn tungstenite errors into our own error type.
@@ -257,6 +280,7 @@ impl ReadFromServer {
                 let heartbeat_setter = heartbeat_setter.clone();
                 let capacity = capacity.clone();
                 let ret_tx = ret_tx.clone();
+                let exit_tx = exit_tx.clone();
 
                 async move {
                     match ws_msg {
@@ -311,6 +335,21 @@ impl ReadFromServer {
                                         .messages_written
                                         .store(new.messages_written, Ordering::SeqCst);
                                 }
+                                // Exit messages tell us how the process ended; the server will
+                                // close the connection next.
+                                ServerMessage::Exit(exit) => {
+                                    debug!(
+                                        "Received exit from server: code {:?}, signal {:?}",
+                                        exit.code, exit.signal
+                                    );
+                                    // If we fail to send the exit status, we'll fall back to the
+                                    // return code in the Close message.
+                                    let _ = exit_tx.unbounded_send(exit);
+                                }
+                                // These are only sent for copies; see the 'cp' module.
+                                ServerMessage::FileInfo(_) | ServerMessage::CopyResult(_) => {
+                                    warn!("Received unexpected copy message from server");
+                                }
                             }
                         }
                         // The API server doesn't use frames, but still logging out a
@@ -588,17 +627,17 @@ impl Heartbeat {
     }
 }
 
-/// HandleSignals is responsible for managing non-terminal signals (like when your window changes
-/// size) and alerting the caller for terminal signals.
+/// HandleSignals is responsible for forwarding signals to the server, like when your window changes
+/// size, or when you want to interrupt the process, and alerting the caller if we should stop.
 struct HandleSignals {
-    /// If a terminal signal is received, its value is sent over this channel.
+    /// If we should stop because of a terminal signal, its value is sent over this channel.
     signal_rx: oneshot::Receiver<i32>,
 }
 
 impl HandleSignals {
     /// Parameters:
-    /// * winch_tx: The channel to which we should send window size change messages.
-    fn new(winch_tx: mpsc::UnboundedSender<Message>) -> Result<Self> {
+    /// * ws_tx: The channel to which we should send window size change and signal messages.
+    fn new(ws_tx: mpsc::UnboundedSender<Message>) -> Result<Self> {
         // Create a channel we use to tell the caller when we receive a terminal signal.
         let (signal_tx, signal_rx) = oneshot::channel();
 
@@ -610,7 +649,7 @@ impl HandleSignals {
 
         debug!("Spawning thread to manage signals");
         thread::spawn(move || {
-            if let Err(e) = Self::handle_signals(signals, winch_tx, signal_tx) {
+            if let Err(e) = Self::handle_signals(signals, ws_tx, signal_tx) {
                 error!("Signal manager failed: {}", e);
             }
         });
@@ -620,19 +659,26 @@ impl HandleSignals {
 
     fn handle_signals(
         mut signals: Signals,
-        winch_tx: mpsc::UnboundedSender<Message>,
+        ws_tx: mpsc::UnboundedSender<Message>,
         signal_tx: oneshot::Sender<i32>,
     ) -> Result<()> {
         use signal::*;
+        // Whether we've already forwarded a terminal signal to the process.
+        let mut forwarded = false;
         loop {
             // Block until our process receives a signal.
             for signal in signals.wait() {
                 if signal == SIGWINCH {
                     // Window size changes can happen any number of times; send an update to the
                     // server and wait for more signals.
-                    Self::send_winch(&winch_tx);
+                    Self::send_winch(&ws_tx);
+                } else if !forwarded && Self::send_signal(&ws_tx, signal) {
+                    // The first terminal signal goes to the process, and we keep waiting for the
+                    // server to tell us how the process exited.
+                    forwarded = true;
                 } else {
-                    // Anything else is terminal; notify the caller and exit.
+                    // If we can't forward it, or the process didn't exit after the first one,
+                    // notify the caller and exit.
                     signal_tx
                         .send(signal)
                         .ok()
@@ -644,6 +690,17 @@ impl HandleSignals {
         }
     }
 
+    /// Try to send a signal for the process to the server, returning whether we could.
+    fn send_signal(tx: &mpsc::UnboundedSender<Message>, signal: i32) -> bool {
+        let Ok(signal) = Signal::try_from(signal) else {
+            return false;
+        };
+        debug!("Forwarding {} to server", signal);
+        serde_json::to_string(&ClientMessage::Signal(signal.as_str().to_string()))
+            .ok()
+            .is_some_and(|msg| tx.unbounded_send(Message::Text(msg)).is_ok())
+    }
+
     /// Try to send a window size update to the server.  We don't consider window size updates to
     /// be critical, since the program is still functioning, so we don't return errors.
     fn send_winch(tx: &mpsc::UnboundedSender<Message>) {
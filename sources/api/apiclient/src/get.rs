@@ -1,8 +1,12 @@
 use snafu::{OptionExt, ResultExt};
 use std::path::Path;
 
+mod filter;
+mod format;
 mod merge_json;
-use merge_json::merge_json;
+pub use filter::Filter;
+pub use format::format_value;
+pub(crate) use merge_json::merge_json;
 
 /// Fetches the given prefixes from the API and merges them into a single Value.  (It's not
 /// expected that given prefixes would overlap, but if they do, later ones take precedence.)
@@ -51,9 +55,35 @@ mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility(pub(super)))]
     pub enum Error {
+        #[snafu(display("Invalid filter '{}': {}", filter, reason))]
+        Filter { filter: String, reason: String },
+
+        #[snafu(display("Unable to flatten response: {}", source))]
+        Flatten {
+            source: datastore::serialization::Error,
+        },
+
+        #[snafu(display("Unknown get format '{}', expected json, toml, flat, or env", format))]
+        Format { format: String },
+
+        #[snafu(display("Filters with list indexes can only be used with the json format"))]
+        IndexFilter,
+
+        #[snafu(display("Unable to serialize response: {}", source))]
+        JsonSerialize { source: serde_json::Error },
+
+        #[snafu(display(
+            "Value of '{}' has more than one line, which the flat format can't show",
+            key
+        ))]
+        Multiline { key: String },
+
         #[snafu(display("Must give prefixes to query"))]
         NoPrefixes,
 
+        #[snafu(display("Only a map of values can be shown as TOML"))]
+        NotTable,
+
         #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
         Request {
             method: String,
@@ -67,6 +97,15 @@ mod error {
             body: String,
             source: serde_json::Error,
         },
+
+        #[snafu(display("Unable to serialize response as TOML: {}", source))]
+        TomlSerialize { source: toml::ser::Error },
+
+        #[snafu(display("Invalid value for '{}': {}", key, source))]
+        Value {
+            key: String,
+            source: serde_json::Error,
+        },
     }
 }
 pub use error::Error;
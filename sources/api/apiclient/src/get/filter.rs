@@ -0,0 +1,146 @@
+use super::{error, Error, Result};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// Filter selects part of a response by its path, like a simple jq filter: ".settings.motd",
+/// ".settings.kubernetes.node-labels."my.label"", or ".settings.ntp.time-servers[0]".  "." selects
+/// the whole response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(filter: &str) -> Result<Self> {
+        let invalid = |reason: &str| error::FilterSnafu { filter, reason }.build();
+
+        let mut steps = Vec::new();
+        let mut rest = filter
+            .strip_prefix('.')
+            .ok_or_else(|| invalid("it must start with '.'"))?;
+        while !rest.is_empty() {
+            if let Some(quoted) = rest.strip_prefix('"') {
+                let end = quoted
+                    .find('"')
+                    .ok_or_else(|| invalid("a quote isn't closed"))?;
+                steps.push(Step::Key(quoted[..end].to_string()));
+                rest = &quoted[end + 1..];
+            } else if let Some(index) = rest.strip_prefix('[') {
+                let end = index
+                    .find(']')
+                    .ok_or_else(|| invalid("a bracket isn't closed"))?;
+                let index = index[..end]
+                    .parse()
+                    .map_err(|_| invalid("list indexes must be numbers"))?;
+                steps.push(Step::Index(index));
+                rest = &rest[end + 2..];
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(invalid("names must not be empty"));
+                }
+                steps.push(Step::Key(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+
+            // Each step is followed by the next name or index, if any.
+            if let Some(next) = rest.strip_prefix('.') {
+                if next.is_empty() || next.starts_with('[') {
+                    return Err(invalid("names must not be empty"));
+                }
+                rest = next;
+            } else if !rest.is_empty() && !rest.starts_with('[') {
+                return Err(invalid("names must be separated by '.'"));
+            }
+        }
+        Ok(Self { steps })
+    }
+}
+
+impl Filter {
+    /// Returns the selected part of the given value, or null if it doesn't exist, as jq does.
+    pub fn select(&self, value: &Value) -> Value {
+        let mut value = value;
+        for step in &self.steps {
+            let next = match step {
+                Step::Key(key) => value.get(key),
+                Step::Index(index) => value.get(index),
+            };
+            match next {
+                Some(next) => value = next,
+                None => return Value::Null,
+            }
+        }
+        value.clone()
+    }
+
+    /// Returns the given value with only the selected part, keeping the path to it, so the names
+    /// of settings don't change.  Filters with list indexes can't be used this way, because we
+    /// can't keep part of a list without changing it.
+    pub fn prune(&self, value: &Value) -> Result<Value> {
+        let mut pruned = match self.select(value) {
+            Value::Null => Value::Object(Map::new()),
+            selected => selected,
+        };
+        for step in self.steps.iter().rev() {
+            match step {
+                Step::Key(key) => {
+                    pruned = Value::Object(Map::from_iter([(key.clone(), pruned)]));
+                }
+                Step::Index(_) => return error::IndexFilterSnafu.fail(),
+            }
+        }
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters() {
+        let value = json!({"settings": {
+            "motd": "hi",
+            "ntp": {"time-servers": ["a", "b"]},
+            "kubernetes": {"node-labels": {"my.label": "x"}},
+        }});
+        let select = |filter: &str| filter.parse::<Filter>().unwrap().select(&value);
+        assert_eq!(select("."), value);
+        assert_eq!(select(".settings.motd"), json!("hi"));
+        assert_eq!(select(".settings.ntp.time-servers[1]"), json!("b"));
+        assert_eq!(
+            select(".settings.kubernetes.node-labels.\"my.label\""),
+            json!("x")
+        );
+        assert_eq!(select(".settings.missing"), Value::Null);
+
+        let filter: Filter = ".settings.ntp".parse().unwrap();
+        assert_eq!(
+            filter.prune(&value).unwrap(),
+            json!({"settings": {"ntp": {"time-servers": ["a", "b"]}}})
+        );
+        let filter: Filter = ".settings.ntp.time-servers[0]".parse().unwrap();
+        assert!(filter.prune(&value).is_err());
+
+        for invalid in [
+            "settings",
+            ".settings.",
+            ".settings..motd",
+            ".\"a\"b",
+            ".a[x]",
+            ".a[0",
+        ] {
+            assert!(invalid.parse::<Filter>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{error, Filter, Result};
+use datastore::serialization::to_pairs;
+use datastore::{deserialize_scalar, Key, ScalarError};
+use serde_json::Value;
+use snafu::{ensure, ResultExt};
+
+/// Formats a response from 'get', or the part of it selected by the given filter; `format` may be
+/// "json" (the default), "toml", "flat", or "env".
+///
+/// JSON output is the selected value, like jq would show.  The other formats keep the full names
+/// of settings, so their output can be given back to apiclient: "toml" is the format accepted by
+/// 'apply' and used for user data, "flat" has a line for each setting in the KEY=VALUE form
+/// accepted by 'set', with lists and ambiguous strings in JSON form, and "env" has a line for each
+/// setting in the form of a shell variable assignment, like SETTINGS_MOTD='hi'.
+pub fn format_value(
+    value: &Value,
+    filter: Option<&Filter>,
+    format: Option<&str>,
+) -> Result<String> {
+    if let None | Some("json") = format {
+        let value = match filter {
+            Some(filter) => filter.select(value),
+            None => value.clone(),
+        };
+        let json = serde_json::to_string_pretty(&value).context(error::JsonSerializeSnafu)?;
+        return Ok(format!("{}\n", json));
+    }
+
+    let value = match filter {
+        Some(filter) => filter.prune(value)?,
+        None => value.clone(),
+    };
+    match format {
+        Some("toml") => {
+            ensure!(value.is_object(), error::NotTableSnafu);
+            toml::to_string(&value).context(error::TomlSerializeSnafu)
+        }
+        Some("flat") => {
+            let mut output = String::new();
+            for (key, value) in pairs(&value)? {
+                let value = match value {
+                    // 'set' reads values that parse as JSON, like "42", as that type, so we quote
+                    // those strings to have them read back as strings.
+                    Value::String(s) if serde_json::from_str::<Value>(&s).is_ok() => {
+                        Value::String(s).to_string()
+                    }
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                // 'set' takes one setting per argument, so we can't break values across lines.
+                ensure!(
+                    !value.contains('\n'),
+                    error::MultilineSnafu { key: key.name() }
+                );
+                output.push_str(&format!("{}={}\n", key.name(), value));
+            }
+            Ok(output)
+        }
+        Some("env") => {
+            let mut output = String::new();
+            for (key, value) in pairs(&value)? {
+                let value = match value {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                output.push_str(&format!("{}={}\n", env_name(&key), shell_quote(&value)));
+            }
+            Ok(output)
+        }
+        Some(format) => error::FormatSnafu { format }.fail(),
+        None => unreachable!("JSON is the default format"),
+    }
+}
+
+/// Returns each setting in the given value, sorted by name.  Lists are kept whole, as settings.
+fn pairs(value: &Value) -> Result<Vec<(Key, Value)>> {
+    let mut pairs = to_pairs(value)
+        .context(error::FlattenSnafu)?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = deserialize_scalar::<Value, ScalarError>(&value)
+                .context(error::ValueSnafu { key: key.name() })?;
+            Ok((key, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    pairs.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+    Ok(pairs)
+}
+
+/// Makes a shell variable name from the segments of a key, like SETTINGS_KUBERNETES_MAX_PODS for
+/// "settings.kubernetes.max-pods".
+fn env_name(key: &Key) -> String {
+    key.segments()
+        .iter()
+        .map(|segment| {
+            segment
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Quotes a value so a POSIX shell reads it back unchanged.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn formats() {
+        let value = json!({"settings": {
+            "motd": "it's here",
+            "kubernetes": {"max-pods": 110, "node-labels": {"my.label": "x", "num": "42"}},
+            "ntp": {"time-servers": ["a", "b"]},
+        }});
+        let filter: Filter = ".settings.kubernetes".parse().unwrap();
+        let format = |filter, format| format_value(&value, filter, Some(format)).unwrap();
+
+        assert_eq!(
+            format(Some(&filter), "json"),
+            format!("{:#}\n", value["settings"]["kubernetes"])
+        );
+        assert_eq!(
+            format(None, "flat"),
+            "settings.kubernetes.max-pods=110\n\
+             settings.kubernetes.node-labels.\"my.label\"=x\n\
+             settings.kubernetes.node-labels.num=\"42\"\n\
+             settings.motd=it's here\n\
+             settings.ntp.time-servers=[\"a\",\"b\"]\n"
+        );
+        assert_eq!(
+            format(Some(&filter), "env"),
+            "SETTINGS_KUBERNETES_MAX_PODS='110'\n\
+             SETTINGS_KUBERNETES_NODE_LABELS_MY_LABEL='x'\n\
+             SETTINGS_KUBERNETES_NODE_LABELS_NUM='42'\n"
+        );
+        assert_eq!(
+            format(None, "env").lines().nth(3),
+            Some(r"SETTINGS_MOTD='it'\''s here'")
+        );
+        assert_eq!(
+            format(Some(&filter), "toml"),
+            "[settings.kubernetes]\n\
+             max-pods = 110\n\n\
+             [settings.kubernetes.node-labels]\n\
+             \"my.label\" = \"x\"\n\
+             num = \"42\"\n"
+        );
+        assert!(format_value(&json!({"motd": "a\nb"}), None, Some("flat")).is_err());
+        assert!(format_value(&json!("motd"), None, Some("toml")).is_err());
+    }
+}
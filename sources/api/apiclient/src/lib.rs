@@ -1,6 +1,14 @@
 //! The apiclient library provides high-level methods to interact with the Bottlerocket API.  See
-//! the documentation for submodules [`apply`], [`exec`], [`get`], [`reboot`], [`report`], [`set`],
-//! and [`update`] for high-level helpers.
+//! the documentation for submodules [`apply`], [`completion`], [`cp`], [`describe`], [`diff`],
+//! [`exec`], [`get`], [`plan`], [`reboot`], [`report`], [`set`], [`snapshot`], [`unset`],
+//! [`update`], and [`watch`] for high-level helpers.
+//!
+//! The [`ssh`] module forwards a local socket to the API socket on another host, so any of these
+//! can be used with hosts you can reach through ssh.
+//!
+//! Programs that use the API can use the typed [`client::Client`], which has methods for common
+//! requests, like getting settings, committing transactions, and starting updates, and maps error
+//! statuses from the API to variants of [`client::Error`].
 //!
 //! For more control, and to handle APIs without high-level wrappers, there are also 'raw' methods
 //! to query an HTTP API over a Unix-domain socket.
@@ -20,13 +28,27 @@ use snafu::{ensure, ResultExt};
 use std::{fmt, fmt::Display, path::Path};
 
 pub mod apply;
+pub mod client;
+pub mod completion;
+pub mod cp;
+pub mod describe;
+pub mod diff;
 pub mod ephemeral_storage;
 pub mod exec;
 pub mod get;
+pub mod plan;
 pub mod reboot;
 pub mod report;
+pub mod schema;
 pub mod set;
+pub mod snapshot;
+pub mod ssh;
+pub mod unset;
 pub mod update;
+pub mod watch;
+
+/// The content type of requests, unless another is given.
+const JSON: &str = "application/json";
 
 mod error {
     use snafu::Snafu;
@@ -84,7 +106,24 @@ where
     S1: AsRef<str>,
     S2: AsRef<str>,
 {
-    let (status, body) = raw_request_unchecked(&socket_path, &uri, &method, data).await?;
+    raw_request_with_content_type(socket_path, uri, method, JSON, data).await
+}
+
+/// Works exactly like raw_request, but sends the request body with the given content type rather
+/// than "application/json", for example to send a JSON Patch to the settings API.
+pub async fn raw_request_with_content_type<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    content_type: &str,
+    data: Option<String>,
+) -> Result<(http::StatusCode, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let (status, body) = send_request(&socket_path, &uri, &method, content_type, data).await?;
 
     // Error if the response status is in not in the 2xx range.
     ensure!(
@@ -109,6 +148,23 @@ pub async fn raw_request_unchecked<P, S1, S2>(
     method: S2,
     data: Option<String>,
 ) -> Result<(http::StatusCode, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    send_request(socket_path, uri, method, JSON, data).await
+}
+
+/// Makes an HTTP request over a Unix-domain socket with a body of the given content type, and
+/// returns the status and body of the response without checking the status.
+async fn send_request<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    content_type: &str,
+    data: Option<String>,
+) -> Result<(http::StatusCode, String)>
 where
     P: AsRef<Path>,
     S1: AsRef<str>,
@@ -129,7 +185,7 @@ where
     let request = Request::builder()
         .method(method)
         .uri(&uri)
-        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_TYPE, content_type)
         .body(request_data)
         .context(error::RequestSetupSnafu)?;
 
@@ -162,6 +218,21 @@ pub(crate) fn rando() -> String {
 pub enum SettingsInput {
     KeyPair(String),
     Json(String),
+    /// An RFC 6902 JSON Patch, with paths relative to the settings, like "/motd".
+    JsonPatch(String),
+    /// An RFC 7396 merge patch, in which null removes a setting.
+    MergePatch(String),
+}
+
+impl SettingsInput {
+    /// Returns the content type the Settings API expects for this input.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SettingsInput::KeyPair(_) | SettingsInput::Json(_) => JSON,
+            SettingsInput::JsonPatch(_) => "application/json-patch+json",
+            SettingsInput::MergePatch(_) => "application/merge-patch+json",
+        }
+    }
 }
 
 impl Display for SettingsInput {
@@ -169,6 +240,8 @@ impl Display for SettingsInput {
         match self {
             SettingsInput::KeyPair(value) => write!(f, "{}", value),
             SettingsInput::Json(value) => write!(f, "{}", value),
+            SettingsInput::JsonPatch(value) => write!(f, "{}", value),
+            SettingsInput::MergePatch(value) => write!(f, "{}", value),
         }
     }
 }
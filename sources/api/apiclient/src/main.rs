@@ -6,19 +6,25 @@
 // library calls based on the given flags, etc.)  The library modules contain the code for talking
 // to the API, which is intended to be reusable by other crates.
 
-use apiclient::{apply, ephemeral_storage, exec, get, reboot, report, set, update, SettingsInput};
+use apiclient::{
+    apply, completion, cp, describe, diff, ephemeral_storage, exec, get, plan, reboot, report, set,
+    snapshot, ssh, unset, update, watch, SettingsInput,
+};
 use log::{info, log_enabled, trace, warn};
 use model::ephemeral_storage::Filesystem;
 use serde::{Deserialize, Serialize};
 use simplelog::{
     ColorChoice, ConfigBuilder as LogConfigBuilder, LevelFilter, TermLogger, TerminalMode,
 };
-use snafu::ResultExt;
+use snafu::{ensure, OptionExt, ResultExt};
 use std::env;
 use std::ffi::OsString;
+use std::io::IsTerminal;
 use std::iter::Peekable;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 use std::vec::IntoIter;
 use unindent::unindent;
 
@@ -29,6 +35,8 @@ const DEFAULT_METHOD: &str = "GET";
 struct Args {
     log_level: LevelFilter,
     socket_path: String,
+    host: Option<String>,
+    hosts_file: Option<PathBuf>,
 }
 
 impl Default for Args {
@@ -36,6 +44,8 @@ impl Default for Args {
         Self {
             log_level: LevelFilter::Info,
             socket_path: constants::API_SOCKET.to_string(),
+            host: None,
+            hosts_file: None,
         }
     }
 }
@@ -44,12 +54,19 @@ impl Default for Args {
 #[derive(Debug)]
 enum Subcommand {
     Apply(ApplyArgs),
+    Completion(CompletionArgs),
+    Cp(CpArgs),
+    Describe(DescribeArgs),
+    Diff(DiffArgs),
     Exec(ExecArgs),
     Get(GetArgs),
     Raw(RawArgs),
     Reboot(RebootArgs),
     Set(SetArgs),
+    Snapshot(SnapshotSubcommand),
+    Unset(UnsetArgs),
     Update(UpdateSubcommand),
+    Watch(WatchArgs),
     Report(ReportSubcommand),
     EphemeralStorage(EphemeralStorageSubcommand),
 }
@@ -57,7 +74,55 @@ enum Subcommand {
 /// Stores user-supplied arguments for the 'apply' subcommand.
 #[derive(Debug)]
 struct ApplyArgs {
+    inputs: Vec<apply::Input>,
+    loader: apply::Loader,
+    dry_run: bool,
+}
+
+/// Stores user-supplied arguments for the 'completion' subcommand.
+#[derive(Debug)]
+enum CompletionArgs {
+    /// Print the completion script for a shell.
+    Script(completion::Shell),
+    /// Print what could complete the last of the given words; this is what the scripts call.
+    Complete(Vec<String>),
+}
+
+/// Stores user-supplied arguments for the 'cp' subcommand.
+#[derive(Debug)]
+struct CpArgs {
+    copy: CpCopy,
+    progress: bool,
+}
+
+/// The two sides of a copy given to the 'cp' subcommand.
+#[derive(Debug)]
+enum CpCopy {
+    FromContainer {
+        target: String,
+        container_path: PathBuf,
+        local_path: PathBuf,
+    },
+    ToContainer {
+        local_path: PathBuf,
+        target: String,
+        container_path: PathBuf,
+    },
+}
+
+/// Stores user-supplied arguments for the 'describe' subcommand.
+#[derive(Debug)]
+struct DescribeArgs {
+    prefixes: Vec<String>,
+    format: Option<String>,
+}
+
+/// Stores user-supplied arguments for the 'diff' subcommand.
+#[derive(Debug)]
+struct DiffArgs {
     input_sources: Vec<String>,
+    transaction: Option<String>,
+    format: Option<String>,
 }
 
 /// Stores user-supplied arguments for the 'exec' subcommand.
@@ -70,7 +135,15 @@ struct ExecArgs {
 
 /// Stores user-supplied arguments for the 'get' subcommand.
 #[derive(Debug)]
-enum GetArgs {
+struct GetArgs {
+    target: GetTarget,
+    format: Option<String>,
+    filter: Option<get::Filter>,
+}
+
+/// What the 'get' subcommand fetches.
+#[derive(Debug)]
+enum GetTarget {
     Prefixes(Vec<String>),
     Uri(String),
 }
@@ -95,9 +168,44 @@ pub struct SetKeyPairSettings {
 
 /// Stores user-supplied arguments for the 'set' subcommand.
 #[derive(Debug)]
-enum SetArgs {
+struct SetArgs {
+    settings: SetSettings,
+    dry_run: bool,
+}
+
+/// Stores the settings given to the 'set' subcommand, in one of the supported forms.
+#[derive(Debug)]
+enum SetSettings {
     Simple(Vec<String>),
     Json(serde_json::Value),
+    JsonPatch(serde_json::Value),
+    MergePatch(serde_json::Value),
+}
+
+/// Stores the 'snapshot' subcommand specified by the user.
+#[derive(Debug)]
+enum SnapshotSubcommand {
+    Export(SnapshotExportArgs),
+    Import(SnapshotImportArgs),
+}
+
+/// Stores user-supplied arguments for the 'snapshot export' subcommand.
+#[derive(Debug)]
+struct SnapshotExportArgs {
+    format: Option<String>,
+    include_generated: bool,
+}
+
+/// Stores user-supplied arguments for the 'snapshot import' subcommand.
+#[derive(Debug)]
+struct SnapshotImportArgs {
+    input_source: String,
+}
+
+/// Stores user-supplied arguments for the 'unset' subcommand.
+#[derive(Debug)]
+struct UnsetArgs {
+    keys: Vec<String>,
 }
 
 /// Stores the 'update' subcommand specified by the user.
@@ -106,6 +214,8 @@ enum UpdateSubcommand {
     Check(UpdateCheckArgs),
     Apply(UpdateApplyArgs),
     Cancel(UpdateCancelArgs),
+    Rollback(UpdateRollbackArgs),
+    Watch(UpdateWatchArgs),
 }
 
 /// The available 'report' subcommands.
@@ -131,19 +241,42 @@ struct FipsReportArgs {
 
 /// Stores user-supplied arguments for the 'update check' subcommand.
 #[derive(Debug)]
-struct UpdateCheckArgs {}
+struct UpdateCheckArgs {
+    source: Option<String>,
+}
 
 /// Stores user-supplied arguments for the 'update apply' subcommand.
 #[derive(Debug)]
 struct UpdateApplyArgs {
     check: bool,
     reboot: bool,
+    source: Option<String>,
+    force: bool,
 }
 
 /// Stores user-supplied arguments for the 'update cancel' subcommand.
 #[derive(Debug)]
 struct UpdateCancelArgs {}
 
+/// Stores user-supplied arguments for the 'update rollback' subcommand.
+#[derive(Debug)]
+struct UpdateRollbackArgs {
+    reboot: bool,
+}
+
+/// Stores user-supplied arguments for the 'update watch' subcommand.
+#[derive(Debug)]
+struct UpdateWatchArgs {
+    options: watch::Options,
+}
+
+/// Stores user-supplied arguments for the 'watch' subcommand.
+#[derive(Debug)]
+struct WatchArgs {
+    prefixes: Vec<String>,
+    options: watch::Options,
+}
+
 /// Stores the 'ephemeral-storage' subcommand specified by the user.
 #[derive(Debug)]
 enum EphemeralStorageSubcommand {
@@ -171,6 +304,203 @@ struct EphemeralStorageFormatArgs {
     format: Option<String>,
 }
 
+/// Describes our arguments for shell completion; keep this in sync with usage() and the arg
+/// parsing functions.
+const COMPLETION_CLI: completion::Cli = completion::Cli {
+    options: &["-h", "--help", "-v", "--verbose"],
+    value_options: &[
+        "-s",
+        "--socket-path",
+        "--log-level",
+        "--host",
+        "--hosts-file",
+    ],
+    commands: &[
+        completion::Command {
+            name: "raw",
+            options: &[],
+            value_options: &["-u", "--uri", "-m", "-X", "--method", "-d", "--data"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "apply",
+            options: &["--dry-run"],
+            value_options: &["--sha256", "--signature", "--trusted-key", "--fetcher"],
+            settings: false,
+            files: true,
+        },
+        completion::Command {
+            name: "completion",
+            options: &[],
+            value_options: &[],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "cp",
+            options: &["-q", "--quiet"],
+            value_options: &[],
+            settings: false,
+            files: true,
+        },
+        completion::Command {
+            name: "describe",
+            options: &[],
+            value_options: &["--format"],
+            settings: true,
+            files: false,
+        },
+        completion::Command {
+            name: "diff",
+            options: &[],
+            value_options: &["--tx", "--format"],
+            settings: false,
+            files: true,
+        },
+        completion::Command {
+            name: "exec",
+            options: &["-t", "--tty", "-T", "--no-tty"],
+            value_options: &[],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "get",
+            options: &[],
+            value_options: &["--format", "--filter"],
+            settings: true,
+            files: false,
+        },
+        completion::Command {
+            name: "reboot",
+            options: &[],
+            value_options: &[],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "report cis",
+            options: &[],
+            value_options: &["-f", "--format", "-l", "--level"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "report cis-k8s",
+            options: &[],
+            value_options: &["-f", "--format", "-l", "--level"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "report fips",
+            options: &[],
+            value_options: &["-f", "--format"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "set",
+            options: &["--dry-run"],
+            value_options: &["-j", "--json", "--json-patch", "--merge-patch"],
+            settings: true,
+            files: false,
+        },
+        completion::Command {
+            name: "snapshot export",
+            options: &[],
+            value_options: &["-f", "--format"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "snapshot import",
+            options: &[],
+            value_options: &[],
+            settings: false,
+            files: true,
+        },
+        completion::Command {
+            name: "unset",
+            options: &[],
+            value_options: &[],
+            settings: true,
+            files: false,
+        },
+        completion::Command {
+            name: "update check",
+            options: &[],
+            value_options: &["--source"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "update apply",
+            options: &["-c", "--check", "-r", "--reboot", "--force"],
+            value_options: &["--source"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "update cancel",
+            options: &[],
+            value_options: &[],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "update rollback",
+            options: &["-r", "--reboot"],
+            value_options: &[],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "update watch",
+            options: &[],
+            value_options: &["--interval", "--until", "--timeout"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "watch",
+            options: &[],
+            value_options: &["--interval", "--until", "--timeout"],
+            settings: true,
+            files: false,
+        },
+        completion::Command {
+            name: "ephemeral-storage init",
+            options: &[],
+            value_options: &["-t", "--filesystem", "--disks"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "ephemeral-storage bind",
+            options: &[],
+            value_options: &["--dirs"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "ephemeral-storage list-disks",
+            options: &[],
+            value_options: &["-f", "--format"],
+            settings: false,
+            files: false,
+        },
+        completion::Command {
+            name: "ephemeral-storage list-dirs",
+            options: &[],
+            value_options: &["-f", "--format"],
+            settings: false,
+            files: false,
+        },
+    ],
+};
+
 /// Informs the user about proper usage of the program and exits.
 fn usage() -> ! {
     let msg = &format!(
@@ -181,19 +511,35 @@ fn usage() -> ! {
             --log-level                Desired amount of output; trace|debug|info|warn|error
             -v, --verbose              Sets log level to 'debug'.  This prints extra info,
                                        like HTTP status code to stderr in 'raw' mode.
+            --host HOST                Talk to the API on another host through ssh, for example
+                                       admin@node1.  The socket path is the path on that host.
+            --hosts-file FILE          Run get, set, update check, or report on each host listed
+                                       in FILE, one per line, through ssh.  Prints a JSON object
+                                       with the output or error from each host, keyed by host.
 
         Subcommands:
             raw                        Makes an HTTP request and prints the response on stdout.
                                        'raw' is the default subcommand and may be omitted.
             apply                      Applies settings from TOML/JSON files at given URIs,
                                        or from stdin.
+            diff                       Shows how a pending transaction or settings files would
+                                       change settings.
             get                        Retrieve and print settings.
+            describe                   Shows the type and metadata of settings.
             set                        Changes settings and applies them to the system.
+            unset                      Removes settings and applies the change to the system.
+            snapshot export            Prints a snapshot of all settings and their metadata.
+            snapshot import            Restores settings from a snapshot and applies them.
             update check               Prints information about available updates.
             update apply               Applies available updates.
             update cancel              Deactivates an applied update.
+            update rollback            Marks the previous version for boot.
+            update watch               Prints changes to the update status as they happen.
+            watch                      Prints changes to settings as they happen.
             reboot                     Reboots the host.
             exec                       Execute a command in a host container.
+            cp                         Copy a file to or from a host container.
+            completion                 Prints a shell script that completes apiclient commands.
             report cis                 Retrieve a Bottlerocket CIS benchmark compliance report.
             report cis-k8s             Retrieve a Kubernetes CIS benchmark compliance report.
             report fips                Retrieve a FIPS Security Policy compliance report.
@@ -213,6 +559,25 @@ fn usage() -> ! {
             [ URI ...]                 The list of URIs to TOML or JSON settings files that you
                                        want to apply to the system.  If no URI is specified, or
                                        if "-" is given, reads from stdin.
+            --sha256 DIGEST            The expected SHA-256 digest, in hex, of the URI before it.
+            --signature URI            A detached Ed25519 signature of the URI before it.
+            --trusted-key PATH         The public key that signatures must be made with.  If
+                                       given, every URI must have a signature.
+            --fetcher SCHEME=PROGRAM   Fetch URIs with the given scheme, like s3, by running
+                                       PROGRAM with the URI and reading its output.  Default:
+                                       apiclient-fetch-SCHEME in PATH, if it exists.
+            --dry-run                  Show the services and configuration files the settings
+                                       would change, without changing anything.
+
+        diff options:
+            [ URI ...]                 TOML or JSON settings files, as for 'apply', to compare with
+                                       settings.  If "-" is given, reads from stdin.  If no URI is
+                                       given, compares live settings with a pending transaction.
+            --tx NAME                  The pending transaction to compare.  If URIs are given, they
+                                       are compared with the settings as they'd be after this
+                                       transaction is committed, rather than live settings.
+                                       Default: "default" if no URI is given.
+            --format FORMAT            Output format; text or json.  Default: text
 
         reboot options:
             None.
@@ -224,6 +589,21 @@ fn usage() -> ! {
 
                                        If neither prefixes nor URI are specified, get will show
                                        settings and OS info.
+            --format FORMAT            Output format: json, toml, flat, or env.  Default: json
+                                       toml is the format taken by 'apply', flat prints a
+                                       KEY=VALUE line for each setting as taken by 'set', and env
+                                       prints a shell variable assignment for each setting.
+            --filter PATH              Only show the part of the response at PATH, for example:
+                                          .settings.kubernetes.node-labels."my.label"
+                                          .settings.ntp.time-servers[0]
+                                       With json, only the value is shown; with other formats,
+                                       settings keep their full names, and PATH can't have list
+                                       indexes.
+
+        describe options:
+            [ PREFIX [PREFIX ...] ]    The settings you want to describe, as for get.  The
+                                       "settings." prefix is optional.  Default: all settings.
+            --format FORMAT            Output format; text or json.  Default: text
 
         set options:
             KEY=VALUE [KEY=VALUE ...]  The settings you want to set.  For example:
@@ -235,17 +615,64 @@ fn usage() -> ! {
                                        which can simplify setting multiple values, and is necessary
                                        for some numeric settings.  For example:
                                           -j '{{"kernel": {{"sysctl": {{"vm.max_map_count": "262144"}}}}}}'
+            --json-patch JSON          Alternatively, you can give an RFC 6902 JSON Patch to edit
+                                       list and map settings element-wise.  Paths are relative to
+                                       the settings.  For example:
+                                          --json-patch '[{{"op": "remove", "path": "/kubernetes/node-taints/dedicated"}}]'
+            --merge-patch JSON         Alternatively, you can give an RFC 7396 merge patch, in
+                                       which null removes a setting.  For example:
+                                          --merge-patch '{{"kubernetes": {{"node-labels": {{"old": null}}}}}}'
+            --dry-run                  Show the services and configuration files the settings
+                                       would change, without changing anything.
+
+        unset options:
+            KEY [KEY ...]              The settings you want to remove, along with any settings
+                                       under them.  For example:
+                                          kubernetes.node-labels.my-label
+                                       The "settings." prefix is optional.
+
+        snapshot export options:
+            -f, --format FORMAT        Format of the snapshot: json|toml.  Default: json
+            --include-generated        Include settings made by setting generators, like the
+                                       hostname, which are usually specific to this host.
+
+        snapshot import options:
+            FILE                       Required; the snapshot file to restore, in TOML or JSON
+                                       format.  If "-" is given, reads from stdin.
 
         update check options:
-            None.
+            --source PATH              Check the local update repository at PATH on the host,
+                                       a directory or a block device holding one, instead of
+                                       the configured repository.
 
         update apply options:
             -c, --check                Automatically `update check` and apply whatever is found.
             -r, --reboot               Automatically reboot if an update was found and applied.
+            --source PATH              Read the update from the local update repository at
+                                       PATH on the host, as with `update check`.
+            --force                    Make the update active even outside of the maintenance
+                                       windows in updog's config file.
 
         update cancel options:
             None.
 
+        update rollback options:
+            -r, --reboot               Automatically reboot once the previous version is marked
+                                       for boot.
+
+        update watch options:
+            The same as watch options, without prefixes.  For example:
+                                          --until update_state=Ready
+
+        watch options:
+            [ PREFIX [PREFIX ...] ]    The settings you want to watch, as for get.  Default: all
+                                       settings.
+            --interval SECONDS         How long to wait before reconnecting, or between checks of
+                                       the update status.  Default: 2
+            --until NAME=VALUE         Stop once the named value, like settings.motd, is VALUE.
+                                       If given more than once, waits for all of them.
+            --timeout SECONDS          Fail if the --until conditions aren't met in this time.
+
         exec options:
             -t, --tty                  Force the server to run the program in a pseudoterminal.
             -T, --no-tty               Force the server not to run the program in a pseudoterminal.
@@ -254,6 +681,19 @@ fn usage() -> ! {
             COMMAND                    Required; the command to run.
             [ ARG ...]                 Any desired arguments to the command.
 
+        cp options:
+            SOURCE DEST                Required; the file to copy and where to copy it.  One of
+                                       them must be in a host container, given as TARGET:PATH
+                                       with an absolute PATH, like admin:/home/ec2-user/file.
+                                       If DEST is a local directory, or a container path ending
+                                       in '/', the file keeps its name.
+            -q, --quiet                Don't show progress.  Progress is only shown on a terminal.
+
+        completion options:
+            SHELL                      Required; the shell to complete commands in; bash, zsh,
+                                       or fish.  For example:
+                                          source <(apiclient completion bash)
+
         report cis options:
             -f, --format               Format of the CIS report (text or json). Default format is text.
             -l, --level                CIS compliance level to report on (1 or 2). Default is 1.
@@ -328,32 +768,82 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
                     .unwrap_or_else(|| usage_msg("Did not give argument to -s | --socket-path"))
             }
 
+            "--host" => {
+                global_args.host = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --host")),
+                )
+            }
+
+            "--hosts-file" => {
+                global_args.hosts_file = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --hosts-file"))
+                        .into(),
+                )
+            }
+
             // Subcommands
-            "raw" | "apply" | "exec" | "get" | "reboot" | "report" | "set" | "update"
+            "raw" | "apply" | "completion" | "cp" | "describe" | "diff" | "exec" | "get"
+            | "reboot" | "report" | "set" | "snapshot" | "unset" | "update" | "watch"
             | "ephemeral-storage"
                 if subcommand.is_none() && !arg.starts_with('-') =>
             {
                 subcommand = Some(arg)
             }
 
+            // Completion is given the words of another command line, which aren't for us.
+            "--" if subcommand.as_deref() == Some("completion") => {
+                subcommand_args.push(arg);
+                subcommand_args.extend(iter.by_ref());
+            }
+
             // Other arguments are passed to the subcommand parser
             _ => subcommand_args.push(arg),
         }
     }
 
-    match subcommand.as_deref() {
+    let subcommand = match subcommand.as_deref() {
         // Default subcommand is 'raw'
-        None | Some("raw") => (global_args, parse_raw_args(subcommand_args)),
-        Some("apply") => (global_args, parse_apply_args(subcommand_args)),
-        Some("exec") => (global_args, parse_exec_args(subcommand_args)),
-        Some("get") => (global_args, parse_get_args(subcommand_args)),
-        Some("reboot") => (global_args, parse_reboot_args(subcommand_args)),
-        Some("report") => (global_args, parse_report_args(subcommand_args)),
-        Some("set") => (global_args, parse_set_args(subcommand_args)),
-        Some("update") => (global_args, parse_update_args(subcommand_args)),
-        Some("ephemeral-storage") => (global_args, parse_ephemeral_storage_args(subcommand_args)),
+        None | Some("raw") => parse_raw_args(subcommand_args),
+        Some("apply") => parse_apply_args(subcommand_args),
+        Some("completion") => parse_completion_args(subcommand_args),
+        Some("cp") => parse_cp_args(subcommand_args),
+        Some("describe") => parse_describe_args(subcommand_args),
+        Some("diff") => parse_diff_args(subcommand_args),
+        Some("exec") => parse_exec_args(subcommand_args),
+        Some("get") => parse_get_args(subcommand_args),
+        Some("reboot") => parse_reboot_args(subcommand_args),
+        Some("report") => parse_report_args(subcommand_args),
+        Some("set") => parse_set_args(subcommand_args),
+        Some("snapshot") => parse_snapshot_args(subcommand_args),
+        Some("unset") => parse_unset_args(subcommand_args),
+        Some("update") => parse_update_args(subcommand_args),
+        Some("watch") => parse_watch_args(subcommand_args),
+        Some("ephemeral-storage") => parse_ephemeral_storage_args(subcommand_args),
         _ => usage_msg("Missing or unknown subcommand"),
+    };
+
+    if global_args.host.is_some() && global_args.hosts_file.is_some() {
+        usage_msg("Cannot give both --host and --hosts-file");
+    }
+    // Other subcommands are interactive, or too disruptive to run on many hosts at once.
+    if global_args.hosts_file.is_some() && !runs_on_hosts(&subcommand) {
+        usage_msg("--hosts-file only works with get, set, update check, and report");
     }
+
+    (global_args, subcommand)
+}
+
+/// Returns whether the subcommand can be run on each host in a hosts file.
+fn runs_on_hosts(subcommand: &Subcommand) -> bool {
+    matches!(
+        subcommand,
+        Subcommand::Get(_)
+            | Subcommand::Set(_)
+            | Subcommand::Update(UpdateSubcommand::Check(_))
+            | Subcommand::Report(_)
+    )
 }
 
 /// Parses arguments for the 'raw' subcommand, which is also the default if no subcommand is
@@ -400,25 +890,203 @@ fn parse_raw_args(args: Vec<String>) -> Subcommand {
 
 /// Parses arguments for the 'apply' subcommand.
 fn parse_apply_args(args: Vec<String>) -> Subcommand {
+    let mut inputs: Vec<apply::Input> = Vec::new();
+    let mut loader = apply::Loader::default();
+    let mut dry_run = false;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--dry-run" => dry_run = true,
+
+            // Digests and signatures apply to the URI given before them.
+            "--sha256" => {
+                let digest = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --sha256"));
+                if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                    usage_msg("--sha256 must be given a SHA-256 digest in hex");
+                }
+                let input = inputs
+                    .last_mut()
+                    .unwrap_or_else(|| usage_msg("--sha256 must follow the URI it applies to"));
+                input.sha256 = Some(digest);
+            }
+            "--signature" => {
+                let signature = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --signature"));
+                let input = inputs
+                    .last_mut()
+                    .unwrap_or_else(|| usage_msg("--signature must follow the URI it applies to"));
+                input.signature = Some(signature);
+            }
+
+            "--trusted-key" => {
+                loader.trusted_key = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --trusted-key"))
+                        .into(),
+                )
+            }
+            "--fetcher" => {
+                let fetcher = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --fetcher"));
+                let (scheme, program) = fetcher
+                    .split_once('=')
+                    .unwrap_or_else(|| usage_msg("--fetcher must be given SCHEME=PROGRAM"));
+                loader
+                    .fetchers
+                    .register(scheme, apply::CommandFetcher::new(program));
+            }
+
+            // Allow "-" for stdin.
+            x if x.starts_with('-') && x != "-" => usage_msg(format!("Unknown argument '{}'", x)),
+
+            _ => inputs.push(arg.into()),
+        }
+    }
+
+    if inputs.is_empty() {
+        // Read from stdin if no URIs were given.
+        inputs.push("-".to_string().into());
+    }
+
+    Subcommand::Apply(ApplyArgs {
+        inputs,
+        loader,
+        dry_run,
+    })
+}
+
+/// Parses arguments for the 'diff' subcommand.
+fn parse_diff_args(args: Vec<String>) -> Subcommand {
     let mut input_sources = Vec::new();
+    let mut transaction = None;
+    let mut format = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--tx" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --tx")),
+                )
+            }
+            "--format" => {
+                format = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --format")),
+                )
+            }
+
+            // Allow "-" for stdin.
+            x if x.starts_with('-') && x != "-" => usage_msg(format!("Unknown argument '{}'", x)),
+
+            _ => input_sources.push(arg),
+        }
+    }
+
+    Subcommand::Diff(DiffArgs {
+        input_sources,
+        transaction,
+        format,
+    })
+}
+
+/// Parses arguments for the 'completion' subcommand.
+fn parse_completion_args(args: Vec<String>) -> Subcommand {
+    let mut iter = args.into_iter();
+    let completion = match (iter.next().as_deref(), iter.next().as_deref()) {
+        (Some("--complete"), Some("--")) => CompletionArgs::Complete(iter.collect()),
+        (Some(shell), None) => CompletionArgs::Script(
+            shell
+                .parse()
+                .unwrap_or_else(|e: completion::Error| usage_msg(e.to_string())),
+        ),
+        (None, _) => usage_msg("Must specify a shell for 'completion'"),
+        _ => usage_msg("'completion' takes only a shell"),
+    };
+    Subcommand::Completion(completion)
+}
+
+/// Parses arguments for the 'cp' subcommand.
+fn parse_cp_args(args: Vec<String>) -> Subcommand {
+    let mut paths = vec![];
+    let mut progress = std::io::stderr().is_terminal();
 
     for arg in args.into_iter() {
-        match arg {
-            // Allow "-" for stdin, but we have no other parameters.
-            x if x.starts_with('-') && x != "-" => {
-                usage_msg("apiclient apply takes no parameters, just a list of URIs.")
+        match arg.as_ref() {
+            "-q" | "--quiet" => progress = false,
+            x if x.starts_with('-') && x != "-" => usage_msg(format!("Unknown argument '{}'", x)),
+            _ => paths.push(arg),
+        }
+    }
+
+    let [source, dest]: [String; 2] = paths
+        .try_into()
+        .unwrap_or_else(|_| usage_msg("'cp' requires a source and a destination"));
+    let copy = match (split_container_path(&source), split_container_path(&dest)) {
+        (Some((target, container_path)), None) => CpCopy::FromContainer {
+            target,
+            container_path,
+            local_path: dest.into(),
+        },
+        (None, Some((target, container_path))) => CpCopy::ToContainer {
+            local_path: source.into(),
+            target,
+            container_path,
+        },
+        _ => usage_msg("'cp' requires exactly one of source and destination to be TARGET:PATH"),
+    };
+
+    Subcommand::Cp(CpArgs { copy, progress })
+}
+
+/// Splits a 'cp' argument like "admin:/home/ec2-user/file" into a target and path.  Local paths
+/// don't start with a target name followed by a colon and an absolute path.
+fn split_container_path(arg: &str) -> Option<(String, PathBuf)> {
+    let (target, path) = arg.split_once(':')?;
+    if target.is_empty() || target.contains('/') {
+        return None;
+    }
+    if !path.starts_with('/') {
+        usage_msg(format!(
+            "Container path '{}' must be absolute, like {}:/path",
+            path, target
+        ));
+    }
+    Some((target.to_string(), path.into()))
+}
+
+/// Parses arguments for the 'describe' subcommand.
+fn parse_describe_args(args: Vec<String>) -> Subcommand {
+    let mut prefixes = vec![];
+    let mut format = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--format" => {
+                format = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --format")),
+                )
             }
 
-            x => input_sources.push(x),
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+
+            _ => prefixes.push(arg),
         }
     }
 
-    if input_sources.is_empty() {
-        // Read from stdin if no URIs were given.
-        input_sources.push("-".to_string());
+    if prefixes.is_empty() {
+        prefixes.push("settings.".to_string());
     }
 
-    Subcommand::Apply(ApplyArgs { input_sources })
+    Subcommand::Describe(DescribeArgs { prefixes, format })
 }
 
 /// Parses arguments for the 'exec' subcommand.
@@ -465,9 +1133,29 @@ fn parse_exec_args(args: Vec<String>) -> Subcommand {
 fn parse_get_args(args: Vec<String>) -> Subcommand {
     let mut prefixes = vec![];
     let mut uri = None;
+    let mut format = None;
+    let mut filter = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--format" => {
+                format = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --format")),
+                )
+            }
+
+            "--filter" => {
+                let path = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --filter"));
+                filter = Some(
+                    path.parse()
+                        .unwrap_or_else(|e: get::Error| usage_msg(e.to_string())),
+                )
+            }
 
-    for arg in args.into_iter() {
-        match &arg {
             x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
 
             x if x.starts_with('/') => {
@@ -481,23 +1169,26 @@ fn parse_get_args(args: Vec<String>) -> Subcommand {
         }
     }
 
-    if let Some(uri) = uri {
+    let target = if let Some(uri) = uri {
         if !prefixes.is_empty() {
             usage_msg("You can specify prefixes or a URI, but not both.");
         }
-        Subcommand::Get(GetArgs::Uri(uri))
+        GetTarget::Uri(uri)
     } else if !prefixes.is_empty() {
         if uri.is_some() {
             usage_msg("You can specify prefixes or a URI, but not both.");
         }
-        Subcommand::Get(GetArgs::Prefixes(prefixes))
+        GetTarget::Prefixes(prefixes)
     } else {
         // A reasonable default is showing OS info and settings.
-        Subcommand::Get(GetArgs::Prefixes(vec![
-            "os.".to_string(),
-            "settings.".to_string(),
-        ]))
-    }
+        GetTarget::Prefixes(vec!["os.".to_string(), "settings.".to_string()])
+    };
+
+    Subcommand::Get(GetArgs {
+        target,
+        format,
+        filter,
+    })
 }
 
 /// Parses arguments for the 'reboot' subcommand.
@@ -516,6 +1207,8 @@ fn parse_reboot_args(args: Vec<String>) -> Subcommand {
 fn parse_set_args(args: Vec<String>) -> Subcommand {
     let mut simple = Vec::new();
     let mut json = None;
+    let mut patch = None;
+    let mut dry_run = false;
 
     let mut iter = args.into_iter();
     while let Some(arg) = iter.next() {
@@ -555,6 +1248,34 @@ fn parse_set_args(args: Vec<String>) -> Subcommand {
                 json = Some(input_map.into());
             }
 
+            "--json-patch" | "--merge-patch" if patch.is_some() => {
+                usage_msg("Can only specify one --json-patch or --merge-patch argument.");
+            }
+            "--json-patch" | "--merge-patch" => {
+                let raw_json = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", arg)));
+
+                let input_val: serde_json::Value =
+                    serde_json::from_str(&raw_json).unwrap_or_else(|e| {
+                        usage_msg(format!("Couldn't parse given JSON input: {}", e))
+                    });
+
+                patch = Some(if arg == "--json-patch" {
+                    if !input_val.is_array() {
+                        usage_msg("JSON Patch must be an array of operations");
+                    }
+                    SetSettings::JsonPatch(input_val)
+                } else {
+                    if !input_val.is_object() {
+                        usage_msg("Merge patch must be an object (map)");
+                    }
+                    SetSettings::MergePatch(input_val)
+                });
+            }
+
+            "--dry-run" => dry_run = true,
+
             x if x.contains('=') => {
                 // Push each key=value pair to vector.
                 simple.push(x.to_string());
@@ -564,15 +1285,33 @@ fn parse_set_args(args: Vec<String>) -> Subcommand {
         }
     }
 
-    if json.is_some() && !simple.is_empty() {
-        usage_msg("Cannot specify key=value pairs and --json settings with 'set'");
+    let given = [json.is_some(), !simple.is_empty(), patch.is_some()];
+    let settings = if given.iter().filter(|given| **given).count() > 1 {
+        usage_msg(
+            "Can only specify one of key=value pairs, --json settings, or a patch with 'set'",
+        );
+    } else if let Some(patch) = patch {
+        patch
     } else if let Some(json) = json {
-        Subcommand::Set(SetArgs::Json(json))
+        SetSettings::Json(json)
     } else if !simple.is_empty() {
-        Subcommand::Set(SetArgs::Simple(simple))
+        SetSettings::Simple(simple)
     } else {
-        usage_msg("Must specify key=value settings or --json settings with 'set'");
+        usage_msg("Must specify key=value settings, --json settings, or a patch with 'set'");
+    };
+
+    Subcommand::Set(SetArgs { settings, dry_run })
+}
+
+/// Parses arguments for the 'unset' subcommand.
+fn parse_unset_args(args: Vec<String>) -> Subcommand {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with('-')) {
+        usage_msg(format!("Unknown argument '{}'", flag));
+    }
+    if args.is_empty() {
+        usage_msg("Must specify settings to remove with 'unset'");
     }
+    Subcommand::Unset(UnsetArgs { keys: args })
 }
 
 /// Parses the desired subcommand of 'update'.
@@ -583,7 +1322,9 @@ fn parse_update_args(args: Vec<String>) -> Subcommand {
     for arg in args.into_iter() {
         match arg.as_ref() {
             // Subcommands
-            "check" | "apply" | "cancel" if subcommand.is_none() && !arg.starts_with('-') => {
+            "check" | "apply" | "cancel" | "rollback" | "watch"
+                if subcommand.is_none() && !arg.starts_with('-') =>
+            {
                 subcommand = Some(arg)
             }
 
@@ -596,6 +1337,8 @@ fn parse_update_args(args: Vec<String>) -> Subcommand {
         Some("check") => parse_update_check_args(subcommand_args),
         Some("apply") => parse_update_apply_args(subcommand_args),
         Some("cancel") => parse_update_cancel_args(subcommand_args),
+        Some("rollback") => parse_update_rollback_args(subcommand_args),
+        Some("watch") => parse_update_watch_args(subcommand_args),
         _ => usage_msg("Missing or unknown subcommand for 'update'"),
     };
 
@@ -604,27 +1347,55 @@ fn parse_update_args(args: Vec<String>) -> Subcommand {
 
 /// Parses arguments for the 'update check' subcommand.
 fn parse_update_check_args(args: Vec<String>) -> UpdateSubcommand {
-    if !args.is_empty() {
-        usage_msg(format!("Unknown arguments: {}", args.join(", ")));
+    let mut source = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--source" => {
+                source = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --source")),
+                )
+            }
+
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
     }
-    UpdateSubcommand::Check(UpdateCheckArgs {})
+
+    UpdateSubcommand::Check(UpdateCheckArgs { source })
 }
 
 /// Parses arguments for the 'update apply' subcommand.
 fn parse_update_apply_args(args: Vec<String>) -> UpdateSubcommand {
     let mut check = false;
     let mut reboot = false;
+    let mut source = None;
+    let mut force = false;
 
-    for arg in args.into_iter() {
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
         match arg.as_ref() {
             "-c" | "--check" => check = true,
             "-r" | "--reboot" => reboot = true,
+            "--force" => force = true,
+            "--source" => {
+                source = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --source")),
+                )
+            }
 
             x => usage_msg(format!("Unknown argument '{}'", x)),
         }
     }
 
-    UpdateSubcommand::Apply(UpdateApplyArgs { check, reboot })
+    UpdateSubcommand::Apply(UpdateApplyArgs {
+        check,
+        reboot,
+        source,
+        force,
+    })
 }
 
 /// Parses arguments for the 'update cancel' subcommand.
@@ -635,6 +1406,165 @@ fn parse_update_cancel_args(args: Vec<String>) -> UpdateSubcommand {
     UpdateSubcommand::Cancel(UpdateCancelArgs {})
 }
 
+/// Parses arguments for the 'update rollback' subcommand.
+fn parse_update_rollback_args(args: Vec<String>) -> UpdateSubcommand {
+    let mut reboot = false;
+
+    for arg in args.into_iter() {
+        match arg.as_ref() {
+            "-r" | "--reboot" => reboot = true,
+
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    UpdateSubcommand::Rollback(UpdateRollbackArgs { reboot })
+}
+
+/// Parses arguments for the 'update watch' subcommand.
+fn parse_update_watch_args(args: Vec<String>) -> UpdateSubcommand {
+    let mut options = watch::Options::default();
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if !parse_watch_option(&arg, &mut iter, &mut options) {
+            usage_msg(format!("Unknown argument '{}'", arg));
+        }
+    }
+
+    UpdateSubcommand::Watch(UpdateWatchArgs { options })
+}
+
+/// Parses arguments for the 'watch' subcommand.
+fn parse_watch_args(args: Vec<String>) -> Subcommand {
+    let mut prefixes = vec![];
+    let mut options = watch::Options::default();
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if parse_watch_option(&arg, &mut iter, &mut options) {
+            continue;
+        }
+        if arg.starts_with('-') {
+            usage_msg(format!("Unknown argument '{}'", arg));
+        }
+        prefixes.push(arg);
+    }
+
+    if prefixes.is_empty() {
+        prefixes.push("settings.".to_string());
+    }
+
+    Subcommand::Watch(WatchArgs { prefixes, options })
+}
+
+/// Parses an option shared by 'watch' and 'update watch' into the given Options, returning false
+/// if the argument isn't one of them.
+fn parse_watch_option(
+    arg: &str,
+    iter: &mut IntoIter<String>,
+    options: &mut watch::Options,
+) -> bool {
+    let mut next = || {
+        iter.next()
+            .unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", arg)))
+    };
+
+    match arg {
+        "--interval" => options.interval = parse_seconds(arg, &next()),
+        "--timeout" => options.timeout = Some(parse_seconds(arg, &next())),
+        "--until" => options.until.push(
+            next()
+                .parse()
+                .unwrap_or_else(|e| usage_msg(format!("{}", e))),
+        ),
+        _ => return false,
+    }
+    true
+}
+
+/// Parses the value of an option given in seconds.
+fn parse_seconds(arg: &str, value: &str) -> Duration {
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+        _ => usage_msg(format!("{} must be a positive number of seconds", arg)),
+    }
+}
+
+/// Parses the desired subcommand of 'snapshot'.
+fn parse_snapshot_args(args: Vec<String>) -> Subcommand {
+    let mut subcommand = None;
+    let mut subcommand_args = Vec::new();
+
+    for arg in args.into_iter() {
+        match arg.as_ref() {
+            // Subcommands
+            "export" | "import" if subcommand.is_none() && !arg.starts_with('-') => {
+                subcommand = Some(arg)
+            }
+
+            // Other arguments are passed to the subcommand parser
+            _ => subcommand_args.push(arg),
+        }
+    }
+
+    let snapshot = match subcommand.as_deref() {
+        Some("export") => parse_snapshot_export_args(subcommand_args),
+        Some("import") => parse_snapshot_import_args(subcommand_args),
+        _ => usage_msg("Missing or unknown subcommand for 'snapshot'"),
+    };
+
+    Subcommand::Snapshot(snapshot)
+}
+
+/// Parses arguments for the 'snapshot export' subcommand.
+fn parse_snapshot_export_args(args: Vec<String>) -> SnapshotSubcommand {
+    let mut format = None;
+    let mut include_generated = false;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-f" | "--format" => {
+                format = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to -f | --format")),
+                )
+            }
+
+            "--include-generated" => include_generated = true,
+
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    SnapshotSubcommand::Export(SnapshotExportArgs {
+        format,
+        include_generated,
+    })
+}
+
+/// Parses arguments for the 'snapshot import' subcommand.
+fn parse_snapshot_import_args(args: Vec<String>) -> SnapshotSubcommand {
+    let mut input_source = None;
+
+    for arg in args.into_iter() {
+        match arg.as_str() {
+            // "-" is allowed as a file name, meaning stdin.
+            x if x.starts_with('-') && x != "-" => usage_msg(format!("Unknown argument '{}'", x)),
+
+            x if input_source.is_none() => input_source = Some(x.to_string()),
+
+            _ => usage_msg("'snapshot import' takes a single snapshot file"),
+        }
+    }
+
+    SnapshotSubcommand::Import(SnapshotImportArgs {
+        input_source: input_source
+            .unwrap_or_else(|| usage_msg("Must specify a snapshot file, or '-' for stdin")),
+    })
+}
+
 /// Parses the desired subcommand of 'report'.
 fn parse_report_args(args: Vec<String>) -> Subcommand {
     let mut subcommand = None;
@@ -867,8 +1797,8 @@ fn collect_non_args(iter: &mut Peekable<IntoIter<String>>) -> Vec<String> {
 
 /// Requests an update status check through the API, printing the updated status, in a pretty
 /// format if possible.
-async fn check(args: &Args) -> Result<String> {
-    let output = update::check(&args.socket_path)
+async fn check(args: &Args, source: Option<&str>) -> Result<String> {
+    let output = update::check(&args.socket_path, source)
         .await
         .context(error::UpdateCheckSnafu)?;
 
@@ -883,12 +1813,269 @@ async fn check(args: &Args) -> Result<String> {
     Ok(output)
 }
 
+/// Copies a file to or from a host container.  If the destination is a local directory, or a
+/// container path ending in '/', the file keeps its name.
+async fn copy_file(args: &Args, cp: CpArgs) -> Result<()> {
+    let copied = match cp.copy {
+        CpCopy::FromContainer {
+            target,
+            container_path,
+            mut local_path,
+        } => {
+            if local_path.is_dir() {
+                local_path.push(file_name(&container_path)?);
+            }
+            cp::copy_from_container(
+                &args.socket_path,
+                &target,
+                &container_path,
+                &local_path,
+                cp.progress,
+            )
+            .await
+        }
+        CpCopy::ToContainer {
+            local_path,
+            target,
+            mut container_path,
+        } => {
+            if container_path.as_os_str().to_string_lossy().ends_with('/') {
+                container_path.push(file_name(&local_path)?);
+            }
+            cp::copy_to_container(
+                &args.socket_path,
+                &local_path,
+                &target,
+                &container_path,
+                cp.progress,
+            )
+            .await
+        }
+    }
+    .context(error::CpSnafu)?;
+    info!(
+        "Copied {} bytes with SHA-256 digest {}",
+        copied.size, copied.sha256
+    );
+    Ok(())
+}
+
+/// Returns the name of the file at the given path, for copying it into a directory.
+fn file_name(path: &Path) -> Result<&std::ffi::OsStr> {
+    path.file_name().context(error::NoFileNameSnafu { path })
+}
+
+/// Fetches and prints the response for the 'get' subcommand.
+async fn print_get(args: &Args, get: &GetArgs) -> Result<()> {
+    print!("{}", get_output(args, get).await?);
+    Ok(())
+}
+
+/// Fetches the response for the 'get' subcommand, in the requested format.
+async fn get_output(args: &Args, get: &GetArgs) -> Result<String> {
+    let result = match &get.target {
+        GetTarget::Uri(uri) => get::get_uri(&args.socket_path, uri.clone()).await,
+        GetTarget::Prefixes(prefixes) => {
+            get::get_prefixes(&args.socket_path, prefixes.clone()).await
+        }
+    };
+    let value = result.context(error::GetSnafu)?;
+    get::format_value(&value, get.filter.as_ref(), get.format.as_deref()).context(error::GetSnafu)
+}
+
+/// Changes settings for the 'set' subcommand, or shows what they'd change with --dry-run.
+async fn set_settings(args: &Args, set: &SetArgs) -> Result<()> {
+    print!("{}", set_output(args, set).await?);
+    Ok(())
+}
+
+/// Changes settings for the 'set' subcommand, returning nothing, or with --dry-run, returns what
+/// they'd change.
+async fn set_output(args: &Args, set: &SetArgs) -> Result<String> {
+    let settings = match &set.settings {
+        SetSettings::Simple(simple) => {
+            trace!("User supplied Key Value settings {:#?}", simple);
+            // The API doesn't take values in JSON form, so send those as JSON input instead.
+            if let Some(json) = set::key_pairs_json(simple).context(error::SetSnafu)? {
+                trace!("Sending Key Value settings as Json {:#?}", json);
+                SettingsInput::Json(json.to_string())
+            } else {
+                // Construct the Key Pair struct.
+                let set_key_pair = SetKeyPairSettings {
+                    request_payload: simple.clone(),
+                };
+                let settings_string =
+                    serde_json::to_string(&set_key_pair).context(error::SerializeSnafu)?;
+                SettingsInput::KeyPair(settings_string)
+            }
+        }
+        SetSettings::Json(json) => {
+            trace!("User supplied Json settings {:#?}", json);
+            // Convert JSON Value to a string.
+            SettingsInput::Json(json.to_string())
+        }
+        SetSettings::JsonPatch(patch) => {
+            trace!("User supplied JSON Patch {:#?}", patch);
+            SettingsInput::JsonPatch(patch.to_string())
+        }
+        SetSettings::MergePatch(patch) => {
+            trace!("User supplied merge patch {:#?}", patch);
+            SettingsInput::MergePatch(patch.to_string())
+        }
+    };
+
+    if set.dry_run {
+        let planned = set::plan(&args.socket_path, settings)
+            .await
+            .context(error::SetSnafu)?;
+        Ok(plan::format_plan(&planned))
+    } else {
+        set::set(&args.socket_path, settings)
+            .await
+            .context(error::SetSnafu)?;
+        Ok(String::new())
+    }
+}
+
+/// Fetches and prints the report for the 'report' subcommand.
+async fn print_report(args: &Args, report: &ReportSubcommand) -> Result<()> {
+    let body = report_output(args, report).await?;
+    if !body.is_empty() {
+        print!("{}", body);
+    }
+    Ok(())
+}
+
+/// Fetches the report for the 'report' subcommand.
+async fn report_output(args: &Args, report: &ReportSubcommand) -> Result<String> {
+    match report {
+        ReportSubcommand::Cis(cis_args) => {
+            report::get_cis_report(
+                &args.socket_path,
+                "bottlerocket",
+                cis_args.format.clone(),
+                cis_args.level,
+            )
+            .await
+        }
+        ReportSubcommand::CisK8s(cis_args) => {
+            report::get_cis_report(
+                &args.socket_path,
+                "kubernetes",
+                cis_args.format.clone(),
+                cis_args.level,
+            )
+            .await
+        }
+        ReportSubcommand::Fips(fips_args) => {
+            report::get_fips_report(&args.socket_path, fips_args.format.clone()).await
+        }
+    }
+    .context(error::ReportSnafu)
+}
+
+/// Runs the subcommand on each of the given hosts in turn, then prints a JSON object keyed by host,
+/// holding either the output from the host or the error it failed with.  Output that's JSON, like
+/// that of 'get', is included as JSON; other output is included as a string, and empty output, as
+/// from 'set', as null.  A failure on one host doesn't stop us from trying the others; we fail at
+/// the end, listing the hosts that failed.
+async fn run_on_hosts(args: &Args, hosts: &[String], subcommand: &Subcommand) -> Result<()> {
+    let mut results = serde_json::Map::new();
+    let mut failed = Vec::new();
+    for host in hosts {
+        let result = match run_on_host(args, host, subcommand).await {
+            Ok(output) => serde_json::json!({ "output": output_value(output) }),
+            Err(e) => {
+                failed.push(host.as_str());
+                serde_json::json!({ "error": e.to_string() })
+            }
+        };
+        results.insert(host.clone(), result);
+    }
+    let results = serde_json::to_string_pretty(&results).context(error::SerializeSnafu)?;
+    println!("{}", results);
+
+    ensure!(
+        failed.is_empty(),
+        error::HostsSnafu {
+            count: failed.len(),
+            total: hosts.len(),
+            failed: failed.join(", "),
+        }
+    );
+    Ok(())
+}
+
+/// Runs the subcommand on the given host, through ssh, and returns its output.
+async fn run_on_host(args: &Args, host: &str, subcommand: &Subcommand) -> Result<String> {
+    let tunnel = ssh::Tunnel::open(host, &args.socket_path)
+        .await
+        .context(error::SshSnafu)?;
+    let args = Args {
+        log_level: args.log_level,
+        socket_path: tunnel.socket_path().display().to_string(),
+        host: Some(host.to_string()),
+        hosts_file: None,
+    };
+    match subcommand {
+        Subcommand::Get(get) => get_output(&args, get).await,
+        Subcommand::Set(set) => set_output(&args, set).await,
+        Subcommand::Update(UpdateSubcommand::Check(check_args)) => {
+            update::check(&args.socket_path, check_args.source.as_deref())
+                .await
+                .context(error::UpdateCheckSnafu)
+        }
+        Subcommand::Report(report) => report_output(&args, report).await,
+        _ => unreachable!("parse_args only allows hosts files with subcommands in runs_on_hosts"),
+    }
+}
+
+/// Returns the output from a host as it's shown in the results of run_on_hosts.
+fn output_value(output: String) -> serde_json::Value {
+    if output.trim().is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_str(&output).unwrap_or(serde_json::Value::String(output))
+    }
+}
+
+/// Prints what could complete the last of the given words, one per line.  Setting names are
+/// fetched from the API, if it's available; otherwise we offer what we can without them.
+async fn print_completions(args: &Args, words: &[String]) {
+    let completion = completion::complete(&COMPLETION_CLI, words);
+    let mut candidates = completion.words;
+    if completion.settings {
+        let socket_path = completion
+            .socket_path
+            .as_deref()
+            .unwrap_or(&args.socket_path);
+        match completion::setting_names(socket_path, &completion.current).await {
+            Ok(names) => candidates.extend(names),
+            Err(e) => trace!("Unable to complete setting names: {}", e),
+        }
+    }
+    for candidate in candidates {
+        println!("{}", candidate);
+    }
+}
+
+/// Watches the given target, printing changes as they're seen.
+async fn print_changes(args: &Args, target: watch::Target, options: &watch::Options) -> Result<()> {
+    // Only color the output for people, not for files or pipes.
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    watch::watch(&args.socket_path, &target, options, |changes| {
+        print!("{}", watch::format_changes(changes, color))
+    })
+    .await
+    .context(error::WatchSnafu)
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 // Main dispatch
 
 /// Main entry point, dispatches subcommands.
 async fn run() -> Result<()> {
-    let (args, subcommand) = parse_args(env::args());
+    let (mut args, subcommand) = parse_args(env::args());
     trace!("Parsed args for subcommand {:?}: {:?}", subcommand, args);
 
     // We use TerminalMode::Stderr because apiclient users expect server response data on stdout.
@@ -905,6 +2092,24 @@ async fn run() -> Result<()> {
     #[cfg(feature = "tls")]
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
 
+    if let Some(hosts_file) = &args.hosts_file {
+        let hosts = ssh::read_hosts_file(hosts_file).context(error::HostsFileSnafu)?;
+        return run_on_hosts(&args, &hosts, &subcommand).await;
+    }
+
+    // With --host, the socket path is a path on that host, which we reach through a local socket
+    // forwarded by ssh.  The tunnel is closed when it's dropped, after the subcommand is done.
+    let _tunnel = match &args.host {
+        Some(host) => {
+            let tunnel = ssh::Tunnel::open(host, &args.socket_path)
+                .await
+                .context(error::SshSnafu)?;
+            args.socket_path = tunnel.socket_path().display().to_string();
+            Some(tunnel)
+        }
+        None => None,
+    };
+
     match subcommand {
         Subcommand::Raw(raw) => {
             let (status, body) =
@@ -926,9 +2131,51 @@ async fn run() -> Result<()> {
         }
 
         Subcommand::Apply(apply) => {
-            apply::apply(&args.socket_path, apply.input_sources)
+            if apply.dry_run {
+                let planned = apply::plan(&args.socket_path, apply.inputs, &apply.loader)
+                    .await
+                    .context(error::ApplySnafu)?;
+                print!("{}", plan::format_plan(&planned));
+            } else {
+                apply::apply(&args.socket_path, apply.inputs, &apply.loader)
+                    .await
+                    .context(error::ApplySnafu)?;
+            }
+        }
+
+        Subcommand::Diff(diff) => {
+            let changes = diff::diff(
+                &args.socket_path,
+                diff.transaction.as_deref(),
+                diff.input_sources.into_iter().map(Into::into).collect(),
+                &apply::Loader::default(),
+            )
+            .await
+            .context(error::DiffSnafu)?;
+            // Only color the output for people, not for files or pipes.
+            let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+            let output = diff::format_diff(&changes, diff.format.as_deref(), color)
+                .context(error::DiffSnafu)?;
+            print!("{}", output);
+        }
+
+        Subcommand::Completion(CompletionArgs::Script(shell)) => {
+            print!("{}", completion::script(&COMPLETION_CLI, shell));
+        }
+
+        Subcommand::Completion(CompletionArgs::Complete(words)) => {
+            print_completions(&args, &words).await;
+        }
+
+        Subcommand::Cp(cp) => copy_file(&args, cp).await?,
+
+        Subcommand::Describe(describe) => {
+            let descriptions = describe::describe(&args.socket_path, describe.prefixes)
                 .await
-                .context(error::ApplySnafu)?;
+                .context(error::DescribeSnafu)?;
+            let output = describe::format_descriptions(&descriptions, describe.format.as_deref())
+                .context(error::DescribeSnafu)?;
+            print!("{}", output);
         }
 
         Subcommand::Exec(exec) => {
@@ -937,16 +2184,7 @@ async fn run() -> Result<()> {
                 .context(error::ExecSnafu)?;
         }
 
-        Subcommand::Get(get) => {
-            let result = match get {
-                GetArgs::Uri(uri) => get::get_uri(&args.socket_path, uri).await,
-                GetArgs::Prefixes(prefixes) => get::get_prefixes(&args.socket_path, prefixes).await,
-            };
-            let value = result.context(error::GetSnafu)?;
-            let pretty =
-                serde_json::to_string_pretty(&value).expect("JSON Value already validated as JSON");
-            println!("{}", pretty);
-        }
+        Subcommand::Get(get) => print_get(&args, &get).await?,
 
         Subcommand::Reboot(_reboot) => {
             reboot::reboot(&args.socket_path)
@@ -954,38 +2192,39 @@ async fn run() -> Result<()> {
                 .context(error::RebootSnafu)?;
         }
 
-        Subcommand::Set(set) => {
-            let settings = match set {
-                SetArgs::Simple(simple) => {
-                    trace!("User supplied Key Value settings {:#?}", simple);
-                    // Construct the Key Pair struct.
-                    let set_key_pair = SetKeyPairSettings {
-                        request_payload: simple,
-                    };
-                    let settings_string =
-                        serde_json::to_string(&set_key_pair).context(error::SerializeSnafu)?;
-                    SettingsInput::KeyPair(settings_string)
-                }
-                SetArgs::Json(json) => {
-                    trace!("User supplied Json settings {:#?}", json);
-                    // Convert JSON Value to a string.
-                    SettingsInput::Json(json.to_string())
-                }
-            };
+        Subcommand::Set(set) => set_settings(&args, &set).await?,
+
+        Subcommand::Snapshot(subcommand) => match subcommand {
+            SnapshotSubcommand::Export(export) => {
+                let snapshot = snapshot::export(&args.socket_path, export.include_generated)
+                    .await
+                    .context(error::SnapshotSnafu)?;
+                let output = snapshot::format_snapshot(&snapshot, export.format.as_deref())
+                    .context(error::SnapshotSnafu)?;
+                println!("{}", output.trim_end());
+            }
 
-            set::set(&args.socket_path, settings)
+            SnapshotSubcommand::Import(import) => {
+                snapshot::import(&args.socket_path, &import.input_source)
+                    .await
+                    .context(error::SnapshotSnafu)?;
+            }
+        },
+
+        Subcommand::Unset(unset) => {
+            unset::unset(&args.socket_path, &unset.keys)
                 .await
-                .context(error::SetSnafu)?;
+                .context(error::UnsetSnafu)?;
         }
 
         Subcommand::Update(subcommand) => match subcommand {
-            UpdateSubcommand::Check(_check) => {
-                check(&args).await?;
+            UpdateSubcommand::Check(check_args) => {
+                check(&args, check_args.source.as_deref()).await?;
             }
 
             UpdateSubcommand::Apply(apply) => {
                 if apply.check {
-                    let output = check(&args).await?;
+                    let output = check(&args, apply.source.as_deref()).await?;
                     // Exit early if no update is required, either because none is available or one
                     // is already applied and ready.
                     if !update::required(&output) {
@@ -993,7 +2232,7 @@ async fn run() -> Result<()> {
                     }
                 }
 
-                update::apply(&args.socket_path)
+                update::apply(&args.socket_path, apply.source.as_deref(), apply.force)
                     .await
                     .context(error::UpdateApplySnafu)?;
 
@@ -1013,49 +2252,36 @@ async fn run() -> Result<()> {
                     .await
                     .context(error::UpdateCancelSnafu)?;
             }
-        },
 
-        Subcommand::Report(subcommand) => match subcommand {
-            ReportSubcommand::Cis(cis_args) => {
-                let body = report::get_cis_report(
-                    &args.socket_path,
-                    "bottlerocket",
-                    cis_args.format,
-                    cis_args.level,
-                )
-                .await
-                .context(error::ReportSnafu)?;
+            UpdateSubcommand::Rollback(rollback) => {
+                update::rollback(&args.socket_path)
+                    .await
+                    .context(error::UpdateRollbackSnafu)?;
 
-                if !body.is_empty() {
-                    print!("{}", body);
+                if rollback.reboot {
+                    reboot::reboot(&args.socket_path)
+                        .await
+                        .context(error::RebootSnafu)?;
+                } else {
+                    info!("The previous version will be booted on next reboot.");
                 }
             }
 
-            ReportSubcommand::CisK8s(cis_args) => {
-                let body = report::get_cis_report(
-                    &args.socket_path,
-                    "kubernetes",
-                    cis_args.format,
-                    cis_args.level,
-                )
-                .await
-                .context(error::ReportSnafu)?;
-
-                if !body.is_empty() {
-                    print!("{}", body);
-                }
+            UpdateSubcommand::Watch(watch) => {
+                print_changes(&args, watch::Target::UpdateStatus, &watch.options).await?;
             }
+        },
 
-            ReportSubcommand::Fips(fips_args) => {
-                let body = report::get_fips_report(&args.socket_path, fips_args.format)
-                    .await
-                    .context(error::ReportSnafu)?;
+        Subcommand::Watch(watch) => {
+            print_changes(
+                &args,
+                watch::Target::Prefixes(watch.prefixes),
+                &watch.options,
+            )
+            .await?;
+        }
 
-                if !body.is_empty() {
-                    print!("{}", body);
-                }
-            }
-        },
+        Subcommand::Report(report) => print_report(&args, &report).await?,
 
         Subcommand::EphemeralStorage(subcommand) => match subcommand {
             EphemeralStorageSubcommand::Init(cfg_args) => {
@@ -1106,7 +2332,10 @@ async fn main() {
 }
 
 mod error {
-    use apiclient::{apply, ephemeral_storage, exec, get, reboot, report, set, update};
+    use apiclient::{
+        apply, cp, describe, diff, ephemeral_storage, exec, get, reboot, report, set, snapshot,
+        ssh, unset, update, watch,
+    };
     use snafu::Snafu;
 
     #[derive(Debug, Snafu)]
@@ -1115,15 +2344,40 @@ mod error {
         #[snafu(display("Failed to apply settings: {}", source))]
         Apply { source: apply::Error },
 
+        #[snafu(display("Failed to copy: {}", source))]
+        Cp { source: cp::Error },
+
+        #[snafu(display("Failed to describe settings: {}", source))]
+        Describe { source: describe::Error },
+
+        #[snafu(display("Failed to compare settings: {}", source))]
+        Diff { source: diff::Error },
+
         #[snafu(display("Failed to exec: {}", source))]
-        Exec { source: exec::Error },
+        Exec {
+            #[snafu(source(from(exec::Error, Box::new)))]
+            source: Box<exec::Error>,
+        },
 
         #[snafu(display("Failed to get settings: {}", source))]
         Get { source: get::Error },
 
+        #[snafu(display("Failed on {} of {} hosts: {}", count, total, failed))]
+        Hosts {
+            count: usize,
+            total: usize,
+            failed: String,
+        },
+
+        #[snafu(display("Failed to read hosts: {}", source))]
+        HostsFile { source: ssh::Error },
+
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
 
+        #[snafu(display("Can't copy '{}' into a directory, it has no file name", path.display()))]
+        NoFileName { path: std::path::PathBuf },
+
         #[snafu(display("Failed to reboot: {}", source))]
         Reboot { source: reboot::Error },
 
@@ -1144,6 +2398,15 @@ mod error {
         #[snafu(display("Failed to change settings: {}", source))]
         Set { source: set::Error },
 
+        #[snafu(display("Failed to snapshot settings: {}", source))]
+        Snapshot { source: snapshot::Error },
+
+        #[snafu(display("Failed to connect through ssh: {}", source))]
+        Ssh { source: ssh::Error },
+
+        #[snafu(display("Failed to remove settings: {}", source))]
+        Unset { source: unset::Error },
+
         #[snafu(display("Failed to apply update: {}", source))]
         UpdateApply { source: update::Error },
 
@@ -1153,6 +2416,12 @@ mod error {
         #[snafu(display("Failed to check for updates: {}", source))]
         UpdateCheck { source: update::Error },
 
+        #[snafu(display("Failed to roll back: {}", source))]
+        UpdateRollback { source: update::Error },
+
+        #[snafu(display("Failed to watch for changes: {}", source))]
+        Watch { source: watch::Error },
+
         #[snafu(display("Failed to initialize ephemeral storage: {}", source))]
         EphemeralStorage { source: ephemeral_storage::Error },
     }
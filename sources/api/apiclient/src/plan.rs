@@ -0,0 +1,82 @@
+//! The plan module asks the API server what committing a pending transaction would change, without
+//! committing or applying anything, and then discards the transaction.  This is what backs the
+//! --dry-run options of 'set' and 'apply'.
+
+use model::plan::TransactionPlan;
+use snafu::ResultExt;
+use std::path::Path;
+
+/// Returns what committing and applying the given transaction would change, then deletes the
+/// transaction so its changes can't be committed later by accident.
+pub async fn plan<P>(socket_path: P, transaction: &str) -> Result<TransactionPlan>
+where
+    P: AsRef<Path>,
+{
+    let uri = format!("/tx/plan?tx={}", transaction);
+    let method = "POST";
+    let planned = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method });
+
+    // Discard the transaction whether or not planning worked.
+    let uri = format!("/tx?tx={}", transaction);
+    let method = "DELETE";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    let (_status, body) = planned?;
+    serde_json::from_str(&body).context(error::ResponseJsonSnafu)
+}
+
+/// Formats a plan for people to read, listing the changed settings and affected services, then
+/// showing a unified diff of each configuration file that would change.
+pub fn format_plan(plan: &TransactionPlan) -> String {
+    let mut output = String::from("Changed settings:\n");
+    for key in &plan.changed_keys {
+        output.push_str(&format!("    {}\n", key));
+    }
+
+    let mut services: Vec<_> = plan.services.keys().collect();
+    services.sort();
+    if services.is_empty() {
+        output.push_str("No services would be restarted.\n");
+    } else {
+        output.push_str("Services that would be restarted:\n");
+        for service in services {
+            output.push_str(&format!("    {}\n", service));
+        }
+    }
+
+    for file in &plan.configuration_files {
+        match &file.error {
+            Some(error) => output.push_str(&format!("Unable to render {}: {}\n", file.path, error)),
+            None if file.diff.is_empty() => {
+                output.push_str(&format!("No changes to {}\n", file.path))
+            }
+            None => output.push_str(&file.diff),
+        }
+    }
+    output
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid plan: {}", source))]
+        ResponseJson { source: serde_json::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,197 @@
+//! The schema module fetches the JSON schema of settings from the API and lists the settings it
+//! describes, with their types and the constraints on their values, whether or not they're set.
+//!
+//! The API's schema is partial: sections without properties, like "settings.kubernetes", are
+//! open, and aren't listed here, so callers should look at the live settings under them instead.
+
+use datastore::{Key, KeyType};
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Schema keywords that constrain the values a setting can take.
+const CONSTRAINTS: &[&str] = &[
+    "enum",
+    "format",
+    "maxLength",
+    "maximum",
+    "minLength",
+    "minimum",
+    "pattern",
+];
+
+/// SettingSchema describes one setting as the model defines it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingSchema {
+    /// The type of the setting's value, like "string" or "list of integer".
+    pub value_type: String,
+    /// Schema keywords that constrain the value, like "enum" or "maximum".
+    pub constraints: BTreeMap<String, Value>,
+}
+
+/// Maps the dotted name of each setting the schema describes, like "settings.motd", to its schema.
+pub type SettingSchemas = BTreeMap<String, SettingSchema>;
+
+/// Fetches the JSON schema of settings from the API.
+pub async fn get_schema<P>(socket_path: P) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings/schema";
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+    serde_json::from_str(&body).context(error::ResponseJsonSnafu { body })
+}
+
+/// Lists the settings described by the given schema.  Sections with named properties, like
+/// "settings.ntp", are broken down into their settings; objects with a schema for their values are
+/// settings that hold maps; other objects are open sections, and are left out.
+pub fn settings(schema: &Value) -> Result<SettingSchemas> {
+    let mut settings = BTreeMap::new();
+    add_settings(&mut settings, &mut vec!["settings".to_string()], schema)?;
+    Ok(settings)
+}
+
+fn add_settings(
+    settings: &mut SettingSchemas,
+    segments: &mut Vec<String>,
+    schema: &Value,
+) -> Result<()> {
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+        for (name, property) in properties {
+            segments.push(name.clone());
+            add_settings(settings, segments, property)?;
+            segments.pop();
+        }
+        return Ok(());
+    }
+    if is_open(schema) {
+        return Ok(());
+    }
+
+    let key = Key::from_segments(KeyType::Data, &segments[..]).context(error::KeySnafu {
+        name: segments.join("."),
+    })?;
+    let constraints = CONSTRAINTS
+        .iter()
+        .filter_map(|&keyword| {
+            schema
+                .get(keyword)
+                .map(|value| (keyword.to_string(), value.clone()))
+        })
+        .collect();
+    settings.insert(
+        key.name().clone(),
+        SettingSchema {
+            value_type: schema_type(schema),
+            constraints,
+        },
+    );
+    Ok(())
+}
+
+/// Returns whether a schema is an open section, an object that doesn't say what it holds.
+fn is_open(schema: &Value) -> bool {
+    schema.get("type").and_then(Value::as_str) == Some("object")
+        && !schema
+            .get("additionalProperties")
+            .is_some_and(Value::is_object)
+}
+
+/// Returns the type given by a schema, like "string" or "map of string".
+fn schema_type(schema: &Value) -> String {
+    match schema.get("type").and_then(Value::as_str) {
+        Some("array") => match schema.get("items") {
+            Some(items) => format!("list of {}", schema_type(items)),
+            None => "list".to_string(),
+        },
+        Some("object") => match schema.get("additionalProperties") {
+            Some(values) if values.is_object() => format!("map of {}", schema_type(values)),
+            _ => "map".to_string(),
+        },
+        Some(other) => other.to_string(),
+        None => "any".to_string(),
+    }
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Invalid setting name '{}' in schema: {}", name, source))]
+        Key {
+            name: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid JSON '{}' - {}", body, source))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn list_settings() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "motd": {"type": "string"},
+                "kernel": {
+                    "type": "object",
+                    "properties": {
+                        "lockdown": {"type": "string", "enum": ["none", "integrity"]},
+                    },
+                },
+                "ntp": {
+                    "type": "object",
+                    "properties": {
+                        "time-servers": {"type": "array", "items": {"type": "string"}},
+                    },
+                },
+                "labels": {"type": "object", "additionalProperties": {"type": "string"}},
+                "kubernetes": {"type": "object"},
+            },
+            "additionalProperties": true,
+        });
+        let settings = settings(&schema).unwrap();
+        let types: BTreeMap<&str, &str> = settings
+            .iter()
+            .map(|(name, schema)| (name.as_str(), schema.value_type.as_str()))
+            .collect();
+        assert_eq!(
+            types,
+            BTreeMap::from([
+                ("settings.kernel.lockdown", "string"),
+                ("settings.labels", "map of string"),
+                ("settings.motd", "string"),
+                ("settings.ntp.time-servers", "list of string"),
+            ])
+        );
+        assert_eq!(
+            settings["settings.kernel.lockdown"].constraints,
+            BTreeMap::from([("enum".to_string(), json!(["none", "integrity"]))])
+        );
+    }
+}
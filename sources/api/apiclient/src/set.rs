@@ -1,5 +1,8 @@
-use crate::{rando, SettingsInput};
-use snafu::ResultExt;
+use crate::{plan, rando, SettingsInput};
+use datastore::{Key, KeyType};
+use model::plan::TransactionPlan;
+use serde_json::{Map, Value};
+use snafu::{ensure, OptionExt, ResultExt};
 use std::path::Path;
 
 /// Changes the requested settings through the API, then commits and applies the transaction
@@ -7,6 +10,36 @@ use std::path::Path;
 /// the settings you want to change.  If you're deserializing a request from a user, for example,
 /// the created Settings will only have the requested keys populated.
 pub async fn set<P>(socket_path: P, settings: SettingsInput) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, settings).await?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    Ok(())
+}
+
+/// Like `set`, but rather than committing and applying the changes, returns what doing so would
+/// change, and then discards them.
+pub async fn plan<P>(socket_path: P, settings: SettingsInput) -> Result<TransactionPlan>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, settings).await?;
+    plan::plan(&socket_path, &transaction)
+        .await
+        .context(error::PlanSnafu)
+}
+
+/// Sends the requested settings changes to the API in a new transaction, and returns the name of
+/// the transaction.
+async fn stage<P>(socket_path: P, settings: SettingsInput) -> Result<String>
 where
     P: AsRef<Path>,
 {
@@ -14,23 +47,84 @@ where
     let transaction = format!("apiclient-set-{}", rando());
 
     // Send the settings changes to the server.
+    let content_type = settings.content_type();
     let (uri, settings_data) = match settings {
         SettingsInput::KeyPair(value) => (format!("/settings/keypair?tx={}", transaction), value),
-        SettingsInput::Json(value) => (format!("/settings?tx={}", transaction), value),
+        SettingsInput::Json(value)
+        | SettingsInput::JsonPatch(value)
+        | SettingsInput::MergePatch(value) => (format!("/settings?tx={}", transaction), value),
     };
     let method = "PATCH";
-    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, Some(settings_data))
-        .await
-        .context(error::RequestSnafu { uri, method })?;
+    let (_status, _body) = crate::raw_request_with_content_type(
+        &socket_path,
+        &uri,
+        method,
+        content_type,
+        Some(settings_data),
+    )
+    .await
+    .context(error::RequestSnafu { uri, method })?;
 
-    // Commit the transaction and apply it to the system.
-    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
-    let method = "POST";
-    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
-        .await
-        .context(error::RequestSnafu { uri, method })?;
+    Ok(transaction)
+}
 
-    Ok(())
+/// The API's KEY=VALUE form of 'set' guesses whether each value is a boolean, a number, or a
+/// string, so it can't take lists, or strings that look like other types.  To allow for them,
+/// values may be given in JSON form, like `ntp.time-servers=["a", "b"]` or `motd="42"`.  If any
+/// value is in JSON form, this returns a settings object built from all of the given pairs, to be
+/// sent as JSON input; otherwise, it returns None, and the pairs can be sent as they are.
+///
+/// Other values are typed the same way the API types them.
+pub fn key_pairs_json(pairs: &[String]) -> Result<Option<Value>> {
+    let mut json_form = false;
+    let mut settings = Value::Object(Map::new());
+    for pair in pairs {
+        let (raw_key, raw_value) = pair
+            .split_once('=')
+            .context(error::InvalidKeyPairSnafu { input: pair })?;
+        let key = Key::new(KeyType::Data, raw_key).context(error::KeySnafu { name: raw_key })?;
+
+        let value = if let Ok(b) = serde_json::from_str::<bool>(raw_value) {
+            Value::from(b)
+        } else if let Ok(u) = serde_json::from_str::<u64>(raw_value) {
+            Value::from(u)
+        } else if let Ok(f) = serde_json::from_str::<f64>(raw_value) {
+            Value::from(f)
+        } else if let Ok(list) = serde_json::from_str::<Vec<Value>>(raw_value) {
+            json_form = true;
+            Value::from(list)
+        } else if let Ok(s) = serde_json::from_str::<String>(raw_value) {
+            json_form = true;
+            Value::from(s)
+        } else {
+            Value::from(raw_value)
+        };
+
+        // Settings objects given as JSON input don't include the "settings" prefix.
+        let mut segments = key.segments().as_slice();
+        if segments[0] == "settings" && segments.len() > 1 {
+            segments = &segments[1..];
+        }
+        let (last, parents) = segments.split_last().expect("keys have segments");
+        let mut object = &mut settings;
+        for segment in parents {
+            object = object
+                .as_object_mut()
+                .context(error::KeyConflictSnafu { name: raw_key })?
+                .entry(segment.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+        }
+        let object = object
+            .as_object_mut()
+            .context(error::KeyConflictSnafu { name: raw_key })?;
+        ensure!(
+            !object.contains_key(last),
+            error::KeyConflictSnafu { name: raw_key }
+        );
+        object.insert(last.clone(), value);
+    }
+
+    Ok(json_form.then_some(settings))
 }
 
 mod error {
@@ -39,6 +133,21 @@ mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility(pub(super)))]
     pub enum Error {
+        #[snafu(display("Invalid KEY=VALUE input '{}'", input))]
+        InvalidKeyPair { input: String },
+
+        #[snafu(display("Invalid setting name '{}': {}", name, source))]
+        Key {
+            name: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Setting '{}' conflicts with another given setting", name))]
+        KeyConflict { name: String },
+
+        #[snafu(display("Unable to plan changes: {}", source))]
+        Plan { source: crate::plan::Error },
+
         #[snafu(display("Unable to serialize data: {}", source))]
         Serialize { source: serde_json::Error },
 
@@ -53,3 +162,46 @@ mod error {
 }
 pub use error::Error;
 pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn pairs(input: &[&str]) -> Vec<String> {
+        input.iter().map(|pair| pair.to_string()).collect()
+    }
+
+    #[test]
+    fn plain_pairs_stay_key_pairs() {
+        let input = pairs(&["motd=hi", "settings.kubernetes.max-pods=110"]);
+        assert_eq!(key_pairs_json(&input).unwrap(), None);
+    }
+
+    #[test]
+    fn json_form_builds_settings() {
+        let input = pairs(&[
+            "motd=\"42\"",
+            "settings.ntp.time-servers=[\"a\", \"b\"]",
+            "kubernetes.max-pods=110",
+            "kubernetes.node-labels.\"my.label\"=x",
+            "host-containers.admin.enabled=false",
+        ]);
+        assert_eq!(
+            key_pairs_json(&input).unwrap(),
+            Some(json!({
+                "motd": "42",
+                "ntp": {"time-servers": ["a", "b"]},
+                "kubernetes": {"max-pods": 110, "node-labels": {"my.label": "x"}},
+                "host-containers": {"admin": {"enabled": false}},
+            }))
+        );
+    }
+
+    #[test]
+    fn conflicting_keys_fail() {
+        let input = pairs(&["motd=\"hi\"", "motd.extra=1"]);
+        assert!(key_pairs_json(&input).is_err());
+        assert!(key_pairs_json(&pairs(&["motd"])).is_err());
+    }
+}
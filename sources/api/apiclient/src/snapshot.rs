@@ -0,0 +1,166 @@
+//! The snapshot module saves the system's settings, with their metadata, to a portable file, and
+//! restores them from one.  Restored settings are checked against the current settings model by
+//! the API server, then committed and applied in a single transaction; metadata isn't restored.
+
+use crate::rando;
+use datastore::snapshot::Snapshot;
+use serde::de::{Deserialize, IntoDeserializer};
+use snafu::{ensure, ResultExt};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Returns a snapshot of the current settings and their metadata.  Settings made by setting
+/// generators are only included if `include_generated` is true.
+pub async fn export<P>(socket_path: P, include_generated: bool) -> Result<Snapshot>
+where
+    P: AsRef<Path>,
+{
+    let uri = format!("/snapshot?include-generated={}", include_generated);
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    serde_json::from_str(&body).context(error::ResponseJsonSnafu)
+}
+
+/// Formats a snapshot for saving; `format` may be "json" (the default) or "toml".
+pub fn format_snapshot(snapshot: &Snapshot, format: Option<&str>) -> Result<String> {
+    match format {
+        None | Some("json") => {
+            serde_json::to_string_pretty(snapshot).context(error::JsonSerializeSnafu)
+        }
+        Some("toml") => toml::to_string(snapshot).context(error::TomlSerializeSnafu),
+        Some(format) => error::FormatSnafu { format }.fail(),
+    }
+}
+
+/// Reads a snapshot in TOML or JSON format from the given file (or from stdin, if given "-"), then
+/// restores it in a new transaction, which is committed and applied to the system.
+pub async fn import<P>(socket_path: P, input_source: &str) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let input = if input_source == "-" {
+        let mut output = String::new();
+        tokio::io::stdin()
+            .read_to_string(&mut output)
+            .await
+            .context(error::StdinReadSnafu)?;
+        output
+    } else {
+        tokio::fs::read_to_string(input_source)
+            .await
+            .context(error::FileReadSnafu { input_source })?
+    };
+    let snapshot = parse_snapshot(&input, input_source)?;
+    let json = serde_json::to_string(&snapshot).context(error::JsonSerializeSnafu)?;
+
+    // We use a specific transaction ID so we don't commit any other changes that may be pending.
+    let transaction = format!("apiclient-snapshot-{}", rando());
+
+    let uri = format!("/snapshot?tx={}", transaction);
+    let method = "POST";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, Some(json))
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    Ok(())
+}
+
+/// Parses a snapshot from TOML or JSON text.
+fn parse_snapshot(input: &str, input_source: &str) -> Result<Snapshot> {
+    // Try to parse the input as (arbitrary) TOML.  If that fails, try to parse it as JSON.
+    let json_val = match toml::from_str::<toml::Value>(input) {
+        Ok(toml_val) => {
+            // Go through a JSON value so that data values are represented the way the data store
+            // expects, e.g. TOML integers as JSON numbers.
+            let d = toml_val.into_deserializer();
+            serde_json::Value::deserialize(d).context(error::TomlToJsonSnafu { input_source })?
+        }
+        Err(toml_err) => serde_json::from_str(input).context(error::InputTypeSnafu {
+            input_source,
+            toml_err,
+        })?,
+    };
+
+    ensure!(
+        json_val.is_object(),
+        error::SnapshotTypeSnafu { input_source }
+    );
+    serde_json::from_value(json_val).context(error::InvalidSnapshotSnafu { input_source })
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Failed to read given file '{}': {}", input_source, source))]
+        FileRead {
+            input_source: String,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Unknown snapshot format '{}', expected 'json' or 'toml'", format))]
+        Format { format: String },
+
+        #[snafu(display(
+            "Input '{}' is not valid TOML or JSON.  (TOML error: {})  (JSON error: {})",
+            input_source,
+            toml_err,
+            source
+        ))]
+        InputType {
+            input_source: String,
+            toml_err: Box<toml::de::Error>,
+            #[snafu(source(from(serde_json::Error, Box::new)))]
+            source: Box<serde_json::Error>,
+        },
+
+        #[snafu(display("Input '{}' is not a valid snapshot: {}", input_source, source))]
+        InvalidSnapshot {
+            input_source: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to serialize snapshot to JSON: {}", source))]
+        JsonSerialize { source: serde_json::Error },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid snapshot: {}", source))]
+        ResponseJson { source: serde_json::Error },
+
+        #[snafu(display("Snapshot from '{}' is not a TOML table / JSON object", input_source))]
+        SnapshotType { input_source: String },
+
+        #[snafu(display("Failed to read standard input: {}", source))]
+        StdinRead { source: std::io::Error },
+
+        #[snafu(display("Failed to translate TOML from '{}' to JSON: {}", input_source, source))]
+        TomlToJson {
+            input_source: String,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Failed to serialize snapshot to TOML: {}", source))]
+        TomlSerialize { source: toml::ser::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,245 @@
+//! The ssh module lets apiclient talk to the API on other hosts.  A Tunnel runs ssh to forward a
+//! local Unix-domain socket to the API socket on the remote host, so the other modules can use the
+//! local socket path as if the API were running here.
+//!
+//! ssh is run with the user's own configuration, so keys, ports, and jump hosts can be set up in
+//! ~/.ssh/config as usual.  It's run in batch mode, so it fails rather than prompting for a password
+//! or host key confirmation, which would stall a run across many hosts.
+
+use snafu::{ensure, ResultExt};
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+/// How often we check whether ssh has set up the forwarded socket.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long ssh may take to connect to a host, in seconds.
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// How long we wait for ssh to set up the forwarded socket, including connecting and logging in.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tunnel is a running ssh process that forwards a local socket to the API socket on a remote host.
+/// ssh is stopped and the local socket is removed when the Tunnel is dropped.
+#[derive(Debug)]
+pub struct Tunnel {
+    child: Child,
+    dir: PathBuf,
+    socket_path: PathBuf,
+}
+
+impl Tunnel {
+    /// Connects to the given host, like "user@node" or "ssh://user@node:2222", and forwards a local
+    /// socket to the API socket at `remote_socket_path` on that host.  Returns once the local
+    /// socket is ready, or fails if ssh exits first, for example because it couldn't connect, or if
+    /// the socket isn't ready within OPEN_TIMEOUT.
+    pub async fn open<P>(host: &str, remote_socket_path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        ensure!(
+            !host.is_empty() && !host.starts_with('-'),
+            error::HostSnafu { host }
+        );
+
+        // Only we should be able to use the forwarded socket, so it goes in a private directory.
+        let dir = std::env::temp_dir().join(format!("apiclient-ssh-{}", crate::rando()));
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .context(error::TempDirSnafu { path: &dir })?;
+        let socket_path = dir.join("api.sock");
+
+        let mut command = Command::new("ssh");
+        command
+            .args(ssh_args(host, &socket_path, remote_socket_path.as_ref()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .kill_on_drop(true);
+        // Stop ssh if we exit without dropping the Tunnel, as 'exec' does to return the exit code
+        // of its command.  Our runtime's worker threads live as long as we do, so it doesn't
+        // matter which thread spawns ssh.
+        // SAFETY: prctl is async-signal-safe and only changes the state of the child process.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = match command.spawn() {
+            Ok(child) => child,
+            Err(source) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(source).context(error::SpawnSnafu);
+            }
+        };
+        // From here, dropping the Tunnel cleans up after ssh.
+        let mut tunnel = Self {
+            child,
+            dir,
+            socket_path,
+        };
+
+        let deadline = tokio::time::Instant::now() + OPEN_TIMEOUT;
+        while !tunnel.socket_path.exists() {
+            if let Some(status) = tunnel.child.try_wait().context(error::WaitSnafu)? {
+                return error::ExitedSnafu { host, status }.fail();
+            }
+            ensure!(
+                tokio::time::Instant::now() < deadline,
+                error::TimeoutSnafu {
+                    host,
+                    timeout: OPEN_TIMEOUT
+                }
+            );
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(tunnel)
+    }
+
+    /// Returns the path of the local socket, which can be given to the other modules in place of
+    /// the API socket path.
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Returns the arguments to ssh for forwarding the local socket to the remote socket.  No command is
+/// run on the remote host; "--" keeps ssh from taking the host for an option.  Batch mode keeps ssh
+/// from prompting, and the connect timeout keeps it from waiting on unreachable hosts.
+fn ssh_args(host: &str, local_socket_path: &Path, remote_socket_path: &Path) -> Vec<OsString> {
+    let mut forward = local_socket_path.as_os_str().to_os_string();
+    forward.push(":");
+    forward.push(remote_socket_path);
+    vec![
+        "-N".into(),
+        "-o".into(),
+        "ExitOnForwardFailure=yes".into(),
+        "-o".into(),
+        "BatchMode=yes".into(),
+        "-o".into(),
+        format!("ConnectTimeout={}", CONNECT_TIMEOUT_SECS).into(),
+        "-L".into(),
+        forward,
+        "--".into(),
+        host.into(),
+    ]
+}
+
+/// Returns the hosts listed in the given hosts file, one per line.  Blank lines, and lines starting
+/// with '#', are skipped.
+pub fn read_hosts_file<P>(path: P) -> Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path).context(error::HostsFileSnafu { path })?;
+    let hosts = parse_hosts(&contents);
+    ensure!(!hosts.is_empty(), error::NoHostsSnafu { path });
+    Ok(hosts)
+}
+
+fn parse_hosts(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+    use std::process::ExitStatus;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display(
+            "ssh to '{}' exited before forwarding the API socket: {}",
+            host,
+            status
+        ))]
+        Exited { host: String, status: ExitStatus },
+
+        #[snafu(display("Invalid host '{}'", host))]
+        Host { host: String },
+
+        #[snafu(display("Unable to read hosts file '{}': {}", path.display(), source))]
+        HostsFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("No hosts listed in hosts file '{}'", path.display()))]
+        NoHosts { path: PathBuf },
+
+        #[snafu(display("Unable to run ssh: {}", source))]
+        Spawn { source: std::io::Error },
+
+        #[snafu(display("Unable to create directory '{}': {}", path.display(), source))]
+        TempDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("ssh to '{}' didn't forward the API socket within {:?}", host, timeout))]
+        Timeout {
+            host: String,
+            timeout: std::time::Duration,
+        },
+
+        #[snafu(display("Unable to check on ssh: {}", source))]
+        Wait { source: std::io::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            parse_hosts("# lab nodes\nadmin@node1\n\n  admin@node2  \nssh://admin@node3:2222\n"),
+            vec!["admin@node1", "admin@node2", "ssh://admin@node3:2222"]
+        );
+        assert_eq!(
+            ssh_args(
+                "admin@node1",
+                Path::new("/tmp/apiclient-ssh-x/api.sock"),
+                Path::new("/run/api.sock")
+            ),
+            vec![
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "ConnectTimeout=10",
+                "-L",
+                "/tmp/apiclient-ssh-x/api.sock:/run/api.sock",
+                "--",
+                "admin@node1"
+            ]
+        );
+    }
+}
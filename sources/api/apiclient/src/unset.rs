@@ -0,0 +1,52 @@
+use crate::rando;
+use snafu::ResultExt;
+use std::path::Path;
+
+/// Removes the requested settings, and any settings under them, through the API, then commits and
+/// applies the transaction containing those changes.  Keys may be given with or without the
+/// "settings." prefix.
+pub async fn unset<P>(socket_path: P, keys: &[String]) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    // We use a specific transaction ID so we don't commit any other changes that may be pending.
+    let transaction = format!("apiclient-unset-{}", rando());
+
+    // Mark the settings for removal.  Setting names can include quotes, so encode the query.
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("keys", &keys.join(","))
+        .append_pair("tx", &transaction)
+        .finish();
+    let uri = format!("/settings?{}", query);
+    let method = "DELETE";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::RequestSnafu { uri, method })?;
+
+    Ok(())
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
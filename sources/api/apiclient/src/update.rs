@@ -27,15 +27,17 @@ use std::path::Path;
 use std::time::Duration;
 use tokio::time;
 
-/// Refresh the list of available updates and return the current status.
-pub async fn check<P>(socket_path: P) -> Result<String>
+/// Refresh the list of available updates and return the current status.  If a source is given,
+/// updates are listed from the local update repository at that path on the host, either a
+/// directory or a block device holding one, instead of the configured repository.
+pub async fn check<P>(socket_path: P, source: Option<&str>) -> Result<String>
 where
     P: AsRef<Path>,
 {
     info!("Refreshing updates...");
     let (_body, status) = wait_request(
         socket_path,
-        "/actions/refresh-updates",
+        &action_uri("refresh-updates", source),
         "POST",
         None,
         "refresh",
@@ -69,15 +71,17 @@ pub fn required(check_output: &str) -> bool {
     }
 }
 
-/// Applies the update shown as selected in the output of check(), and makes it active.
-pub async fn apply<P>(socket_path: P) -> Result<()>
+/// Applies the update shown as selected in the output of check(), and makes it active.  If a
+/// source is given, the update is read from the local update repository at that path, as with
+/// check().  If forced, the update is made active even outside of updog's maintenance windows.
+pub async fn apply<P>(socket_path: P, source: Option<&str>, force: bool) -> Result<()>
 where
     P: AsRef<Path>,
 {
     info!("Downloading and applying update to disk...");
     let (_body, _status) = wait_request(
         &socket_path,
-        "/actions/prepare-update",
+        &action_uri("prepare-update", source),
         "POST",
         None,
         "prepare",
@@ -87,9 +91,14 @@ where
     .context(error::PrepareUpdateSnafu)?;
 
     info!("Setting the update active so it will apply on the next reboot...");
+    let activate_uri = if force {
+        "/actions/activate-update?force=true"
+    } else {
+        "/actions/activate-update"
+    };
     let (_body, _status) = wait_request(
         &socket_path,
-        "/actions/activate-update",
+        activate_uri,
         "POST",
         None,
         "activate",
@@ -119,8 +128,42 @@ where
     Ok(status)
 }
 
+/// Rolls back to the previous version by marking the inactive partition set for boot.  The API
+/// only allows this if the inactive partition set holds an older version that has booted
+/// successfully.
+pub async fn rollback<P>(socket_path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    info!("Rolling back to the previous version...");
+    let (_body, status) = wait_request(
+        socket_path,
+        "/actions/rollback",
+        "POST",
+        None,
+        "rollback",
+        &WaitPolicy::new(Duration::from_millis(100), 10 * 5),
+    )
+    .await?;
+
+    Ok(status)
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// Returns the URI of the given update action, with the update source, if any.
+fn action_uri(action: &str, source: Option<&str>) -> String {
+    match source {
+        Some(source) => {
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("source", source)
+                .finish();
+            format!("/actions/{}?{}", action, query)
+        }
+        None => format!("/actions/{}", action),
+    }
+}
+
 /// Pulls a nested field out of a JSON string.  The input is a list of strings representing the
 /// nested structures, e.g. ["a", "b"] to select the 42 from {"a": {"b": 42}}.
 ///
@@ -0,0 +1,500 @@
+//! The watch module follows changes to settings, or to the update status.  Settings changes are
+//! streamed from the API's /settings/watch WebSocket as they're committed; the update status isn't
+//! kept in the data store, so it's polled.  Changes are shown the same way 'apiclient diff' shows
+//! them, each with a timestamp, and watching can stop once given conditions are met.
+
+use crate::diff::{self, SettingsDiff};
+use crate::exec::connect::websocket_connect;
+use crate::{get, raw_request_unchecked};
+use chrono::{SecondsFormat, Utc};
+use datastore::{Key, KeyType};
+use futures::{SinkExt, StreamExt};
+use http::StatusCode;
+use log::{debug, trace, warn};
+use model::watch::SettingsChange;
+use serde_json::Value;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+/// How many times in a row we'll fail to fetch the watched values, or lose the settings stream,
+/// before giving up.  The API can briefly be unavailable, for example while settings are applied.
+const MAX_FAILURES: u32 = 5;
+
+/// The server closes the settings stream if it doesn't hear from us, so we ping it this often.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Target is what to watch.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Settings under the given prefixes, as with 'apiclient get'.  Only settings can be watched.
+    Prefixes(Vec<String>),
+    /// The update status, as shown by 'apiclient update check'.
+    UpdateStatus,
+}
+
+/// Condition is met when the value with the given dotted name, like "settings.motd" or
+/// "update_state", is the given value.  String values are compared without quotes, and other
+/// values are compared as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    name: String,
+    value: String,
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    /// Parses a condition of the form "NAME=VALUE".
+    fn from_str(condition: &str) -> Result<Self> {
+        let (name, value) = condition
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .context(error::ConditionSnafu { condition })?;
+        Ok(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl Condition {
+    fn is_met(&self, values: &BTreeMap<String, Value>) -> bool {
+        values.get(&self.name).is_some_and(|value| match value {
+            Value::String(s) => *s == self.value,
+            other => serde_json::from_str::<Value>(&self.value).is_ok_and(|value| value == *other),
+        })
+    }
+}
+
+/// Options control how often we check for changes, and when we stop.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// How long to wait between checks of the update status, or before reconnecting after losing
+    /// the settings stream.
+    pub interval: Duration,
+    /// Stop once all of these conditions are met.  If empty, watch until interrupted.
+    pub until: Vec<Condition>,
+    /// Fail if the conditions aren't met within this long.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            until: Vec::new(),
+            timeout: None,
+        }
+    }
+}
+
+/// Watches the target, calling `on_change` with any changes.  The first call includes all current
+/// values, as additions.  Returns once all conditions in the options are met, which can be right
+/// away.
+pub async fn watch<P, F>(
+    socket_path: P,
+    target: &Target,
+    options: &Options,
+    on_change: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&SettingsDiff),
+{
+    let mut watcher = Watcher {
+        options,
+        start: Instant::now(),
+        values: BTreeMap::new(),
+        on_change,
+    };
+    match target {
+        Target::Prefixes(prefixes) => {
+            watch_settings(socket_path.as_ref(), prefixes, &mut watcher).await
+        }
+        Target::UpdateStatus => poll_update_status(socket_path.as_ref(), &mut watcher).await,
+    }
+}
+
+/// Watcher holds the last known values of the target, and reports changes to them.
+struct Watcher<'a, F> {
+    options: &'a Options,
+    start: Instant,
+    values: BTreeMap<String, Value>,
+    on_change: F,
+}
+
+impl<F> Watcher<'_, F>
+where
+    F: FnMut(&SettingsDiff),
+{
+    /// Replaces the known values with the given ones, calling `on_change` with any changes.
+    /// Returns whether all conditions are met.
+    fn update(&mut self, current: BTreeMap<String, Value>) -> bool {
+        let changes = diff::changes(std::mem::take(&mut self.values), current.clone());
+        self.values = current;
+        if !changes.is_empty() {
+            (self.on_change)(&changes);
+        }
+        !self.options.until.is_empty()
+            && self
+                .options
+                .until
+                .iter()
+                .all(|condition| condition.is_met(&self.values))
+    }
+
+    /// Returns when we have to stop waiting for the conditions, if there's a timeout.
+    fn deadline(&self) -> Option<Instant> {
+        self.options.timeout.map(|timeout| self.start + timeout)
+    }
+
+    /// Fails if the timeout has passed.
+    fn check_timeout(&self) -> Result<()> {
+        if let Some(deadline) = self.deadline() {
+            ensure!(Instant::now() < deadline, self.timed_out());
+        }
+        Ok(())
+    }
+
+    fn timed_out(&self) -> error::TimedOutSnafu<String> {
+        error::TimedOutSnafu {
+            waited: format!("{:?}", self.options.timeout.unwrap_or_default()),
+        }
+    }
+}
+
+/// Follows changes to settings under the given prefixes through the settings stream, reconnecting
+/// if it's lost.
+async fn watch_settings<F>(
+    socket_path: &Path,
+    prefixes: &[String],
+    watcher: &mut Watcher<'_, F>,
+) -> Result<()>
+where
+    F: FnMut(&SettingsDiff),
+{
+    let prefix_keys = prefixes
+        .iter()
+        .map(|prefix| {
+            let key = Key::new(KeyType::Data, prefix.trim_end_matches('.'))
+                .context(error::InvalidPrefixSnafu { prefix })?;
+            ensure!(
+                key.segments()[0] == "settings",
+                error::NotSettingsSnafu { prefix }
+            );
+            Ok(key)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut failures = 0;
+    loop {
+        match follow_settings(socket_path, prefixes, &prefix_keys, watcher, &mut failures).await {
+            Err(e @ error::Error::TimedOut { .. }) => return Err(e),
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    return Err(e);
+                }
+                warn!("Lost settings stream, failure #{}: {}", failures, e);
+            }
+            Ok(()) => return Ok(()),
+        }
+
+        watcher.check_timeout()?;
+        time::sleep(watcher.options.interval).await;
+    }
+}
+
+/// Connects to the settings stream, reports the current settings, and then reports changes as
+/// they're committed, until the conditions are met.  `prefix_keys` are the `prefixes` as keys, for
+/// matching settings by whole segments.  Resets `failures` once connected.
+async fn follow_settings<F>(
+    socket_path: &Path,
+    prefixes: &[String],
+    prefix_keys: &[Key],
+    watcher: &mut Watcher<'_, F>,
+    failures: &mut u32,
+) -> Result<()>
+where
+    F: FnMut(&SettingsDiff),
+{
+    // The server can filter by one prefix; with more, we get all settings and filter them here.
+    let uri = match prefixes {
+        [prefix] => format!(
+            "/settings/watch?prefix={}",
+            url::form_urlencoded::byte_serialize(prefix.as_bytes()).collect::<String>()
+        ),
+        _ => "/settings/watch".to_string(),
+    };
+    let ws_stream = websocket_connect(socket_path, &uri)
+        .await
+        .context(error::ConnectSnafu)?;
+    let (mut write, mut read) = ws_stream.split();
+
+    // Fetch the current settings after connecting, so we don't miss a commit in between.
+    let current = get::get_prefixes(socket_path, prefixes.to_vec())
+        .await
+        .context(error::GetSnafu)?;
+    let mut values = diff::flatten_value(&current).context(error::FlattenSnafu)?;
+    // Fetching by prefix matches names as strings, so "settings.motd" also gets "settings.motdx".
+    values.retain(|name, _| under_prefixes(name, prefix_keys));
+    *failures = 0;
+
+    let mut met = watcher.update(values.clone());
+    let mut heartbeat = time::interval(HEARTBEAT_INTERVAL);
+    let deadline = watcher.deadline();
+    while !met {
+        tokio::select! {
+            msg = read.next() => {
+                let msg = msg
+                    .context(error::StreamClosedSnafu)?
+                    .context(error::ReadWebSocketSnafu)?;
+                match msg {
+                    Message::Text(text) => {
+                        let change: SettingsChange = serde_json::from_str(&text)
+                            .context(error::ResponseJsonSnafu { body: text.clone() })?;
+                        apply_change(&mut values, change, prefix_keys);
+                        met = watcher.update(values.clone());
+                    }
+                    Message::Close(_) => return error::StreamClosedSnafu.fail(),
+                    // tungstenite answers pings for us.
+                    _ => {}
+                }
+            }
+            _ = heartbeat.tick() => {
+                write
+                    .send(Message::Ping(Vec::new()))
+                    .await
+                    .context(error::WriteWebSocketSnafu)?;
+            }
+            _ = sleep_until(deadline) => return watcher.timed_out().fail(),
+        }
+    }
+
+    if let Err(e) = write.close().await {
+        debug!("Failed to close settings stream: {}", e);
+    }
+    Ok(())
+}
+
+/// Updates the given values with a change from the settings stream, ignoring settings that aren't
+/// under the given prefixes.
+fn apply_change(values: &mut BTreeMap<String, Value>, change: SettingsChange, prefixes: &[Key]) {
+    trace!("Settings changed by transaction '{}'", change.transaction);
+    for (name, value) in change.settings {
+        if !under_prefixes(&name, prefixes) {
+            continue;
+        }
+        match value {
+            Some(value) => values.insert(name, value),
+            None => values.remove(&name),
+        };
+    }
+}
+
+/// Returns whether the setting with the given name is under one of the given prefixes, matching
+/// whole segments.
+fn under_prefixes(name: &str, prefixes: &[Key]) -> bool {
+    Key::new(KeyType::Data, name).is_ok_and(|key| {
+        prefixes
+            .iter()
+            .any(|prefix| key.starts_with_segments(prefix.segments()))
+    })
+}
+
+/// Waits until the given time, or forever if there's none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Checks the update status every interval, reporting changes.
+async fn poll_update_status<F>(socket_path: &Path, watcher: &mut Watcher<'_, F>) -> Result<()>
+where
+    F: FnMut(&SettingsDiff),
+{
+    let mut failures = 0;
+    loop {
+        match fetch_update_status(socket_path).await {
+            Ok(Some(current)) => {
+                failures = 0;
+                if watcher.update(current) {
+                    return Ok(());
+                }
+            }
+            Ok(None) => trace!("Status is locked, checking again later"),
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    return Err(e);
+                }
+                warn!("Unable to check for changes, failure #{}: {}", failures, e);
+            }
+        }
+
+        watcher.check_timeout()?;
+        time::sleep(watcher.options.interval).await;
+    }
+}
+
+/// Returns the current update status, or None if it can't be known right now because an update
+/// command holds the lock.  While a command holds the lock, the API includes the status as the
+/// command last wrote it, if it can, so we can follow progress like downloads.
+async fn fetch_update_status(socket_path: &Path) -> Result<Option<BTreeMap<String, Value>>> {
+    let uri = "/updates/status";
+    let (code, body) = raw_request_unchecked(socket_path, uri, "GET", None)
+        .await
+        .context(error::RequestSnafu { uri })?;
+    let value = match code {
+        // There's no status until the first update command is run.
+        StatusCode::NOT_FOUND => Value::Null,
+        StatusCode::LOCKED => match serde_json::from_str(&body) {
+            Ok(value) => value,
+            Err(_) => return Ok(None),
+        },
+        code if code.is_success() => {
+            serde_json::from_str(&body).context(error::ResponseJsonSnafu { body })?
+        }
+        code => return error::ResponseStatusSnafu { uri, code, body }.fail(),
+    };
+    diff::flatten_value(&value)
+        .map(Some)
+        .context(error::FlattenSnafu)
+}
+
+/// Formats changes as 'apiclient diff' does, starting each line with the current time.
+pub fn format_changes(changes: &SettingsDiff, color: bool) -> String {
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    diff::format_text(changes, color)
+        .lines()
+        .map(|line| format!("{} {}\n", now, line))
+        .collect()
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display("Invalid condition '{}', expected NAME=VALUE", condition))]
+        Condition { condition: String },
+
+        // This is from the exec module, which includes enough context.
+        #[snafu(display("{}", source))]
+        Connect {
+            #[snafu(source(from(crate::exec::connect::Error, Box::new)))]
+            source: Box<crate::exec::connect::Error>,
+        },
+
+        #[snafu(display("{}", source))]
+        Flatten { source: crate::diff::Error },
+
+        #[snafu(display("{}", source))]
+        Get { source: crate::get::Error },
+
+        #[snafu(display("Invalid prefix '{}': {}", prefix, source))]
+        InvalidPrefix {
+            prefix: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Only settings can be watched, not '{}'", prefix))]
+        NotSettings { prefix: String },
+
+        #[snafu(display("Failed to read settings stream: {}", source))]
+        ReadWebSocket {
+            #[snafu(source(from(tokio_tungstenite::tungstenite::Error, Box::new)))]
+            source: Box<tokio_tungstenite::tungstenite::Error>,
+        },
+
+        #[snafu(display("Failed GET request to '{}': {}", uri, source))]
+        Request {
+            uri: String,
+            #[snafu(source(from(crate::Error, Box::new)))]
+            source: Box<crate::Error>,
+        },
+
+        #[snafu(display("Response contained invalid JSON '{}' - {}", body, source))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("GET request to '{}' returned {}: {}", uri, code, body))]
+        ResponseStatus {
+            uri: String,
+            code: http::StatusCode,
+            body: String,
+        },
+
+        #[snafu(display("Settings stream closed by server"))]
+        StreamClosed,
+
+        #[snafu(display("Timed out after {} waiting for conditions to be met", waited))]
+        TimedOut { waited: String },
+
+        #[snafu(display("Failed to write to settings stream: {}", source))]
+        WriteWebSocket {
+            #[snafu(source(from(tokio_tungstenite::tungstenite::Error, Box::new)))]
+            source: Box<tokio_tungstenite::tungstenite::Error>,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn conditions() {
+        let values = BTreeMap::from([
+            ("update_state".to_string(), json!("Ready")),
+            ("most_recent_command.exit_status".to_string(), json!(0)),
+        ]);
+        let met = |condition: &str| condition.parse::<Condition>().unwrap().is_met(&values);
+        assert!(met("update_state=Ready"));
+        assert!(!met("update_state=Idle"));
+        assert!(met("most_recent_command.exit_status=0"));
+        assert!(!met("chosen_update.version=1.0.0"));
+        assert!("update_state".parse::<Condition>().is_err());
+        assert!("=Ready".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn changes_applied() {
+        let mut values = BTreeMap::from([
+            ("settings.motd".to_string(), json!("hi")),
+            ("settings.ntp.time-servers".to_string(), json!(["a"])),
+        ]);
+        let change = SettingsChange {
+            transaction: "tx".to_string(),
+            settings: HashMap::from([
+                ("settings.motd".to_string(), None),
+                ("settings.ntp.time-servers".to_string(), Some(json!(["b"]))),
+                ("settings.kubernetes.max-pods".to_string(), Some(json!(10))),
+                ("settings.motdx".to_string(), Some(json!("x"))),
+            ]),
+        };
+        let prefixes = [
+            Key::new(KeyType::Data, "settings.motd").unwrap(),
+            Key::new(KeyType::Data, "settings.ntp").unwrap(),
+        ];
+        apply_change(&mut values, change, &prefixes);
+        assert_eq!(
+            values,
+            BTreeMap::from([("settings.ntp.time-servers".to_string(), json!(["b"]))])
+        );
+    }
+}
@@ -19,6 +19,8 @@ const DEFAULT_BIND_PATH: &str = "/run/api.sock";
 /// By default, when the user requests that we run a process via /exec, we run the process through
 /// this containerd socket.
 const DEFAULT_EXEC_SOCKET: &str = "/run/host-containerd/containerd.sock";
+/// By default, this is where we keep the audit log of settings changes.
+const DEFAULT_AUDIT_LOG: &str = "/var/lib/bottlerocket/api-audit.log";
 
 type Result<T> = std::result::Result<T, error::Error>;
 
@@ -46,6 +48,8 @@ struct Args {
     socket_gid: Option<Gid>,
     socket_path: String,
     exec_socket_path: String,
+    audit_log_path: String,
+    policy_path: Option<String>,
 }
 
 /// Informs the user about proper usage of the program and exits.
@@ -57,12 +61,16 @@ fn usage() -> ! {
             [ --socket-path PATH ]
             [ --socket-gid GROUP_ID ]
             [ --exec-socket-path PATH ]
+            [ --audit-log-path PATH ]
+            [ --policy-path PATH ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]
 
     --socket-path defaults to {}
-    --exec-socket-path (for apiclient exec) defaults to {}",
-        program_name, DEFAULT_BIND_PATH, DEFAULT_EXEC_SOCKET
+    --exec-socket-path (for apiclient exec) defaults to {}
+    --audit-log-path defaults to {}
+    --policy-path gives a policy limiting what each client may do; by default, all are allowed",
+        program_name, DEFAULT_BIND_PATH, DEFAULT_EXEC_SOCKET, DEFAULT_AUDIT_LOG
     );
     process::exit(2);
 }
@@ -80,6 +88,8 @@ fn parse_args(args: env::Args) -> Args {
     let mut socket_gid = None;
     let mut socket_path = None;
     let mut exec_socket_path = None;
+    let mut audit_log_path = None;
+    let mut policy_path = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -127,6 +137,20 @@ fn parse_args(args: env::Args) -> Args {
                     }))
             }
 
+            "--audit-log-path" => {
+                audit_log_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --audit-log-path")),
+                )
+            }
+
+            "--policy-path" => {
+                policy_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --policy-path")),
+                )
+            }
+
             _ => usage(),
         }
     }
@@ -137,6 +161,8 @@ fn parse_args(args: env::Args) -> Args {
         log_level: log_level.unwrap_or(LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_BIND_PATH.to_string()),
         exec_socket_path: exec_socket_path.unwrap_or_else(|| DEFAULT_EXEC_SOCKET.to_string()),
+        audit_log_path: audit_log_path.unwrap_or_else(|| DEFAULT_AUDIT_LOG.to_string()),
+        policy_path,
     }
 }
 
@@ -174,6 +200,8 @@ async fn run() -> Result<()> {
         threads,
         args.socket_gid,
         args.exec_socket_path,
+        args.audit_log_path,
+        args.policy_path,
     )
     .await
     .context(error::ServerSnafu)
@@ -8,6 +8,12 @@ The server listens to HTTP requests on a Unix-domain socket.
 There is no built-in authentication - local access to the socket should be limited to processes and containers that should be able to configure the system.
 Remote access should only be allowed through an authenticated control channel such as SSH or SSM.
 
+Clients that can reach the socket have full control by default.
+To limit them, give apiserver a policy file with `--policy-path`.
+The policy's rules match clients by the user ID, group ID, or SELinux label the kernel reports for their connection, and limit each to certain routes and methods, like read-only access to `/settings`, and to changing certain settings.
+Requests the policy doesn't allow fail with 403 Forbidden and a message saying which rule refused them.
+See the `server::policy` module for the format.
+
 # Design
 
 ## API
@@ -19,16 +25,49 @@ The Settings APIs are particularly important.
 You can GET settings from the `/settings` endpoint.
 You can also PATCH changes to the `/settings` endpoint.
 Settings are stored as a pending transaction until a commit API is called.
-Pending settings can be retrieved from `/tx` to see what will change.
+To remove settings, send a `DELETE` to `/settings?keys=...`; the removal is also pending until commit.
+To edit list and map settings element-wise, `PATCH` an [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON Patch with content type `application/json-patch+json`, or an [RFC 7396](https://www.rfc-editor.org/rfc/rfc7396) merge patch, in which `null` removes a setting, with content type `application/merge-patch+json`.
+Patch paths are relative to the settings, like `/motd`, and patches apply to the settings as they'd be after the transaction is committed.
+A failed `test` operation fails the request with 409 Conflict, and nothing is changed unless the patched settings match the data model.
+Pending settings can be retrieved from `/tx` to see what will change, and `/tx/settings` shows the whole of the settings as they'd be after commit.
+`/tx/patch` shows the transaction as a JSON merge patch against live settings, so settings it will remove are shown as `null`.
 
 Upon making a `/tx/commit` POST call, the pending transaction is made live.
 Upon making an `/tx/apply` POST call, an external settings applier tool is called to apply the changes to the system and restart services as necessary.
 There's also `/tx/commit_and_apply` to do both, which is the most common case.
+To see what committing and applying would do without changing anything, `POST` to `/tx/plan`.
+It returns the changed keys, the services that would be restarted, and a diff of each of their configuration files rendered against the pending settings.
 
 If you don't specify a transaction, the "default" transaction is used, so you usually don't have to think about it.
 If you want to group changes into transactions yourself, you can add a `tx` parameter to the APIs mentioned above.
 For example, if you want the name "FOO", you can `PATCH` to `/settings?tx=FOO` and `POST` to `/tx/commit_and_apply?tx=FOO`.
 
+Each commit is recorded in a bounded history, which you can see with a `GET` to `/tx/history`.
+To undo a commit, `POST` to `/tx/rollback?id=N` with the commit's ID; the old values are made live and applied, and the rollback is recorded as a new commit.
+A rollback is refused if any of the commit's keys have been changed by a later commit, so roll back later commits first.
+
+Each commit made through the API, including rollbacks, is also added to an audit log, which you can see with a `GET` to `/audit`, or `/audit?limit=N` for the most recent entries.
+Entries hold the time, the transaction, the user, group, process ID, command name, and SELinux label of the client that made the commit, and the old and new value of each changed key.
+Values of settings that can hold secrets, like credentials and user data, are redacted.
+The log is kept at `/var/lib/bottlerocket/api-audit.log` by default, and is rotated once it reaches 1 MiB, keeping one older file.
+
+To save the settings of a host, `GET` a snapshot from `/snapshot`; it includes the live settings, their metadata, and the OS version.
+Settings made by a setting generator, like the hostname, are specific to the host, so they're only included if you pass `include-generated=true`.
+To restore it, later or on another host, `POST` it to `/snapshot`.
+The settings are checked against the data model and staged in a transaction, which you can commit as usual.
+Live settings that aren't in the snapshot are staged for removal, so the settings match the snapshot once committed; generated settings are kept if the snapshot doesn't have them.
+Only settings can be restored, since other data and metadata come from the OS image; the snapshot's metadata isn't restored, so the host keeps its own.
+
+To avoid overwriting changes made by another client, you can use the `ETag` header returned by `GET /settings`, which holds the revision of the live settings.
+Pass it in an `If-Match` header to `PATCH /settings`, `PATCH /settings/keypair`, `/tx/commit`, or `/tx/commit_and_apply`, and the request fails with 409 Conflict if the live settings have changed since.
+
+To react to changes without polling, open a WebSocket to `/settings/watch`, optionally with a `prefix` parameter like `/settings/watch?prefix=kubernetes`.
+After each commit (or rollback) that changes settings under the prefix, the server sends a JSON message with the transaction name and the new value of each changed key, or null for keys that are no longer set.
+
+To run a command in a host container and get its output without a WebSocket, `POST` a JSON object like `{"target": "admin", "command": ["ls", "/"]}` to `/exec/run`, optionally with `env`, a map of variables to add to the command's environment, and `timeout`, in seconds, which defaults to 60.
+The response holds the exit status and the stdout and stderr of the command, each cut off after 1 MiB, with flags saying whether they were cut off and whether the command was stopped for running past its timeout.
+The command gets no input and no TTY; use the `/exec` WebSocket for interactive use.
+
 Requests are directed by `server::router`.
 `server::controller` maps requests into our data model.
 
@@ -42,22 +81,28 @@ The data model describes system settings, services using those settings, and con
 It also has a more general structure for metadata.
 Metadata entries can be stored for any data field in the model.
 
+The settings model is loaded from a plugin at runtime, so its types can't be inspected directly; `GET /settings/schema` returns the JSON schema of settings from [openapi.yaml](../openapi.yaml), with the type of each setting it describes.
+That schema is kept by hand, so it's partial, and marked with `x-partial`; sections without properties, like `settings.kubernetes`, are open, and clients should look at the live settings under them.
+
 ## Data store
 
 Data from the model is stored in a key/value data store.
 Keys are dotted strings like "settings.service.abc".
 This naturally implies some grouping and hierarchy of the data, corresponding to the model.
 
-The current data store implementation maps keys to filesystem paths and stores the value in a file.
+By default, the data store maps keys to filesystem paths and stores the value in a file.
 Metadata about a data key is stored in a file at the data key path + "." + the metadata key.
 The default data store location is `/var/lib/bottlerocket/datastore/current`, and the filesystem format makes it fairly easy to inspect.
 
+If apiserver is built with the `single-file-datastore` feature, the data store is instead kept in a single file, `datastore.json`, in the same directory.
+Commits to the single file are atomic, so a commit changes all of its keys or none of them.
+A copy of live data and metadata is still kept in the filesystem format, so builds without the feature can read it after a rollback or downgrade.
+
 For more detail, see [datastore](../datastore).
 
 # Current limitations
 
 * Data store locking is coarse; read requests can happen in parallel, but a write request will block everything else.
-* There's no support for rolling back commits.
 * There are no metrics.
 
 # Example usage
@@ -0,0 +1,319 @@
+//! The 'audit' module keeps a log of the commits made through the API: when each happened, which
+//! transaction was committed, which local process asked for it, and how each changed setting's
+//! value changed.  Values of sensitive settings are redacted before they're written.
+//!
+//! The log is a file of JSON lines.  When it grows past MAX_AUDIT_LOG_SIZE, it's moved aside to
+//! a file with a ".1" suffix, replacing any older one, so at most two files' worth is kept.
+
+use super::error::{self, Result};
+use super::peer::PeerCredentials;
+use chrono::{DateTime, Utc};
+use datastore::{deserialize_scalar, Commit, ScalarError};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{OptionExt, ResultExt};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The size at which the audit log is moved aside and a new one started.
+const MAX_AUDIT_LOG_SIZE: u64 = 1024 * 1024;
+
+/// Settings whose values may hold secrets, given as key names; keys under these are also redacted.
+/// This matches what logdog leaves out of its settings dump.
+const SENSITIVE_SETTINGS: &[&str] = &[
+    "settings.kubernetes.bootstrap-token",
+    // Can contain a username:password component
+    "settings.network.https-proxy",
+    "settings.kubernetes.server-key",
+    "settings.container-registry.credentials",
+    // Can be stored in settings.aws.credentials, but user can also add creds here
+    "settings.aws.config",
+    "settings.aws.credentials",
+];
+
+/// The value written in place of redacted values.
+const REDACTED: &str = "REDACTED";
+
+/// AuditEntry records one commit to live settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    /// When the commit was made.
+    pub(crate) timestamp: DateTime<Utc>,
+    /// The ID of the commit in the data store's history.
+    pub(crate) commit: u64,
+    /// The name of the transaction that was committed.
+    pub(crate) transaction: String,
+    /// The client that requested the commit, if known.
+    pub(crate) peer: Option<PeerCredentials>,
+    /// Maps the name of each changed key to its values before and after the commit.
+    pub(crate) changes: BTreeMap<String, AuditChange>,
+}
+
+/// AuditChange holds the value of a key before and after a commit; None means it wasn't set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AuditChange {
+    pub(crate) old: Option<Value>,
+    pub(crate) new: Option<Value>,
+}
+
+impl AuditEntry {
+    /// Builds an entry from the data store's record of a commit, redacting sensitive values.
+    pub(crate) fn new(commit: &Commit, peer: Option<PeerCredentials>) -> Result<Self> {
+        let mut changes = BTreeMap::new();
+        for (name, change) in &commit.changes {
+            let change = AuditChange {
+                old: audit_value(name, change.old.as_deref())?,
+                new: audit_value(name, change.new.as_deref())?,
+            };
+            changes.insert(name.clone(), change);
+        }
+        Ok(Self {
+            timestamp: commit.timestamp,
+            commit: commit.id,
+            transaction: commit.transaction.clone(),
+            peer,
+            changes,
+        })
+    }
+}
+
+/// Deserializes a value from the data store for the audit log, redacting it if the key is
+/// sensitive.
+fn audit_value(name: &str, value: Option<&str>) -> Result<Option<Value>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    if is_sensitive(name) {
+        return Ok(Some(Value::String(REDACTED.to_string())));
+    }
+    let mut value = deserialize_scalar::<Value, ScalarError>(value)
+        .context(error::InvalidValueSnafu { key: name })?;
+    strip_user_data(&mut value);
+    Ok(Some(value))
+}
+
+/// Returns whether the key with the given name is, or is under, a sensitive setting, or is
+/// user data, which can hold anything.
+fn is_sensitive(name: &str) -> bool {
+    SENSITIVE_SETTINGS.iter().any(|sensitive| {
+        name == *sensitive
+            || name
+                .strip_prefix(sensitive)
+                .is_some_and(|rest| rest.starts_with('.'))
+    }) || name.split('.').any(|segment| segment == "user-data")
+}
+
+/// Redacts the value of any "user-data" field found in the given value.
+fn strip_user_data(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if key == "user-data" {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    strip_user_data(value)
+                }
+            }
+        }
+        Value::Array(array) => {
+            for child in array {
+                strip_user_data(child)
+            }
+        }
+        _ => {}
+    }
+}
+
+/// AuditLog is the on-disk log of audit entries.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    /// Held while writing or reading, so a reader doesn't see the log mid-rotation.
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub(crate) fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The path the log is moved to when it's full.
+    fn rotated_path(&self) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(".1");
+        path.into()
+    }
+
+    /// Appends an entry to the log, first moving the log aside if it's full.
+    pub(crate) fn record(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry).context(error::AuditSerializeSnafu)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().ok().context(error::AuditLockSnafu)?;
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() >= MAX_AUDIT_LOG_SIZE => {
+                debug!("Rotating audit log at {}", self.path.display());
+                fs::rename(&self.path, self.rotated_path())
+                    .context(error::AuditWriteSnafu { path: &self.path })?;
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(error::AuditWriteSnafu { path: &self.path }),
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(error::AuditWriteSnafu { path: &self.path })?;
+        file.write_all(line.as_bytes())
+            .context(error::AuditWriteSnafu { path: &self.path })
+    }
+
+    /// Returns the entries in the log, oldest first.  If `limit` is given, only that many of the
+    /// most recent entries are returned.
+    pub(crate) fn entries(&self, limit: Option<usize>) -> Result<Vec<AuditEntry>> {
+        let _guard = self.lock.lock().ok().context(error::AuditLockSnafu)?;
+        let mut entries = read_entries(&self.rotated_path())?;
+        entries.extend(read_entries(&self.path)?);
+        if let Some(limit) = limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+}
+
+/// Reads the entries from one audit log file; a missing file has no entries.  Lines that can't be
+/// parsed, like one cut short by a crash, are skipped.
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(error::AuditReadSnafu { path }),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context(error::AuditReadSnafu { path })?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping invalid line in {}: {}", path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use datastore::KeyChange;
+    use maplit::hashmap;
+    use serde_json::json;
+
+    fn change(old: Option<&str>, new: Option<&str>) -> KeyChange {
+        KeyChange {
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn redaction() {
+        let commit = Commit::new(
+            3,
+            "tx",
+            hashmap!(
+                "settings.motd".to_string() => change(Some("\"hi\""), Some("\"bye\"")),
+                "settings.aws.credentials".to_string() => change(None, Some("\"secret\"")),
+                "settings.kubernetes.bootstrap-token".to_string() => change(Some("\"a\""), None),
+                "settings.host-containers.admin.user-data".to_string() =>
+                    change(None, Some("\"c2VjcmV0\"")),
+                "settings.aws.config-file".to_string() => change(None, Some("\"x\"")),
+            ),
+        );
+        let entry = AuditEntry::new(&commit, None).unwrap();
+        assert_eq!(entry.commit, 3);
+        assert_eq!(entry.transaction, "tx");
+        assert_eq!(
+            entry.changes["settings.motd"],
+            AuditChange {
+                old: Some(json!("hi")),
+                new: Some(json!("bye")),
+            }
+        );
+        assert_eq!(
+            entry.changes["settings.aws.credentials"].new,
+            Some(json!(REDACTED))
+        );
+        assert_eq!(
+            entry.changes["settings.kubernetes.bootstrap-token"],
+            AuditChange {
+                old: Some(json!(REDACTED)),
+                new: None,
+            }
+        );
+        assert_eq!(
+            entry.changes["settings.host-containers.admin.user-data"].new,
+            Some(json!(REDACTED))
+        );
+        // Only whole segments match.
+        assert_eq!(
+            entry.changes["settings.aws.config-file"].new,
+            Some(json!("x"))
+        );
+    }
+
+    #[test]
+    fn nested_user_data() {
+        let value = r#"[{"name": "a", "user-data": "c2VjcmV0"}]"#;
+        assert_eq!(
+            audit_value("settings.bootstrap-commands.x", Some(value)).unwrap(),
+            Some(json!([{"name": "a", "user-data": REDACTED}]))
+        );
+    }
+
+    #[test]
+    fn log_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.log"));
+        assert!(log.entries(None).unwrap().is_empty());
+
+        // Make entries big enough that a few fill the log.
+        let big = format!("\"{}\"", "x".repeat(MAX_AUDIT_LOG_SIZE as usize / 3));
+        for id in 1..=7 {
+            let commit = Commit::new(
+                id,
+                "tx",
+                hashmap!("settings.motd".to_string() => change(None, Some(&big))),
+            );
+            log.record(&AuditEntry::new(&commit, None).unwrap())
+                .unwrap();
+        }
+
+        // The log was rotated twice, so the oldest entries are gone.
+        let ids: Vec<u64> = log
+            .entries(None)
+            .unwrap()
+            .iter()
+            .map(|e| e.commit)
+            .collect();
+        assert_eq!(ids, vec![4, 5, 6, 7]);
+
+        let ids: Vec<u64> = log
+            .entries(Some(2))
+            .unwrap()
+            .iter()
+            .map(|e| e.commit)
+            .collect();
+        assert_eq!(ids, vec![6, 7]);
+    }
+}
@@ -12,11 +12,22 @@ use crate::server::error::{self, Result};
 use actix_web::HttpResponse;
 use datastore::deserialization::{from_map, from_map_with_prefix};
 use datastore::serialization::to_pairs_with_prefix;
-use datastore::{deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value};
+use datastore::snapshot::{self, Snapshot};
+use datastore::{
+    deserialize_scalar, Commit, Committed, DataStore, Key, KeyType, ScalarError, Value,
+    PENDING_REMOVAL,
+};
+use model::plan::{ConfigurationFilePlan, RenderRequest};
+use model::watch::SettingsChange;
 use model::{ConfigurationFiles, Services, Settings};
 use num::FromPrimitive;
 use std::os::unix::process::ExitStatusExt;
 use thar_be_updates::error::TbuErrorStatus;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command as AsyncCommand;
+
+/// The API's OpenAPI description, whose Settings schema describes the settings model.
+const OPENAPI: &str = include_str!("../../../openapi.yaml");
 
 /// List the open transactions from the data store.
 pub(crate) fn list_transactions<D>(datastore: &D) -> Result<HashSet<String>>
@@ -44,6 +55,105 @@ where
         .map(|maybe_settings| maybe_settings.unwrap_or_default())
 }
 
+/// Build a JSON merge patch of the pending changes in the given transaction, as it would be
+/// applied to live settings: settings it sets have their pending values, and settings it removes
+/// are null.  The patch will be empty if there are no pending changes.
+pub(crate) fn get_transaction_patch<D, S>(datastore: &D, transaction: S) -> Result<Value>
+where
+    D: DataStore,
+    S: Into<String>,
+{
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let settings: Settings =
+        get_prefix(datastore, &pending, "settings.", None)?.unwrap_or_default();
+    let mut patch = serde_json::to_value(settings).context(error::SettingsToJsonSnafu)?;
+
+    let removals = datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStoreSnafu { op: "get_prefix" })?
+        .into_iter()
+        .filter(|(_, value)| value == PENDING_REMOVAL);
+    for (key, _) in removals {
+        // Like Settings, the patch starts below the "settings" segment.
+        mark_removed(&mut patch, &key.segments()[1..]);
+    }
+    Ok(patch)
+}
+
+/// Sets the value at the given path in a merge patch to null, meaning it's removed.
+fn mark_removed(patch: &mut Value, path: &[String]) {
+    let Some((name, parents)) = path.split_last() else {
+        return;
+    };
+    let mut node = patch;
+    for parent in parents {
+        let Value::Object(map) = node else {
+            return;
+        };
+        node = map
+            .entry(parent.clone())
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+    if let Value::Object(map) = node {
+        map.insert(name.clone(), Value::Null);
+    }
+}
+
+/// Lists the keys changed or removed by the given pending transaction.
+pub(crate) fn get_transaction_keys<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .list_populated_keys("settings.", &pending)
+        .context(error::DataStoreSnafu {
+            op: "list_populated_keys",
+        })
+}
+
+/// Build a Settings from live data with the given transaction's pending changes applied, as it
+/// would be after the transaction is committed.
+pub(crate) fn get_pending_settings<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<Settings> {
+    let data = get_pending_data(datastore, transaction)?;
+    from_map_with_prefix(None, &data).context(error::DeserializationSnafu {
+        given: "pending settings",
+    })
+}
+
+/// Returns the settings data keys and serialized values from live data with the given
+/// transaction's pending changes applied.
+fn get_pending_data<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashMap<Key, String>> {
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStoreSnafu { op: "get_prefix" })?;
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let changes = datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStoreSnafu { op: "get_prefix" })?;
+
+    for (key, value) in changes {
+        if value == PENDING_REMOVAL {
+            data.remove(&key);
+        } else {
+            data.insert(key, value);
+        }
+    }
+    Ok(data)
+}
+
 /// Deletes the transaction from the data store, removing any uncommitted settings under that
 /// transaction name.
 pub(crate) fn delete_transaction<D: DataStore>(
@@ -254,11 +364,13 @@ where
 {
     let find_prefix = find_prefix.as_ref();
 
-    let data = datastore
+    let mut data = datastore
         .get_prefix(find_prefix, committed)
         .with_context(|_| error::DataStoreSnafu {
             op: format!("get_prefix '{}' for {:?}", find_prefix, committed),
         })?;
+    // Keys pending removal have no value to show.
+    data.retain(|_, value| value != PENDING_REMOVAL);
     if data.is_empty() {
         return Ok(None);
     }
@@ -306,6 +418,39 @@ pub(crate) fn get_services_names<D: DataStore>(
     get_map_from_prefix(datastore, "services.".to_string(), names, committed)
 }
 
+/// Build a collection of the Service items affected by changes to the given keys, according to
+/// their 'affected-services' metadata.
+pub(crate) fn get_affected_services<D: DataStore>(
+    datastore: &D,
+    keys: &HashSet<Key>,
+) -> Result<Services> {
+    let key_names = keys.iter().map(|key| key.name().as_str()).collect();
+    let affected = get_metadata_for_data_keys(datastore, "affected-services", &key_names)?;
+
+    // Each key's metadata is a list of service names.
+    let names = affected
+        .values()
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    get_services_names(datastore, &names, &Committed::Live)
+}
+
+/// Build a collection of the ConfigurationFile items used by the given services, using data from
+/// the datastore.
+pub(crate) fn get_services_configuration_files<D: DataStore>(
+    datastore: &D,
+    services: &Services,
+) -> Result<ConfigurationFiles> {
+    let names = services
+        .values()
+        .flat_map(|service| &service.configuration_files)
+        .map(|name| name.as_ref())
+        .collect();
+    get_configuration_files_names(datastore, &names, &Committed::Live)
+}
+
 /// Build a collection of ConfigurationFile items with the given names using data from the
 /// datastore.
 pub(crate) fn get_configuration_files_names<D: DataStore>(
@@ -377,6 +522,267 @@ pub(crate) fn set_settings<D: DataStore>(
         .context(error::DataStoreSnafu { op: "set_keys" })
 }
 
+/// Returns the JSON schema of settings from the API's OpenAPI description, giving the type of each
+/// setting it describes.  The schema is kept by hand and only partly describes the model, so it's
+/// marked with "x-partial"; sections without properties, like "kubernetes", are left open.
+pub(crate) fn get_settings_schema() -> Result<serde_json::Value> {
+    let openapi: serde_json::Value =
+        serde_yaml::from_str(OPENAPI).context(error::OpenApiParseSnafu)?;
+    openapi
+        .pointer("/components/schemas/Settings")
+        .cloned()
+        .context(error::MissingSettingsSchemaSnafu)
+}
+
+/// Returns the data keys that setting the given Settings would write.
+pub(crate) fn settings_keys(settings: &Settings) -> Result<HashSet<Key>> {
+    let settings_json = serde_json::to_value(settings).context(error::SettingsToJsonSnafu)?;
+    let pairs = to_pairs_with_prefix("settings", &settings_json)
+        .context(error::DataStoreSerializationSnafu { given: "Settings" })?;
+    Ok(pairs.into_keys().collect())
+}
+
+/// Returns the data keys that restoring the given snapshot would write or remove.
+pub(crate) fn snapshot_keys<D: DataStore>(
+    datastore: &D,
+    snapshot: &Snapshot,
+) -> Result<HashSet<Key>> {
+    let mut keys = snapshot
+        .data
+        .keys()
+        .map(|name| {
+            Key::new(KeyType::Data, name).context(error::NewKeySnafu {
+                key_type: "data",
+                name,
+            })
+        })
+        .collect::<Result<HashSet<_>>>()?;
+    keys.extend(
+        snapshot::removals(datastore, snapshot, "settings.")
+            .context(error::DataStoreSnafu { op: "removals" })?,
+    );
+    Ok(keys)
+}
+
+/// Marks the given settings, and any settings under them, for removal in the given pending
+/// transaction; they're removed from live data when the transaction is committed.  Settings that
+/// are only set in the transaction are dropped from it.  Returns the keys that will be removed.
+pub(crate) fn unset_settings<D: DataStore>(
+    datastore: &mut D,
+    keys: &HashSet<Key>,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    let mut data = get_pending_data(datastore, transaction)?;
+
+    let mut removals = HashSet::new();
+    for key in keys {
+        let found: Vec<_> = data
+            .keys()
+            .filter(|data_key| data_key.starts_with_segments(key.segments()))
+            .cloned()
+            .collect();
+        ensure!(
+            !found.is_empty(),
+            error::MissingDataSnafu { prefix: key.name() }
+        );
+        removals.extend(found);
+    }
+
+    // Make sure the settings left behind after the commit still fit the model.
+    data.retain(|key, _| !removals.contains(key));
+    from_map_with_prefix::<_, _, Settings, _>(None, &data).context(error::InvalidRemovalSnafu)?;
+
+    let mut pairs = HashMap::new();
+    let mut drop = HashSet::new();
+    for key in &removals {
+        let live =
+            datastore
+                .key_populated(key, &Committed::Live)
+                .context(error::DataStoreSnafu {
+                    op: "key_populated",
+                })?;
+        if live {
+            pairs.insert(key.clone(), PENDING_REMOVAL);
+        } else {
+            drop.insert(key.clone());
+        }
+    }
+
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .set_keys(&pairs, &pending)
+        .context(error::DataStoreSnafu { op: "set_keys" })?;
+    datastore
+        .unset_keys(&drop, &pending)
+        .context(error::DataStoreSnafu { op: "unset_keys" })?;
+
+    Ok(removals)
+}
+
+/// SettingsPatch holds the writes that make a pending transaction match a patched copy of its
+/// settings.  It's built by plan_settings_patch and written by stage_settings_patch, so callers can
+/// check the affected keys in between.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SettingsPatch {
+    /// Keys to write to the transaction, with their serialized values; live keys that the patch
+    /// removed are given PENDING_REMOVAL.
+    set: HashMap<Key, String>,
+    /// Keys that were only set in the transaction and that the patch removed.
+    drop: HashSet<Key>,
+}
+
+impl SettingsPatch {
+    /// Returns every key the patch changes.
+    pub(crate) fn keys(&self) -> HashSet<Key> {
+        self.set.keys().chain(self.drop.iter()).cloned().collect()
+    }
+}
+
+/// Builds the settings as they'd be after the given transaction is committed, lets `patch` change
+/// their JSON form, and works out the writes needed to stage the result in the transaction.  The
+/// patched settings have to fit the data model.
+pub(crate) fn plan_settings_patch<D, F>(
+    datastore: &D,
+    transaction: &str,
+    patch: F,
+) -> Result<SettingsPatch>
+where
+    D: DataStore,
+    F: FnOnce(&mut serde_json::Value) -> Result<()>,
+{
+    let settings = get_pending_settings(datastore, transaction)?;
+    let mut settings_json = serde_json::to_value(&settings).context(error::SettingsToJsonSnafu)?;
+    let old = to_pairs_with_prefix("settings", &settings_json)
+        .context(error::DataStoreSerializationSnafu { given: "Settings" })?;
+
+    patch(&mut settings_json)?;
+
+    // Round-trip through the model so we check types and write values in their usual form.
+    let patched: Settings =
+        serde_json::from_value(settings_json).context(error::PatchedSettingsSnafu)?;
+    let patched_json = serde_json::to_value(&patched).context(error::SettingsToJsonSnafu)?;
+    let new = to_pairs_with_prefix("settings", &patched_json)
+        .context(error::DataStoreSerializationSnafu { given: "Settings" })?;
+
+    let mut plan = SettingsPatch::default();
+    for (key, value) in &new {
+        if old.get(key) != Some(value) {
+            plan.set.insert(key.clone(), value.clone());
+        }
+    }
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        let live =
+            datastore
+                .key_populated(key, &Committed::Live)
+                .context(error::DataStoreSnafu {
+                    op: "key_populated",
+                })?;
+        if live {
+            plan.set.insert(key.clone(), PENDING_REMOVAL.to_string());
+        } else {
+            plan.drop.insert(key.clone());
+        }
+    }
+    Ok(plan)
+}
+
+/// Writes a patch planned by plan_settings_patch to the given transaction.
+pub(crate) fn stage_settings_patch<D: DataStore>(
+    datastore: &mut D,
+    plan: &SettingsPatch,
+    transaction: &str,
+) -> Result<()> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .set_keys(&plan.set, &pending)
+        .context(error::DataStoreSnafu { op: "set_keys" })?;
+    datastore
+        .unset_keys(&plan.drop, &pending)
+        .context(error::DataStoreSnafu { op: "unset_keys" })
+}
+
+/// Applies an RFC 6902 JSON Patch to a settings document.  Paths are relative to the settings, like
+/// "/motd".
+pub(crate) fn apply_json_patch(
+    settings: &mut serde_json::Value,
+    patch: &json_patch::Patch,
+) -> Result<()> {
+    json_patch::patch(settings, patch).map_err(|e| match e.kind {
+        json_patch::PatchErrorKind::TestFailed => error::Error::PatchTestFailed {
+            operation: e.operation,
+            path: e.path,
+        },
+        _ => error::Error::JsonPatch { source: e },
+    })
+}
+
+/// Applies an RFC 7396 merge patch to a settings document; null values remove settings.
+pub(crate) fn apply_merge_patch(
+    settings: &mut serde_json::Value,
+    patch: &serde_json::Value,
+) -> Result<()> {
+    ensure!(patch.is_object(), error::MergePatchTypeSnafu);
+    json_patch::merge(settings, patch);
+    Ok(())
+}
+
+/// Takes a snapshot of live settings and their metadata, recording the given OS version so users
+/// can tell where it came from.  Settings with a setting generator are specific to this host, so
+/// they're only included if `include_generated` is true.
+pub(crate) fn export_snapshot<D: DataStore>(
+    datastore: &D,
+    os_version: String,
+    include_generated: bool,
+) -> Result<Snapshot> {
+    let mut snapshot = snapshot::export(datastore, "settings.", include_generated)
+        .context(error::DataStoreSnafu { op: "export" })?;
+    snapshot.os_version = Some(os_version);
+    Ok(snapshot)
+}
+
+/// Restores the settings in a snapshot into the given pending transaction, so that once it's
+/// committed, the settings match the snapshot: its settings are written, and live settings that
+/// aren't in it are marked for removal.  Settings with a setting generator are kept if the snapshot
+/// doesn't have them, since the host generates its own.  Only settings can be restored, and the
+/// result has to fit the data model; nothing is written unless it does.  Returns the keys that
+/// were written or marked for removal.
+///
+/// The snapshot's metadata isn't restored; it comes from the OS image, so the host's own is kept.
+pub(crate) fn import_snapshot<D: DataStore>(
+    datastore: &mut D,
+    snapshot: &Snapshot,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    // Other data, like services, comes from the OS image and could be wrong for this host.
+    for key in snapshot.data.keys() {
+        ensure!(
+            key.starts_with("settings."),
+            error::SnapshotNotSettingsSnafu { key }
+        );
+    }
+
+    // Check the settings as they'll be after the commit.
+    let pairs = snapshot.data_pairs().context(error::InvalidSnapshotSnafu)?;
+    let removals = snapshot::removals(datastore, snapshot, "settings.")
+        .context(error::DataStoreSnafu { op: "removals" })?;
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStoreSnafu { op: "get_prefix" })?;
+    data.retain(|key, _| !removals.contains(key));
+    data.extend(pairs);
+    from_map_with_prefix::<_, _, Settings, _>(None, &data).context(error::SnapshotModelSnafu)?;
+
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    snapshot::import(datastore, snapshot, "settings.", &pending)
+        .context(error::DataStoreSnafu { op: "import" })
+}
+
 // This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
 // data model and check types.
 /// Gets the value of a metadata key for the requested list of data keys.
@@ -454,6 +860,81 @@ where
         .context(error::DataStoreSnafu { op: "commit" })
 }
 
+/// Builds a SettingsChange, as sent to watch clients, holding the live values of the given keys
+/// after a commit of the given transaction.
+pub(crate) fn settings_change<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+    keys: &HashSet<Key>,
+) -> Result<SettingsChange> {
+    let mut settings = HashMap::new();
+    for key in keys {
+        let value_str = datastore
+            .get_key(key, &Committed::Live)
+            .context(error::DataStoreSnafu { op: "get_key" })?;
+        let value = match value_str {
+            Some(value_str) => Some(
+                deserialize_scalar::<Value, ScalarError>(&value_str)
+                    .context(error::InvalidValueSnafu { key: key.name() })?,
+            ),
+            None => None,
+        };
+        settings.insert(key.name().clone(), value);
+    }
+    Ok(SettingsChange {
+        transaction: transaction.to_string(),
+        settings,
+    })
+}
+
+/// Returns the revision of the live datastore, which changes with each commit.
+pub(crate) fn get_revision<D: DataStore>(datastore: &D) -> Result<u64> {
+    datastore
+        .revision()
+        .context(error::DataStoreSnafu { op: "revision" })
+}
+
+/// Lists the commits recorded in the datastore's history, oldest first.
+pub(crate) fn list_commits<D: DataStore>(datastore: &D) -> Result<Vec<Commit>> {
+    datastore
+        .list_commits()
+        .context(error::DataStoreSnafu { op: "list_commits" })
+}
+
+/// Returns the data keys that rolling back the given commit would change.
+pub(crate) fn rollback_keys<D: DataStore>(datastore: &D, id: u64) -> Result<HashSet<Key>> {
+    let commit = list_commits(datastore)?
+        .into_iter()
+        .find(|commit| commit.id == id)
+        .context(error::NoSuchCommitSnafu { id })?;
+    commit
+        .changes
+        .keys()
+        .map(|name| {
+            Key::new(KeyType::Data, name).context(error::NewKeySnafu {
+                key_type: "data",
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Rolls back the given commit, restoring the live values of the keys it changed, and returns the
+/// changed keys.
+pub(crate) fn rollback_commit<D: DataStore>(datastore: &mut D, id: u64) -> Result<HashSet<Key>> {
+    // Pull out the errors the user can do something about, so they get a useful status code.
+    datastore.rollback_commit(id).map_err(|e| match e {
+        datastore::Error::NoSuchCommit { id } => error::Error::NoSuchCommit { id },
+        datastore::Error::RollbackConflict { id, key } => {
+            error::Error::RollbackConflict { id, key }
+        }
+        source => error::Error::DataStore {
+            op: "rollback".to_string(),
+            source: Box::new(source),
+        },
+    })
+}
+
 /// Launches the config applier to make appropriate changes to the system based on any settings
 /// that have been committed.  Can be called after a commit, with the keys that changed in that
 /// commit, or called on its own to reset configuration state with all known keys.
@@ -533,6 +1014,56 @@ where
     Ok(())
 }
 
+/// Asks the config applier to render the requested configuration files and compare them to the
+/// files on disk, without writing anything or restarting services.
+pub(crate) async fn render_configuration_files(
+    request: &RenderRequest,
+) -> Result<Vec<ConfigurationFilePlan>> {
+    trace!("Serializing the render request: {:?}", request);
+    let cmd_input = serde_json::to_string(request).context(error::CommandSerializationSnafu {
+        given: "render request",
+    })?;
+
+    // Unlike when applying changes, we don't ask the config applier to fork, because we need its
+    // output; it doesn't write or restart anything, so it's reasonably quick.
+    debug!("Launching thar-be-settings to plan changes");
+    let mut cmd = AsyncCommand::new("/usr/bin/thar-be-settings")
+        .arg("--plan")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(error::ConfigApplierStartSnafu)?;
+
+    // Send the request to the config applier, then close its stdin so it knows we're done.
+    trace!("Sending render request");
+    let mut stdin = cmd.stdin.take().context(error::ConfigApplierStdinSnafu)?;
+    stdin
+        .write_all(cmd_input.as_bytes())
+        .await
+        .context(error::ConfigApplierWriteSnafu)?;
+    drop(stdin);
+
+    let output = cmd
+        .wait_with_output()
+        .await
+        .context(error::ConfigApplierWaitSnafu)?;
+    ensure!(
+        output.status.success(),
+        error::ConfigApplierPlanSnafu {
+            exit_code: match output.status.code() {
+                Some(code) => code,
+                None => output.status.signal().unwrap_or(1),
+            },
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+
+    serde_json::from_slice(&output.stdout).context(error::ConfigApplierOutputSnafu {
+        stdout: output.stdout.clone(),
+    })
+}
+
 /// Dispatches an update command via `thar-be-updates`
 pub(crate) fn dispatch_update_command(args: &[&str]) -> Result<HttpResponse> {
     let status = Command::new("/usr/bin/thar-be-updates")
@@ -552,6 +1083,8 @@ pub(crate) fn dispatch_update_command(args: &[&str]) -> Result<HttpResponse> {
         Some(TbuErrorStatus::DisallowCommand) => error::Error::DisallowCommand,
         Some(TbuErrorStatus::UpdateDoesNotExist) => error::Error::UpdateDoesNotExist,
         Some(TbuErrorStatus::NoStagedImage) => error::Error::NoStagedImage,
+        Some(TbuErrorStatus::InvalidSource) => error::Error::InvalidUpdateSource,
+        Some(TbuErrorStatus::InvalidRollback) => error::Error::InvalidRollback,
         // other errors
         _ => error::Error::UpdateError,
     };
@@ -838,6 +1371,268 @@ mod test {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn unset_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"json string\"", &Committed::Live)
+            .unwrap();
+
+        // Keys that aren't set can't be removed
+        let missing = Key::new(KeyType::Data, "settings.ntp").unwrap();
+        unset_settings(&mut ds, &hashset!(missing), tx).unwrap_err();
+
+        // Removal is pending until commit
+        let removed = unset_settings(&mut ds, &hashset!(motd.clone()), tx).unwrap();
+        assert_eq!(removed, hashset!(motd.clone()));
+        assert!(ds.key_populated(&motd, &Committed::Live).unwrap());
+        let patch = get_transaction_patch(&ds, tx).unwrap();
+        assert_eq!(patch, serde_json::json!({"motd": null}));
+
+        commit_transaction(&mut ds, tx).unwrap();
+        assert!(!ds.key_populated(&motd, &Committed::Live).unwrap());
+
+        // Settings only set in the transaction are dropped from it
+        let pending = Committed::Pending { tx: tx.into() };
+        ds.set_key(&motd, "\"pending\"", &pending).unwrap();
+        let removed = unset_settings(&mut ds, &hashset!(motd.clone()), tx).unwrap();
+        assert_eq!(removed, hashset!(motd.clone()));
+        assert!(!ds.key_populated(&motd, &pending).unwrap());
+        assert_eq!(
+            get_transaction_patch(&ds, tx).unwrap(),
+            serde_json::json!({})
+        );
+    }
+
+    #[test]
+    fn mark_removed_works() {
+        let mut patch = serde_json::json!({"ntp": {"options": ["iburst"]}});
+        mark_removed(&mut patch, &["ntp".into(), "time-servers".into()]);
+        mark_removed(
+            &mut patch,
+            &["kubernetes".into(), "node-labels".into(), "a.b".into()],
+        );
+        assert_eq!(
+            patch,
+            serde_json::json!({
+                "ntp": {"options": ["iburst"], "time-servers": null},
+                "kubernetes": {"node-labels": {"a.b": null}},
+            })
+        );
+    }
+
+    #[test]
+    fn get_pending_settings_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"live\"", &Committed::Live).unwrap();
+
+        // Pending changes replace live values
+        let pending = Committed::Pending {
+            tx: "change".into(),
+        };
+        ds.set_key(&motd, "\"pending\"", &pending).unwrap();
+        assert_eq!(
+            get_transaction_keys(&ds, "change").unwrap(),
+            hashset!(motd.clone())
+        );
+        let settings = get_pending_settings(&ds, "change").unwrap();
+        assert_eq!(extract!(settings.motd), Some("pending".to_string()));
+
+        // Pending removals hide live values
+        unset_settings(&mut ds, &hashset!(motd.clone()), "remove").unwrap();
+        let settings = get_pending_settings(&ds, "remove").unwrap();
+        assert_eq!(extract!(settings.motd), None);
+
+        // Live data is unchanged
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(extract!(settings.motd), Some("live".to_string()));
+    }
+
+    #[test]
+    fn settings_schema_works() {
+        let schema = get_settings_schema().unwrap();
+        assert_eq!(
+            schema.pointer("/properties/motd/type"),
+            Some(&serde_json::json!("string"))
+        );
+        assert_eq!(
+            schema.pointer("/properties/aws/properties/region/type"),
+            Some(&serde_json::json!("string"))
+        );
+        assert_eq!(schema.pointer("/x-partial"), Some(&serde_json::json!(true)));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let meta = Key::new(KeyType::Meta, "affected-services").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+        ds.set_metadata(&meta, &motd, "[\"motd\"]").unwrap();
+
+        let snapshot = export_snapshot(&ds, "1.2.3".to_string(), false).unwrap();
+        assert_eq!(snapshot.os_version.as_deref(), Some("1.2.3"));
+        assert_eq!(
+            snapshot.metadata["settings.motd"]["affected-services"],
+            serde_json::json!(["motd"])
+        );
+
+        // The host's own metadata is kept, whatever the snapshot came from, and settings that
+        // aren't in the snapshot are removed.
+        let mut restored = MemoryDataStore::new();
+        let servers = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        restored.set_metadata(&meta, &motd, "[\"other\"]").unwrap();
+        restored
+            .set_key(&servers, "[\"a\"]", &Committed::Live)
+            .unwrap();
+        assert_eq!(
+            snapshot_keys(&restored, &snapshot).unwrap(),
+            hashset!(motd.clone(), servers.clone())
+        );
+        let written = import_snapshot(&mut restored, &snapshot, "restore").unwrap();
+        assert_eq!(written, hashset!(motd.clone(), servers.clone()));
+        let settings = get_pending_settings(&restored, "restore").unwrap();
+        assert_eq!(extract!(settings.motd), Some("hi".to_string()));
+        assert_eq!(extract!(settings.ntp), None);
+        assert_eq!(restored.get_key(&motd, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            restored.get_metadata_raw(&meta, &motd).unwrap(),
+            Some("[\"other\"]".to_string())
+        );
+
+        // Only settings can be restored.
+        let mut other = snapshot.clone();
+        other
+            .data
+            .insert("services.x.restart-commands".to_string(), "[]".into());
+        assert!(matches!(
+            import_snapshot(&mut restored, &other, "restore"),
+            Err(error::Error::SnapshotNotSettings { .. })
+        ));
+    }
+
+    #[test]
+    fn settings_patch_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "patch";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let servers = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+
+        // A JSON patch can remove live settings and add new ones.
+        let patch: json_patch::Patch = serde_json::from_value(serde_json::json!([
+            {"op": "test", "path": "/motd", "value": "hi"},
+            {"op": "remove", "path": "/motd"},
+            {"op": "add", "path": "/ntp", "value": {"time-servers": ["a"]}},
+        ]))
+        .unwrap();
+        let plan =
+            plan_settings_patch(&ds, tx, |settings| apply_json_patch(settings, &patch)).unwrap();
+        assert_eq!(plan.keys(), hashset!(motd.clone(), servers.clone()));
+        stage_settings_patch(&mut ds, &plan, tx).unwrap();
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap().as_deref(),
+            Some(PENDING_REMOVAL)
+        );
+        assert_eq!(
+            ds.get_key(&servers, &pending).unwrap(),
+            Some("[\"a\"]".to_string())
+        );
+
+        // A merge patch can drop settings that were only pending, and put back live ones.
+        let patch = serde_json::json!({"ntp": null, "motd": "hi"});
+        let plan =
+            plan_settings_patch(&ds, tx, |settings| apply_merge_patch(settings, &patch)).unwrap();
+        stage_settings_patch(&mut ds, &plan, tx).unwrap();
+        assert_eq!(ds.get_key(&servers, &pending).unwrap(), None);
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap(),
+            Some("\"hi\"".to_string())
+        );
+
+        // Failed tests and results that don't fit the model are rejected.
+        let patch: json_patch::Patch = serde_json::from_value(serde_json::json!([
+            {"op": "test", "path": "/motd", "value": "bye"},
+        ]))
+        .unwrap();
+        assert!(matches!(
+            plan_settings_patch(&ds, tx, |settings| apply_json_patch(settings, &patch)),
+            Err(error::Error::PatchTestFailed { .. })
+        ));
+        let patch = serde_json::json!({"motd": 5});
+        assert!(matches!(
+            plan_settings_patch(&ds, tx, |settings| apply_merge_patch(settings, &patch)),
+            Err(error::Error::PatchedSettings { .. })
+        ));
+        assert!(matches!(
+            apply_merge_patch(&mut serde_json::json!({}), &serde_json::json!([])),
+            Err(error::Error::MergePatchType)
+        ));
+    }
+
+    #[test]
+    fn get_affected_services_works() {
+        let mut ds = MemoryDataStore::new();
+        // Set directly with data store
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &motd,
+            "[\"foo\"]",
+        )
+        .unwrap();
+        for (key, value) in &[
+            ("services.foo.configuration-files", "[\"file1\"]"),
+            ("services.foo.restart-commands", "[\"echo hi\"]"),
+            ("services.bar.configuration-files", "[\"file2\"]"),
+            ("services.bar.restart-commands", "[\"echo hi\"]"),
+            ("configuration-files.file1.path", "\"/etc/file1\""),
+            (
+                "configuration-files.file1.template-path",
+                "\"/templates/file1\"",
+            ),
+            ("configuration-files.file2.path", "\"/etc/file2\""),
+            (
+                "configuration-files.file2.template-path",
+                "\"/templates/file2\"",
+            ),
+        ] {
+            ds.set_key(
+                &Key::new(KeyType::Data, key).unwrap(),
+                value,
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+
+        // Only the affected service and its files are returned
+        let services = get_affected_services(&ds, &hashset!(motd)).unwrap();
+        assert_eq!(
+            services,
+            hashmap!("foo".to_string() => Service {
+                configuration_files: vec!["file1".try_into().unwrap()],
+                restart_commands: vec!["echo hi".to_string()]
+            })
+        );
+        let files = get_services_configuration_files(&ds, &services).unwrap();
+        assert_eq!(
+            files,
+            hashmap!("file1".to_string() => ConfigurationFile {
+                path: "/etc/file1".try_into().unwrap(),
+                template_path: "/templates/file1".try_into().unwrap(),
+                mode: None,
+            })
+        );
+
+        // Keys without the metadata affect no services
+        let ntp = Key::new(KeyType::Data, "settings.ntp").unwrap();
+        let services = get_affected_services(&ds, &hashset!(ntp)).unwrap();
+        assert!(services.is_empty());
+    }
+
     #[test]
     fn commit_works() {
         // Set directly with data store
@@ -858,7 +1653,9 @@ mod test {
         get_settings(&ds, &Committed::Live).unwrap_err();
 
         // Commit, pending -> live
+        assert_eq!(get_revision(&ds).unwrap(), 0);
         commit_transaction(&mut ds, tx).unwrap();
+        assert_eq!(get_revision(&ds).unwrap(), 1);
 
         // No more pending settings
         get_settings(&ds, &pending).unwrap_err();
@@ -866,4 +1663,52 @@ mod test {
         let settings = get_settings(&ds, &Committed::Live).unwrap();
         assert_eq!(extract!(settings.motd), Some("json string".into()));
     }
+
+    #[test]
+    fn rollback_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"json string\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        let commits = list_commits(&ds).unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].transaction, tx);
+
+        // Roll back, live -> unset
+        assert_eq!(
+            rollback_commit(&mut ds, commits[0].id).unwrap(),
+            hashset!(motd)
+        );
+        get_settings(&ds, &Committed::Live).unwrap_err();
+
+        // Unknown commits are reported as such
+        assert!(matches!(
+            rollback_commit(&mut ds, 42),
+            Err(error::Error::NoSuchCommit { id: 42 })
+        ));
+    }
+
+    #[test]
+    fn settings_change_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let gone = Key::new(KeyType::Data, "settings.gone").unwrap();
+        ds.set_key(&motd, "\"json string\"", &Committed::Live)
+            .unwrap();
+
+        let change = settings_change(&ds, "tx", &hashset!(motd, gone)).unwrap();
+        assert_eq!(
+            change,
+            SettingsChange {
+                transaction: "tx".to_string(),
+                settings: hashmap!(
+                    "settings.motd".to_string() => Some(Value::String("json string".to_string())),
+                    "settings.gone".to_string() => None,
+                ),
+            }
+        );
+    }
 }
@@ -56,9 +56,37 @@ pub enum Error {
     #[snafu(display("Tried to commit with no pending changes"))]
     CommitWithNoPending,
 
+    #[snafu(display("Tried to plan with no pending changes"))]
+    PlanWithNoPending,
+
+    #[snafu(display("Invalid '{}' header: {}", name, source))]
+    InvalidHeader {
+        name: String,
+        source: actix_web::http::header::ToStrError,
+    },
+
+    #[snafu(display(
+        "Live settings are at revision {}, not matching If-Match '{}'; fetch settings and try again",
+        revision,
+        given
+    ))]
+    RevisionMismatch { given: String, revision: u64 },
+
+    #[snafu(display("Commit ID '{}' is not a valid number: {}", input, source))]
+    InvalidCommitId {
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
     #[snafu(display("Unable to get OS release data: {}", source))]
     ReleaseData { source: bottlerocket_release::Error },
 
+    #[snafu(display("Unable to parse the API description: {}", source))]
+    OpenApiParse { source: serde_yaml::Error },
+
+    #[snafu(display("The API description has no settings schema"))]
+    MissingSettingsSchema,
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Controller errors
@@ -78,6 +106,16 @@ pub enum Error {
         source: Box<datastore::Error>,
     },
 
+    #[snafu(display("No commit with ID {} in history", id))]
+    NoSuchCommit { id: u64 },
+
+    #[snafu(display(
+        "Can't roll back commit {}: key '{}' has changed since; roll back later commits first",
+        id,
+        key
+    ))]
+    RollbackConflict { id: u64, key: String },
+
     #[snafu(display("Error deserializing {}: {} ", given, source))]
     Deserialization {
         given: String,
@@ -142,9 +180,137 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Value of '{}' is not valid JSON: {}", key, source))]
+    InvalidValue {
+        key: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Removing those settings would leave invalid settings: {}", source))]
+    InvalidRemoval {
+        source: datastore::deserialization::Error,
+    },
+
+    #[snafu(display("Invalid snapshot: {}", source))]
+    InvalidSnapshot {
+        #[snafu(source(from(datastore::Error, Box::new)))]
+        source: Box<datastore::Error>,
+    },
+
+    #[snafu(display("Snapshot contains '{}', but only settings can be restored", key))]
+    SnapshotNotSettings { key: String },
+
+    #[snafu(display("Snapshot settings don't match the data model: {}", source))]
+    SnapshotModel {
+        source: datastore::deserialization::Error,
+    },
+
+    #[snafu(display("Unable to apply JSON patch: {}", source))]
+    JsonPatch { source: json_patch::PatchError },
+
+    #[snafu(display(
+        "JSON patch 'test' operation {} failed: value at '{}' did not match",
+        operation,
+        path
+    ))]
+    PatchTestFailed { operation: usize, path: String },
+
+    #[snafu(display("Merge patch must be a JSON object"))]
+    MergePatchType,
+
+    #[snafu(display("Patched settings don't match the data model: {}", source))]
+    PatchedSettings { source: serde_json::Error },
+
     #[snafu(display("Failed to split the string: {}", input))]
     InvalidKeyPair { input: String },
 
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Policy errors
+    #[snafu(display("Unable to read API policy {}: {}", path.display(), source))]
+    PolicyRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Invalid API policy {}: {}", path.display(), source))]
+    PolicyParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display(
+        "Invalid settings prefix '{}' in API policy {}: {}",
+        prefix,
+        path.display(),
+        source
+    ))]
+    PolicySetting {
+        path: PathBuf,
+        prefix: String,
+        #[snafu(source(from(datastore::Error, Box::new)))]
+        source: Box<datastore::Error>,
+    },
+
+    #[snafu(display("The API policy doesn't allow access from {}", client))]
+    ClientDenied { client: String },
+
+    #[snafu(display(
+        "The API policy rule '{}' doesn't allow {} to {} {}",
+        rule,
+        client,
+        method,
+        path
+    ))]
+    RouteDenied {
+        client: String,
+        rule: String,
+        method: String,
+        path: String,
+    },
+
+    #[snafu(display(
+        "The API policy rule '{}' doesn't allow {} to change '{}'",
+        rule,
+        client,
+        key
+    ))]
+    SettingDenied {
+        client: String,
+        rule: String,
+        key: String,
+    },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Audit log errors
+    #[snafu(display("Another thread poisoned the audit log lock by panicking"))]
+    AuditLock,
+
+    #[snafu(display("Unable to read audit log {}: {}", path.display(), source))]
+    AuditRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to serialize audit entry: {}", source))]
+    AuditSerialize { source: serde_json::Error },
+
+    #[snafu(display("Unable to write audit log {}: {}", path.display(), source))]
+    AuditWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Invalid audit entry limit '{}': {}", input, source))]
+    InvalidAuditLimit {
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display(
+        "Invalid value '{}' for '{}', expected true or false: {}",
+        input,
+        name,
+        source
+    ))]
+    InvalidBool {
+        name: &'static str,
+        input: String,
+        source: std::str::ParseBoolError,
+    },
+
     #[snafu(display("Prefix '{}' is not a valid key: {}", prefix, source))]
     InvalidPrefix {
         prefix: String,
@@ -170,6 +336,23 @@ pub enum Error {
     #[snafu(display("Unable to send input to config applier: {}", source))]
     ConfigApplierWrite { source: io::Error },
 
+    #[snafu(display(
+        "Config applier failed to plan changes, exit code: {}, stderr: {}",
+        exit_code,
+        stderr
+    ))]
+    ConfigApplierPlan { exit_code: i32, stderr: String },
+
+    #[snafu(display(
+        "Failed to parse planned changes from '{}': {}",
+        String::from_utf8_lossy(stdout),
+        source
+    ))]
+    ConfigApplierOutput {
+        stdout: Vec<u8>,
+        source: serde_json::Error,
+    },
+
     #[snafu(display("Unable to start shutdown: {}", source))]
     Shutdown { source: io::Error },
 
@@ -194,6 +377,21 @@ pub enum Error {
 
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+    // Exec run errors
+    #[snafu(display("Invalid exec run request: {}", reason))]
+    InvalidExecRun { reason: String },
+
+    #[snafu(display("Unable to start command: {}", source))]
+    ExecRunSpawn { source: io::Error },
+
+    #[snafu(display("Lost track of command process"))]
+    ExecRunProcess,
+
+    #[snafu(display("Failed waiting for command to exit: {}", source))]
+    ExecRunWait { source: io::Error },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
     // Update related errors
     #[snafu(display("Unable to start the update dispatcher: {} ", source))]
     UpdateDispatcher { source: io::Error },
@@ -213,6 +411,12 @@ pub enum Error {
     #[snafu(display("No update image applied to staging partition"))]
     NoStagedImage,
 
+    #[snafu(display("Unable to use the given update source"))]
+    InvalidUpdateSource,
+
+    #[snafu(display("Inactive partition set doesn't hold a previous version to roll back to"))]
+    InvalidRollback,
+
     #[snafu(display("Update action not allowed according to update state"))]
     DisallowCommand,
 
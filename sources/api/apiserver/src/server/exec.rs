@@ -2,6 +2,9 @@
 //! command through containerd and use a WebSocket for communication with the client.  Process
 //! input and output is sent back and forth directly through a binary channel, and control messages
 //! are sent through a multiplexed text channel.
+//!
+//! Clients that just want a command's output can instead use the 'run' module, which runs a
+//! command to completion in a single HTTP request.
 
 // Implementation note: this module manages the WebSocket, which is created for us by Actix, and
 // Actix works with 'actors' - individual entities that can send each other different message types
@@ -14,16 +17,22 @@ use actix::prelude::{Actor, ActorContext, AsyncContext, Handler, StreamHandler};
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws::{self, Message};
 use log::{debug, error, info};
-use model::exec::{Capacity, ClientMessage, ServerMessage};
+use model::exec::{
+    Capacity, ClientMessage, CopyDirection, CopyResult, Exit, FileInfo, ServerMessage,
+};
+use nix::sys::signal::Signal;
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::mpsc::TrySendError;
 use std::time::{Duration, Instant};
 
 mod child;
+mod run;
 mod stop;
 use child::ChildHandles;
+pub(crate) use run::exec_run;
 use stop::{ok_or_stop, some_or_stop, stop};
 
 /// To guard against stale connections, we send ping and pong messages through the channel
@@ -89,6 +98,10 @@ pub(crate) struct WsExec {
     /// This represents the path to the containerd socket that we use to spawn the requested
     /// process in a container namespace.
     exec_socket_path: PathBuf,
+
+    /// Whether the client wants an Exit message when the process ends.  Older clients don't
+    /// understand it, so they only get the return code in the Close message.
+    exit_status: bool,
 }
 
 impl WsExec {
@@ -97,6 +110,7 @@ impl WsExec {
             heartbeat: Instant::now(),
             child_handles: None,
             exec_socket_path,
+            exit_status: false,
         }
     }
 
@@ -228,6 +242,7 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                                init.target,
                                init.command,
                                init.tty.is_some());
+                        self.exit_status = init.exit_status;
                         // Spawn the process, getting back handles that let us interact with it.
                         let child_handles = ok_or_stop!(
                             ChildHandles::new(init, &self.exec_socket_path, ctx.address()),
@@ -238,6 +253,37 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                         self.child_handles = Some(child_handles);
                     }
 
+                    // Copy is sent instead of Initialize when the client wants to copy a file to
+                    // or from the container rather than run a command.
+                    ClientMessage::Copy(copy) => {
+                        debug!(
+                            "Client requested copy {} target container '{}' of path {:?}",
+                            match copy.direction {
+                                CopyDirection::ToContainer(_) => "to",
+                                CopyDirection::FromContainer => "from",
+                            },
+                            copy.target,
+                            copy.path
+                        );
+                        if !PathBuf::from(&copy.path).is_absolute() {
+                            stop(
+                                ctx,
+                                Some("copy path must be absolute"),
+                                ws::CloseCode::Invalid,
+                            );
+                            return;
+                        }
+                        // Copy clients always check the exit status.
+                        self.exit_status = true;
+                        let child_handles = ok_or_stop!(
+                            ChildHandles::copy(copy, &self.exec_socket_path, ctx.address()),
+                            ctx,
+                            "failed to spawn process",
+                            ws::CloseCode::Error
+                        );
+                        self.child_handles = Some(child_handles);
+                    }
+
                     // This means the client is done reading input from the user and we can close
                     // the write channel to the process, closing its stdin.
                     ClientMessage::ContentComplete => {
@@ -264,6 +310,25 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                             debug!("Received client winch before child was spawned");
                         }
                     }
+
+                    // This means the client received a signal, like SIGINT, that it wants to
+                    // forward to the child process rather than just disconnecting.  We'll tell
+                    // the client how the process exited, as usual.
+                    ClientMessage::Signal(name) => {
+                        let signal = ok_or_stop!(
+                            Signal::from_str(&name),
+                            ctx,
+                            format!("invalid signal '{}'", name),
+                            ws::CloseCode::Invalid
+                        );
+                        let child_handles = some_or_stop!(
+                            &self.child_handles,
+                            ctx,
+                            Some("Signal sent before initialization"),
+                            ws::CloseCode::Policy
+                        );
+                        child_handles.signal(signal);
+                    }
                 }
             }
 
@@ -317,14 +382,22 @@ mod message {
         pub(super) output: Vec<u8>,
     }
 
-    /// Represents the return code of the child process that should be communicated to the client
-    /// while closing the WebSocket.
+    /// Represents the exit status of the child process that should be communicated to the client
+    /// before closing the WebSocket.
     #[derive(actix::Message)]
     #[rtype(result = "()")]
     pub(super) struct ProcessReturn {
-        pub(super) code: i32,
+        pub(super) exit: super::Exit,
+        /// If the process was copying a file, what it transferred.
+        pub(super) copy: Option<super::CopyResult>,
     }
 
+    /// Represents the permissions and size of a file being copied from the container, which must
+    /// be sent to the client before the file data.
+    #[derive(actix::Message)]
+    #[rtype(result = "()")]
+    pub(super) struct FileInfo(pub(super) super::FileInfo);
+
     /// Represents a capacity update that tells the client how much data we're prepared to receive.
     #[derive(Debug, actix::Message)]
     #[rtype(result = "()")]
@@ -348,18 +421,67 @@ impl Handler<message::ProcessOutput> for WsExec {
 impl Handler<message::ProcessReturn> for WsExec {
     type Result = ();
 
-    /// Sends the process return code to the client inside a Close message.
+    /// Sends the process exit status to the client in an Exit message, if it asked for one, then
+    /// closes the connection, also including a shell-style return code in the Close message for
+    /// older clients.
     fn handle(&mut self, msg: message::ProcessReturn, ctx: &mut Self::Context) -> Self::Result {
-        info!("exec process returned {}", msg.code);
+        let exit = &msg.exit;
+        info!(
+            "exec process returned code {:?}, signal {:?}",
+            exit.code, exit.signal
+        );
+        // Use shell-style return codes for signals.
+        let code = exit.code.or(exit.signal.map(|signal| 128 + signal));
+
+        for server_msg in final_messages(msg, self.exit_status) {
+            match serde_json::to_string(&server_msg) {
+                Ok(msg) => ctx.text(msg),
+                Err(e) => error!("Failed to serialize final message: {}", e),
+            }
+        }
+
         // nix deals with i32 (c_int) return codes, but we know they're never negative; really,
         // they're just a u8.  If that assumption breaks for some reason, we don't have a
         // reasonable code to send to the user, so just give a 0.
-        let code = u16::try_from(msg.code).unwrap_or(0);
+        let code = code.and_then(|code| u16::try_from(code).ok()).unwrap_or(0);
         // We send the process return code in the closing frame's reason message.
         stop(ctx, Some(code.to_string()), ws::CloseCode::Normal);
     }
 }
 
+/// Returns the messages to send the client once the process has returned.  For a copy, the client
+/// checks what we transferred before accepting the file.  The Exit message is only included if the
+/// client asked for it.
+fn final_messages(msg: message::ProcessReturn, exit_status: bool) -> Vec<ServerMessage> {
+    let mut messages = Vec::new();
+    if let Some(copy) = msg.copy {
+        messages.push(ServerMessage::CopyResult(copy));
+    }
+    if exit_status {
+        messages.push(ServerMessage::Exit(msg.exit));
+    }
+    messages
+}
+
+impl Handler<message::FileInfo> for WsExec {
+    type Result = ();
+
+    /// Sends the permissions and size of a file being copied to the client.
+    fn handle(&mut self, msg: message::FileInfo, ctx: &mut Self::Context) -> Self::Result {
+        debug!(
+            "Sending file info; mode {:o}, size {}",
+            msg.0.mode, msg.0.size
+        );
+        let msg = ok_or_stop!(
+            serde_json::to_string(&ServerMessage::FileInfo(msg.0)),
+            ctx,
+            "failed to send file info",
+            ws::CloseCode::Error,
+        );
+        ctx.text(msg);
+    }
+}
+
 impl Handler<message::CapacityUpdate> for WsExec {
     type Result = ();
 
@@ -378,3 +500,70 @@ impl Handler<message::CapacityUpdate> for WsExec {
         ctx.text(msg);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::exec::Initialize;
+    use serde::Deserialize;
+
+    /// The server messages understood by clients from before Exit was added.
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    enum BaselineServerMessage {
+        Capacity(Capacity),
+    }
+
+    fn process_return() -> message::ProcessReturn {
+        message::ProcessReturn {
+            exit: Exit {
+                code: Some(3),
+                signal: None,
+            },
+            copy: None,
+        }
+    }
+
+    #[test]
+    fn baseline_client_frames() {
+        // Older clients don't send exit_status.
+        let command = vec![std::ffi::OsString::from("ls")];
+        let init: ClientMessage = serde_json::from_value(serde_json::json!({
+            "Initialize": {"command": command, "target": "admin", "tty": null}
+        }))
+        .unwrap();
+        let ClientMessage::Initialize(init) = init else {
+            panic!("not an Initialize: {:?}", init);
+        };
+        assert!(!init.exit_status);
+
+        let capacity = ServerMessage::Capacity(Capacity {
+            max_messages_outstanding: MAX_MESSAGES_OUTSTANDING,
+            messages_written: 0,
+        });
+        let frames = std::iter::once(capacity).chain(final_messages(process_return(), false));
+        for frame in frames {
+            let text = serde_json::to_string(&frame).unwrap();
+            serde_json::from_str::<BaselineServerMessage>(&text)
+                .unwrap_or_else(|e| panic!("baseline client can't parse {}: {}", text, e));
+        }
+    }
+
+    #[test]
+    fn exit_frame_when_requested() {
+        let init = Initialize {
+            command: vec!["ls".into()],
+            target: "admin".to_string(),
+            tty: None,
+            exit_status: true,
+        };
+        let frames = final_messages(process_return(), init.exit_status);
+        assert!(matches!(
+            frames.as_slice(),
+            [ServerMessage::Exit(Exit {
+                code: Some(3),
+                signal: None
+            })]
+        ));
+    }
+}
@@ -5,6 +5,10 @@
 //! the desired container and command are given by the caller.  We can also optionally create a PTY
 //! for the task to run in, useful for interactive programs.  Process output is sent back as actor
 //! messages, and process input is received on a channel.
+//!
+//! A child can also copy a file to or from the container.  We run a small shell script in the
+//! container to read or write the file, keeping its error output apart from the file data, and
+//! track the data so we can tell the client exactly what we transferred.
 
 // Implementation note: the main job of this module is communicating with the child process.  We
 // use simple blocking calls for communication, so we organize the module with threads and
@@ -23,7 +27,9 @@ use actix::prelude::{Addr, SendError};
 use bytes::Bytes;
 use libc::{ioctl, login_tty, winsize as WinSize, TIOCSWINSZ as SetWinSize};
 use log::{debug, error};
-use model::exec::{Capacity, Initialize, Size, TtyInit};
+use model::exec::{
+    Capacity, Copy, CopyDirection, CopyResult, Exit, FileInfo, Initialize, Size, TtyInit,
+};
 use nix::{
     errno::Errno,
     fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
@@ -33,20 +39,34 @@ use nix::{
     unistd::{close, pipe2, read, Pid},
 };
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
 use snafu::{OptionExt, ResultExt};
 use std::convert::TryFrom;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::os::unix::{
     io::{FromRawFd, IntoRawFd, RawFd},
     process::CommandExt,
 };
-use std::process::{Command, Stdio};
+use std::process::{ChildStderr, Command, Stdio};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::thread::{self, sleep};
 use std::time::Duration;
 
+/// For a copy from the container, this prints the permissions and size of the file on the first
+/// line, then the file itself.  The path is given as $1.
+const COPY_FROM_SCRIPT: &str = r#"stat -L -c '%a %s' "$1" && exec cat "$1""#;
+
+/// For a copy to the container, this writes the file next to its destination, and moves it into
+/// place only if it's complete.  The path, permissions, and size are given as $1, $2, and $3.
+const COPY_TO_SCRIPT: &str = r#"tmp="$1.apiclient-cp.$$"
+if cat > "$tmp" && [ "$(stat -c %s "$tmp")" = "$3" ] && chmod "$2" "$tmp" && mv -f "$tmp" "$1"; then
+    exit 0
+fi
+rm -f "$tmp"
+exit 1"#;
+
 /// ChildHandles represents a spawned child process and contains the handles necessary to interact
 /// with it.
 #[derive(Debug)]
@@ -80,42 +100,62 @@ impl ChildHandles {
         exec_socket_path: impl AsRef<OsStr>,
         ws_addr: Addr<WsExec>,
     ) -> Result<Self> {
-        // containerd requires an "exec ID" for each task exec.
-        let exec_id = format!(
-            "apiexec-{}",
-            thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(16)
-                .map(char::from)
-                .collect::<String>()
-        );
-
-        // We use ctr as a simple interface to containerd exec requests; it does bookkeeping for us
-        // that's required by containerd, and is simpler to interact with than the containerd API,
-        // at least in Rust in 2021.
-        let mut command = Command::new("/usr/bin/ctr");
-
-        // Point it at the requested containerd socket; changes are useful for local testing.
-        command.arg("-a");
-        // (The path is a different type, OsStr, so it's passed separately.)
-        command.arg(exec_socket_path.as_ref());
+        Self::spawn(init, None, exec_socket_path, ws_addr)
+    }
 
-        // Ask ctr to exec into an existing task, with a TTY if requested by the user.
-        command.args(["task", "exec", "--exec-id", &exec_id]);
-        if init.tty.is_some() {
-            command.arg("--tty");
+    /// Like new, but rather than running a command given by the client, runs a script that copies
+    /// a file to or from the container.  The path must be absolute.
+    pub(crate) fn copy(
+        copy: Copy,
+        exec_socket_path: impl AsRef<OsStr>,
+        ws_addr: Addr<WsExec>,
+    ) -> Result<Self> {
+        let mut command: Vec<OsString> = vec!["sh".into(), "-c".into()];
+        match &copy.direction {
+            CopyDirection::FromContainer => {
+                command.extend([COPY_FROM_SCRIPT.into(), "sh".into(), copy.path]);
+            }
+            CopyDirection::ToContainer(info) => {
+                command.extend([
+                    COPY_TO_SCRIPT.into(),
+                    "sh".into(),
+                    copy.path,
+                    format!("{:o}", info.mode & 0o7777).into(),
+                    info.size.to_string().into(),
+                ]);
+            }
         }
-        // Pass the target container (task) and the requested command.
-        command.arg(init.target);
-        command.args(init.command);
+        let init = Initialize {
+            command,
+            target: copy.target,
+            tty: None,
+            exit_status: true,
+        };
+        Self::spawn(init, Some(copy.direction), exec_socket_path, ws_addr)
+    }
 
-        // ctr sets up a basic environment for spawned processes; there's no reason to inherit
-        // anything from apiserver.
-        command.env_clear();
+    /// Spawns the process described by `init`.  If `copy` is given, the process is copying a file,
+    /// so we keep its error output apart from its output, and track the file data.
+    fn spawn(
+        init: Initialize,
+        copy: Option<CopyDirection>,
+        exec_socket_path: impl AsRef<OsStr>,
+        ws_addr: Addr<WsExec>,
+    ) -> Result<Self> {
+        let mut command = ctr_command(
+            exec_socket_path,
+            &init.target,
+            &init.command,
+            init.tty.is_some(),
+        );
 
         // Get read and write file descriptors, configured appropriately for the requested TTY
         // setup.  (Sometimes we'll also have a fd to close because PTYs are finicky.)
-        let child_fds = ChildFds::new(&mut command, &init.tty)?;
+        let child_fds = if copy.is_some() {
+            ChildFds::copy_fds(&mut command)?
+        } else {
+            ChildFds::new(&mut command, &init.tty)?
+        };
 
         // We don't want to pass through a "real" TERM value because TUI programs will query for
         // terminal capabilities, like cursor position and color support, and we don't feed that
@@ -164,8 +204,28 @@ impl ChildHandles {
                 });
             }
 
+            // For a copy, whichever thread handles the file data tells us what it transferred on
+            // this channel, and we collect error output separately.
+            let (transfer_tx, transfer_rx) = sync_channel(1);
+            let (read_transfer_tx, write_transfer_tx) = match copy {
+                Some(CopyDirection::FromContainer) => (Some(transfer_tx), None),
+                Some(CopyDirection::ToContainer(_)) => (None, Some(transfer_tx)),
+                None => (None, None),
+            };
+            let copy_outcome = match copy {
+                Some(_) => {
+                    let stderr = child.stderr.take().context(error::NoStderrSnafu)?;
+                    Some(CopyOutcome {
+                        transfer_rx,
+                        stderr_rx: ReadStderr::new(stderr).stderr_rx,
+                    })
+                }
+                None => None,
+            };
+
             // Set up the thread that reads output from the child, sending it to the WebSocket.
-            let read_from_child = ReadFromChild::new(child_fds.read_fd, ws_addr.clone());
+            let read_from_child =
+                ReadFromChild::new(child_fds.read_fd, ws_addr.clone(), read_transfer_tx);
 
             // If we didn't create a PTY, we have to fetch the child's stdin handle; this isn't
             // available until after the child is spawned, so ChildFds can't do it.
@@ -179,11 +239,11 @@ impl ChildHandles {
             };
 
             // Set up the thread that writes input from the WebSocket to the child.
-            let write_to_child = WriteToChild::new(write_fd, ws_addr.clone());
+            let write_to_child = WriteToChild::new(write_fd, ws_addr.clone(), write_transfer_tx);
 
             // Set up the thread that waits for the child to exit, at which point it can clean up
             // and send the return code through the WebSocket.
-            let _ = WaitForChild::new(pid, ws_addr, read_from_child.complete_rx);
+            let _ = WaitForChild::new(pid, ws_addr, read_from_child.complete_rx, copy_outcome);
 
             Ok(Self {
                 pid,
@@ -220,6 +280,15 @@ impl ChildHandles {
         let _ = kill(pid, Signal::SIGTERM);
     }
 
+    /// Sends the given signal to the child process.  Like stop, this is best-effort; if the process
+    /// is already gone, we'll soon tell the client how it exited.
+    pub(crate) fn signal(&self, signal: Signal) {
+        // Note: as described in stop_impl, ctr only forwards signals to the requested process
+        // if we didn't start it with a PTY.
+        debug!("Sending {} to child", signal);
+        let _ = kill(self.pid, signal);
+    }
+
     /// Updates the window size that's recorded in the PTY device, which allows the child process
     /// to receive the new size and adjust its output, if desired.  Note that this is treated as a
     /// "nice to have" without a return value because the program will still function and it's easy
@@ -241,6 +310,54 @@ impl ChildHandles {
     }
 }
 
+/// Builds a ctr command that runs the given command in the target container (task), with a TTY if
+/// requested.
+///
+/// We use ctr as a simple interface to containerd exec requests; it does bookkeeping for us that's
+/// required by containerd, and is simpler to interact with than the containerd API, at least in
+/// Rust in 2021.
+pub(crate) fn ctr_command<S>(
+    exec_socket_path: impl AsRef<OsStr>,
+    target: &str,
+    args: &[S],
+    tty: bool,
+) -> Command
+where
+    S: AsRef<OsStr>,
+{
+    // containerd requires an "exec ID" for each task exec.
+    let exec_id = format!(
+        "apiexec-{}",
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect::<String>()
+    );
+
+    let mut command = Command::new("/usr/bin/ctr");
+
+    // Point it at the requested containerd socket; changes are useful for local testing.
+    command.arg("-a");
+    // (The path is a different type, OsStr, so it's passed separately.)
+    command.arg(exec_socket_path.as_ref());
+
+    // Ask ctr to exec into an existing task, with a TTY if requested by the user.
+    command.args(["task", "exec", "--exec-id", &exec_id]);
+    if tty {
+        command.arg("--tty");
+    }
+    // Pass the target container (task) and the requested command.
+    command.arg(target);
+    command.args(args);
+
+    // ctr sets up a basic environment for spawned processes; there's no reason to inherit
+    // anything from apiserver.
+    command.env_clear();
+
+    command
+}
+
 /// ChildFds sets up read and write file descriptors for a Command (before it's spawned) based on
 /// whether the user requested a TTY.
 struct ChildFds {
@@ -352,6 +469,26 @@ impl ChildFds {
     }
 }
 
+impl ChildFds {
+    /// Sets up FDs for copying a file.  This is like the non-TTY case, but stderr is kept apart
+    /// from stdout, so error output doesn't end up in the file.
+    fn copy_fds(child: &mut Command) -> Result<Self> {
+        debug!("Creating Stdio pipes for copy request");
+        let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC).context(error::CreatePipeSnafu)?;
+        let stdout = unsafe { Stdio::from_raw_fd(write_fd) };
+        child.stdout(stdout);
+        // We read stderr with ReadStderr after spawning the child.
+        child.stderr(Stdio::piped());
+        child.stdin(Stdio::piped());
+
+        Ok(Self {
+            read_fd,
+            write_fd: None,
+            close_fd: None,
+        })
+    }
+}
+
 /// Set CLOEXEC on the given file descriptor so it's automatically closed in child processes.
 fn cloexec(fd: RawFd) -> Result<()> {
     // First, get the current settings.
@@ -375,6 +512,72 @@ fn dup(fd: RawFd) -> Result<RawFd> {
     fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(minimum_fd)).context(error::DupFdSnafu)
 }
 
+/// Transfer tracks the file data of a copy, so we can tell the client what we transferred.
+struct Transfer {
+    size: u64,
+    digest: Sha256,
+}
+
+impl Transfer {
+    fn new() -> Self {
+        Self {
+            size: 0,
+            digest: Sha256::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.size += data.len() as u64;
+        self.digest.update(data);
+    }
+
+    fn finish(self, stderr: String) -> CopyResult {
+        let sha256 = self
+            .digest
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        CopyResult {
+            size: self.size,
+            sha256,
+            stderr,
+        }
+    }
+}
+
+/// CopyOutcome holds the channels that tell us the result of a copy once the child is done.
+struct CopyOutcome {
+    /// The thread handling file data sends what it transferred on this channel.
+    transfer_rx: Receiver<Transfer>,
+    /// ReadStderr sends the error output of the child on this channel.
+    stderr_rx: Receiver<String>,
+}
+
+/// ReadStderr is responsible for collecting the error output of a child that's copying a file, so
+/// it can be sent to the client when the copy is done.
+struct ReadStderr {
+    /// The error output is sent on this channel once the child closes stderr.
+    stderr_rx: Receiver<String>,
+}
+
+impl ReadStderr {
+    fn new(mut stderr: ChildStderr) -> Self {
+        let (stderr_tx, stderr_rx) = sync_channel(1);
+
+        debug!("Spawning thread to read error output from child");
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Err(e) = stderr.read_to_end(&mut output) {
+                error!("Failed reading error output from child: {}", e);
+            }
+            let _ = stderr_tx.send(String::from_utf8_lossy(&output).into_owned());
+        });
+
+        Self { stderr_rx }
+    }
+}
+
 /// WriteToChild is responsible for accepting user input from a channel connected to the WebSocket
 /// and writing that input to the child's stdin.  Based on its write progress, it sends capacity
 /// updates back to the client (through the WebSocket actor) so the client knows how much progress
@@ -389,7 +592,14 @@ impl WriteToChild {
     /// * write_fd: The stdin FD of the child to which we'll write process input.
     ///
     /// * ws_addr: The address of the WebSocket actor, to which we'll send capacity updates.
-    fn new(write_fd: RawFd, ws_addr: Addr<WsExec>) -> Self {
+    ///
+    /// * transfer_tx: If given, the input is file data for a copy, and we'll send what we wrote
+    ///   to this channel once the input is complete.
+    fn new(
+        write_fd: RawFd,
+        ws_addr: Addr<WsExec>,
+        transfer_tx: Option<SyncSender<Transfer>>,
+    ) -> Self {
         // Create a File from the FD so we can use convenience methods like write_all.
         // This method is marked unsafe to represent that it takes sole ownership of the fd; we
         // dup() write_fd so closes of read_fd or write_fd don't break the other.
@@ -401,16 +611,22 @@ impl WriteToChild {
         let (write_tx, write_rx) = sync_channel(MAX_MESSAGES_OUTSTANDING as usize);
 
         debug!("Spawning thread to write to child");
-        thread::spawn(move || Self::write_to_child(write_file, ws_addr, write_rx));
+        thread::spawn(move || Self::write_to_child(write_file, ws_addr, write_rx, transfer_tx));
 
         Self { write_tx }
     }
 
-    fn write_to_child(mut file: File, ws_addr: Addr<WsExec>, write_rx: Receiver<Bytes>) {
+    fn write_to_child(
+        mut file: File,
+        ws_addr: Addr<WsExec>,
+        write_rx: Receiver<Bytes>,
+        transfer_tx: Option<SyncSender<Transfer>>,
+    ) {
         // Keep track of the number of messages we've written to the child.  We put this in the
         // capacity update to the client, and use it to determine whether we're ready to send an
         // update.
         let mut messages_written = 0u64;
+        let mut transfer = transfer_tx.as_ref().map(|_| Transfer::new());
 
         while let Ok(data) = write_rx.recv() {
             // If we can't write to the child process, end the loop and drop the channel so the
@@ -419,6 +635,9 @@ impl WriteToChild {
                 error!("Failed to write to child process: {}", e);
                 break;
             }
+            if let Some(transfer) = transfer.as_mut() {
+                transfer.update(&data);
+            }
 
             messages_written += 1;
             // Every so often, send a capacity update to the client so it knows what we've written
@@ -434,6 +653,12 @@ impl WriteToChild {
                 ws_addr.do_send(message::CapacityUpdate(capacity));
             }
         }
+
+        // Close the child's stdin before reporting the transfer, so it knows the file is done.
+        drop(file);
+        if let (Some(transfer_tx), Some(transfer)) = (transfer_tx, transfer) {
+            let _ = transfer_tx.send(transfer);
+        }
     }
 }
 
@@ -445,38 +670,56 @@ impl WaitForChild {
     /// Parameters:
     /// * pid: The child process ID we're waiting for.
     ///
-    /// * ws_addr: The address of the WebSocket actor, to which we'll send the return code.
+    /// * ws_addr: The address of the WebSocket actor, to which we'll send the exit status.
     ///
     /// * read_complete_rx: We should receive a signal on this channel when the reader thread is
     ///   finished.  PTY I/O is buffered in the kernel, so when a process exits, it doesn't mean
     ///   we're done reading from the PTY; this lets us be sure.
-    fn new(pid: Pid, ws_addr: Addr<WsExec>, read_complete_rx: Receiver<()>) -> Self {
+    ///
+    /// * copy_outcome: If the child is copying a file, this gives us the result of the copy, which
+    ///   we'll also send.
+    fn new(
+        pid: Pid,
+        ws_addr: Addr<WsExec>,
+        read_complete_rx: Receiver<()>,
+        copy_outcome: Option<CopyOutcome>,
+    ) -> Self {
         debug!("Spawning thread to wait for child exit");
-        thread::spawn(move || Self::wait_for_child(pid, ws_addr, read_complete_rx));
+        thread::spawn(move || Self::wait_for_child(pid, ws_addr, read_complete_rx, copy_outcome));
 
         Self {}
     }
 
-    fn wait_for_child(pid: Pid, ws_addr: Addr<WsExec>, read_complete_rx: Receiver<()>) {
+    fn wait_for_child(
+        pid: Pid,
+        ws_addr: Addr<WsExec>,
+        read_complete_rx: Receiver<()>,
+        copy_outcome: Option<CopyOutcome>,
+    ) {
         // Wait for the child to exit.  (Command::wait closes stdin; we need more control.)
         let res = waitpid(Some(pid), None);
         debug!("Child process exited");
 
-        let code = match res {
+        let exit = match res {
             // If it exited with a code, use that.
-            Ok(WaitStatus::Exited(_pid, code)) => code,
+            Ok(WaitStatus::Exited(_pid, code)) => Exit {
+                code: Some(code),
+                signal: None,
+            },
 
-            // Use shell-style return codes for signals.
-            Ok(WaitStatus::Signaled(_pid, signal, _core)) => {
-                // (nix signals are repr(i32) to match c_int from libc.)
-                let signal_int = signal as i32;
-                128 + signal_int
-            }
+            // (nix signals are repr(i32) to match c_int from libc.)
+            Ok(WaitStatus::Signaled(_pid, signal, _core)) => Exit {
+                code: None,
+                signal: Some(signal as i32),
+            },
 
             // waitpid() shouldn't complete unless the process terminated, since we didn't request
             // notification of stopped/signaled processes.  If we get here, we don't know what
-            // happened and don't have a useful code to send.
-            _ => 0,
+            // happened and don't have anything useful to send.
+            _ => Exit {
+                code: None,
+                signal: None,
+            },
         };
 
         // Wait for reads to complete from the PTY, if possible; PTYs are buffered so we may not
@@ -491,9 +734,21 @@ impl WaitForChild {
         // few milliseconds, but losing output is bad.
         let _ = read_complete_rx.recv_timeout(Duration::from_millis(500));
 
-        // Return code is a mandatory message back to client, so use do_send to ignore mailbox
+        // For a copy, collect what was transferred.  The file data is complete by now, unless the
+        // client didn't finish sending it, in which case the copy failed anyway.
+        let copy = copy_outcome.map(|outcome| {
+            let timeout = Duration::from_millis(500);
+            let transfer = outcome
+                .transfer_rx
+                .recv_timeout(timeout)
+                .unwrap_or_else(|_| Transfer::new());
+            let stderr = outcome.stderr_rx.recv_timeout(timeout).unwrap_or_default();
+            transfer.finish(stderr)
+        });
+
+        // Exit status is a mandatory message back to client, so use do_send to ignore mailbox
         // limits.
-        ws_addr.do_send(message::ProcessReturn { code });
+        ws_addr.do_send(message::ProcessReturn { exit, copy });
     }
 }
 
@@ -509,16 +764,30 @@ impl ReadFromChild {
     /// * read_fd: The file descriptor of the child from which we'll read process output.
     ///
     /// * ws_addr: The address of the WebSocket actor, to which we'll send process output.
-    fn new(read_fd: RawFd, ws_addr: Addr<WsExec>) -> Self {
+    ///
+    /// * transfer_tx: If given, the child is copying a file from the container, and we'll send
+    ///   what we read to this channel once we're done.
+    fn new(
+        read_fd: RawFd,
+        ws_addr: Addr<WsExec>,
+        transfer_tx: Option<SyncSender<Transfer>>,
+    ) -> Self {
         let (complete_tx, complete_rx) = sync_channel(1);
 
         debug!("Spawning thread to read from child");
-        thread::spawn(move || Self::read_from_child(read_fd, ws_addr, complete_tx));
+        thread::spawn(move || Self::read_from_child(read_fd, ws_addr, complete_tx, transfer_tx));
 
         Self { complete_rx }
     }
 
-    fn read_from_child(fd: RawFd, ws_addr: Addr<WsExec>, complete_tx: SyncSender<()>) {
+    fn read_from_child(
+        fd: RawFd,
+        ws_addr: Addr<WsExec>,
+        complete_tx: SyncSender<()>,
+        transfer_tx: Option<SyncSender<Transfer>>,
+    ) {
+        let mut copy = transfer_tx.as_ref().map(|_| CopyFrom::new());
+
         // Read until the process is done or we fail.
         'outer: loop {
             // Read a batch of data at a time; 4k is a balanced number for small and large jobs.
@@ -532,6 +801,14 @@ impl ReadFromChild {
                     // Don't store extra zeroes if the child didn't have a full buffer's worth.
                     output.truncate(n);
 
+                    // For a copy, only send the file data itself.
+                    if let Some(copy) = copy.as_mut() {
+                        output = copy.file_data(output, &ws_addr);
+                        if output.is_empty() {
+                            continue;
+                        }
+                    }
+
                     // Send the output to the WebSocket actor for transmission to the client.  If
                     // the actor's mailbox is full, just keep trying; we don't have to worry about
                     // backpressure here because there are no buffers filling up, the child can't
@@ -575,11 +852,63 @@ impl ReadFromChild {
                 }
             }
         }
-        // Notify that we're done reading.
+        // Report what we read for a copy, then notify that we're done reading.
+        if let (Some(transfer_tx), Some(copy)) = (transfer_tx, copy) {
+            let _ = transfer_tx.send(copy.transfer);
+        }
         let _ = complete_tx.try_send(());
     }
 }
 
+/// CopyFrom tracks the output of a child that's copying a file from the container.  The output
+/// starts with a line giving the permissions and size of the file, which we send to the client as
+/// FileInfo, and the rest is file data.
+struct CopyFrom {
+    /// The first line of output, until we've seen all of it.
+    header: Option<Vec<u8>>,
+    transfer: Transfer,
+}
+
+impl CopyFrom {
+    fn new() -> Self {
+        Self {
+            header: Some(Vec::new()),
+            transfer: Transfer::new(),
+        }
+    }
+
+    /// Returns the file data in the given output, sending FileInfo to the client first.
+    fn file_data(&mut self, mut output: Vec<u8>, ws_addr: &Addr<WsExec>) -> Vec<u8> {
+        if let Some(header) = self.header.as_mut() {
+            let Some(end) = output.iter().position(|b| *b == b'\n') else {
+                header.append(&mut output);
+                return output;
+            };
+            header.extend(output.drain(..=end));
+            match Self::parse_header(header) {
+                // FileInfo is a mandatory message, and must be sent before the data, so use
+                // do_send to ignore mailbox limits.
+                Some(info) => ws_addr.do_send(message::FileInfo(info)),
+                // The client won't accept data without FileInfo, and will report the failure.
+                None => error!("Copy sent invalid file info: {:?}", header),
+            }
+            self.header = None;
+        }
+        self.transfer.update(&output);
+        output
+    }
+
+    /// Parses the permissions, in octal, and the size of a file, as given by COPY_FROM_SCRIPT.
+    fn parse_header(header: &[u8]) -> Option<FileInfo> {
+        let header = std::str::from_utf8(header).ok()?;
+        let (mode, size) = header.trim_end().split_once(' ')?;
+        Some(FileInfo {
+            mode: u32::from_str_radix(mode, 8).ok()?,
+            size: size.parse().ok()?,
+        })
+    }
+}
+
 mod error {
     use snafu::Snafu;
     use std::io;
@@ -605,6 +934,9 @@ mod error {
         #[snafu(display("Child had invalid PID '{}', should never happen", given))]
         InvalidPid { given: u32 },
 
+        #[snafu(display("Child has no stderr, should never happen"))]
+        NoStderr,
+
         #[snafu(display("Child has no stdin, should never happen"))]
         NoStdin,
 
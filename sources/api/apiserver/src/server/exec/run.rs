@@ -0,0 +1,234 @@
+//! The 'run' module lets clients run a command in another container with a plain HTTP request,
+//! for callers like orchestration tools that don't need interaction and would rather not manage a
+//! WebSocket.  The process is started through ctr, the same way as for the WebSocket, but it gets
+//! no input, and its output is collected and returned once it exits.
+
+use super::child::ctr_command;
+use crate::server::error::{self, Result};
+use crate::server::SharedData;
+use actix_web::{web, HttpResponse};
+use log::{debug, info, warn};
+use model::exec::{Exit, RunRequest, RunResult};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::time::{self, Instant};
+
+/// How long a command may run if the request doesn't say.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+/// The longest timeout a request may give.  This is meant for commands that finish; long-running
+/// programs are better run as host containers, or through the WebSocket.
+const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+/// How many bytes of stdout and of stderr we keep.  Anything past this is read and dropped, so the
+/// command isn't blocked on a full pipe.
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+/// How long a command has to exit after we ask it to stop, before we kill ctr.
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// Once the command exits, how long we wait for the rest of its output.  Something else in the
+/// container could have inherited the output pipes and kept them open.
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Runs the requested command to completion and responds with its output and exit status.
+pub(crate) async fn exec_run(
+    req: web::Json<RunRequest>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let req = req.into_inner();
+    ensure!(
+        !req.target.is_empty(),
+        error::InvalidExecRunSnafu {
+            reason: "target must not be empty"
+        }
+    );
+    ensure!(
+        !req.command.is_empty(),
+        error::InvalidExecRunSnafu {
+            reason: "command must not be empty"
+        }
+    );
+    if let Some(name) = req
+        .env
+        .keys()
+        .find(|name| name.is_empty() || name.contains('='))
+    {
+        return error::InvalidExecRunSnafu {
+            reason: format!("invalid environment variable name '{}'", name),
+        }
+        .fail();
+    }
+    let timeout = match req.timeout.map(Duration::from_secs) {
+        Some(timeout) if timeout.is_zero() || timeout > MAX_TIMEOUT => {
+            return error::InvalidExecRunSnafu {
+                reason: format!(
+                    "timeout must be between 1 and {} seconds",
+                    MAX_TIMEOUT.as_secs()
+                ),
+            }
+            .fail();
+        }
+        Some(timeout) => timeout,
+        None => DEFAULT_TIMEOUT,
+    };
+
+    // ctr runs processes with the container's environment and can't add to it, so we have env add
+    // any requested variables inside the container.
+    let mut args = Vec::new();
+    if !req.env.is_empty() {
+        args.push("env".to_string());
+        args.extend(
+            req.env
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value)),
+        );
+    }
+    args.extend(req.command);
+
+    info!(
+        "Running command in target container '{}' with timeout {:?}",
+        req.target, timeout
+    );
+    let mut command = Command::from(ctr_command(
+        &data.exec_socket_path,
+        &req.target,
+        &args,
+        false,
+    ));
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    debug!("Spawning command for exec run request: {:?}", command);
+    let mut child = command.spawn().context(error::ExecRunSpawnSnafu)?;
+    let pid = child
+        .id()
+        .and_then(|id| i32::try_from(id).ok())
+        .map(Pid::from_raw)
+        .context(error::ExecRunProcessSnafu)?;
+
+    let mut stdout_pipe = child.stdout.take();
+    let mut stderr_pipe = child.stderr.take();
+    let mut stdout = Output::default();
+    let mut stderr = Output::default();
+    let mut stdout_buf = vec![0; 4096];
+    let mut stderr_buf = vec![0; 4096];
+
+    let mut status = None;
+    let mut timed_out = false;
+    let deadline = time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    // Collect output until the process exits and its output is closed, stopping the process if it
+    // runs too long.
+    while status.is_none() || stdout_pipe.is_some() || stderr_pipe.is_some() {
+        tokio::select! {
+            n = read_some(&mut stdout_pipe, &mut stdout_buf) => match n {
+                Ok(n) if n > 0 => stdout.push(&stdout_buf[..n]),
+                _ => stdout_pipe = None,
+            },
+            n = read_some(&mut stderr_pipe, &mut stderr_buf) => match n {
+                Ok(n) if n > 0 => stderr.push(&stderr_buf[..n]),
+                _ => stderr_pipe = None,
+            },
+            exit_status = child.wait(), if status.is_none() => {
+                status = Some(exit_status.context(error::ExecRunWaitSnafu)?);
+                deadline.as_mut().reset(Instant::now() + OUTPUT_GRACE_PERIOD);
+            }
+            _ = &mut deadline => {
+                if status.is_some() {
+                    debug!("Output still open after command exited, returning what we have");
+                    break;
+                }
+                if timed_out {
+                    // ctr didn't exit after SIGTERM, so kill it; the command may live on in the
+                    // container, but we can't wait for it forever.
+                    warn!("Command didn't stop after {:?}, killing ctr", STOP_GRACE_PERIOD);
+                    let _ = child.start_kill();
+                } else {
+                    // ctr forwards signals to the command because it has no TTY, whereas SIGKILL
+                    // would only stop ctr itself.
+                    info!("Command ran past its timeout of {:?}, stopping it", timeout);
+                    timed_out = true;
+                    let _ = kill(pid, Signal::SIGTERM);
+                }
+                deadline.as_mut().reset(Instant::now() + STOP_GRACE_PERIOD);
+            }
+        }
+    }
+
+    // The loop only ends once we have a status.
+    let status = status.context(error::ExecRunProcessSnafu)?;
+    let result = RunResult {
+        exit: Exit {
+            code: status.code(),
+            signal: status.signal(),
+        },
+        stdout: String::from_utf8_lossy(&stdout.data).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr.data).into_owned(),
+        stdout_truncated: stdout.truncated,
+        stderr_truncated: stderr.truncated,
+        timed_out,
+    };
+    info!(
+        "exec run returned code {:?}, signal {:?}",
+        result.exit.code, result.exit.signal
+    );
+
+    let body = serde_json::to_string(&result).context(error::ResponseSerializationSnafu)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(body))
+}
+
+/// Reads from the given pipe, if it's still open; otherwise, never returns, so the caller can keep
+/// selecting on it.
+async fn read_some<R>(pipe: &mut Option<R>, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    match pipe {
+        Some(pipe) => pipe.read(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Output holds what we've kept of a stream of process output.
+#[derive(Debug, Default)]
+struct Output {
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+impl Output {
+    /// Keeps as much of the given output as fits under MAX_OUTPUT_BYTES.
+    fn push(&mut self, output: &[u8]) {
+        let room = MAX_OUTPUT_BYTES.saturating_sub(self.data.len());
+        if output.len() > room {
+            self.truncated = true;
+        }
+        self.data
+            .extend_from_slice(&output[..output.len().min(room)]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn output_limit() {
+        let mut output = Output::default();
+        output.push(&vec![b'a'; MAX_OUTPUT_BYTES - 1]);
+        assert!(!output.truncated);
+        output.push(b"bc");
+        assert!(output.truncated);
+        assert_eq!(output.data.len(), MAX_OUTPUT_BYTES);
+        assert_eq!(output.data.last(), Some(&b'b'));
+    }
+}
@@ -1,41 +1,54 @@
 //! The server module owns the API surface.  It interfaces with the datastore through the
 //! server::controller module.
 
+mod audit;
 mod controller;
 mod ephemeral_storage;
 mod error;
 mod exec;
+mod peer;
+mod policy;
+mod watch;
 
 pub use error::Error;
 
 use actix_web::{
-    body::BoxBody, error::ResponseError, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+    body::BoxBody, error::ResponseError, guard, http::header, middleware, web, App,
+    CustomizeResponder, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use datastore::{serialize_scalar, Committed, FilesystemDataStore, Key, KeyType, Value};
+use datastore::snapshot::Snapshot;
+use datastore::{serialize_scalar, Commit, Committed, DefaultDataStore, Key, KeyType, Value};
 use error::Result;
 use fs2::FileExt;
 use http::StatusCode;
-use log::info;
+use log::{error, info};
 use model::ephemeral_storage::{Bind, Init};
+use model::plan::{RenderRequest, TransactionPlan};
 use model::{ConfigurationFiles, Model, Report, Services, Settings};
 use nix::unistd::{chown, Gid};
 use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{set_permissions, File, Permissions};
+use std::fs::{self, set_permissions, File, Permissions};
+use std::io;
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
 use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync;
-use thar_be_updates::status::{UpdateStatus, UPDATE_LOCKFILE};
+use thar_be_updates::status::UPDATE_LOCKFILE;
 use tokio::process::Command as AsyncCommand;
 
 const BLOODHOUND_BIN: &str = "/usr/bin/bloodhound";
 const BLOODHOUND_K8S_CHECKS: &str = "/usr/libexec/cis-checks/kubernetes";
 const BLOODHOUND_FIPS_CHECKS: &str = "/usr/libexec/fips-checks/bottlerocket";
 
+/// Content types of the patch documents accepted by PATCH /settings, besides plain JSON.
+const JSON_PATCH: &str = "application/json-patch+json";
+const MERGE_PATCH: &str = "application/merge-patch+json";
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // sd_notify helper
@@ -62,24 +75,37 @@ fn notify_unix_socket_ready() -> Result<()> {
 /// This is the primary interface of the module.  It defines the server and application that actix
 /// spawns for requests.  It creates a shared datastore handle that can be used by handler methods
 /// to interface with the controller.
-pub async fn serve<P1, P2, P3>(
+pub async fn serve<P1, P2, P3, P4, P5>(
     socket_path: P1,
     datastore_path: P2,
     threads: usize,
     socket_gid: Option<Gid>,
     exec_socket_path: P3,
+    audit_log_path: P4,
+    policy_path: Option<P5>,
 ) -> Result<()>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
     P3: Into<PathBuf>,
+    P4: Into<PathBuf>,
+    P5: AsRef<Path>,
 {
+    // Load the policy first, so a bad policy stops us before we open the socket.
+    let policy = match policy_path {
+        Some(policy_path) => Some(sync::Arc::new(policy::Policy::from_path(policy_path)?)),
+        None => None,
+    };
+
     // SharedData gives us a convenient way to make data available to handler methods when it
     // doesn't come from the request itself.  It's easier than the ownership tricks required to
     // pass parameters to the handler methods.
     let shared_data = web::Data::new(SharedData {
-        ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
+        ds: sync::RwLock::new(DefaultDataStore::new(datastore_path)),
         exec_socket_path: exec_socket_path.into(),
+        watchers: watch::Watchers::default(),
+        audit_log: audit::AuditLog::new(audit_log_path),
+        policy,
     });
 
     let http_server = HttpServer::new(move || {
@@ -87,13 +113,32 @@ where
             // This makes the data store available to API methods merely by having a Data
             // parameter.
             .app_data(shared_data.clone())
+            // Check each request against the policy, if any, before handling it.
+            .wrap(middleware::from_fn(policy::authorize))
             // Retrieve the full API model; not all data is writable, so we only support GET.
             .route("/", web::get().to(get_model))
             .service(
                 web::scope("/settings")
                     .route("", web::get().to(get_settings))
+                    // Patch documents are JSON too, so these have to be matched before the
+                    // plain JSON route.
+                    .route(
+                        "",
+                        web::patch()
+                            .guard(content_type(JSON_PATCH))
+                            .to(json_patch_settings),
+                    )
+                    .route(
+                        "",
+                        web::patch()
+                            .guard(content_type(MERGE_PATCH))
+                            .to(merge_patch_settings),
+                    )
                     .route("", web::patch().to(patch_settings))
-                    .route("/keypair", web::patch().to(patch_settings_key_pair)),
+                    .route("", web::delete().to(delete_settings))
+                    .route("/keypair", web::patch().to(patch_settings_key_pair))
+                    .route("/schema", web::get().to(get_settings_schema))
+                    .route("/watch", web::get().to(watch::ws_watch)),
             )
             .service(
                 // Transaction support
@@ -101,13 +146,24 @@ where
                     .route("/list", web::get().to(get_transaction_list))
                     .route("", web::get().to(get_transaction))
                     .route("", web::delete().to(delete_transaction))
+                    .route("/settings", web::get().to(get_transaction_settings))
+                    .route("/patch", web::get().to(get_transaction_patch))
                     .route("/commit", web::post().to(commit_transaction))
+                    .route("/plan", web::post().to(plan_transaction))
                     .route("/apply", web::post().to(apply_changes))
                     .route(
                         "/commit_and_apply",
                         web::post().to(commit_transaction_and_apply),
-                    ),
+                    )
+                    .route("/history", web::get().to(get_commit_history))
+                    .route("/rollback", web::post().to(rollback_commit)),
+            )
+            .service(
+                web::scope("/snapshot")
+                    .route("", web::get().to(get_snapshot))
+                    .route("", web::post().to(import_snapshot)),
             )
+            .service(web::scope("/audit").route("", web::get().to(get_audit_log)))
             .service(web::scope("/os").route("", web::get().to(get_os_info)))
             .service(
                 web::scope("/metadata")
@@ -127,6 +183,7 @@ where
                     .route("/prepare-update", web::post().to(prepare_update))
                     .route("/activate-update", web::post().to(activate_update))
                     .route("/deactivate-update", web::post().to(deactivate_update))
+                    .route("/rollback", web::post().to(rollback))
                     .route(
                         "/ephemeral-storage/init",
                         web::post().to(initialize_ephemeral_storage),
@@ -146,6 +203,7 @@ where
             )
             .service(web::scope("/updates").route("/status", web::get().to(get_update_status)))
             .service(web::resource("/exec").route(web::get().to(exec::ws_exec)))
+            .service(web::resource("/exec/run").route(web::post().to(exec::exec_run)))
             .service(
                 web::scope("/report")
                     .route("", web::get().to(list_reports))
@@ -153,8 +211,13 @@ where
                     .route("/fips", web::get().to(get_fips_report)),
             )
     })
+    // The kernel can tell us who's on the other end of each connection; this makes that available
+    // to handlers, for the audit log and policy.
+    .on_connect(peer::store_peer_credentials)
     .workers(threads)
-    .bind_uds(socket_path.as_ref())
+    // HttpServer::bind_uds doesn't call on_connect handlers, so we bind the socket ourselves and
+    // use listen_uds, which does.  Like bind_uds, remove any old socket first.
+    .listen_uds(bind_socket(socket_path.as_ref())?)
     .context(error::BindSocketSnafu {
         path: socket_path.as_ref(),
     })?;
@@ -175,6 +238,16 @@ where
     http_server.run().await.context(error::ServerStartSnafu)
 }
 
+/// Binds the API socket at the given path, replacing any socket left from an earlier run.
+fn bind_socket(socket_path: &Path) -> Result<UnixListener> {
+    match fs::remove_file(socket_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context(error::BindSocketSnafu { path: socket_path }),
+    }
+    UnixListener::bind(socket_path).context(error::BindSocketSnafu { path: socket_path })
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // Handler methods called by the router
@@ -268,11 +341,12 @@ async fn get_model_prefix(data: web::Data<SharedData>, prefix: &str) -> Result<M
 // actix-web doesn't support Query for enums, so we use a HashMap and check for the expected keys
 // ourselves.
 /// Return the live settings from the data store; if 'keys' or 'prefix' are specified in query
-/// parameters, return the subset of matching settings.
+/// parameters, return the subset of matching settings.  The ETag header holds the revision of the
+/// live data store, which can be given in an If-Match header to requests that change settings.
 async fn get_settings(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
-) -> Result<SettingsResponse> {
+) -> Result<CustomizeResponder<SettingsResponse>> {
     let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
 
     let settings = if let Some(keys_str) = query.get("keys") {
@@ -293,32 +367,105 @@ async fn get_settings(
     } else {
         controller::get_settings(&*datastore, &Committed::Live)
     }?;
+    let revision = controller::get_revision(&*datastore)?;
 
-    Ok(SettingsResponse(settings))
+    Ok(SettingsResponse(settings)
+        .customize()
+        .insert_header((header::ETAG, etag(revision))))
 }
 
 /// Apply the requested settings to the pending data store
 async fn patch_settings(
+    req: HttpRequest,
     settings: web::Json<Settings>,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<HttpResponse> {
+    policy::check_settings(&req, &controller::settings_keys(&settings)?)?;
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
     controller::set_settings(&mut *datastore, &settings, transaction)?;
     Ok(HttpResponse::NoContent().finish()) // 204
 }
 
+/// Apply an RFC 6902 JSON Patch to the settings as they'd be after the transaction is committed,
+/// and save the result to the pending data store.  Returns the keys that were changed.
+async fn json_patch_settings(
+    req: HttpRequest,
+    patch: web::Json<json_patch::Patch>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
+    let plan = controller::plan_settings_patch(&*datastore, transaction, |settings| {
+        controller::apply_json_patch(settings, &patch)
+    })?;
+    let keys = plan.keys();
+    policy::check_settings(&req, &keys)?;
+    controller::stage_settings_patch(&mut *datastore, &plan, transaction)?;
+    Ok(ChangedKeysResponse(keys))
+}
+
+/// Apply an RFC 7396 merge patch, in which null removes a setting, to the settings as they'd be
+/// after the transaction is committed, and save the result to the pending data store.  Returns the
+/// keys that were changed.
+async fn merge_patch_settings(
+    req: HttpRequest,
+    patch: web::Json<serde_json::Value>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
+    let plan = controller::plan_settings_patch(&*datastore, transaction, |settings| {
+        controller::apply_merge_patch(settings, &patch)
+    })?;
+    let keys = plan.keys();
+    policy::check_settings(&req, &keys)?;
+    controller::stage_settings_patch(&mut *datastore, &plan, transaction)?;
+    Ok(ChangedKeysResponse(keys))
+}
+
+/// Mark the settings given in the 'keys' query parameter, and any settings under them, for removal
+/// in the pending data store.  As with 'set', the "settings." prefix is optional.  Returns the keys
+/// that will be removed when the transaction is committed.
+async fn delete_settings(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let keys_str = query
+        .get("keys")
+        .context(error::MissingInputSnafu { input: "keys" })?;
+    let keys = comma_separated("keys", keys_str)?
+        .into_iter()
+        .map(settings_key)
+        .collect::<Result<HashSet<Key>>>()?;
+    policy::check_settings(&req, &keys)?;
+    let transaction = transaction_name(&query);
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
+    let removed = controller::unset_settings(&mut *datastore, &keys, transaction)?;
+    Ok(ChangedKeysResponse(removed))
+}
+
 // Apply the requested settings in Key Value pair.
 async fn patch_settings_key_pair(
+    req: HttpRequest,
     settings: web::Json<SetKeyPairSettings>,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<HttpResponse> {
     // Convert to a Map of Key Value pairs.
     let settings_key_pair_map = construct_key_pair_map(&settings.request_payload)?;
+    policy::check_settings(&req, settings_key_pair_map.keys())?;
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
     // We massage the values in the input key pair map.
     // The data store deserialization code understands how to turn the key names
     // (a.b.c) and serialized values into the nested Settings structure.
@@ -345,6 +492,30 @@ async fn get_transaction(
     Ok(SettingsResponse(data))
 }
 
+/// Get any pending changes in the given transaction, or the "default" transaction if unspecified,
+/// as a JSON merge patch; settings the transaction removes are null.
+async fn get_transaction_patch(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<TransactionPatchResponse> {
+    let transaction = transaction_name(&query);
+    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let data = controller::get_transaction_patch(&*datastore, transaction)?;
+    Ok(TransactionPatchResponse(data))
+}
+
+/// Get the settings as they'd be after the given transaction is committed: live settings with its
+/// pending changes and removals applied.
+async fn get_transaction_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<SettingsResponse> {
+    let transaction = transaction_name(&query);
+    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let settings = controller::get_pending_settings(&*datastore, transaction)?;
+    Ok(SettingsResponse(settings))
+}
+
 /// Delete the given transaction, or the "default" transaction if unspecified.
 async fn delete_transaction(
     query: web::Query<HashMap<String, String>>,
@@ -359,21 +530,65 @@ async fn delete_transaction(
 /// Save settings changes from the given transaction, or the "default" transaction if unspecified,
 /// to the live data store.  Returns the list of changed keys.
 async fn commit_transaction(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
 
     let changes = controller::commit_transaction(&mut *datastore, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPendingSnafu.fail();
     }
+    record_commit(&req, &data, &datastore);
+    notify_watchers(&data, &datastore, transaction, &changes);
 
     Ok(ChangedKeysResponse(changes))
 }
 
+/// Shows what committing and applying the given transaction, or the "default" transaction if
+/// unspecified, would do, without changing anything: the keys that would change, the services
+/// that would be restarted, and a diff of each of their configuration files rendered against the
+/// pending settings.
+async fn plan_transaction(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<TransactionPlanResponse> {
+    let transaction = transaction_name(&query);
+
+    // Gather what we need from the data store first, so we don't hold the lock while rendering.
+    let (changed_keys, services, configuration_files, settings) = {
+        let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+        let changed_keys = controller::get_transaction_keys(&*datastore, transaction)?;
+        ensure!(!changed_keys.is_empty(), error::PlanWithNoPendingSnafu);
+        let services = controller::get_affected_services(&*datastore, &changed_keys)?;
+        let configuration_files =
+            controller::get_services_configuration_files(&*datastore, &services)?;
+        let settings = controller::get_pending_settings(&*datastore, transaction)?;
+        (changed_keys, services, configuration_files, settings)
+    };
+    let os = controller::get_os_info()?;
+
+    let request = RenderRequest {
+        configuration_files,
+        template_data: serde_json::json!({
+            "settings": settings,
+            "os": os,
+        }),
+    };
+    let configuration_files = controller::render_configuration_files(&request).await?;
+
+    Ok(TransactionPlanResponse(TransactionPlan {
+        transaction: transaction.to_string(),
+        changed_keys: changed_keys.iter().map(|key| key.name().clone()).collect(),
+        services,
+        configuration_files,
+    }))
+}
+
 /// Starts settings appliers for any changes that have been committed to the data store.  This
 /// updates config files, runs restart commands, etc.
 async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
@@ -391,17 +606,56 @@ async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<Htt
 /// perform both a commit and an apply.  Commits the given transaction, or the "default"
 /// transaction if unspecified.
 async fn commit_transaction_and_apply(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    check_revision(&req, &datastore)?;
 
     let changes = controller::commit_transaction(&mut *datastore, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPendingSnafu.fail();
     }
+    record_commit(&req, &data, &datastore);
+    notify_watchers(&data, &datastore, transaction, &changes);
+
+    let key_names = changes.iter().map(|k| k.name()).collect();
+    controller::apply_changes(Some(&key_names))?;
+
+    Ok(ChangedKeysResponse(changes))
+}
+
+/// Returns the history of commits to the live data store, oldest first, including the value of
+/// each changed key before and after the commit.
+async fn get_commit_history(data: web::Data<SharedData>) -> Result<CommitHistoryResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let commits = controller::list_commits(&*datastore)?;
+    Ok(CommitHistoryResponse(commits))
+}
+
+/// Rolls back the commit with the ID given in the 'id' query parameter, restoring the live values
+/// of the keys it changed, and then applies the changes like commit_and_apply.  Returns the list of
+/// changed keys.
+async fn rollback_commit(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let id_str = query
+        .get("id")
+        .context(error::MissingInputSnafu { input: "id" })?;
+    let id = id_str
+        .parse()
+        .context(error::InvalidCommitIdSnafu { input: id_str })?;
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    policy::check_settings(&req, &controller::rollback_keys(&*datastore, id)?)?;
+
+    let changes = controller::rollback_commit(&mut *datastore, id)?;
+    record_commit(&req, &data, &datastore);
+    notify_watchers(&data, &datastore, &format!("rollback-{}", id), &changes);
 
     let key_names = changes.iter().map(|k| k.name()).collect();
     controller::apply_changes(Some(&key_names))?;
@@ -409,6 +663,70 @@ async fn commit_transaction_and_apply(
     Ok(ChangedKeysResponse(changes))
 }
 
+/// Returns the audit log of commits made through the API, oldest first.  If the 'limit' query
+/// parameter is given, only that many of the most recent entries are returned.
+async fn get_audit_log(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<AuditLogResponse> {
+    let limit = match query.get("limit") {
+        Some(limit) => Some(
+            limit
+                .parse()
+                .context(error::InvalidAuditLimitSnafu { input: limit })?,
+        ),
+        None => None,
+    };
+    let entries = data.audit_log.entries(limit)?;
+    Ok(AuditLogResponse(entries))
+}
+
+/// Returns a snapshot of live settings and their metadata, which can be saved and restored later
+/// or on another host with import_snapshot.  Settings with a setting generator, like the hostname,
+/// are only included if you pass 'include-generated=true'.
+async fn get_snapshot(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<SnapshotResponse> {
+    let include_generated = match query.get("include-generated") {
+        Some(include_generated) => include_generated.parse().context(error::InvalidBoolSnafu {
+            name: "include-generated",
+            input: include_generated,
+        })?,
+        None => false,
+    };
+    let os = controller::get_os_info()?;
+    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let snapshot =
+        controller::export_snapshot(&*datastore, os.version_id.to_string(), include_generated)?;
+    Ok(SnapshotResponse(snapshot))
+}
+
+/// Restores the settings in the given snapshot into a pending transaction, checking them against
+/// the data model first; live settings that aren't in the snapshot are marked for removal.
+/// Returns the keys that were written or marked for removal; like any other pending change,
+/// they're not live until the transaction is committed.
+async fn import_snapshot(
+    req: HttpRequest,
+    snapshot: web::Json<Snapshot>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    policy::check_settings(&req, &controller::snapshot_keys(&*datastore, &snapshot)?)?;
+    check_revision(&req, &datastore)?;
+    let written = controller::import_snapshot(&mut *datastore, &snapshot, transaction)?;
+    Ok(ChangedKeysResponse(written))
+}
+
+/// Returns the partial JSON schema of settings: the type of each setting it describes, whether or
+/// not it's set.
+async fn get_settings_schema() -> Result<SettingsSchemaResponse> {
+    let schema = controller::get_settings_schema()?;
+    Ok(SettingsSchemaResponse(schema))
+}
+
 /// Returns information about the OS image, like variant and version.  If you pass a 'prefix' query
 /// string, only field names starting with that prefix will be included.  Returns a
 /// BottlerocketReleaseResponse, which contains a serde_json Value instead of a BottlerocketRelease
@@ -534,15 +852,23 @@ async fn get_configuration_files(
     Ok(ConfigurationFilesResponse(resp))
 }
 
-/// Get the update status from 'thar-be-updates'
-async fn get_update_status() -> Result<UpdateStatusResponse> {
+/// Get the update status from 'thar-be-updates'.  While an update command holds the update lock,
+/// this still responds with 423 Locked, but includes the status as it was last written, so callers
+/// can follow the progress of the command.
+async fn get_update_status() -> Result<HttpResponse> {
     let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpenSnafu)?;
-    lockfile
-        .try_lock_shared()
-        .context(error::UpdateShareLockSnafu)?;
+    if let Err(e) = lockfile.try_lock_shared() {
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(e).context(error::UpdateShareLockSnafu);
+        }
+        return match thar_be_updates::status::get_update_status_in_progress() {
+            Ok(update_status) => Ok(HttpResponse::build(StatusCode::LOCKED).json(update_status)),
+            Err(_) => Err(e).context(error::UpdateShareLockSnafu),
+        };
+    }
     let result = thar_be_updates::status::get_update_status(&lockfile);
     match result {
-        Ok(update_status) => Ok(UpdateStatusResponse(update_status)),
+        Ok(update_status) => Ok(HttpResponse::Ok().json(update_status)),
         Err(e) => match e {
             thar_be_updates::error::Error::NoStatusFile { .. } => {
                 error::UninitializedUpdateStatusSnafu.fail()
@@ -552,19 +878,51 @@ async fn get_update_status() -> Result<UpdateStatusResponse> {
     }
 }
 
-/// Refreshes the list of updates and checks if an update is available matching the configured version lock
-async fn refresh_updates() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["refresh"])
+/// Refreshes the list of updates and checks if an update is available matching the configured version lock.
+/// If you pass a 'source' query string, the updates are listed from the local update repository
+/// at that path, a directory or a block device holding one, instead of the configured repository.
+async fn refresh_updates(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&update_command_args("refresh", &query)?)
 }
 
-/// Prepares update by downloading the images to the staging partition set
-async fn prepare_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["prepare"])
+/// Prepares update by downloading the images to the staging partition set.  If you pass a 'source'
+/// query string, the images are read from the local update repository at that path instead.
+async fn prepare_update(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&update_command_args("prepare", &query)?)
 }
 
-/// "Activates" an already staged update by bumping the priority bits on the staging partition set
-async fn activate_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["activate"])
+/// Returns the arguments to thar-be-updates for the given command, with the update source from the
+/// query string, if any.
+fn update_command_args<'a>(
+    command: &'a str,
+    query: &'a HashMap<String, String>,
+) -> Result<Vec<&'a str>> {
+    let mut args = vec![command];
+    if let Some(source) = query.get("source") {
+        ensure!(
+            !source.is_empty(),
+            error::EmptyInputSnafu { input: "source" }
+        );
+        args.extend(["--source", source]);
+    }
+    Ok(args)
+}
+
+/// "Activates" an already staged update by bumping the priority bits on the staging partition set.
+/// If you pass 'force=true', it's activated even outside of updog's maintenance windows.
+async fn activate_update(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    let force = match query.get("force") {
+        Some(force) => force.parse().context(error::InvalidBoolSnafu {
+            name: "force",
+            input: force,
+        })?,
+        None => false,
+    };
+    let mut args = vec!["activate"];
+    if force {
+        args.push("--force");
+    }
+    controller::dispatch_update_command(&args)
 }
 
 /// "Deactivates" an already activated update by rolling back actions done by 'activate-update'
@@ -572,6 +930,12 @@ async fn deactivate_update() -> Result<HttpResponse> {
     controller::dispatch_update_command(&["deactivate"])
 }
 
+/// Rolls back to the previous version by marking the inactive partition set for boot, if it holds
+/// an older version that has booted successfully
+async fn rollback() -> Result<HttpResponse> {
+    controller::dispatch_update_command(&["rollback"])
+}
+
 /// Reboots the machine
 async fn reboot() -> Result<HttpResponse> {
     debug!("Rebooting now");
@@ -749,6 +1113,71 @@ fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
     query.get("tx").map(String::as_str).unwrap_or("default")
 }
 
+/// Matches requests whose Content-Type is the given media type, ignoring any parameters.
+fn content_type(essence: &'static str) -> impl guard::Guard {
+    guard::fn_guard(move |ctx| {
+        ctx.header::<header::ContentType>()
+            .is_some_and(|content_type| content_type.essence_str() == essence)
+    })
+}
+
+/// Formats a revision of the live data store as an ETag.
+fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
+/// Checks the request's If-Match header, if any, against the revision of the live data store.
+/// Clients get the revision from the ETag of GET /settings, and can send it back to make sure live
+/// settings haven't changed since they read them.  Call this while holding the write lock, so the
+/// revision can't change before the request is done.
+fn check_revision(req: &HttpRequest, datastore: &DefaultDataStore) -> Result<()> {
+    let given = match req.headers().get(header::IF_MATCH) {
+        Some(value) => value
+            .to_str()
+            .context(error::InvalidHeaderSnafu { name: "If-Match" })?,
+        None => return Ok(()),
+    };
+    let revision = controller::get_revision(datastore)?;
+    let current = etag(revision);
+    ensure!(
+        given
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == current),
+        error::RevisionMismatchSnafu { given, revision }
+    );
+    Ok(())
+}
+
+/// Adds the commit that was just made to the audit log, along with the credentials of the client
+/// that made it.  The commit has already happened, so failures are logged rather than returned.
+fn record_commit(req: &HttpRequest, data: &SharedData, datastore: &DefaultDataStore) {
+    let peer = req.conn_data::<peer::PeerCredentials>().cloned();
+    let result = controller::list_commits(datastore).and_then(|commits| match commits.last() {
+        Some(commit) => data
+            .audit_log
+            .record(&audit::AuditEntry::new(commit, peer)?),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        error!("Unable to record commit in audit log: {}", e);
+    }
+}
+
+/// Tells any watch clients about the new values of keys changed by a commit.  The commit has
+/// already happened, so failures are logged rather than returned.
+fn notify_watchers(
+    data: &SharedData,
+    datastore: &DefaultDataStore,
+    transaction: &str,
+    changes: &HashSet<Key>,
+) {
+    match controller::settings_change(datastore, transaction, changes) {
+        Ok(change) => data.watchers.notify(change),
+        Err(e) => error!("Unable to notify watch clients of commit: {}", e),
+    }
+}
+
 // Helpers methods for the 'set' API
 
 fn construct_key_pair_map(settings_key_pair_vec: &Vec<String>) -> Result<HashMap<Key, String>> {
@@ -761,18 +1190,7 @@ fn construct_key_pair_map(settings_key_pair_vec: &Vec<String>) -> Result<HashMap
                 .ok_or_else(|| Error::InvalidKeyPair {
                     input: settings_key_pair.clone(),
                 })?;
-        let mut key = Key::new(KeyType::Data, raw_key)
-            .context(error::InvalidPrefixSnafu { prefix: raw_key })?;
-        // Add "settings" prefix if the user didn't give a known prefix, to ease usage
-        let key_prefix = &key.segments()[0];
-
-        if key_prefix != "settings" {
-            let mut segments = key.segments().clone();
-            segments.insert(0, "settings".to_string());
-            key = Key::from_segments(KeyType::Data, &segments)
-                .context(error::InvalidPrefixSnafu { prefix: raw_key })?;
-        }
-
+        let key = settings_key(raw_key)?;
         settings_key_pair_map.insert(key, value.to_string());
     }
     trace!("Key=Value map: {:#?}", settings_key_pair_map);
@@ -780,6 +1198,22 @@ fn construct_key_pair_map(settings_key_pair_vec: &Vec<String>) -> Result<HashMap
     massage_set_input(settings_key_pair_map)
 }
 
+/// Makes a data key from user input, adding the "settings" prefix if the user didn't give it, to
+/// ease usage.
+fn settings_key(raw_key: &str) -> Result<Key> {
+    let key =
+        Key::new(KeyType::Data, raw_key).context(error::InvalidPrefixSnafu { prefix: raw_key })?;
+    let key_prefix = &key.segments()[0];
+
+    if key_prefix == "settings" {
+        return Ok(key);
+    }
+    let mut segments = key.segments().clone();
+    segments.insert(0, "settings".to_string());
+    Key::from_segments(KeyType::Data, &segments)
+        .context(error::InvalidPrefixSnafu { prefix: raw_key })
+}
+
 /// We want the key=val form of 'set' to be as simple as possible; we don't want users to have to
 /// annotate or structure their input too much just to tell us the data type, but unfortunately
 /// knowledge of the data type is required to deserialize with the current datastore ser/de code.
@@ -791,7 +1225,7 @@ fn construct_key_pair_map(settings_key_pair_vec: &Vec<String>) -> Result<HashMap
 ///
 /// If this goes wrong -- for example the user wants a string "42" -- we'll get a deserialization
 /// error, and can print a clear error and request the user use JSON input form to handle
-/// situations with more complex types.g
+/// situations with more complex types.
 ///
 /// If you have an idea for how to improve deserialization so we don't have to do this, please say!
 fn massage_set_input(input_map: HashMap<Key, String>) -> Result<HashMap<Key, String>> {
@@ -835,6 +1269,23 @@ impl ResponseError for error::Error {
             InvalidPrefix { .. } => StatusCode::BAD_REQUEST,
             DeserializeJson { .. } => StatusCode::BAD_REQUEST,
             InvalidKeyPair { .. } => StatusCode::BAD_REQUEST,
+            InvalidCommitId { .. } => StatusCode::BAD_REQUEST,
+            InvalidAuditLimit { .. } => StatusCode::BAD_REQUEST,
+            InvalidBool { .. } => StatusCode::BAD_REQUEST,
+            InvalidHeader { .. } => StatusCode::BAD_REQUEST,
+            InvalidRemoval { .. } => StatusCode::BAD_REQUEST,
+            InvalidExecRun { .. } => StatusCode::BAD_REQUEST,
+            InvalidSnapshot { .. } => StatusCode::BAD_REQUEST,
+            SnapshotNotSettings { .. } => StatusCode::BAD_REQUEST,
+            SnapshotModel { .. } => StatusCode::BAD_REQUEST,
+            MergePatchType => StatusCode::BAD_REQUEST,
+            PatchedSettings { .. } => StatusCode::BAD_REQUEST,
+            InvalidUpdateSource => StatusCode::BAD_REQUEST,
+
+            // 403 Forbidden
+            ClientDenied { .. } => StatusCode::FORBIDDEN,
+            RouteDenied { .. } => StatusCode::FORBIDDEN,
+            SettingDenied { .. } => StatusCode::FORBIDDEN,
 
             // 404 Not Found
             MissingData { .. } => StatusCode::NOT_FOUND,
@@ -842,10 +1293,13 @@ impl ResponseError for error::Error {
             UpdateDoesNotExist { .. } => StatusCode::NOT_FOUND,
             NoStagedImage { .. } => StatusCode::NOT_FOUND,
             UninitializedUpdateStatus { .. } => StatusCode::NOT_FOUND,
+            NoSuchCommit { .. } => StatusCode::NOT_FOUND,
 
             // 422 Unprocessable Entity
             CommitWithNoPending => StatusCode::UNPROCESSABLE_ENTITY,
+            PlanWithNoPending => StatusCode::UNPROCESSABLE_ENTITY,
             ReportNotSupported { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            JsonPatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
 
             // 423 Locked
             UpdateShareLock { .. } => StatusCode::LOCKED,
@@ -853,9 +1307,20 @@ impl ResponseError for error::Error {
 
             // 409 Conflict
             DisallowCommand { .. } => StatusCode::CONFLICT,
+            InvalidRollback => StatusCode::CONFLICT,
+            RollbackConflict { .. } => StatusCode::CONFLICT,
+            RevisionMismatch { .. } => StatusCode::CONFLICT,
+            PatchTestFailed { .. } => StatusCode::CONFLICT,
 
             // 500 Internal Server Error
             DataStoreLock => StatusCode::INTERNAL_SERVER_ERROR,
+            AuditLock => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicySetting { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AuditRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AuditSerialize { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AuditWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseSerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             BindSocket { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ServerStart { .. } => StatusCode::INTERNAL_SERVER_ERROR,
@@ -868,17 +1333,22 @@ impl ResponseError for error::Error {
             EphemeralInitialize { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             EphemeralListDisks { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             InvalidMetadata { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            InvalidValue { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierFork { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierStart { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierStdin {} => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWait { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ConfigApplierPlan { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ConfigApplierOutput { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotify { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotifyStatus {} => StatusCode::INTERNAL_SERVER_ERROR,
             SetPermissions { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SetGroup { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SettingsToJson { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ReleaseData { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            OpenApiParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            MissingSettingsSchema => StatusCode::INTERNAL_SERVER_ERROR,
             Shutdown { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Reboot { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateDispatcher { .. } => StatusCode::INTERNAL_SERVER_ERROR,
@@ -888,6 +1358,9 @@ impl ResponseError for error::Error {
             UpdateLockOpen { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ReportExec { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ReportResult { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ExecRunSpawn { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ExecRunProcess => StatusCode::INTERNAL_SERVER_ERROR,
+            ExecRunWait { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
         HttpResponse::build(status_code).body(self.to_string())
@@ -903,8 +1376,11 @@ struct SetKeyPairSettings {
 /// SharedData is responsible for any data needed by web handlers that isn't provided by the client
 /// in the request.
 pub(crate) struct SharedData {
-    ds: sync::RwLock<FilesystemDataStore>,
+    ds: sync::RwLock<DefaultDataStore>,
     exec_socket_path: PathBuf,
+    watchers: watch::Watchers,
+    audit_log: audit::AuditLog,
+    policy: Option<sync::Arc<policy::Policy>>,
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -956,6 +1432,10 @@ impl_responder_for!(SettingsResponse, self, self.0);
 struct BottlerocketReleaseResponse(serde_json::Value);
 impl_responder_for!(BottlerocketReleaseResponse, self, self.0);
 
+/// This lets us respond from our handler methods with the JSON schema of settings
+struct SettingsSchemaResponse(serde_json::Value);
+impl_responder_for!(SettingsSchemaResponse, self, self.0);
+
 /// This lets us respond from our handler methods with a HashMap (or Result<HashMap>) for metadata
 struct MetadataResponse(HashMap<String, Value>);
 impl_responder_for!(MetadataResponse, self, self.0);
@@ -964,10 +1444,6 @@ impl_responder_for!(MetadataResponse, self, self.0);
 struct ServicesResponse(Services);
 impl_responder_for!(ServicesResponse, self, self.0);
 
-/// This lets us respond from our handler methods with a UpdateStatus (or Result<UpdateStatus>)
-struct UpdateStatusResponse(UpdateStatus);
-impl_responder_for!(UpdateStatusResponse, self, self.0);
-
 /// This lets us respond from our handler methods with a ConfigurationFiles (or
 /// Result<ConfigurationFiles>)
 struct ConfigurationFilesResponse(ConfigurationFiles);
@@ -979,6 +1455,21 @@ impl_responder_for!(ChangedKeysResponse, self, self.0);
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
 
+struct TransactionPatchResponse(serde_json::Value);
+impl_responder_for!(TransactionPatchResponse, self, self.0);
+
+struct TransactionPlanResponse(TransactionPlan);
+impl_responder_for!(TransactionPlanResponse, self, self.0);
+
+struct CommitHistoryResponse(Vec<Commit>);
+impl_responder_for!(CommitHistoryResponse, self, self.0);
+
+struct AuditLogResponse(Vec<audit::AuditEntry>);
+impl_responder_for!(AuditLogResponse, self, self.0);
+
+struct SnapshotResponse(Snapshot);
+impl_responder_for!(SnapshotResponse, self, self.0);
+
 struct ReportListResponse(Vec<Report>);
 impl_responder_for!(ReportListResponse, self, self.0);
 
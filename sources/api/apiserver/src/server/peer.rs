@@ -0,0 +1,97 @@
+//! The 'peer' module finds out who's on the other end of an API connection.  The kernel reports
+//! the credentials and SELinux label of the process that connected to the Unix-domain socket; we
+//! store them with the connection so handlers can use them for the audit log and the policy.
+
+use actix_web::dev::Extensions;
+use actix_web::rt::net::UnixStream;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt;
+use std::fs;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// The most we'll read of an SELinux label; real labels are far shorter.
+const MAX_LABEL_LEN: usize = 4096;
+
+/// PeerCredentials identifies the process on the other end of an API connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PeerCredentials {
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pid: Option<i32>,
+    /// The command name of the process, from /proc, read when it connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) comm: Option<String>,
+    /// The SELinux label of the process, if SELinux is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+}
+
+impl fmt::Display for PeerCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid {} gid {}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid {}", pid)?;
+        }
+        if let Some(comm) = &self.comm {
+            write!(f, " ({})", comm)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, " label '{}'", label)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stores the credentials of the peer in the connection's data, where handlers can find them with
+/// HttpRequest::conn_data.  This is given to HttpServer::on_connect.
+pub(crate) fn store_peer_credentials(conn: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = conn.downcast_ref::<UnixStream>() else {
+        return;
+    };
+    match stream.peer_cred() {
+        Ok(cred) => {
+            let pid = cred.pid();
+            let comm = pid.and_then(|pid| fs::read_to_string(format!("/proc/{}/comm", pid)).ok());
+            data.insert(PeerCredentials {
+                uid: cred.uid(),
+                gid: cred.gid(),
+                pid,
+                comm: comm.map(|comm| comm.trim_end().to_string()),
+                label: peer_label(stream.as_raw_fd()),
+            });
+        }
+        Err(e) => warn!("Unable to get credentials of API client: {}", e),
+    }
+}
+
+/// Returns the SELinux label of the peer of the given socket, or None if it's not available, for
+/// example because SELinux is disabled.
+fn peer_label(fd: RawFd) -> Option<String> {
+    let mut buf = vec![0u8; MAX_LABEL_LEN];
+    let mut len = buf.len() as libc::socklen_t;
+    // getsockopt is unsafe because it writes through the pointer it's given; the kernel writes at
+    // most `len` bytes, which is the size of our buffer.
+    let rc = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERSEC,
+            buf.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    buf.truncate(len as usize);
+    // The label may be NUL-terminated.
+    while buf.last() == Some(&0) {
+        buf.pop();
+    }
+    String::from_utf8(buf)
+        .ok()
+        .filter(|label| !label.is_empty())
+}
@@ -0,0 +1,422 @@
+//! The 'policy' module limits what each client of the API may do.  Without a policy, any process
+//! that can reach the API socket has full control; with one, clients are matched by the user,
+//! group, and SELinux label the kernel reports for their connection, and are limited to the
+//! routes and settings their rule allows.
+//!
+//! A policy is a TOML file like this:
+//!
+//! ```toml
+//! # What to do with clients that no rule matches: "allow" (the default) or "deny".
+//! default = "deny"
+//!
+//! # Rules are checked in order, and the first rule that matches the client applies.  A rule
+//! # matches if all of the uid, gid, and label it gives match; a rule that gives none matches
+//! # everyone.  A label with no ':' matches just the SELinux type of the client's label.
+//! [[rule]]
+//! name = "monitoring"
+//! uid = 1000
+//! # Only these routes may be used.  A path allows the paths under it, too, and if no methods are
+//! # given, all methods are allowed.  Without 'routes', every route is allowed.
+//! routes = [
+//!     { path = "/settings", methods = ["GET"] },
+//!     { path = "/os" },
+//! ]
+//!
+//! [[rule]]
+//! name = "control"
+//! label = "control_t"
+//! # Only settings under these prefixes may be changed.  Without 'settings', all settings may be.
+//! settings = ["settings.kubernetes", "settings.motd"]
+//! ```
+//!
+//! Routes are checked by middleware before a request is handled.  Settings are checked by the
+//! handlers that stage changes, since only they know which settings a request changes; commits
+//! are allowed or denied by route, like other requests.
+
+use super::error::{self, Result};
+use super::peer::PeerCredentials;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest};
+use datastore::{Key, KeyType};
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::Path;
+
+/// Policy holds the rules that decide what each client may do.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Policy {
+    /// Whether clients that no rule matches are allowed full access.
+    #[serde(default)]
+    default: DefaultAccess,
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum DefaultAccess {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Rule matches a set of clients and says what they may do.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Rule {
+    /// A name for the rule, used in error messages.
+    name: Option<String>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    label: Option<String>,
+    /// The routes the client may use, or None to allow all routes.
+    routes: Option<Vec<RouteRule>>,
+    /// The prefixes of settings the client may change, or None to allow all settings.
+    settings: Option<Vec<String>>,
+}
+
+/// RouteRule allows requests to a path, and paths under it, with the given methods, or any method
+/// if none are given.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteRule {
+    path: String,
+    methods: Option<Vec<String>>,
+}
+
+/// Access is what a policy allows one client to do.  The middleware stores it in each request's
+/// extensions so handlers can check the settings it changes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Access {
+    /// The client may do anything.
+    Full,
+    /// The client matched the given rule.
+    Rule(Rule),
+    /// The client matched no rule, and the policy denies such clients.
+    Denied,
+}
+
+impl Policy {
+    /// Reads and parses the policy file at the given path.
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let policy_str = fs::read_to_string(path).context(error::PolicyReadSnafu { path })?;
+        let policy: Self = toml::from_str(&policy_str).context(error::PolicyParseSnafu { path })?;
+
+        // Check settings prefixes now, so a typo doesn't quietly deny changes later.
+        for prefix in policy
+            .rules
+            .iter()
+            .flat_map(|rule| rule.settings.iter().flatten())
+        {
+            settings_prefix(prefix).context(error::PolicySettingSnafu { path, prefix })?;
+        }
+        Ok(policy)
+    }
+
+    /// Returns what the policy allows the given client to do.  Clients whose credentials are
+    /// unknown only match rules that don't check credentials.
+    pub(crate) fn access(&self, peer: Option<&PeerCredentials>) -> Access {
+        match self.rules.iter().find(|rule| rule.matches(peer)) {
+            Some(rule) => Access::Rule(rule.clone()),
+            None => match self.default {
+                DefaultAccess::Allow => Access::Full,
+                DefaultAccess::Deny => Access::Denied,
+            },
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, peer: Option<&PeerCredentials>) -> bool {
+        let Some(peer) = peer else {
+            return self.uid.is_none() && self.gid.is_none() && self.label.is_none();
+        };
+        self.uid.is_none_or(|uid| uid == peer.uid)
+            && self.gid.is_none_or(|gid| gid == peer.gid)
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|label| label_matches(label, peer.label.as_deref()))
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("unnamed")
+    }
+
+    fn allows_route(&self, method: &str, path: &str) -> bool {
+        let Some(routes) = &self.routes else {
+            return true;
+        };
+        routes.iter().any(|route| {
+            path_matches(&route.path, path)
+                && route
+                    .methods
+                    .as_ref()
+                    .is_none_or(|methods| methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+        })
+    }
+
+    fn allows_setting(&self, key: &Key) -> bool {
+        let Some(prefixes) = &self.settings else {
+            return true;
+        };
+        prefixes.iter().any(|prefix| {
+            settings_prefix(prefix).is_ok_and(|prefix| key.starts_with_segments(prefix.segments()))
+        })
+    }
+}
+
+/// Checks a label from a rule against the client's label.  A rule label with no ':' is just an
+/// SELinux type, and matches the type component of the client's label.
+fn label_matches(rule_label: &str, peer_label: Option<&str>) -> bool {
+    let Some(peer_label) = peer_label else {
+        return false;
+    };
+    if rule_label.contains(':') {
+        rule_label == peer_label
+    } else {
+        peer_label.split(':').nth(2) == Some(rule_label)
+    }
+}
+
+/// Checks whether a request path is the given rule path or under it.
+fn path_matches(rule_path: &str, path: &str) -> bool {
+    let rule_path = rule_path.trim_end_matches('/');
+    path == rule_path
+        || path
+            .strip_prefix(rule_path)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Parses a settings prefix from a policy, which may have a trailing dot, into a data key.
+fn settings_prefix(prefix: &str) -> datastore::Result<Key> {
+    Key::new(KeyType::Data, prefix.trim_end_matches('.'))
+}
+
+/// Describes a client for error messages.
+fn client(peer: Option<&PeerCredentials>) -> String {
+    match peer {
+        Some(peer) => peer.to_string(),
+        None => "unknown client".to_string(),
+    }
+}
+
+/// Middleware that checks each request against the policy, if there is one, and stores the
+/// client's Access in the request for handlers to check settings against.
+pub(crate) async fn authorize(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> std::result::Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let policy = req
+        .app_data::<web::Data<super::SharedData>>()
+        .and_then(|data| data.policy.clone());
+    if let Some(policy) = policy {
+        let peer = req.conn_data::<PeerCredentials>();
+        let access = policy.access(peer);
+        match &access {
+            Access::Full => {}
+            Access::Denied => {
+                return Err(error::ClientDeniedSnafu {
+                    client: client(peer),
+                }
+                .build()
+                .into())
+            }
+            Access::Rule(rule) => {
+                let method = req.method().as_str();
+                let path = req.path();
+                if !rule.allows_route(method, path) {
+                    return Err(error::RouteDeniedSnafu {
+                        client: client(peer),
+                        rule: rule.name(),
+                        method,
+                        path,
+                    }
+                    .build()
+                    .into());
+                }
+            }
+        }
+        req.extensions_mut().insert(access);
+    }
+    next.call(req).await
+}
+
+/// Checks that the client may change the given settings.  Call this from handlers that stage
+/// changes to settings.
+pub(crate) fn check_settings<'a, I>(req: &HttpRequest, keys: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a Key>,
+{
+    let extensions = req.extensions();
+    let Some(Access::Rule(rule)) = extensions.get::<Access>() else {
+        // The middleware has already turned away clients who are denied everything.
+        return Ok(());
+    };
+    for key in keys {
+        ensure!(
+            rule.allows_setting(key),
+            error::SettingDeniedSnafu {
+                client: client(req.conn_data::<PeerCredentials>()),
+                rule: rule.name(),
+                key: key.name(),
+            }
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::controller;
+    use actix_web::test::TestRequest;
+    use datastore::memory::MemoryDataStore;
+    use datastore::{Committed, DataStore};
+
+    fn peer(uid: u32, gid: u32, label: Option<&str>) -> PeerCredentials {
+        PeerCredentials {
+            uid,
+            gid,
+            pid: None,
+            comm: None,
+            label: label.map(str::to_string),
+        }
+    }
+
+    fn policy() -> Policy {
+        toml::from_str(
+            r#"
+            default = "deny"
+
+            [[rule]]
+            name = "monitoring"
+            uid = 1000
+            routes = [
+                { path = "/settings", methods = ["GET"] },
+                { path = "/os/" },
+            ]
+
+            [[rule]]
+            name = "control"
+            label = "control_t"
+            settings = ["settings.kubernetes", "settings.motd."]
+
+            [[rule]]
+            gid = 0
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn rule_name(access: Access) -> Option<String> {
+        match access {
+            Access::Rule(rule) => rule.name,
+            other => panic!("expected a rule, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_policy_rules() {
+        let policy: Policy = toml::from_str("").unwrap();
+        assert_eq!(policy.access(Some(&peer(1000, 1000, None))), Access::Full);
+        assert_eq!(policy.access(None), Access::Full);
+    }
+
+    #[test]
+    fn matching() {
+        let policy = policy();
+        assert_eq!(
+            rule_name(policy.access(Some(&peer(1000, 1000, None)))),
+            Some("monitoring".to_string())
+        );
+        assert_eq!(
+            rule_name(policy.access(Some(&peer(5, 5, Some("system_u:system_r:control_t:s0"))))),
+            Some("control".to_string())
+        );
+        assert_eq!(rule_name(policy.access(Some(&peer(5, 0, None)))), None);
+        assert_eq!(policy.access(Some(&peer(5, 5, None))), Access::Denied);
+        assert_eq!(
+            policy.access(Some(&peer(5, 5, Some("system_u:system_r:other_t:s0")))),
+            Access::Denied
+        );
+        assert_eq!(policy.access(None), Access::Denied);
+    }
+
+    #[test]
+    fn routes() {
+        let policy = policy();
+        let Access::Rule(rule) = policy.access(Some(&peer(1000, 1000, None))) else {
+            panic!("expected a rule");
+        };
+        assert!(rule.allows_route("GET", "/settings"));
+        assert!(rule.allows_route("get", "/settings/watch"));
+        assert!(!rule.allows_route("PATCH", "/settings"));
+        assert!(!rule.allows_route("GET", "/settingsx"));
+        assert!(rule.allows_route("POST", "/os"));
+        assert!(!rule.allows_route("POST", "/actions/reboot"));
+    }
+
+    #[test]
+    fn settings() {
+        let policy = policy();
+        let Access::Rule(rule) =
+            policy.access(Some(&peer(5, 5, Some("system_u:system_r:control_t:s0"))))
+        else {
+            panic!("expected a rule");
+        };
+        let key = |name| Key::new(KeyType::Data, name).unwrap();
+        assert!(rule.allows_setting(&key("settings.motd")));
+        assert!(rule.allows_setting(&key("settings.kubernetes.node-labels.a")));
+        assert!(!rule.allows_setting(&key("settings.kubernetes-extra")));
+        assert!(!rule.allows_setting(&key("settings.host-containers.admin.enabled")));
+        assert!(rule.allows_route("POST", "/actions/reboot"));
+    }
+
+    #[test]
+    fn rollback_settings() {
+        let policy = policy();
+        let Access::Rule(rule) =
+            policy.access(Some(&peer(5, 5, Some("system_u:system_r:control_t:s0"))))
+        else {
+            panic!("expected a rule");
+        };
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Access::Rule(rule));
+
+        let mut ds = MemoryDataStore::new();
+        let commit = |ds: &mut MemoryDataStore, name, value| {
+            let tx = "test";
+            let key = Key::new(KeyType::Data, name).unwrap();
+            let pending = Committed::Pending { tx: tx.into() };
+            ds.set_key(&key, value, &pending).unwrap();
+            ds.commit_transaction(tx).unwrap();
+            ds.list_commits().unwrap().last().unwrap().id
+        };
+        let motd = commit(&mut ds, "settings.motd", "\"hi\"");
+        let admin = commit(&mut ds, "settings.host-containers.admin.enabled", "true");
+
+        // Rolling back a commit changes the same keys, so the rule has to allow all of them.
+        let keys = controller::rollback_keys(&ds, motd).unwrap();
+        assert!(check_settings(&req, &keys).is_ok());
+        let keys = controller::rollback_keys(&ds, admin).unwrap();
+        assert!(matches!(
+            check_settings(&req, &keys),
+            Err(error::Error::SettingDenied { .. })
+        ));
+        assert!(matches!(
+            controller::rollback_keys(&ds, 42),
+            Err(error::Error::NoSuchCommit { id: 42 })
+        ));
+    }
+
+    #[test]
+    fn unknown_fields() {
+        assert!(toml::from_str::<Policy>("[[rule]]\nuser = 5\n").is_err());
+    }
+}
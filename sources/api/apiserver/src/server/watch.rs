@@ -0,0 +1,209 @@
+//! The 'watch' module lets clients follow changes to settings without polling.  Each client gets a
+//! WebSocket, and every time a commit changes live settings under the client's prefix, we send it
+//! a SettingsChange message with the new values through the Text channel.
+
+// Implementation note: like the 'exec' module, this uses an Actix actor to manage each WebSocket.
+// Handlers that commit changes tell the shared Watchers registry, which forwards the change to
+// each connected actor; the actors do their own prefix filtering and send to their client.
+
+use actix::prelude::{Actor, ActorContext, AsyncContext, Handler, Recipient, StreamHandler};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws::{self, Message};
+use datastore::{Key, KeyType};
+use log::{debug, error, info};
+use model::watch::SettingsChange;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// To guard against stale connections, we send ping and pong messages through the channel
+/// regularly as a 'heartbeat'; this is how often we send them.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// If we haven't heard from the client in this much time, we consider it gone and we stop.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts the WebSocket, handing control of the message stream to a WsWatch actor that sends
+/// changes to keys under the 'prefix' query parameter.  As with GET /settings, the "settings."
+/// prefix is implied, and all settings are watched if no prefix is given.
+pub(crate) async fn ws_watch(
+    r: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<crate::server::SharedData>,
+) -> Result<HttpResponse, Error> {
+    let prefix = match query.get("prefix") {
+        Some(prefix) if prefix.starts_with("settings") => prefix.clone(),
+        Some(prefix) => format!("settings.{}", prefix),
+        None => "settings".to_string(),
+    };
+    info!("Received watch request for prefix '{}'", prefix);
+    // Prefixes are matched by whole segments, so "settings.motd" doesn't match "settings.motdx".
+    let prefix = Key::new(KeyType::Data, prefix.trim_end_matches('.'))
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    ws::start(WsWatch::new(prefix, data.watchers.clone()), &r, stream)
+}
+
+/// Watchers is a registry of the connected watch clients, shared between request handlers so
+/// that whichever handler commits a change can notify them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Watchers {
+    recipients: Arc<Mutex<Vec<Recipient<message::SettingsChanged>>>>,
+}
+
+impl Watchers {
+    fn add(&self, recipient: Recipient<message::SettingsChanged>) {
+        if let Ok(mut recipients) = self.recipients.lock() {
+            recipients.push(recipient);
+        }
+    }
+
+    /// Sends the given change to every connected watch client, forgetting any that have gone.
+    pub(crate) fn notify(&self, change: SettingsChange) {
+        let change = Arc::new(change);
+        match self.recipients.lock() {
+            Ok(mut recipients) => {
+                recipients.retain(|recipient| recipient.connected());
+                debug!("Notifying {} watch clients of change", recipients.len());
+                for recipient in recipients.iter() {
+                    recipient.do_send(message::SettingsChanged(change.clone()));
+                }
+            }
+            // Watching is best-effort; failing to notify shouldn't fail the commit.
+            Err(e) => error!("Unable to notify watch clients: {}", e),
+        }
+    }
+}
+
+/// WsWatch is an actor that represents the WebSocket connection to a watch client.
+#[derive(Debug)]
+pub(crate) struct WsWatch {
+    /// This tracks the last time we heard from the client; if it's been too long, we consider the
+    /// connection stale and terminate it.
+    heartbeat: Instant,
+
+    /// Only changes to keys under this prefix are sent to the client.
+    prefix: Key,
+
+    /// The registry we join when started, so we hear about changes.
+    watchers: Watchers,
+}
+
+impl WsWatch {
+    fn new(prefix: Key, watchers: Watchers) -> Self {
+        Self {
+            heartbeat: Instant::now(),
+            prefix,
+            watchers,
+        }
+    }
+
+    /// This starts a task that's responsible for confirming that our connection to the client
+    /// isn't stale.  We ping the client regularly so it knows we're alive, and we confirm that the
+    /// client has pinged us recently so we know it's alive.
+    fn heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |actor, ctx| {
+            if Instant::now().duration_since(actor.heartbeat) > CLIENT_TIMEOUT {
+                info!("watch client heartbeat failed, disconnecting");
+                ctx.stop();
+                return;
+            }
+
+            debug!("watch client heartbeat ok, sending ping");
+            ctx.ping(b"");
+        });
+    }
+}
+
+impl Actor for WsWatch {
+    type Context = ws::WebsocketContext<Self>;
+
+    /// When the actor is first started, we start the heartbeat and join the registry of watchers.
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+        self.watchers.add(ctx.address().recipient());
+    }
+}
+
+impl StreamHandler<Result<Message, ws::ProtocolError>> for WsWatch {
+    /// This handler is run every time we receive a message from the client.  Watching is one-way,
+    /// so we only expect heartbeats and a Close.
+    fn handle(&mut self, msg: Result<Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(Message::Ping(msg)) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&msg);
+            }
+
+            Ok(Message::Pong(_)) => {
+                self.heartbeat = Instant::now();
+            }
+
+            Ok(Message::Close(reason)) => {
+                info!("Client closed watch connection with reason: {:?}", reason);
+                ctx.close(reason);
+                ctx.stop();
+            }
+
+            Ok(Message::Text(_)) | Ok(Message::Binary(_)) | Ok(Message::Continuation(_)) => {
+                info!("Closing watch connection after unexpected client message");
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Unsupported,
+                    description: Some("watch clients may not send messages".to_string()),
+                }));
+                ctx.stop();
+            }
+
+            // no-op
+            Ok(Message::Nop) => {}
+
+            Err(e) => {
+                error!("Stopping after receiving error message: {}", e);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+/// The 'message' module contains the non-WebSocket messages that our WebSocket actor can handle.
+mod message {
+    use std::sync::Arc;
+
+    /// Represents a commit to live settings; the change is shared between all watch clients.
+    #[derive(actix::Message)]
+    #[rtype(result = "()")]
+    pub(crate) struct SettingsChanged(pub(super) Arc<super::SettingsChange>);
+}
+
+impl Handler<message::SettingsChanged> for WsWatch {
+    type Result = ();
+
+    /// Sends the part of the change under our prefix to the client, if any.
+    fn handle(&mut self, msg: message::SettingsChanged, ctx: &mut Self::Context) -> Self::Result {
+        let settings: HashMap<_, _> = msg
+            .0
+            .settings
+            .iter()
+            .filter(|(name, _)| {
+                Key::new(KeyType::Data, name)
+                    .is_ok_and(|key| key.starts_with_segments(self.prefix.segments()))
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        if settings.is_empty() {
+            return;
+        }
+
+        let change = SettingsChange {
+            transaction: msg.0.transaction.clone(),
+            settings,
+        };
+        match serde_json::to_string(&change) {
+            Ok(text) => ctx.text(text),
+            Err(e) => error!(
+                "Unable to serialize settings change for watch client: {}",
+                e
+            ),
+        }
+    }
+}
@@ -17,6 +17,9 @@ pub enum Error {
     #[snafu(display("Error serializing scalar {}: {} ", given, source))]
     SerializeScalar { given: String, source: ScalarError },
 
+    #[snafu(display("Error deserializing scalar {}: {} ", given, source))]
+    DeserializeScalar { given: String, source: ScalarError },
+
     #[snafu(display("Key would traverse outside data store: {}", name))]
     PathTraversal { name: String },
 
@@ -59,6 +62,37 @@ pub enum Error {
 
     #[snafu(display("Key name beyond maximum length {}: {}", name, max))]
     KeyTooLong { name: String, max: usize },
+
+    #[snafu(display("No commit with ID {} in history", id))]
+    NoSuchCommit { id: u64 },
+
+    #[snafu(display("Can't roll back commit {}: key '{}' has changed since", id, key))]
+    RollbackConflict { id: u64, key: String },
+
+    #[snafu(display("Error serializing commit {}: {}", id, source))]
+    SerializeCommit { id: u64, source: serde_json::Error },
+
+    #[snafu(display("Error deserializing commit at {}: {}", path.display(), source))]
+    DeserializeCommit {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Snapshot has version {}, but only version {} is supported",
+        version,
+        supported
+    ))]
+    SnapshotVersion { version: u32, supported: u32 },
+
+    #[snafu(display("Error serializing data store: {}", source))]
+    SerializeDataStore { source: serde_json::Error },
+
+    #[snafu(display("Error deserializing data store at {}: {}", path.display(), source))]
+    DeserializeDataStore {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
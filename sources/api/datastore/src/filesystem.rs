@@ -3,6 +3,9 @@
 //!
 //! Data is kept in files with paths resembling the keys, e.g. a/b/c for a.b.c, and metadata is
 //! kept in a suffixed file next to the data, e.g. a/b/c.meta for metadata "meta" about a.b.c
+//!
+//! Commit history is kept as one JSON file per commit, named by commit ID, e.g. history/42, and
+//! the revision of the live data is kept in a 'revision' file.
 
 use log::{debug, error, trace};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
@@ -13,6 +16,7 @@ use std::io;
 use std::path::{self, Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
+use super::history::{self, Commit, MAX_COMMIT_HISTORY};
 use super::key::{Key, KeyType};
 use super::{error, Committed, DataStore, Result};
 
@@ -29,6 +33,8 @@ const ENCODE_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'_').remove(b'-')
 pub struct FilesystemDataStore {
     live_path: PathBuf,
     pending_base_path: PathBuf,
+    history_path: PathBuf,
+    revision_path: PathBuf,
 }
 
 impl FilesystemDataStore {
@@ -36,7 +42,38 @@ impl FilesystemDataStore {
         FilesystemDataStore {
             live_path: base_path.as_ref().join("live"),
             pending_base_path: base_path.as_ref().join("pending"),
+            history_path: base_path.as_ref().join("history"),
+            revision_path: base_path.as_ref().join("revision"),
+        }
+    }
+
+    /// Returns whether the live data store has been created.
+    pub fn exists(&self) -> bool {
+        self.live_path.exists()
+    }
+
+    /// Sets the revision of the live data, e.g. when copying another data store.
+    pub(crate) fn set_revision(&mut self, revision: u64) -> Result<()> {
+        write_file_mkdir(self.revision_path.clone(), revision.to_string())
+    }
+
+    /// Removes the whole data store from disk, e.g. after converting it to another format.
+    pub fn remove(self) -> Result<()> {
+        for path in [&self.live_path, &self.pending_base_path, &self.history_path] {
+            if let Err(e) = fs::remove_dir_all(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e).context(error::IoSnafu { path });
+                }
+            }
         }
+        if let Err(e) = fs::remove_file(&self.revision_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e).context(error::IoSnafu {
+                    path: &self.revision_path,
+                });
+            }
+        }
+        Ok(())
     }
 
     /// Returns the appropriate filesystem path for pending or live data.
@@ -486,8 +523,9 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        let transaction = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
         // Get data for changed keys
         let pending_data = self.get_prefix("settings.", &pending)?;
@@ -500,9 +538,16 @@ impl DataStore for FilesystemDataStore {
         // Save Keys for return value
         let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
 
+        // Capture the live values we're about to replace, so the commit can be rolled back
+        let commit = history::build_commit(self, transaction, &pending_data)?;
+
         // Apply changes to live
         debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, &Committed::Live)?;
+        let (changes, removals) = history::split_removals(pending_data);
+        self.set_keys(&changes, &Committed::Live)?;
+        self.unset_keys(&removals, &Committed::Live)?;
+        self.record_commit(&commit)?;
+        self.increment_revision()?;
 
         // Remove pending
         debug!("Removing old pending keys");
@@ -581,6 +626,88 @@ impl DataStore for FilesystemDataStore {
 
         Ok(transactions)
     }
+
+    fn revision(&self) -> Result<u64> {
+        let data = match fs::read_to_string(&self.revision_path) {
+            Ok(data) => data,
+            // If there's no revision file, nothing has been committed yet.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(e).context(error::IoSnafu {
+                    path: &self.revision_path,
+                })
+            }
+        };
+        data.trim().parse().ok().context(error::CorruptionSnafu {
+            msg: format!("invalid revision '{}'", data.trim()),
+            path: &self.revision_path,
+        })
+    }
+
+    fn increment_revision(&mut self) -> Result<u64> {
+        let revision = self.revision()? + 1;
+        self.set_revision(revision)?;
+        Ok(revision)
+    }
+
+    /// Commits are stored as files under the history directory, named by commit ID, so to list
+    /// them we read each file and order them by ID.
+    fn list_commits(&self) -> Result<Vec<Commit>> {
+        let entries = match fs::read_dir(&self.history_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                // If there's no history directory, nothing has been committed yet.
+                if e.kind() == io::ErrorKind::NotFound {
+                    return Ok(Vec::new());
+                }
+                return Err(e).context(error::IoSnafu {
+                    path: &self.history_path,
+                });
+            }
+        };
+
+        let mut commits = Vec::new();
+        for entry in entries {
+            let entry = entry.context(error::IoSnafu {
+                path: &self.history_path,
+            })?;
+            let path = entry.path();
+            // Skip anything that isn't named like a commit, e.g. editor swap files.
+            if commit_id_from_path(&path).is_none() {
+                trace!("Skipping non-commit file {}", path.display());
+                continue;
+            }
+            let data = fs::read_to_string(&path).context(error::IoSnafu { path: &path })?;
+            let commit: Commit =
+                serde_json::from_str(&data).context(error::DeserializeCommitSnafu { path })?;
+            commits.push(commit);
+        }
+
+        commits.sort_by_key(|commit| commit.id);
+        Ok(commits)
+    }
+
+    fn record_commit(&mut self, commit: &Commit) -> Result<()> {
+        let data =
+            serde_json::to_string(commit).context(error::SerializeCommitSnafu { id: commit.id })?;
+        write_file_mkdir(self.history_path.join(commit.id.to_string()), data)?;
+
+        // Remove the oldest commits beyond our limit.
+        let commits = self.list_commits()?;
+        let excess = commits.len().saturating_sub(MAX_COMMIT_HISTORY);
+        for old in &commits[..excess] {
+            let path = self.history_path.join(old.id.to_string());
+            debug!("Removing old commit record {}", path.display());
+            fs::remove_file(&path).context(error::IoSnafu { path })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the commit ID from the name of a file in the history directory, if it has one.
+fn commit_id_from_path(path: &Path) -> Option<u64> {
+    path.file_name()?.to_str()?.parse().ok()
 }
 
 #[cfg(test)]
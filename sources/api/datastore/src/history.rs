@@ -0,0 +1,91 @@
+//! The history module defines the record a data store keeps of each commit to live data, which is
+//! what allows a commit to be rolled back later.
+//!
+//! Commit records hold the value of each changed key before and after the commit, so that a
+//! rollback can restore the old values and confirm nothing else has changed them since.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::{Committed, DataStore, Key, Result, PENDING_REMOVAL};
+
+/// The maximum number of commits a data store should keep in its history; older commits are
+/// discarded and can no longer be rolled back.
+pub const MAX_COMMIT_HISTORY: usize = 32;
+
+/// Commit is a record of the changes made to live data by one commit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Commit {
+    /// Identifies the commit; IDs increase with each commit.
+    pub id: u64,
+    /// The name of the transaction that was committed.
+    pub transaction: String,
+    /// When the commit was made.
+    pub timestamp: DateTime<Utc>,
+    /// Maps the name of each changed data key to its values before and after the commit.
+    pub changes: HashMap<String, KeyChange>,
+}
+
+/// KeyChange holds the live value of a data key before and after a commit; None means the key
+/// wasn't set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyChange {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl Commit {
+    /// Creates a record of the given changes, made now.
+    pub fn new<S: Into<String>>(
+        id: u64,
+        transaction: S,
+        changes: HashMap<String, KeyChange>,
+    ) -> Self {
+        Self {
+            id,
+            transaction: transaction.into(),
+            timestamp: Utc::now(),
+            changes,
+        }
+    }
+}
+
+/// Splits pending data into the keys to write to live, and the keys to remove from live.
+pub(crate) fn split_removals(data: HashMap<Key, String>) -> (HashMap<Key, String>, HashSet<Key>) {
+    let (removals, changes): (HashMap<_, _>, HashMap<_, _>) = data
+        .into_iter()
+        .partition(|(_, value)| value == PENDING_REMOVAL);
+    (changes, removals.into_keys().collect())
+}
+
+/// Returns the ID to use for the next commit, given the existing history, oldest first.
+pub(crate) fn next_commit_id(history: &[Commit]) -> u64 {
+    history.last().map(|commit| commit.id + 1).unwrap_or(1)
+}
+
+/// Builds the record of a commit that would write the given pending data to live, using the
+/// current live value of each key as its old value.  Call this before writing the data.
+pub(crate) fn build_commit<D, S>(
+    datastore: &D,
+    transaction: S,
+    data: &HashMap<Key, String>,
+) -> Result<Commit>
+where
+    D: DataStore + ?Sized,
+    S: Into<String>,
+{
+    let mut changes = HashMap::new();
+    for (key, value) in data {
+        let old = datastore.get_key(key, &Committed::Live)?;
+        changes.insert(
+            key.name().clone(),
+            KeyChange {
+                old,
+                new: Some(value.clone()).filter(|value| value != PENDING_REMOVAL),
+            },
+        );
+    }
+    let id = next_commit_id(&datastore.list_commits()?);
+    Ok(Commit::new(id, transaction, changes))
+}
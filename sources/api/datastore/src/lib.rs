@@ -7,13 +7,52 @@ For more detail about their usage, see [apiserver](../apiserver).
 
 # Library
 
-This library provides a trait defining the exact requirements, along with basic implementations for filesystem and memory data stores.
+This library provides a trait defining the exact requirements, along with implementations for filesystem, single-file, and memory data stores.
 
 There's also a common error type and some methods that implementations of DataStore should generally share, like scalar serialization.
 
 We represent scalars -- the actual values stored under a datastore key -- using JSON, just to have a convenient human-readable form.
 (TOML doesn't allow raw scalars.  The JSON spec doesn't seem to either, but this works, and the format is so simple for scalars that it could be easily swapped out if needed.)
 
+# Backends
+
+`FilesystemDataStore` keeps each key in its own file, with paths resembling the key names.
+It's easy to inspect, but committing many keys is slow, and a commit that's interrupted can leave some keys changed and others not.
+
+`SingleFileDataStore` keeps everything in one JSON file that's replaced atomically, so a commit changes all of its keys or none of them.
+The file is parsed once and kept in memory, so nothing else may change it while it's open.
+
+`MemoryDataStore` is for testing.
+
+`DefaultDataStore` is the backend used by the API system, chosen at build time: it's `SingleFileDataStore` if the `single-file` feature is enabled, and `FilesystemDataStore` otherwise.
+Both keep their data in the same directory, so the versioned directories managed by storewolf and migrator don't change.
+Migrations only understand the filesystem layout, so migrator gives them a filesystem copy of a single-file data store.
+`convert_to_default` converts a data store from the other backend, so a host keeps its data when it boots a build that uses a different backend.
+
+Converting to a single file isn't one-way.
+Older builds, and builds without the `single-file` feature, only read the filesystem layout, so `SingleFileDataStore` keeps a copy of live data, metadata, and the revision in that layout next to its file, and updates it after each change.
+A host that rolls back or downgrades to such a build sees the same settings.
+If that build changes them, the copy no longer matches the single file, and `convert_to_default` converts the copy again the next time a single-file build boots.
+Pending transactions and commit history aren't copied, so the history kept in the single file is replaced by the copy's when that happens.
+
+# Commit history
+
+Data stores keep a bounded history of commits to live data, recording the old and new value of each changed key.
+A commit from the history can be rolled back, restoring the old values, as long as the keys haven't been changed again since.
+The rollback is itself recorded as a commit.
+
+Keys can be removed through a transaction by setting their pending value to `PENDING_REMOVAL`; they're removed from live data when the transaction is committed.
+
+Each commit or rollback also increases the data store's revision, a counter that clients can use to tell whether live data changed since they last read it.
+
+# Snapshots
+
+The `snapshot` module exports the live data and metadata under a prefix to a portable `Snapshot`, which can be saved as JSON or TOML and imported into another data store, for example to restore settings on another host or after a reimage.
+Snapshots record the version of their format, and importing a snapshot with an unknown version fails.
+Data is imported with a given commit status, so it can go through a pending transaction like any other change.
+Importing makes the data under the prefix match the snapshot, so data that's not in the snapshot is removed.
+Metadata comes from the OS image, so it isn't imported, and data with a setting generator is host-specific, so it's only exported if asked for and isn't removed by an import.
+
 # Serialization and deserialization
 
 The `serialization` module provides code to serialize Rust types into a mapping of datastore-acceptable keys (a.b.c) and values.
@@ -23,25 +62,103 @@ The `deserialization` module provides code to deserialize datastore-acceptable k
 # Current limitations
 
 * The user (e.g. apiserver) needs to handle locking.
-* There's no support for rolling back transactions.
+* The revision only tracks commits and rollbacks; writing directly to live data with `set_key` doesn't change it.
 * The `serialization` module can't handle complex types under lists; it assumes lists can be serialized as scalars.
 */
 
 pub mod deserialization;
 pub mod error;
 pub mod filesystem;
+pub mod history;
 pub mod key;
 pub mod memory;
 pub mod serialization;
+pub mod single_file;
+pub mod snapshot;
 
 pub use error::{Error, Result};
 pub use filesystem::FilesystemDataStore;
+pub use history::{Commit, KeyChange, MAX_COMMIT_HISTORY};
 pub use key::{Key, KeyType, KEY_SEPARATOR, KEY_SEPARATOR_STR};
+pub use single_file::SingleFileDataStore;
 
 use log::{info, trace};
 use serde::{Deserialize, Serialize};
-use snafu::OptionExt;
+use snafu::{ensure, OptionExt};
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// The data store backend used by the API system, chosen at build time with the `single-file`
+/// feature.
+#[cfg(feature = "single-file")]
+pub type DefaultDataStore = SingleFileDataStore;
+/// The data store backend used by the API system, chosen at build time with the `single-file`
+/// feature.
+#[cfg(not(feature = "single-file"))]
+pub type DefaultDataStore = FilesystemDataStore;
+
+/// Makes sure the single-file data store at the given base path holds the latest data.  If only
+/// the filesystem layout exists, or the filesystem copy was changed by a build that doesn't use the
+/// single file, it's converted to a single file.  The filesystem copy is kept, and kept up to date,
+/// so builds that only read the filesystem layout still see the data.  Returns whether anything was
+/// converted.
+#[cfg(feature = "single-file")]
+pub fn convert_to_default<P: AsRef<Path>>(base_path: P) -> Result<bool> {
+    let base_path = base_path.as_ref();
+    let filesystem = FilesystemDataStore::new(base_path);
+    let single_file = SingleFileDataStore::new(base_path);
+    if single_file.exists() {
+        if !single_file.mirror_complete() || !filesystem.exists() {
+            // A change was interrupted before the filesystem copy caught up.
+            single_file.sync_mirror()?;
+            return Ok(false);
+        }
+        if single_file.mirror_matches()? {
+            return Ok(false);
+        }
+        info!(
+            "Filesystem data store at {} was changed by a build without the single file, converting it again",
+            base_path.display()
+        );
+        let mut single_file = SingleFileDataStore::from_data_store(base_path, &filesystem)?;
+        // Let clients know the data changed underneath them.
+        single_file.increment_revision()?;
+        return Ok(true);
+    }
+    if !filesystem.exists() {
+        return Ok(false);
+    }
+    info!(
+        "Converting filesystem data store at {} to a single file",
+        base_path.display()
+    );
+    SingleFileDataStore::from_data_store(base_path, &filesystem)?.sync_mirror()?;
+    Ok(true)
+}
+
+/// If the data store at the given base path is only stored by the backend that isn't
+/// DefaultDataStore, converts it to DefaultDataStore and removes the old copy.  Returns whether
+/// anything was converted.
+#[cfg(not(feature = "single-file"))]
+pub fn convert_to_default<P: AsRef<Path>>(base_path: P) -> Result<bool> {
+    let base_path = base_path.as_ref();
+    let single_file = SingleFileDataStore::new(base_path);
+    if FilesystemDataStore::new(base_path).exists() || !single_file.exists() {
+        return Ok(false);
+    }
+    info!(
+        "Converting single-file data store at {} to the filesystem",
+        base_path.display()
+    );
+    single_file.to_filesystem(base_path)?;
+    single_file.remove()?;
+    Ok(true)
+}
+
+/// In a pending transaction, a data key with this value is to be removed from live data when the
+/// transaction is committed.  It can't be confused with real data because serialization skips
+/// null values.
+pub const PENDING_REMOVAL: &str = "null";
 
 /// Committed represents whether we want to look at pending (uncommitted) or live (committed) data
 /// in the datastore.
@@ -123,8 +240,8 @@ pub trait DataStore {
     /// Ok(()); we return Err only if we failed to check or remove the key.
     fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()>;
 
-    /// Applies pending changes from the given transaction to the live datastore.  Returns the
-    /// list of changed keys.
+    /// Applies pending changes from the given transaction to the live datastore, removing any
+    /// keys whose pending value is PENDING_REMOVAL.  Returns the list of changed keys.
     fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>;
@@ -138,6 +255,78 @@ pub trait DataStore {
     /// Returns a list of the names of any pending transactions in the data store.
     fn list_transactions(&self) -> Result<HashSet<String>>;
 
+    /// Returns the revision of the live data, which increases each time a commit or rollback
+    /// changes it; clients can compare revisions to tell whether live data has moved.  A data
+    /// store that's never been committed to has revision 0.
+    fn revision(&self) -> Result<u64>;
+    /// Increases the revision of the live data, returning the new revision.  Implementations
+    /// call this whenever they commit changes to live data.
+    fn increment_revision(&mut self) -> Result<u64>;
+
+    /// Returns the commits recorded in the data store's history, oldest first.
+    fn list_commits(&self) -> Result<Vec<Commit>>;
+    /// Adds the given commit to the data store's history.  Implementers should discard the
+    /// oldest commits so no more than MAX_COMMIT_HISTORY are kept.
+    fn record_commit(&mut self, commit: &Commit) -> Result<()>;
+
+    /// Restores the live values of the keys changed by the commit with the given ID to what they
+    /// were before that commit, and records the rollback as a new commit.  Returns the list of
+    /// changed keys.
+    ///
+    /// Fails without changing anything if any of the keys have been changed since the commit;
+    /// later commits touching those keys need to be rolled back first.
+    fn rollback_commit(&mut self, id: u64) -> Result<HashSet<Key>> {
+        let commits = self.list_commits()?;
+        let commit = commits
+            .iter()
+            .find(|commit| commit.id == id)
+            .context(error::NoSuchCommitSnafu { id })?;
+
+        // Check all keys before changing any, so a conflict doesn't leave a partial rollback.
+        let mut changes = HashMap::new();
+        for (name, change) in &commit.changes {
+            let key = Key::new(KeyType::Data, name)?;
+            let current = self.get_key(&key, &Committed::Live)?;
+            ensure!(
+                current == change.new,
+                error::RollbackConflictSnafu { id, key: name }
+            );
+            changes.insert(
+                key,
+                KeyChange {
+                    old: change.new.clone(),
+                    new: change.old.clone(),
+                },
+            );
+        }
+
+        for (key, change) in &changes {
+            match &change.new {
+                Some(value) => {
+                    info!("Rolled back data key {}", key.name());
+                    self.set_key(key, value, &Committed::Live)?;
+                }
+                None => {
+                    info!("Rolled back data key {} to unset", key.name());
+                    self.unset_key(key, &Committed::Live)?;
+                }
+            }
+        }
+
+        let rollback = Commit::new(
+            history::next_commit_id(&commits),
+            format!("rollback-{}", id),
+            changes
+                .iter()
+                .map(|(key, change)| (key.name().clone(), change.clone()))
+                .collect(),
+        );
+        self.record_commit(&rollback)?;
+        self.increment_revision()?;
+
+        Ok(changes.into_keys().collect())
+    }
+
     /// Set multiple data keys at once in the data store.
     ///
     /// Implementers can replace the default implementation if there's a faster way than setting
@@ -171,6 +360,26 @@ pub trait DataStore {
         Ok(())
     }
 
+    /// Set multiple metadata keys at once in the data store.  `metadata` maps pairs of
+    /// (metadata key, data key) to values.
+    ///
+    /// Implementers can replace the default implementation if there's a faster way than setting
+    /// each metadata key individually.
+    fn set_metadata_keys<S>(&mut self, metadata: &HashMap<(Key, Key), S>) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        for ((metadata_key, data_key), value) in metadata {
+            trace!(
+                "Setting metadata key {} for data key {}",
+                metadata_key.name(),
+                data_key.name()
+            );
+            self.set_metadata(metadata_key, data_key, value)?;
+        }
+        Ok(())
+    }
+
     /// Retrieves all keys starting with the given prefix, returning them in a Key -> value map.
     ///
     /// Can be followed up by a deserialize::from_map call to build a structure.
@@ -290,110 +499,4 @@ fn deserializer_for_scalar(scalar: &str) -> ScalarDeserializer<'_> {
 pub type Value = serde_json::Value;
 
 #[cfg(test)]
-mod test {
-    use super::memory::MemoryDataStore;
-    use super::{Committed, DataStore, Key, KeyType};
-    use maplit::{hashmap, hashset};
-
-    #[test]
-    fn set_unset_keys() {
-        let mut m = MemoryDataStore::new();
-
-        let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
-        let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
-        let k3 = Key::new(KeyType::Data, "memtest3").unwrap();
-        let v1 = "memvalue1".to_string();
-        let v2 = "memvalue2".to_string();
-        let v3 = "memvalue3".to_string();
-        let data = hashmap!(
-            k1.clone() => &v1,
-            k2.clone() => &v2,
-            k3.clone() => &v3,
-        );
-
-        let tx = "test transaction";
-        let pending = Committed::Pending { tx: tx.into() };
-        m.set_keys(&data, &pending).unwrap();
-
-        assert_eq!(m.get_key(&k1, &pending).unwrap(), Some(v1));
-        assert_eq!(m.get_key(&k2, &pending).unwrap(), Some(v2));
-        assert_eq!(m.get_key(&k3, &pending).unwrap(), Some(v3.clone()));
-
-        let unset = hashset!(k1.clone(), k2.clone());
-        m.unset_keys(&unset, &pending).unwrap();
-
-        assert_eq!(m.get_key(&k1, &pending).unwrap(), None);
-        assert_eq!(m.get_key(&k2, &pending).unwrap(), None);
-        assert_eq!(m.get_key(&k3, &pending).unwrap(), Some(v3));
-    }
-
-    #[test]
-    fn get_metadata_inheritance() {
-        let mut m = MemoryDataStore::new();
-
-        let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
-        let parent = Key::new(KeyType::Data, "a").unwrap();
-        let grandchild = Key::new(KeyType::Data, "a.b.c").unwrap();
-
-        // Set metadata on parent
-        m.set_metadata(&meta, &parent, "value").unwrap();
-        // Metadata shows up on grandchild...
-        assert_eq!(
-            m.get_metadata(&meta, &grandchild).unwrap(),
-            Some("value".to_string())
-        );
-        // ...but only through inheritance, not directly.
-        assert_eq!(m.get_metadata_raw(&meta, &grandchild).unwrap(), None);
-    }
-
-    #[test]
-    fn get_prefix() {
-        let mut m = MemoryDataStore::new();
-        let data = hashmap!(
-            Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
-            Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string(),
-            Key::new(KeyType::Data, "y.3").unwrap() => "y3".to_string(),
-        );
-        let tx = "test transaction";
-        let pending = Committed::Pending { tx: tx.into() };
-        m.set_keys(&data, &pending).unwrap();
-
-        assert_eq!(
-            m.get_prefix("x.", &pending).unwrap(),
-            hashmap!(Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
-                     Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string())
-        );
-    }
-
-    #[test]
-    fn get_metadata_prefix() {
-        let mut m = MemoryDataStore::new();
-
-        // Build some data keys to which we can attach metadata; they don't actually have to be
-        // set in the data store.
-        let k1 = Key::new(KeyType::Data, "x.1").unwrap();
-        let k2 = Key::new(KeyType::Data, "x.2").unwrap();
-        let k3 = Key::new(KeyType::Data, "y.3").unwrap();
-
-        // Set some metadata to check
-        let mk1 = Key::new(KeyType::Meta, "metatest1").unwrap();
-        let mk2 = Key::new(KeyType::Meta, "metatest2").unwrap();
-        let mk3 = Key::new(KeyType::Meta, "metatest3").unwrap();
-        m.set_metadata(&mk1, &k1, "41").unwrap();
-        m.set_metadata(&mk2, &k2, "42").unwrap();
-        m.set_metadata(&mk3, &k3, "43").unwrap();
-
-        // Check all metadata
-        assert_eq!(
-            m.get_metadata_prefix("x.", &None as &Option<&str>).unwrap(),
-            hashmap!(k1 => hashmap!(mk1 => "41".to_string()),
-                     k2.clone() => hashmap!(mk2.clone() => "42".to_string()))
-        );
-
-        // Check metadata matching a given name
-        assert_eq!(
-            m.get_metadata_prefix("x.", &Some("metatest2")).unwrap(),
-            hashmap!(k2 => hashmap!(mk2 => "42".to_string()))
-        );
-    }
-}
+mod test;
@@ -5,17 +5,22 @@
 
 use std::collections::{HashMap, HashSet};
 
+use super::history::{self, Commit, MAX_COMMIT_HISTORY};
 use super::{Committed, DataStore, Key, Result};
 
 #[derive(Debug, Default)]
 pub struct MemoryDataStore {
     // Transaction name -> (key -> data)
-    pending: HashMap<String, HashMap<Key, String>>,
+    pub(crate) pending: HashMap<String, HashMap<Key, String>>,
     // Committed (live) data.
-    live: HashMap<Key, String>,
+    pub(crate) live: HashMap<Key, String>,
     // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
     // arbitrary (string/serialized) values.
-    metadata: HashMap<Key, HashMap<Key, String>>,
+    pub(crate) metadata: HashMap<Key, HashMap<Key, String>>,
+    // Commit history, oldest first.
+    pub(crate) history: Vec<Commit>,
+    // Revision of the live data.
+    pub(crate) revision: u64,
 }
 
 impl MemoryDataStore {
@@ -152,10 +157,16 @@ impl DataStore for MemoryDataStore {
     {
         // Remove anything pending for this transaction
         if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Apply pending changes to live
-            self.set_keys(&pending, &Committed::Live)?;
+            // Apply pending changes to live, recording the values they replace
+            let commit = history::build_commit(self, transaction, &pending)?;
+            let keys = pending.keys().cloned().collect();
+            let (changes, removals) = history::split_removals(pending);
+            self.set_keys(&changes, &Committed::Live)?;
+            self.unset_keys(&removals, &Committed::Live)?;
+            self.record_commit(&commit)?;
+            self.increment_revision()?;
             // Return keys that were committed
-            Ok(pending.keys().cloned().collect())
+            Ok(keys)
         } else {
             Ok(HashSet::new())
         }
@@ -177,99 +188,24 @@ impl DataStore for MemoryDataStore {
     fn list_transactions(&self) -> Result<HashSet<String>> {
         Ok(self.pending.keys().cloned().collect())
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::super::{Committed, DataStore, Key, KeyType};
-    use super::MemoryDataStore;
-    use maplit::hashset;
-
-    #[test]
-    fn get_set_unset() {
-        let mut m = MemoryDataStore::new();
-        let k = Key::new(KeyType::Data, "memtest").unwrap();
-        let v = "memvalue";
-        m.set_key(&k, v, &Committed::Live).unwrap();
-        assert_eq!(
-            m.get_key(&k, &Committed::Live).unwrap(),
-            Some(v.to_string())
-        );
 
-        let mdkey = Key::new(KeyType::Meta, "testmd").unwrap();
-        let md = "mdval";
-        m.set_metadata(&mdkey, &k, md).unwrap();
-        assert_eq!(
-            m.get_metadata_raw(&mdkey, &k).unwrap(),
-            Some(md.to_string())
-        );
-
-        m.unset_metadata(&mdkey, &k).unwrap();
-        assert_eq!(m.get_metadata_raw(&mdkey, &k).unwrap(), None);
-
-        m.unset_key(&k, &Committed::Live).unwrap();
-        assert_eq!(m.get_key(&k, &Committed::Live).unwrap(), None);
+    fn revision(&self) -> Result<u64> {
+        Ok(self.revision)
     }
 
-    #[test]
-    fn populated() {
-        let mut m = MemoryDataStore::new();
-        let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
-        let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
-        let v = "memvalue";
-        m.set_key(&k1, v, &Committed::Live).unwrap();
-        m.set_key(&k2, v, &Committed::Live).unwrap();
-
-        assert!(m.key_populated(&k1, &Committed::Live).unwrap());
-        assert!(m.key_populated(&k2, &Committed::Live).unwrap());
-        assert_eq!(
-            m.list_populated_keys("", &Committed::Live).unwrap(),
-            hashset!(k1, k2),
-        );
-
-        let bad_key = Key::new(KeyType::Data, "memtest3").unwrap();
-        assert!(!m.key_populated(&bad_key, &Committed::Live).unwrap());
+    fn increment_revision(&mut self) -> Result<u64> {
+        self.revision += 1;
+        Ok(self.revision)
     }
 
-    #[test]
-    fn commit() {
-        let mut m = MemoryDataStore::new();
-        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
-        let v = "memvalue";
-        let tx = "test transaction";
-        let pending = Committed::Pending { tx: tx.into() };
-        m.set_key(&k, v, &pending).unwrap();
-
-        assert!(m.key_populated(&k, &pending).unwrap());
-        assert!(!m.key_populated(&k, &Committed::Live).unwrap());
-        m.commit_transaction(tx).unwrap();
-        assert!(!m.key_populated(&k, &pending).unwrap());
-        assert!(m.key_populated(&k, &Committed::Live).unwrap());
+    fn list_commits(&self) -> Result<Vec<Commit>> {
+        Ok(self.history.clone())
     }
 
-    #[test]
-    fn delete_transaction() {
-        let mut m = MemoryDataStore::new();
-        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
-        let v = "memvalue";
-        let tx = "test transaction";
-        let pending = Committed::Pending { tx: tx.into() };
-        m.set_key(&k, v, &pending).unwrap();
-
-        // Set something in a different transaction to ensure it doesn't get deleted
-        let k2 = Key::new(KeyType::Data, "settings.x.y.z").unwrap();
-        let v2 = "memvalue 2";
-        let tx2 = "test transaction 2";
-        let pending2 = Committed::Pending { tx: tx2.into() };
-        m.set_key(&k2, v2, &pending2).unwrap();
-
-        assert!(m.key_populated(&k, &pending).unwrap());
-        assert!(!m.key_populated(&k, &Committed::Live).unwrap());
-        m.delete_transaction(tx).unwrap();
-        assert!(!m.key_populated(&k, &pending).unwrap());
-        assert!(!m.key_populated(&k, &Committed::Live).unwrap());
-
-        // Assure other transactions were not deleted
-        assert!(m.key_populated(&k2, &pending2).unwrap());
+    fn record_commit(&mut self, commit: &Commit) -> Result<()> {
+        self.history.push(commit.clone());
+        let excess = self.history.len().saturating_sub(MAX_COMMIT_HISTORY);
+        self.history.drain(..excess);
+        Ok(())
     }
 }
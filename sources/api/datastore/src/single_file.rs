@@ -0,0 +1,552 @@
+//! This implementation of the DataStore trait keeps all data, metadata, pending transactions,
+//! commit history, and the revision in a single JSON file.
+//!
+//! The file is parsed on first use and kept in memory; each change writes a new copy of the file
+//! next to the old one and renames it into place, so a change is either entirely visible or not at
+//! all.  The in-memory copy is only replaced when we write, so the file must not be changed by
+//! anything else while a SingleFileDataStore has it open.  In
+//! particular, committing a transaction that changes many keys is atomic, unlike with
+//! FilesystemDataStore, where each key is a separate file.
+//!
+//! The file is kept in the same directory FilesystemDataStore would use, so the versioned
+//! directories and symlinks managed by storewolf and migrator work the same for both.
+//!
+//! Builds that don't use this backend only read the filesystem layout, so a copy of the live data,
+//! metadata, and revision is also kept there, and updated after each change to them.  The copy is
+//! only for those builds; pending transactions and commit history aren't copied.  A marker file is
+//! removed before each change and written again once the copy has caught up, so an interrupted
+//! change can be told apart from a change made to the copy by another build.
+
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use super::history::Commit;
+use super::key::{Key, KeyType};
+use super::memory::MemoryDataStore;
+use super::{error, Committed, DataStore, FilesystemDataStore, Result};
+
+/// The name of the data store file, relative to the base path of the data store.
+const DATA_STORE_FILE: &str = "datastore.json";
+/// The name of the file that marks the filesystem copy as matching the data store file, relative
+/// to the base path of the data store.
+const MIRROR_MARKER_FILE: &str = "datastore.json.mirrored";
+
+#[derive(Debug)]
+pub struct SingleFileDataStore {
+    base_path: PathBuf,
+    path: PathBuf,
+    // The parsed contents of the file, or None if they haven't been loaded yet, or if a change
+    // failed partway and they have to be reloaded.
+    cache: Mutex<Option<MemoryDataStore>>,
+}
+
+/// The format of the data store file.  Keys are stored by name because Key can't be deserialized
+/// without knowing its type, and ordered so the file is easier to inspect.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Contents {
+    revision: u64,
+    live: BTreeMap<String, String>,
+    // Transaction name -> (key name -> data)
+    pending: BTreeMap<String, BTreeMap<String, String>>,
+    // Data key name -> (metadata key name -> value)
+    metadata: BTreeMap<String, BTreeMap<String, String>>,
+    history: Vec<Commit>,
+}
+
+impl SingleFileDataStore {
+    pub fn new<P: AsRef<Path>>(base_path: P) -> SingleFileDataStore {
+        SingleFileDataStore {
+            base_path: base_path.as_ref().to_path_buf(),
+            path: base_path.as_ref().join(DATA_STORE_FILE),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Returns whether the data store file has been created.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Creates a SingleFileDataStore at the given base path holding a copy of everything in the
+    /// given data store, including pending transactions, metadata, history, and the revision.
+    /// This is how an existing FilesystemDataStore is converted.
+    pub fn from_data_store<P, D>(base_path: P, source: &D) -> Result<SingleFileDataStore>
+    where
+        P: AsRef<Path>,
+        D: DataStore,
+    {
+        let mut store = MemoryDataStore::new();
+        (store.live, store.metadata) = live_and_metadata(source)?;
+        for tx in source.list_transactions()? {
+            let pending = source.get_prefix("", &Committed::Pending { tx: tx.clone() })?;
+            store.pending.insert(tx, pending);
+        }
+        store.history = source.list_commits()?;
+        store.revision = source.revision()?;
+
+        let mut single_file = SingleFileDataStore::new(base_path);
+        single_file.save(&store)?;
+        *single_file
+            .cache
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = Some(store);
+        Ok(single_file)
+    }
+
+    /// Creates a FilesystemDataStore at the given base path holding a copy of everything in this
+    /// data store.  This is the reverse of `from_data_store`, for moving back to the filesystem
+    /// format.
+    pub fn to_filesystem<P: AsRef<Path>>(&self, base_path: P) -> Result<FilesystemDataStore> {
+        self.read(|store| {
+            let mut target = FilesystemDataStore::new(base_path);
+            target.set_keys(&store.live, &Committed::Live)?;
+            for (tx, pending) in &store.pending {
+                target.set_keys(pending, &Committed::Pending { tx: tx.clone() })?;
+            }
+            for (data_key, metadata) in &store.metadata {
+                for (meta_key, value) in metadata {
+                    target.set_metadata(meta_key, data_key, value)?;
+                }
+            }
+            for commit in &store.history {
+                target.record_commit(commit)?;
+            }
+            target.set_revision(store.revision)?;
+            Ok(target)
+        })
+    }
+
+    /// Removes the data store file, e.g. after converting it to another format.  The filesystem
+    /// copy is left alone.
+    pub fn remove(self) -> Result<()> {
+        self.unmark_mirror()?;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context(error::IoSnafu { path: &self.path })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether the filesystem copy was brought up to date after the last change.  If not,
+    /// a change was interrupted before the copy was written.
+    pub fn mirror_complete(&self) -> bool {
+        self.base_path.join(MIRROR_MARKER_FILE).exists()
+    }
+
+    /// Returns whether the filesystem copy holds the same live data and metadata as the data store
+    /// file.  If the copy is complete but doesn't match, it was changed by a build that doesn't use
+    /// this backend.
+    pub fn mirror_matches(&self) -> Result<bool> {
+        let (live, metadata) = read_mirror(&self.base_path)?;
+        self.read(|store| {
+            Ok(store.live == live
+                && flatten_metadata(&store.metadata) == flatten_metadata(&metadata))
+        })
+    }
+
+    /// Rewrites the filesystem copy so it matches the data store file.
+    pub fn sync_mirror(&self) -> Result<()> {
+        let (live, metadata) = read_mirror(&self.base_path)?;
+        self.unmark_mirror()?;
+        self.read(|store| self.write_mirror(&live, &flatten_metadata(&metadata), store))
+    }
+
+    /// Removes the marker that says the filesystem copy is up to date.
+    fn unmark_mirror(&self) -> Result<()> {
+        let marker = self.base_path.join(MIRROR_MARKER_FILE);
+        match fs::remove_file(&marker) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context(error::IoSnafu { path: marker })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Updates the filesystem copy from the given old live data and metadata to match the given
+    /// store, then writes the marker that says it's up to date.
+    fn write_mirror(
+        &self,
+        old_live: &HashMap<Key, String>,
+        old_metadata: &HashMap<(Key, Key), String>,
+        store: &MemoryDataStore,
+    ) -> Result<()> {
+        let mut mirror = FilesystemDataStore::new(&self.base_path);
+        debug!("Updating filesystem copy at {}", self.base_path.display());
+
+        let changed: HashMap<&Key, &String> = store
+            .live
+            .iter()
+            .filter(|(key, value)| old_live.get(*key) != Some(*value))
+            .collect();
+        for (key, value) in changed {
+            mirror.set_key(key, value, &Committed::Live)?;
+        }
+        let removed = old_live
+            .keys()
+            .filter(|key| !store.live.contains_key(*key))
+            .cloned()
+            .collect();
+        mirror.unset_keys(&removed, &Committed::Live)?;
+
+        let metadata = flatten_metadata(&store.metadata);
+        for ((data_key, meta_key), value) in &metadata {
+            if old_metadata.get(&(data_key.clone(), meta_key.clone())) != Some(value) {
+                mirror.set_metadata(meta_key, data_key, value)?;
+            }
+        }
+        for (data_key, meta_key) in old_metadata.keys() {
+            if !metadata.contains_key(&(data_key.clone(), meta_key.clone())) {
+                mirror.unset_metadata(meta_key, data_key)?;
+            }
+        }
+        mirror.set_revision(store.revision)?;
+
+        let marker = self.base_path.join(MIRROR_MARKER_FILE);
+        File::create(&marker).context(error::IoSnafu { path: marker })?;
+        Ok(())
+    }
+
+    /// Reads the data store file into memory.  A data store file that doesn't exist yet is
+    /// treated as empty.
+    fn load(&self) -> Result<MemoryDataStore> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(MemoryDataStore::new()),
+            Err(e) => return Err(e).context(error::IoSnafu { path: &self.path }),
+        };
+        let contents: Contents = serde_json::from_str(&data)
+            .context(error::DeserializeDataStoreSnafu { path: &self.path })?;
+
+        let data_keys = |map: BTreeMap<String, String>| -> Result<HashMap<Key, String>> {
+            map.into_iter()
+                .map(|(name, value)| Ok((Key::new(KeyType::Data, name)?, value)))
+                .collect()
+        };
+        let mut store = MemoryDataStore::new();
+        store.live = data_keys(contents.live)?;
+        for (tx, pending) in contents.pending {
+            store.pending.insert(tx, data_keys(pending)?);
+        }
+        for (data_key, metadata) in contents.metadata {
+            let metadata = metadata
+                .into_iter()
+                .map(|(name, value)| Ok((Key::new(KeyType::Meta, name)?, value)))
+                .collect::<Result<_>>()?;
+            store
+                .metadata
+                .insert(Key::new(KeyType::Data, data_key)?, metadata);
+        }
+        store.history = contents.history;
+        store.revision = contents.revision;
+        Ok(store)
+    }
+
+    /// Writes the data store file.  The data is written and synced to a temporary file first,
+    /// then renamed over the old file, so readers see either the old or the new data, even if
+    /// we're interrupted.
+    fn save(&self, store: &MemoryDataStore) -> Result<()> {
+        let names = |map: &HashMap<Key, String>| -> BTreeMap<String, String> {
+            map.iter()
+                .map(|(key, value)| (key.name().clone(), value.clone()))
+                .collect()
+        };
+        let contents = Contents {
+            revision: store.revision,
+            live: names(&store.live),
+            pending: store
+                .pending
+                .iter()
+                .map(|(tx, pending)| (tx.clone(), names(pending)))
+                .collect(),
+            metadata: store
+                .metadata
+                .iter()
+                // Don't keep empty entries for data keys whose metadata was all removed.
+                .filter(|(_, metadata)| !metadata.is_empty())
+                .map(|(data_key, metadata)| (data_key.name().clone(), names(metadata)))
+                .collect(),
+            history: store.history.clone(),
+        };
+        let data = serde_json::to_string(&contents).context(error::SerializeDataStoreSnafu)?;
+
+        let dir = self.path.parent().with_context(|| error::InternalSnafu {
+            msg: format!("Data store file has no parent: {}", self.path.display()),
+        })?;
+        fs::create_dir_all(dir).context(error::IoSnafu { path: dir })?;
+
+        let temp_path = self.path.with_extension("json.new");
+        trace!("Writing data store to {}", temp_path.display());
+        let mut file = File::create(&temp_path).context(error::IoSnafu { path: &temp_path })?;
+        file.write_all(data.as_bytes())
+            .and_then(|()| file.sync_all())
+            .context(error::IoSnafu { path: &temp_path })?;
+
+        debug!("Replacing data store file {}", self.path.display());
+        fs::rename(&temp_path, &self.path).context(error::IoSnafu { path: &self.path })?;
+        // Sync the directory so the rename itself is durable.
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .context(error::IoSnafu { path: dir })
+    }
+
+    /// Runs a read-only operation against the current contents of the data store, loading the
+    /// file if it hasn't been loaded yet.
+    fn read<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&MemoryDataStore) -> Result<T>,
+    {
+        // The cache is only ever replaced whole, so it's still usable if a panic poisoned the lock.
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let store = match cache.take() {
+            Some(store) => store,
+            None => self.load()?,
+        };
+        let result = f(&store);
+        *cache = Some(store);
+        result
+    }
+
+    /// Runs an operation against the current contents of the data store, then saves the result.
+    /// If the operation or the save fails, nothing is saved and the cached contents are dropped,
+    /// so no operation is left half-done; the file is loaded again by the next operation.
+    fn update<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut MemoryDataStore) -> Result<T>,
+    {
+        let cached = self
+            .cache
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let mut store = match cached {
+            Some(store) => store,
+            None => self.load()?,
+        };
+
+        // If the filesystem copy is behind, it's caught up from what's really there, rather than
+        // from what we had before this change.
+        let (old_live, old_metadata) = if self.mirror_complete() {
+            (store.live.clone(), store.metadata.clone())
+        } else {
+            read_mirror(&self.base_path)?
+        };
+        let old_metadata = flatten_metadata(&old_metadata);
+        let old_revision = store.revision;
+
+        let result = f(&mut store)?;
+
+        let mirror_changed = !self.mirror_complete()
+            || store.live != old_live
+            || flatten_metadata(&store.metadata) != old_metadata
+            || store.revision != old_revision;
+        if mirror_changed {
+            self.unmark_mirror()?;
+        }
+        self.save(&store)?;
+        if mirror_changed {
+            // The data store file is saved, so the change has happened; if the copy can't be
+            // updated, the marker stays missing and it's caught up by a later change or at boot.
+            if let Err(e) = self.write_mirror(&old_live, &old_metadata, &store) {
+                warn!(
+                    "Failed to update filesystem copy at {}: {}",
+                    self.base_path.display(),
+                    e
+                );
+            }
+        }
+        *self.cache.get_mut().unwrap_or_else(PoisonError::into_inner) = Some(store);
+        Ok(result)
+    }
+}
+
+/// Data keys mapped to their metadata keys and values.
+type Metadata = HashMap<Key, HashMap<Key, String>>;
+
+/// Reads the live data and metadata from the given data store, with metadata as it's set, without
+/// inheritance from parent keys.
+fn live_and_metadata<D: DataStore>(source: &D) -> Result<(HashMap<Key, String>, Metadata)> {
+    let live = source.get_prefix("", &Committed::Live)?;
+    let mut metadata = HashMap::new();
+    for (data_key, meta_keys) in source.list_populated_metadata("", &None as &Option<&str>)? {
+        let mut data_metadata = HashMap::new();
+        for meta_key in meta_keys {
+            let value = source.get_metadata_raw(&meta_key, &data_key)?.context(
+                error::ListedMetaNotPresentSnafu {
+                    meta_key: meta_key.name(),
+                    data_key: data_key.name(),
+                },
+            )?;
+            data_metadata.insert(meta_key, value);
+        }
+        metadata.insert(data_key, data_metadata);
+    }
+    Ok((live, metadata))
+}
+
+/// Reads the live data and metadata from the filesystem copy at the given base path, which is
+/// empty if it hasn't been written yet.
+fn read_mirror(base_path: &Path) -> Result<(HashMap<Key, String>, Metadata)> {
+    let mirror = FilesystemDataStore::new(base_path);
+    if !mirror.exists() {
+        return Ok(Default::default());
+    }
+    live_and_metadata(&mirror)
+}
+
+/// Flattens metadata to (data key, metadata key) pairs, so it can be compared without regard to
+/// data keys that have no metadata left.
+fn flatten_metadata(metadata: &Metadata) -> HashMap<(Key, Key), String> {
+    metadata
+        .iter()
+        .flat_map(|(data_key, metadata)| {
+            metadata
+                .iter()
+                .map(move |(meta_key, value)| ((data_key.clone(), meta_key.clone()), value.clone()))
+        })
+        .collect()
+}
+
+// Each method runs the in-memory implementation against the whole contents at once, including
+// those that have default implementations in the trait; otherwise, they'd save the file once for
+// each key, and changes to several keys wouldn't be atomic.
+impl DataStore for SingleFileDataStore {
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
+        self.read(|store| store.key_populated(key, committed))
+    }
+
+    fn list_populated_keys<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        self.read(|store| store.list_populated_keys(prefix, committed))
+    }
+
+    fn list_populated_metadata<S1, S2>(
+        &self,
+        prefix: S1,
+        metadata_key_name: &Option<S2>,
+    ) -> Result<HashMap<Key, HashSet<Key>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.read(|store| store.list_populated_metadata(prefix, metadata_key_name))
+    }
+
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
+        self.read(|store| store.get_key(key, committed))
+    }
+
+    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        self.update(|store| store.set_key(key, value, committed))
+    }
+
+    fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
+        self.update(|store| store.unset_key(key, committed))
+    }
+
+    fn get_metadata(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
+        self.read(|store| store.get_metadata(metadata_key, data_key))
+    }
+
+    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
+        self.read(|store| store.get_metadata_raw(metadata_key, data_key))
+    }
+
+    fn set_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+    ) -> Result<()> {
+        self.update(|store| store.set_metadata(metadata_key, data_key, value))
+    }
+
+    fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()> {
+        self.update(|store| store.unset_metadata(metadata_key, data_key))
+    }
+
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        self.update(|store| store.commit_transaction(transaction))
+    }
+
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        self.update(|store| store.delete_transaction(transaction))
+    }
+
+    fn list_transactions(&self) -> Result<HashSet<String>> {
+        self.read(|store| store.list_transactions())
+    }
+
+    fn revision(&self) -> Result<u64> {
+        self.read(|store| store.revision())
+    }
+
+    fn increment_revision(&mut self) -> Result<u64> {
+        self.update(|store| store.increment_revision())
+    }
+
+    fn list_commits(&self) -> Result<Vec<Commit>> {
+        self.read(|store| store.list_commits())
+    }
+
+    fn record_commit(&mut self, commit: &Commit) -> Result<()> {
+        self.update(|store| store.record_commit(commit))
+    }
+
+    fn rollback_commit(&mut self, id: u64) -> Result<HashSet<Key>> {
+        self.update(|store| store.rollback_commit(id))
+    }
+
+    fn set_keys<S>(&mut self, pairs: &HashMap<Key, S>, committed: &Committed) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        self.update(|store| store.set_keys(pairs, committed))
+    }
+
+    fn unset_keys(&mut self, keys: &HashSet<Key>, committed: &Committed) -> Result<()> {
+        self.update(|store| store.unset_keys(keys, committed))
+    }
+
+    fn set_metadata_keys<S>(&mut self, metadata: &HashMap<(Key, Key), S>) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        self.update(|store| store.set_metadata_keys(metadata))
+    }
+
+    fn get_prefix<S: AsRef<str>>(
+        &self,
+        find_prefix: S,
+        committed: &Committed,
+    ) -> Result<HashMap<Key, String>> {
+        self.read(|store| store.get_prefix(find_prefix, committed))
+    }
+
+    fn get_metadata_prefix<S1, S2>(
+        &self,
+        find_prefix: S1,
+        metadata_key_name: &Option<S2>,
+    ) -> Result<HashMap<Key, HashMap<Key, String>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.read(|store| store.get_metadata_prefix(find_prefix, metadata_key_name))
+    }
+}
@@ -0,0 +1,304 @@
+//! A snapshot is a portable copy of the live data and metadata under a prefix of a data store,
+//! suitable for saving to a file and restoring into another data store, e.g. on another host or
+//! after a reimage.
+//!
+//! Keys are given by name and values are deserialized, rather than kept in the data store's
+//! serialized form, so snapshots are readable and can be written as JSON or TOML.
+//!
+//! Metadata is included so a snapshot is a complete record of the data store, but it isn't
+//! restored.  It comes from the OS image rather than the user, so the host a snapshot is restored
+//! on keeps its own.
+//!
+//! Data with a setting generator, like a hostname or node IP, is specific to the host it was
+//! generated on, so it's left out of snapshots unless asked for, and a host keeps its generated
+//! data when a snapshot without it is restored.
+
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{
+    deserialize_scalar, error, serialize_scalar, Committed, DataStore, Key, KeyType, Result,
+    ScalarError, Value, PENDING_REMOVAL,
+};
+
+/// The version of the snapshot format written by this library.  Snapshots with other versions are
+/// rejected rather than guessed at.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The metadata key that names the program generating a data key's value.
+const SETTING_GENERATOR: &str = "setting-generator";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Snapshot {
+    /// The version of the snapshot format.
+    pub version: u32,
+    /// The version of the OS the snapshot was taken from, if known.  This is informational; the
+    /// data is checked against the model of the OS it's restored on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// The live data, mapping data key names to values.
+    pub data: BTreeMap<String, Value>,
+    /// The metadata of data keys, mapping data key names to metadata key names to values.  This
+    /// is informational; it isn't restored.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+impl Snapshot {
+    /// Returns the snapshot's data as data keys and serialized values, the form used by the data
+    /// store and by deserialization::from_map.
+    pub fn data_pairs(&self) -> Result<HashMap<Key, String>> {
+        ensure!(
+            self.version == SNAPSHOT_VERSION,
+            error::SnapshotVersionSnafu {
+                version: self.version,
+                supported: SNAPSHOT_VERSION,
+            }
+        );
+
+        self.data
+            .iter()
+            .map(|(name, value)| {
+                let key = Key::new(KeyType::Data, name)?;
+                let value = serialize_scalar::<_, ScalarError>(value)
+                    .context(error::SerializeScalarSnafu { given: name })?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+/// Takes a snapshot of the live data and metadata of the data keys whose names start with the
+/// given prefix.  Metadata is included as it's set, without inheritance from parent keys.  Data
+/// with a setting generator is only included if `include_generated` is true.
+pub fn export<D: DataStore>(
+    datastore: &D,
+    prefix: &str,
+    include_generated: bool,
+) -> Result<Snapshot> {
+    let mut data = BTreeMap::new();
+    for (key, value) in datastore.get_prefix(prefix, &Committed::Live)? {
+        if !include_generated && is_generated(datastore, &key)? {
+            continue;
+        }
+        let value = deserialize_scalar::<_, ScalarError>(&value)
+            .context(error::DeserializeScalarSnafu { given: key.name() })?;
+        data.insert(key.name().clone(), value);
+    }
+
+    let mut metadata = BTreeMap::new();
+    for (data_key, meta_keys) in
+        datastore.list_populated_metadata(prefix, &None as &Option<&str>)?
+    {
+        let mut metadata_for_data = BTreeMap::new();
+        for meta_key in meta_keys {
+            let value = datastore.get_metadata_raw(&meta_key, &data_key)?.context(
+                error::ListedMetaNotPresentSnafu {
+                    meta_key: meta_key.name(),
+                    data_key: data_key.name(),
+                },
+            )?;
+            let value = deserialize_scalar::<_, ScalarError>(&value).context(
+                error::DeserializeScalarSnafu {
+                    given: format!("{} metadata {}", data_key, meta_key),
+                },
+            )?;
+            metadata_for_data.insert(meta_key.name().clone(), value);
+        }
+        metadata.insert(data_key.name().clone(), metadata_for_data);
+    }
+
+    Ok(Snapshot {
+        version: SNAPSHOT_VERSION,
+        os_version: None,
+        data,
+        metadata,
+    })
+}
+
+/// Returns the live data keys under the given prefix that restoring the snapshot would remove,
+/// so the data store matches the snapshot: those that aren't in the snapshot, other than those
+/// with a setting generator, which the host keeps.
+pub fn removals<D: DataStore>(
+    datastore: &D,
+    snapshot: &Snapshot,
+    prefix: &str,
+) -> Result<HashSet<Key>> {
+    let mut removals = HashSet::new();
+    for key in datastore.list_populated_keys(prefix, &Committed::Live)? {
+        if !snapshot.data.contains_key(key.name()) && !is_generated(datastore, &key)? {
+            removals.insert(key);
+        }
+    }
+    Ok(removals)
+}
+
+/// Restores a snapshot into the data store, so its data under the given prefix matches the
+/// snapshot.  Data is written with the given commit status, so it can be put in a pending
+/// transaction and go through the usual commit process; live data that's not in the snapshot is
+/// removed, or marked with PENDING_REMOVAL in a pending transaction.  Metadata isn't restored.
+/// Returns the data keys that were written or removed.
+///
+/// Everything in the snapshot is checked before anything is written, so an invalid snapshot
+/// doesn't leave a partial restore.
+pub fn import<D: DataStore>(
+    datastore: &mut D,
+    snapshot: &Snapshot,
+    prefix: &str,
+    committed: &Committed,
+) -> Result<HashSet<Key>> {
+    let data = snapshot.data_pairs()?;
+    let removals = removals(datastore, snapshot, prefix)?;
+
+    datastore.set_keys(&data, committed)?;
+    match committed {
+        Committed::Live => datastore.unset_keys(&removals, committed)?,
+        Committed::Pending { .. } => {
+            let pending_removals: HashMap<_, _> = removals
+                .iter()
+                .map(|key| (key.clone(), PENDING_REMOVAL))
+                .collect();
+            datastore.set_keys(&pending_removals, committed)?;
+        }
+    }
+
+    let mut changed: HashSet<Key> = data.into_keys().collect();
+    changed.extend(removals);
+    Ok(changed)
+}
+
+/// Returns whether the given data key, or one of its parents, has a setting generator.
+fn is_generated<D: DataStore>(datastore: &D, key: &Key) -> Result<bool> {
+    let generator = Key::new(KeyType::Meta, SETTING_GENERATOR)?;
+    Ok(datastore.get_metadata(&generator, key)?.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::MemoryDataStore;
+    use maplit::btreemap;
+    use serde_json::json;
+
+    #[test]
+    fn export_import() {
+        let mut source = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        let other = Key::new(KeyType::Data, "services.x").unwrap();
+        let meta = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let generator = Key::new(KeyType::Meta, SETTING_GENERATOR).unwrap();
+        source
+            .set_key(&motd, "\"hello\"", &Committed::Live)
+            .unwrap();
+        source
+            .set_key(&hostname, "\"source\"", &Committed::Live)
+            .unwrap();
+        source.set_key(&other, "\"x\"", &Committed::Live).unwrap();
+        source.set_metadata(&meta, &motd, "[\"motd\"]").unwrap();
+        source
+            .set_metadata(&generator, &hostname, "\"netdog generate-hostname\"")
+            .unwrap();
+
+        let snapshot = export(&source, "settings.", false).unwrap();
+        assert_eq!(
+            snapshot.data,
+            btreemap!("settings.motd".to_string() => json!("hello"))
+        );
+        assert_eq!(
+            snapshot.metadata,
+            btreemap!(
+                "settings.motd".to_string() => btreemap!(
+                    "affected-services".to_string() => json!(["motd"]),
+                ),
+                "settings.network.hostname".to_string() => btreemap!(
+                    "setting-generator".to_string() => json!("netdog generate-hostname"),
+                ),
+            )
+        );
+
+        // Generated data is only included if asked for.
+        let with_generated = export(&source, "settings.", true).unwrap();
+        assert_eq!(
+            with_generated.data["settings.network.hostname"],
+            json!("source")
+        );
+
+        // Data that's not in the snapshot is removed, except for the target's generated data.
+        let mut target = MemoryDataStore::new();
+        let ntp = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        target.set_key(&ntp, "[\"a\"]", &Committed::Live).unwrap();
+        target
+            .set_key(&hostname, "\"target\"", &Committed::Live)
+            .unwrap();
+        target
+            .set_metadata(&generator, &hostname, "\"netdog generate-hostname\"")
+            .unwrap();
+        assert_eq!(
+            removals(&target, &snapshot, "settings.").unwrap(),
+            HashSet::from([ntp.clone()])
+        );
+
+        let pending = Committed::Pending { tx: "tx".into() };
+        let changed = import(&mut target, &snapshot, "settings.", &pending).unwrap();
+        assert_eq!(changed, HashSet::from([motd.clone(), ntp.clone()]));
+        assert_eq!(
+            target.get_key(&motd, &pending).unwrap(),
+            Some("\"hello\"".to_string())
+        );
+        assert_eq!(
+            target.get_key(&ntp, &pending).unwrap(),
+            Some(PENDING_REMOVAL.to_string())
+        );
+        assert_eq!(target.get_metadata_raw(&meta, &motd).unwrap(), None);
+
+        target.commit_transaction("tx").unwrap();
+        assert_eq!(target.get_key(&ntp, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            target.get_key(&hostname, &Committed::Live).unwrap(),
+            Some("\"target\"".to_string())
+        );
+    }
+
+    #[test]
+    fn import_ignores_metadata() {
+        let snapshot: Snapshot = serde_json::from_value(json!({
+            "version": SNAPSHOT_VERSION,
+            "data": {"settings.motd": "hello"},
+            "metadata": {"settings.motd": {"setting-generator": "/usr/bin/evil"}},
+        }))
+        .unwrap();
+        let mut target = MemoryDataStore::new();
+        import(&mut target, &snapshot, "settings.", &Committed::Live).unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let generator = Key::new(KeyType::Meta, SETTING_GENERATOR).unwrap();
+        assert_eq!(target.get_metadata_raw(&generator, &motd).unwrap(), None);
+        assert_eq!(
+            target.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"hello\"".to_string())
+        );
+    }
+
+    #[test]
+    fn import_checks_version() {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION + 1,
+            os_version: None,
+            data: btreemap!("settings.motd".to_string() => json!("hello")),
+            metadata: BTreeMap::new(),
+        };
+        let mut target = MemoryDataStore::new();
+        let ntp = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        target.set_key(&ntp, "[\"a\"]", &Committed::Live).unwrap();
+        assert!(matches!(
+            import(&mut target, &snapshot, "settings.", &Committed::Live),
+            Err(crate::Error::SnapshotVersion { .. })
+        ));
+        assert_eq!(
+            target.list_populated_keys("", &Committed::Live).unwrap(),
+            HashSet::from([ntp])
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! Tests of DataStore behavior that should hold for every backend.  Each test is written against
+//! the trait, and the `all_backend_tests` macro runs all of them against each backend.  This module is
+//! conditionally compiled for cfg(test) only.
+use super::memory::MemoryDataStore;
+use super::{
+    Committed, DataStore, Error, FilesystemDataStore, Key, KeyType, SingleFileDataStore,
+    MAX_COMMIT_HISTORY, PENDING_REMOVAL,
+};
+use maplit::{hashmap, hashset};
+
+/// Creates a module for a backend with a test for each of the given test functions.  `$new` is
+/// run at the start of each test and returns a guard, such as a temporary directory, that's kept
+/// until the end of the test, and an empty data store.
+macro_rules! backend_tests {
+    ($backend:ident, $new:expr, [$($test:ident),* $(,)?]) => {
+        mod $backend {
+            use super::*;
+
+            $(
+                #[test]
+                fn $test() {
+                    let (_guard, mut ds) = $new;
+                    super::$test(&mut ds);
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! all_backend_tests {
+    ($backend:ident, $new:expr) => {
+        backend_tests!(
+            $backend,
+            $new,
+            [
+                get_set_unset,
+                populated,
+                commit,
+                commit_removal,
+                delete_transaction,
+                commit_history,
+                commit_history_bounded,
+                set_unset_keys,
+                set_metadata_keys,
+                get_metadata_inheritance,
+                get_prefix,
+                get_metadata_prefix,
+                rollback_commit,
+                rollback_commit_conflict,
+            ]
+        );
+    };
+}
+
+all_backend_tests!(memory, ((), MemoryDataStore::new()));
+all_backend_tests!(filesystem, {
+    let dir = tempfile::tempdir().unwrap();
+    let ds = FilesystemDataStore::new(dir.path());
+    (dir, ds)
+});
+all_backend_tests!(single_file, {
+    let dir = tempfile::tempdir().unwrap();
+    let ds = SingleFileDataStore::new(dir.path());
+    (dir, ds)
+});
+
+fn get_set_unset<D: DataStore>(m: &mut D) {
+    let k = Key::new(KeyType::Data, "memtest").unwrap();
+    let v = "memvalue";
+    m.set_key(&k, v, &Committed::Live).unwrap();
+    assert_eq!(
+        m.get_key(&k, &Committed::Live).unwrap(),
+        Some(v.to_string())
+    );
+
+    let mdkey = Key::new(KeyType::Meta, "testmd").unwrap();
+    let md = "mdval";
+    m.set_metadata(&mdkey, &k, md).unwrap();
+    assert_eq!(
+        m.get_metadata_raw(&mdkey, &k).unwrap(),
+        Some(md.to_string())
+    );
+
+    m.unset_metadata(&mdkey, &k).unwrap();
+    assert_eq!(m.get_metadata_raw(&mdkey, &k).unwrap(), None);
+
+    m.unset_key(&k, &Committed::Live).unwrap();
+    assert_eq!(m.get_key(&k, &Committed::Live).unwrap(), None);
+}
+
+fn populated<D: DataStore>(m: &mut D) {
+    let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
+    let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
+    let v = "memvalue";
+    m.set_key(&k1, v, &Committed::Live).unwrap();
+    m.set_key(&k2, v, &Committed::Live).unwrap();
+
+    assert!(m.key_populated(&k1, &Committed::Live).unwrap());
+    assert!(m.key_populated(&k2, &Committed::Live).unwrap());
+    assert_eq!(
+        m.list_populated_keys("", &Committed::Live).unwrap(),
+        hashset!(k1, k2),
+    );
+
+    let bad_key = Key::new(KeyType::Data, "memtest3").unwrap();
+    assert!(!m.key_populated(&bad_key, &Committed::Live).unwrap());
+}
+
+fn commit<D: DataStore>(m: &mut D) {
+    let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+    let v = "memvalue";
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+    m.set_key(&k, v, &pending).unwrap();
+
+    assert!(m.key_populated(&k, &pending).unwrap());
+    assert!(!m.key_populated(&k, &Committed::Live).unwrap());
+    m.commit_transaction(tx).unwrap();
+    assert!(!m.key_populated(&k, &pending).unwrap());
+    assert!(m.key_populated(&k, &Committed::Live).unwrap());
+}
+
+fn commit_removal<D: DataStore>(m: &mut D) {
+    let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+    m.set_key(&k, "memvalue", &Committed::Live).unwrap();
+    m.set_key(&k, PENDING_REMOVAL, &pending).unwrap();
+
+    assert_eq!(m.commit_transaction(tx).unwrap(), hashset!(k.clone()));
+    assert!(!m.key_populated(&k, &Committed::Live).unwrap());
+    let commits = m.list_commits().unwrap();
+    assert_eq!(commits[0].changes["settings.a.b.c"].new, None);
+}
+
+fn delete_transaction<D: DataStore>(m: &mut D) {
+    let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+    let v = "memvalue";
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+    m.set_key(&k, v, &pending).unwrap();
+
+    // Set something in a different transaction to ensure it doesn't get deleted
+    let k2 = Key::new(KeyType::Data, "settings.x.y.z").unwrap();
+    let v2 = "memvalue 2";
+    let tx2 = "test transaction 2";
+    let pending2 = Committed::Pending { tx: tx2.into() };
+    m.set_key(&k2, v2, &pending2).unwrap();
+
+    assert!(m.key_populated(&k, &pending).unwrap());
+    assert!(!m.key_populated(&k, &Committed::Live).unwrap());
+    m.delete_transaction(tx).unwrap();
+    assert!(!m.key_populated(&k, &pending).unwrap());
+    assert!(!m.key_populated(&k, &Committed::Live).unwrap());
+
+    // Assure other transactions were not deleted
+    assert!(m.key_populated(&k2, &pending2).unwrap());
+}
+
+fn commit_history<D: DataStore>(m: &mut D) {
+    let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+
+    m.set_key(&k, "\"one\"", &pending).unwrap();
+    m.commit_transaction(tx).unwrap();
+    m.set_key(&k, "\"two\"", &pending).unwrap();
+    m.commit_transaction(tx).unwrap();
+
+    assert_eq!(m.revision().unwrap(), 2);
+    let commits = m.list_commits().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0].id, 1);
+    assert_eq!(commits[0].transaction, tx);
+    assert_eq!(commits[1].id, 2);
+    let change = &commits[1].changes["settings.a.b.c"];
+    assert_eq!(change.old.as_deref(), Some("\"one\""));
+    assert_eq!(change.new.as_deref(), Some("\"two\""));
+}
+
+fn commit_history_bounded<D: DataStore>(m: &mut D) {
+    let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+
+    for i in 0..MAX_COMMIT_HISTORY + 3 {
+        m.set_key(&k, i.to_string(), &pending).unwrap();
+        m.commit_transaction(tx).unwrap();
+    }
+
+    let commits = m.list_commits().unwrap();
+    assert_eq!(commits.len(), MAX_COMMIT_HISTORY);
+    assert_eq!(commits[0].id, 4);
+}
+
+fn set_unset_keys<D: DataStore>(m: &mut D) {
+    let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
+    let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
+    let k3 = Key::new(KeyType::Data, "memtest3").unwrap();
+    let v1 = "memvalue1".to_string();
+    let v2 = "memvalue2".to_string();
+    let v3 = "memvalue3".to_string();
+    let data = hashmap!(
+        k1.clone() => &v1,
+        k2.clone() => &v2,
+        k3.clone() => &v3,
+    );
+
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+    m.set_keys(&data, &pending).unwrap();
+
+    assert_eq!(m.get_key(&k1, &pending).unwrap(), Some(v1));
+    assert_eq!(m.get_key(&k2, &pending).unwrap(), Some(v2));
+    assert_eq!(m.get_key(&k3, &pending).unwrap(), Some(v3.clone()));
+
+    let unset = hashset!(k1.clone(), k2.clone());
+    m.unset_keys(&unset, &pending).unwrap();
+
+    assert_eq!(m.get_key(&k1, &pending).unwrap(), None);
+    assert_eq!(m.get_key(&k2, &pending).unwrap(), None);
+    assert_eq!(m.get_key(&k3, &pending).unwrap(), Some(v3));
+}
+
+fn set_metadata_keys<D: DataStore>(m: &mut D) {
+    let meta1 = Key::new(KeyType::Meta, "mymeta1").unwrap();
+    let meta2 = Key::new(KeyType::Meta, "mymeta2").unwrap();
+    let k1 = Key::new(KeyType::Data, "a").unwrap();
+    let k2 = Key::new(KeyType::Data, "b").unwrap();
+    let metadata = hashmap!(
+        (meta1.clone(), k1.clone()) => "value1",
+        (meta2.clone(), k1.clone()) => "value2",
+        (meta1.clone(), k2.clone()) => "value3",
+    );
+    m.set_metadata_keys(&metadata).unwrap();
+
+    assert_eq!(
+        m.get_metadata_raw(&meta1, &k1).unwrap(),
+        Some("value1".to_string())
+    );
+    assert_eq!(
+        m.get_metadata_raw(&meta2, &k1).unwrap(),
+        Some("value2".to_string())
+    );
+    assert_eq!(
+        m.get_metadata_raw(&meta1, &k2).unwrap(),
+        Some("value3".to_string())
+    );
+    assert_eq!(m.get_metadata_raw(&meta2, &k2).unwrap(), None);
+}
+
+fn get_metadata_inheritance<D: DataStore>(m: &mut D) {
+    let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
+    let parent = Key::new(KeyType::Data, "a").unwrap();
+    let grandchild = Key::new(KeyType::Data, "a.b.c").unwrap();
+
+    // Set metadata on parent
+    m.set_metadata(&meta, &parent, "value").unwrap();
+    // Metadata shows up on grandchild...
+    assert_eq!(
+        m.get_metadata(&meta, &grandchild).unwrap(),
+        Some("value".to_string())
+    );
+    // ...but only through inheritance, not directly.
+    assert_eq!(m.get_metadata_raw(&meta, &grandchild).unwrap(), None);
+}
+
+fn get_prefix<D: DataStore>(m: &mut D) {
+    let data = hashmap!(
+        Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
+        Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string(),
+        Key::new(KeyType::Data, "y.3").unwrap() => "y3".to_string(),
+    );
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+    m.set_keys(&data, &pending).unwrap();
+
+    assert_eq!(
+        m.get_prefix("x.", &pending).unwrap(),
+        hashmap!(Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
+                 Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string())
+    );
+}
+
+fn get_metadata_prefix<D: DataStore>(m: &mut D) {
+    // Build some data keys to which we can attach metadata; they don't actually have to be
+    // set in the data store.
+    let k1 = Key::new(KeyType::Data, "x.1").unwrap();
+    let k2 = Key::new(KeyType::Data, "x.2").unwrap();
+    let k3 = Key::new(KeyType::Data, "y.3").unwrap();
+
+    // Set some metadata to check
+    let mk1 = Key::new(KeyType::Meta, "metatest1").unwrap();
+    let mk2 = Key::new(KeyType::Meta, "metatest2").unwrap();
+    let mk3 = Key::new(KeyType::Meta, "metatest3").unwrap();
+    m.set_metadata(&mk1, &k1, "41").unwrap();
+    m.set_metadata(&mk2, &k2, "42").unwrap();
+    m.set_metadata(&mk3, &k3, "43").unwrap();
+
+    // Check all metadata
+    assert_eq!(
+        m.get_metadata_prefix("x.", &None as &Option<&str>).unwrap(),
+        hashmap!(k1 => hashmap!(mk1 => "41".to_string()),
+                 k2.clone() => hashmap!(mk2.clone() => "42".to_string()))
+    );
+
+    // Check metadata matching a given name
+    assert_eq!(
+        m.get_metadata_prefix("x.", &Some("metatest2")).unwrap(),
+        hashmap!(k2 => hashmap!(mk2 => "42".to_string()))
+    );
+}
+
+fn rollback_commit<D: DataStore>(m: &mut D) {
+    let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+    let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+
+    m.set_key(&k1, "1", &Committed::Live).unwrap();
+    m.set_keys(&hashmap!(k1.clone() => "2", k2.clone() => "3"), &pending)
+        .unwrap();
+    m.commit_transaction(tx).unwrap();
+
+    let changed = m.rollback_commit(1).unwrap();
+    assert_eq!(changed, hashset!(k1.clone(), k2.clone()));
+    assert_eq!(
+        m.get_key(&k1, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+    assert_eq!(m.get_key(&k2, &Committed::Live).unwrap(), None);
+
+    // The rollback is recorded, so it can be rolled back in turn.
+    assert_eq!(m.revision().unwrap(), 2);
+    let commits = m.list_commits().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[1].transaction, "rollback-1");
+    m.rollback_commit(2).unwrap();
+    assert_eq!(
+        m.get_key(&k2, &Committed::Live).unwrap(),
+        Some("3".to_string())
+    );
+}
+
+fn rollback_commit_conflict<D: DataStore>(m: &mut D) {
+    let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+    let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+
+    m.set_keys(&hashmap!(k1.clone() => "1", k2.clone() => "2"), &pending)
+        .unwrap();
+    m.commit_transaction(tx).unwrap();
+    m.set_key(&k2, "3", &pending).unwrap();
+    m.commit_transaction(tx).unwrap();
+
+    // The first commit can't be rolled back while the second has changed one of its keys,
+    // and nothing is changed by the attempt.
+    assert!(matches!(
+        m.rollback_commit(1),
+        Err(Error::RollbackConflict { id: 1, .. })
+    ));
+    assert_eq!(
+        m.get_key(&k1, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+
+    assert!(matches!(
+        m.rollback_commit(42),
+        Err(Error::NoSuchCommit { id: 42 })
+    ));
+}
+
+#[test]
+fn single_file_persists() {
+    let dir = tempfile::tempdir().unwrap();
+    let k = Key::new(KeyType::Data, "settings.a").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+
+    let mut m = SingleFileDataStore::new(dir.path());
+    assert!(!m.exists());
+    m.set_key(&k, "1", &pending).unwrap();
+    m.commit_transaction(tx).unwrap();
+    assert!(m.exists());
+
+    let m = SingleFileDataStore::new(dir.path());
+    assert_eq!(
+        m.get_key(&k, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+    assert_eq!(m.revision().unwrap(), 1);
+}
+
+#[test]
+fn single_file_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let k = Key::new(KeyType::Data, "settings.a").unwrap();
+
+    let mut m = SingleFileDataStore::new(dir.path());
+    m.set_key(&k, "1", &Committed::Live).unwrap();
+
+    // The file isn't read again once it's loaded, only replaced when the data store changes.
+    std::fs::write(dir.path().join("datastore.json"), "not json").unwrap();
+    assert_eq!(
+        m.get_key(&k, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+    assert!(SingleFileDataStore::new(dir.path())
+        .get_key(&k, &Committed::Live)
+        .is_err());
+
+    m.set_key(&k, "2", &Committed::Live).unwrap();
+    assert_eq!(
+        SingleFileDataStore::new(dir.path())
+            .get_key(&k, &Committed::Live)
+            .unwrap(),
+        Some("2".to_string())
+    );
+}
+
+#[test]
+fn single_file_mirrors_filesystem() {
+    let dir = tempfile::tempdir().unwrap();
+    let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+    let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+    let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
+
+    let mut m = SingleFileDataStore::new(dir.path());
+    m.set_key(&k1, "1", &Committed::Live).unwrap();
+    m.set_key(&k2, "2", &Committed::Live).unwrap();
+    m.set_metadata(&meta, &k1, "value").unwrap();
+    m.unset_key(&k2, &Committed::Live).unwrap();
+    assert!(m.mirror_complete());
+    assert!(m.mirror_matches().unwrap());
+
+    // Builds that only read the filesystem layout see the same data.
+    let mut fs = FilesystemDataStore::new(dir.path());
+    assert_eq!(
+        fs.get_key(&k1, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+    assert_eq!(fs.get_key(&k2, &Committed::Live).unwrap(), None);
+    assert_eq!(
+        fs.get_metadata_raw(&meta, &k1).unwrap(),
+        Some("value".to_string())
+    );
+
+    // Changes they make are noticed.
+    fs.set_key(&k1, "3", &Committed::Live).unwrap();
+    assert!(m.mirror_complete());
+    assert!(!m.mirror_matches().unwrap());
+
+    m.sync_mirror().unwrap();
+    assert_eq!(
+        fs.get_key(&k1, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+    assert!(m.mirror_matches().unwrap());
+}
+
+#[test]
+fn convert_filesystem_to_single_file_and_back() {
+    let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+    let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+    let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
+    let tx = "test transaction";
+    let pending = Committed::Pending { tx: tx.into() };
+
+    let source_dir = tempfile::tempdir().unwrap();
+    let mut source = FilesystemDataStore::new(source_dir.path());
+    source.set_key(&k1, "1", &pending).unwrap();
+    source.commit_transaction(tx).unwrap();
+    source.set_key(&k2, "2", &pending).unwrap();
+    source.set_metadata(&meta, &k1, "value").unwrap();
+
+    let single_dir = tempfile::tempdir().unwrap();
+    let single = SingleFileDataStore::from_data_store(single_dir.path(), &source).unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+    let target = single.to_filesystem(target_dir.path()).unwrap();
+
+    assert_converted(&single);
+    assert_converted(&target);
+}
+
+/// Checks that a data store holds the data set up by convert_filesystem_to_single_file_and_back.
+fn assert_converted<D: DataStore>(m: &D) {
+    let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+    let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+    let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
+    let pending = Committed::Pending {
+        tx: "test transaction".into(),
+    };
+
+    assert_eq!(
+        m.get_key(&k1, &Committed::Live).unwrap(),
+        Some("1".to_string())
+    );
+    assert_eq!(m.get_key(&k2, &pending).unwrap(), Some("2".to_string()));
+    assert_eq!(
+        m.get_metadata_raw(&meta, &k1).unwrap(),
+        Some("value".to_string())
+    );
+    assert_eq!(m.list_commits().unwrap().len(), 1);
+    assert_eq!(m.revision().unwrap(), 1);
+}
@@ -10,6 +10,12 @@ use std::process::Output;
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
 pub(crate) enum Error {
+    #[snafu(display("Unable to convert single-file data store to '{}': {}", path.display(), source))]
+    ConvertDataStore {
+        path: PathBuf,
+        source: datastore::Error,
+    },
+
     #[snafu(display("Data store path '{}' contains invalid UTF-8", path.display()))]
     DataStorePathNotUTF8 { path: PathBuf },
 
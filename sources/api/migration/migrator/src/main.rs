@@ -10,6 +10,8 @@
 //! * find the version of the given data store
 //! * find migrations between the two versions
 //! * if there are migrations:
+//!   * if the data store is kept in a single file, copy it to the filesystem layout that
+//!     migrations expect
 //!   * run the migrations; the transformed data becomes the new data store
 //! * if there are *no* migrations:
 //!   * just symlink to the old data store
@@ -22,6 +24,7 @@
 extern crate log;
 
 use args::Args;
+use datastore::SingleFileDataStore;
 use direction::Direction;
 use error::Result;
 use futures::{StreamExt, TryStreamExt};
@@ -261,6 +264,27 @@ where
     // intermediate_datastore.
     let mut intermediate_datastore = Option::default();
 
+    // Migrations only understand the filesystem layout, so a data store kept in a single file is
+    // copied to that layout first.  The copy is treated like an intermediate data store.  The
+    // migrated data store is left in the filesystem layout; storewolf converts it to the new
+    // version's format when it starts.
+    let converted_datastore;
+    let single_file = SingleFileDataStore::new(source_datastore);
+    if single_file.exists() {
+        converted_datastore = new_datastore_location(source_datastore, new_version)?;
+        info!(
+            "Copying single-file data store to {} for migrations",
+            converted_datastore.display()
+        );
+        single_file
+            .to_filesystem(&converted_datastore)
+            .context(error::ConvertDataStoreSnafu {
+                path: &converted_datastore,
+            })?;
+        intermediate_datastore = Some(converted_datastore.clone());
+        source_datastore = &converted_datastore;
+    }
+
     for migration in migrations {
         let migration = migration.as_ref();
         let migration = migration
@@ -52,6 +52,15 @@ impl BottlerocketTemplateImporter {
             ..Default::default()
         }
     }
+
+    /// Creates an importer that renders against the given JSON instead of fetching settings from
+    /// the API; see `BottlerocketSettingsResolver::with_settings_json`.
+    pub fn with_settings_json(settings_json: serde_json::Value) -> Self {
+        Self {
+            settings_resolver: BottlerocketSettingsResolver::with_settings_json(settings_json),
+            ..Default::default()
+        }
+    }
 }
 
 impl_template_importer!(
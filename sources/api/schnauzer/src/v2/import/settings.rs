@@ -27,11 +27,25 @@ pub trait SettingsResolver {
 #[derive(Debug, Clone)]
 pub struct BottlerocketSettingsResolver {
     pub api_socket: PathBuf,
+    // If set, used in place of the API's response, e.g. to render against pending settings.
+    settings_json: Option<Value>,
 }
 
 impl BottlerocketSettingsResolver {
     pub fn new(api_socket: PathBuf) -> Self {
-        Self { api_socket }
+        Self {
+            api_socket,
+            settings_json: None,
+        }
+    }
+
+    /// Creates a resolver that uses the given JSON instead of fetching settings from the API.  It
+    /// must look like the API's response from "/", with the "settings" and "os" keys.
+    pub fn with_settings_json(settings_json: Value) -> Self {
+        Self {
+            settings_json: Some(settings_json),
+            ..Default::default()
+        }
     }
 }
 
@@ -39,6 +53,7 @@ impl Default for BottlerocketSettingsResolver {
     fn default() -> Self {
         Self {
             api_socket: constants::API_SOCKET.into(),
+            settings_json: None,
         }
     }
 }
@@ -54,8 +69,11 @@ impl SettingsResolver for BottlerocketSettingsResolver {
         I: Iterator<Item = ExtensionRequirement> + Send,
     {
         // TODO: Modify this to use per-setting requests in the future.
-        let all_settings = get_settings_json(&self.api_socket)
-            .await?
+        let settings_json = match &self.settings_json {
+            Some(settings_json) => settings_json.clone(),
+            None => get_settings_json(&self.api_socket).await?,
+        };
+        let all_settings = settings_json
             .as_object()
             .cloned()
             .context(error::NonJSONObjectSnafu {
@@ -273,6 +291,30 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_fetch_settings_from_json() {
+        let resolver = BottlerocketSettingsResolver::with_settings_json(json!({
+            "settings": {"motd": "hello", "ntp": {"time-servers": ["a"]}},
+            "os": {"arch": "x86_64"},
+        }));
+        let extension_requirements = vec![ExtensionRequirement {
+            name: "motd".to_string(),
+            version: "v1".to_string(),
+            ..Default::default()
+        }];
+        let settings = resolver
+            .fetch_settings(extension_requirements.into_iter())
+            .await
+            .unwrap();
+        assert_eq!(
+            settings,
+            json!({
+                "settings": {"motd": "hello"},
+                "os": {"arch": "x86_64"},
+            })
+        );
+    }
+
     #[test]
     fn test_extract_key_from_api_response() {
         let success_test_cases = [
@@ -11,38 +11,25 @@ The `--transaction` argument can be used to specify another transaction.
 #[macro_use]
 extern crate log;
 
+use apiclient::client::{self, Client};
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::ResultExt;
 use std::str::FromStr;
 use std::{collections::HashMap, env, process};
 
-const API_PENDING_URI_BASE: &str = "/tx";
-const API_COMMIT_URI_BASE: &str = "/tx/commit";
-
 type Result<T> = std::result::Result<T, error::SettingsCommitterError>;
 
 mod error {
-    use http::StatusCode;
     use snafu::Snafu;
 
     /// Potential errors during user data management.
     #[derive(Debug, Snafu)]
     #[snafu(visibility(pub(super)))]
     pub(super) enum SettingsCommitterError {
-        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
-        APIRequest {
-            method: String,
-            uri: String,
-            #[snafu(source(from(apiclient::Error, Box::new)))]
-            source: Box<apiclient::Error>,
-        },
-
-        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
-        APIResponse {
-            method: String,
-            uri: String,
-            code: StatusCode,
-            response_body: String,
+        #[snafu(display("Error committing transaction '{}': {}", transaction, source))]
+        Commit {
+            transaction: String,
+            source: apiclient::client::Error,
         },
 
         #[snafu(display("Logger setup error: {}", source))]
@@ -54,72 +41,35 @@ mod error {
 /// commit if there's a blip in retrieval or parsing of the pending
 /// settings.  We know the system won't be functional without a commit,
 /// but we can live without logging what was committed.
-async fn check_pending_settings<S: AsRef<str>>(socket_path: S, transaction: &str) {
-    let uri = format!("{}?tx={}", API_PENDING_URI_BASE, transaction);
-
-    debug!("GET-ing {} to determine if there are pending settings", uri);
-    let get_result = apiclient::raw_request(socket_path.as_ref(), &uri, "GET", None).await;
-    let response_body = match get_result {
-        Ok((code, response_body)) => {
-            if !code.is_success() {
-                warn!(
-                    "Got {} when sending GET to {}: {}",
-                    code, uri, response_body
-                );
-                return;
-            }
-            response_body
-        }
-        Err(err) => {
-            warn!("Failed to GET pending settings from {}: {}", uri, err);
-            return;
-        }
-    };
-
-    let pending_result: serde_json::Result<HashMap<String, serde_json::Value>> =
-        serde_json::from_str(&response_body);
+async fn check_pending_settings(client: &Client, transaction: &str) {
+    debug!("Checking for pending settings in tx {}", transaction);
+    let pending_result: client::Result<HashMap<String, serde_json::Value>> =
+        client.get_pending_settings(transaction).await;
     match pending_result {
         Ok(pending) => {
             debug!("Pending settings for tx {}: {:?}", transaction, &pending);
         }
         Err(err) => {
-            warn!("Failed to parse response from {}: {}", uri, err);
+            warn!("Failed to get pending settings: {}", err);
         }
     }
 }
 
 /// Commits pending settings to live.
-async fn commit_pending_settings<S: AsRef<str>>(socket_path: S, transaction: &str) -> Result<()> {
-    let uri = format!("{}?tx={}", API_COMMIT_URI_BASE, transaction);
-    debug!("POST-ing to {} to move pending settings to live", uri);
-
-    if let Err(e) = apiclient::raw_request(socket_path.as_ref(), &uri, "POST", None).await {
-        match e {
-            // Some types of response errors are OK for this use.
-            apiclient::Error::ResponseStatus { code, body, .. } => {
-                if code.as_u16() == 422 {
-                    info!("settings-committer found no settings changes to commit");
-                    return Ok(());
-                } else {
-                    return error::APIResponseSnafu {
-                        method: "POST",
-                        uri,
-                        code,
-                        response_body: body,
-                    }
-                    .fail();
-                }
-            }
-            // Any other type of error means we couldn't even make the request.
-            _ => {
-                return Err(e).context(error::APIRequestSnafu {
-                    method: "POST",
-                    uri,
-                });
-            }
+async fn commit_pending_settings(client: &Client, transaction: &str) -> Result<()> {
+    debug!(
+        "Committing tx {} to move pending settings to live",
+        transaction
+    );
+    match client.commit(transaction).await {
+        Ok(_) => Ok(()),
+        // Having nothing to commit is OK for this use.
+        Err(client::Error::Unprocessable { .. }) => {
+            info!("settings-committer found no settings changes to commit");
+            Ok(())
         }
+        Err(e) => Err(e).context(error::CommitSnafu { transaction }),
     }
-    Ok(())
 }
 
 /// Store the args we receive on the command line
@@ -203,11 +153,13 @@ async fn run() -> Result<()> {
     // SimpleLogger will send errors to stderr and anything less to stdout.
     SimpleLogger::init(args.log_level, LogConfig::default()).context(error::LoggerSnafu)?;
 
+    let client = Client::new(&args.socket_path);
+
     info!("Checking pending settings.");
-    check_pending_settings(&args.socket_path, &args.transaction).await;
+    check_pending_settings(&client, &args.transaction).await;
 
     info!("Committing settings.");
-    commit_pending_settings(&args.socket_path, &args.transaction).await?;
+    commit_pending_settings(&client, &args.transaction).await?;
 
     Ok(())
 }
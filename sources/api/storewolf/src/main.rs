@@ -1,10 +1,19 @@
 /*!
 # Introduction
 
-storewolf creates the filesystem datastore used by the API system.
+storewolf creates the datastore used by the API system.
 
 It creates the datastore at a provided path and populates any default settings, as given in the
 TOML file in `/etc/storewolf/defaults.toml`, unless the datastore already exists.
+
+If the datastore exists but was written by the other datastore backend, e.g. because the host
+booted a build with the `single-file-datastore` feature for the first time, storewolf converts it
+to the backend this build uses before anything else reads it.
+
+When converting to a single file, the filesystem copy is kept and updated along with the single
+file, so a rollback or downgrade to a build that only reads the filesystem layout keeps its
+settings.  If such a build changes the settings, storewolf converts the filesystem copy again on
+the next boot with the single file.
 */
 #[macro_use]
 extern crate log;
@@ -23,7 +32,7 @@ use std::{env, fs, process};
 use bottlerocket_modeled_types::SingleLineString;
 use datastore::key::{Key, KeyType};
 use datastore::serialization::{to_pairs, to_pairs_with_prefix};
-use datastore::{self, DataStore, FilesystemDataStore, ScalarError};
+use datastore::{self, DataStore, DefaultDataStore, ScalarError};
 
 // The default path to defaults.toml.
 const DEFAULTS_TOML: &str = "/etc/storewolf/defaults.toml";
@@ -44,8 +53,17 @@ mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility(pub(super)))]
     pub(super) enum StorewolfError {
+        #[snafu(display("Unable to convert datastore to this build's backend: {}", source))]
+        ConvertDatastore {
+            #[snafu(source(from(datastore::Error, Box::new)))]
+            source: Box<datastore::Error>,
+        },
+
         #[snafu(display("Unable to clear pending transactions: {}", source))]
-        DeletePending { source: io::Error },
+        DeletePending {
+            #[snafu(source(from(datastore::Error, Box::new)))]
+            source: Box<datastore::Error>,
+        },
 
         #[snafu(display("Unable to create datastore: {}", source))]
         DatastoreCreation { source: storewolf::error::Error },
@@ -234,7 +252,7 @@ fn parse_metadata_toml(md_toml_val: toml::Value) -> Result<Vec<model::Metadata>>
     Ok(def_metadatas)
 }
 
-/// Creates a new FilesystemDataStore at the given path, with data and metadata coming from
+/// Creates a new DefaultDataStore at the given path, with data and metadata coming from
 /// the variant's TOML default settings files at compile time.
 fn populate_default_datastore<P: AsRef<Path>>(
     base_path: P,
@@ -249,14 +267,13 @@ fn populate_default_datastore<P: AsRef<Path>>(
     // actually lives. This is the start of the chain, whose name never
     // changes, so it can be used consistently by the rest of the OS.
     let datastore_path = base_path.as_ref().join("current");
-    let mut datastore = FilesystemDataStore::new(&datastore_path);
+    let mut datastore = DefaultDataStore::new(&datastore_path);
     let mut existing_data = HashSet::new();
     let mut existing_metadata = HashMap::new();
 
-    // If the "live" path of the datastore exists, query it for populated
-    // meta/data.  Otherwise, create the datastore path.
-    let live_path = &datastore_path.join("live");
-    if live_path.exists() {
+    // If the datastore exists, query it for populated meta/data.  Otherwise,
+    // create the datastore path.
+    if datastore.exists() {
         debug!("Gathering existing data from the datastore");
         existing_metadata = datastore
             .list_populated_metadata("", &None as &Option<&str>)
@@ -265,7 +282,7 @@ fn populate_default_datastore<P: AsRef<Path>>(
             .list_populated_keys("", &datastore::Committed::Live)
             .context(error::QueryDataSnafu)?;
     } else {
-        info!("Creating datastore at: {}", &live_path.display());
+        info!("Creating datastore at: {}", &datastore_path.display());
         create_new_datastore(&base_path, version).context(error::DatastoreCreationSnafu)?;
     }
 
@@ -351,8 +368,8 @@ fn populate_default_datastore<P: AsRef<Path>>(
             .collect();
 
         // For each of the default metadatas, check if it exists in the
-        // datastore. If not, add it to the set of metadatas to write
-        let mut metadata_to_write = HashSet::new();
+        // datastore. If not, add it to the map of metadatas to write
+        let mut metadata_to_write = HashMap::new();
         for def_metadata in def_metadatas {
             let model::Metadata { key, md, val } = def_metadata;
             let data_key = Key::new(KeyType::Data, &key).context(error::InvalidKeySnafu {
@@ -374,7 +391,7 @@ fn populate_default_datastore<P: AsRef<Path>>(
                             given: format!("metadata value '{}'", val),
                         }
                     })?;
-                metadata_to_write.insert((md_key, data_key, value));
+                metadata_to_write.insert((md_key, data_key), value);
             }
         }
 
@@ -382,12 +399,9 @@ fn populate_default_datastore<P: AsRef<Path>>(
             "Writing default metadata to datastore: {:#?}",
             metadata_to_write
         );
-        for metadata in metadata_to_write {
-            let (md, key, val) = metadata;
-            datastore
-                .set_metadata(&md, &key, val)
-                .context(error::WriteMetadataSnafu)?;
-        }
+        datastore
+            .set_metadata_keys(&metadata_to_write)
+            .context(error::WriteMetadataSnafu)?;
     }
 
     // If any other defaults remain (configuration files, services, etc),
@@ -549,16 +563,22 @@ fn run() -> Result<()> {
 
     info!("Storewolf started");
 
+    // Convert the datastore first, so the rest of the run sees the data no
+    // matter which backend wrote it.
+    let datastore_path = Path::new(&args.data_store_base_path).join("current");
+    if datastore::convert_to_default(&datastore_path).context(error::ConvertDatastoreSnafu)? {
+        info!("Converted datastore at: {}", datastore_path.display());
+    }
+
     info!("Deleting pending transactions");
-    let pending_path = Path::new(&args.data_store_base_path)
-        .join("current")
-        .join("pending");
-    if let Err(e) = fs::remove_dir_all(pending_path) {
-        // If there are no pending settings, the directory won't exist.
-        // Ignore the error in this case.
-        if e.kind() != io::ErrorKind::NotFound {
-            Err(e).context(error::DeletePendingSnafu)?
-        }
+    let mut datastore = DefaultDataStore::new(&datastore_path);
+    for transaction in datastore
+        .list_transactions()
+        .context(error::DeletePendingSnafu)?
+    {
+        datastore
+            .delete_transaction(transaction)
+            .context(error::DeletePendingSnafu)?;
     }
 
     // Create the datastore if it doesn't exist
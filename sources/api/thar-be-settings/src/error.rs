@@ -35,6 +35,9 @@ pub enum Error {
         source: num::ParseIntError,
     },
 
+    #[snafu(display("Failed to read configuration file {}: {}", path, source))]
+    ConfigRead { path: String, source: io::Error },
+
     #[snafu(display("Failed to run restart command - '{}': {}", command, source))]
     CommandExecutionFailure { command: String, source: io::Error },
 
@@ -82,6 +85,12 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Changed setting '{}' is not a valid key: {}", key, source))]
+    InvalidKey {
+        key: String,
+        source: datastore::Error,
+    },
+
     #[snafu(display("Error GETing JSON from '{}': {}", uri, source))]
     GetJson {
         uri: String,
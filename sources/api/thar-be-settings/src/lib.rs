@@ -11,18 +11,32 @@ Configuration file data from the API includes paths to template files for each c
 It then renders the templates and rewrites the affected configuration files.
 Service data from the API includes any commands needed to restart services affected by configuration file changes, which are run here.
 
+Keys removed by the commit are included in the changed keys, just like keys that were set.
+Their metadata is still found through their parent keys, so the same services are affected, and their templates are re-rendered without the removed settings.
+Templates should therefore handle optional settings being absent, because rendering in this mode fails on errors.
+Changed keys that are no longer in the live settings are treated as removed, and restart commands are given them in the `REMOVED_SETTINGS` environment variable, alongside `CHANGED_SETTINGS`.
+
 In the standalone ("all keys") mode, it queries the API for all services and configuration files, then renders and rewrites all configuration files and restarts all services.
+
+In the "plan" mode, it's called by the API server to show what committing a transaction would change.
+The API server gives it the configuration files to render and the settings they'd be rendered with after the commit.
+It renders the files and prints a unified diff against each file on disk, without writing files or restarting services.
 */
 
 #[macro_use]
 extern crate log;
 
+use datastore::{Key, KeyType};
+use itertools::join;
+use serde_json::Value;
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::io::{self, Read};
+use std::path::Path;
 
 pub mod config;
 pub mod error;
+pub mod plan;
 pub mod service;
 
 pub use error::Error;
@@ -47,3 +61,71 @@ pub fn get_changed_settings() -> Result<HashSet<String>> {
 
     Ok(changed_settings)
 }
+
+/// Ask the API which of the given changed settings are no longer in the live settings, meaning
+/// the commit removed them.
+pub async fn get_removed_settings<P>(
+    socket_path: P,
+    changed_settings: &HashSet<String>,
+) -> Result<HashSet<String>>
+where
+    P: AsRef<Path>,
+{
+    let query = ("keys", join(changed_settings, ","));
+
+    debug!("Querying API for live values of changed settings");
+    let uri = "/settings";
+    let live: Value = schnauzer::v1::get_json(socket_path, uri, Some(query))
+        .await
+        .context(error::GetJsonSnafu { uri })?;
+    trace!("API response: {:?}", &live);
+
+    removed_settings(changed_settings, &live)
+}
+
+/// Returns the changed settings that don't have a value in the given live settings, which start
+/// below the "settings" segment, like responses from /settings.
+fn removed_settings(changed_settings: &HashSet<String>, live: &Value) -> Result<HashSet<String>> {
+    let mut removed = HashSet::new();
+    for setting in changed_settings {
+        let key = Key::new(KeyType::Data, setting).context(error::InvalidKeySnafu {
+            key: setting.as_str(),
+        })?;
+        let mut value = Some(live);
+        for segment in key.segments().iter().skip(1) {
+            value = value.and_then(|v| v.get(segment));
+        }
+        if value.is_none_or(Value::is_null) {
+            removed.insert(setting.clone());
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashset;
+    use serde_json::json;
+
+    #[test]
+    fn removed_settings_works() {
+        let changed = hashset! {
+            "settings.motd".to_string(),
+            "settings.ntp.time-servers".to_string(),
+            "settings.kubernetes.node-labels.\"example.com/role\"".to_string(),
+            "settings.kubernetes.node-labels.\"example.com/zone\"".to_string(),
+        };
+        let live = json!({
+            "motd": "hi",
+            "kubernetes": {"node-labels": {"example.com/role": "worker"}},
+        });
+        assert_eq!(
+            removed_settings(&changed, &live).unwrap(),
+            hashset! {
+                "settings.ntp.time-servers".to_string(),
+                "settings.kubernetes.node-labels.\"example.com/zone\"".to_string(),
+            }
+        );
+    }
+}
@@ -3,15 +3,16 @@ extern crate log;
 
 use nix::unistd::{fork, ForkResult};
 use schnauzer::BottlerocketTemplateImporter;
-use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger, WriteLogger};
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::env;
+use std::io;
 use std::process;
 use std::str::FromStr;
 use tokio::runtime::Runtime;
 
-use thar_be_settings::{config, get_changed_settings, service};
+use thar_be_settings::{config, get_changed_settings, get_removed_settings, plan, service};
 
 mod error {
     use snafu::Snafu;
@@ -21,16 +22,21 @@ mod error {
     pub(super) enum Error {
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Failed to serialize plan: {}", source))]
+        SerializePlan { source: serde_json::Error },
     }
 }
 
 /// RunMode represents how thar-be-settings was requested to be run, either handling all
 /// configuration files and services, or handling configuration files and services based on
-/// specific keys given by the user.
+/// specific keys given by the user, or only planning changes to configuration files requested by
+/// the API server.
 #[derive(Debug)]
 enum RunMode {
     All,
     SpecificKeys,
+    Plan,
 }
 
 /// Store the args we receive on the command line
@@ -46,7 +52,7 @@ fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
     eprintln!(
         r"Usage: {}
-            [ --all ]
+            [ --all | --plan ]
             [ --daemon ]
             [ --socket-path PATH ]
             [ --log-level trace|debug|info|warn|error ]
@@ -56,6 +62,11 @@ fn usage() -> ! {
     will be read from stdin; only files related to those keys will be written,
     and only services related to those keys will be restarted.
 
+    If --plan is given, a render request from the API server will be read from
+    stdin, and the requested configuration files will be rendered against the
+    data in the request and compared to the files on disk.  Nothing is written
+    and no services are restarted; the results are printed to stdout as JSON.
+
     If --daemon is given, thar-be-settings will fork and do its work in a new
     process; this is useful to prevent blocking an API call.
 
@@ -84,6 +95,8 @@ fn parse_args(args: env::Args) -> Args {
         match arg.as_ref() {
             "--all" => mode = RunMode::All,
 
+            "--plan" => mode = RunMode::Plan,
+
             "--daemon" => daemon = true,
 
             "--log-level" => {
@@ -130,7 +143,7 @@ async fn write_config_files(
     // Ensure all files render properly
     info!("Rendering config files...");
     let strict = match &args.mode {
-        RunMode::SpecificKeys => true,
+        RunMode::SpecificKeys | RunMode::Plan => true,
         RunMode::All => false,
     };
     let rendered = config::render_config_files(&template_importer, config_files, strict).await?;
@@ -149,8 +162,14 @@ async fn write_config_files(
 }
 
 async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
-    // SimpleLogger will send errors to stderr and anything less to stdout.
-    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::LoggerSnafu)?;
+    // SimpleLogger will send errors to stderr and anything less to stdout.  When planning, stdout
+    // is for the results, so all logs go to stderr.
+    if let RunMode::Plan = args.mode {
+        WriteLogger::init(args.log_level, LogConfig::default(), io::stderr())
+            .context(error::LoggerSnafu)?;
+    } else {
+        SimpleLogger::init(args.log_level, LogConfig::default()).context(error::LoggerSnafu)?;
+    }
 
     info!("thar-be-settings started");
 
@@ -165,13 +184,19 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
                 "Requesting affected services for settings: {:?}",
                 &changed_settings
             );
-            let services =
+            let removed_settings =
+                get_removed_settings(&args.socket_path, &changed_settings).await?;
+            if !removed_settings.is_empty() {
+                info!("Settings removed by the commit: {:?}", &removed_settings);
+            }
+            let mut services =
                 service::get_affected_services(&args.socket_path, Some(changed_settings)).await?;
             trace!("Found services: {:?}", services);
             if services.0.is_empty() {
                 info!("No services are affected, exiting...");
                 process::exit(0)
             }
+            services.set_removed_settings(&removed_settings);
 
             // Create a HashSet of configuration file names
             let config_file_names = config::get_config_file_names(&services);
@@ -192,6 +217,15 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
             trace!("Found services: {:?}", services);
             service::restart_services(services)?;
         }
+        RunMode::Plan => {
+            info!("Parsing stdin for configuration files to plan");
+            let request = plan::get_render_request()?;
+
+            info!("Rendering config files against requested data...");
+            let plans = plan::plan_config_files(request).await?;
+            let output = serde_json::to_string(&plans).context(error::SerializePlanSnafu)?;
+            println!("{}", output);
+        }
     }
 
     Ok(())
@@ -0,0 +1,190 @@
+//! The plan module renders configuration files against data given to us by the API server, rather
+//! than against live settings, and compares the results to the files on disk.  Nothing is written
+//! and no services are restarted; this is how the API server shows what a commit would change.
+
+use crate::{error, Result};
+use model::plan::{ConfigurationFilePlan, RenderRequest};
+use schnauzer::BottlerocketTemplateImporter;
+use snafu::ResultExt;
+use std::fs;
+use std::io::{self, Read};
+
+/// The number of unchanged lines shown before and after each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Read stdin and parse into a RenderRequest
+pub fn get_render_request() -> Result<RenderRequest> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .context(error::ReadInputSnafu { from: "stdin" })?;
+    trace!("Raw input from stdin: {}", &input);
+
+    debug!("Parsing stdin as JSON");
+    serde_json::from_str(&input).context(error::InvalidInputSnafu {
+        reason: "Input must be a JSON render request",
+        input,
+    })
+}
+
+/// Renders each requested configuration file and compares it to the file on disk.  A file that
+/// fails to render is reported with its error, rather than failing the whole plan, so the user can
+/// see every problem at once.
+pub async fn plan_config_files(request: RenderRequest) -> Result<Vec<ConfigurationFilePlan>> {
+    // Templates get the same minimized settings they would from the API, but from the request.
+    let template_importer = BottlerocketTemplateImporter::with_settings_json(request.template_data);
+
+    let mut plans = Vec::new();
+    for (name, metadata) in request.configuration_files {
+        debug!("Rendering {}", &name);
+        let path = metadata.path.to_string();
+
+        let try_rendered =
+            schnauzer::render_template_file(&template_importer, &metadata.template_path.as_ref())
+                .await;
+
+        let plan = match try_rendered {
+            Ok(rendered) => {
+                // A file that doesn't exist yet is compared as if it were empty.
+                let current = match fs::read_to_string(&path) {
+                    Ok(current) => current,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(e).context(error::ConfigReadSnafu { path }),
+                };
+                ConfigurationFilePlan {
+                    diff: unified_diff(&current, &rendered, &path),
+                    name,
+                    path,
+                    error: None,
+                }
+            }
+            Err(err) => {
+                warn!("Unable to render template '{}': {}", &name, err);
+                ConfigurationFilePlan {
+                    name,
+                    path,
+                    diff: String::new(),
+                    error: Some(err.to_string()),
+                }
+            }
+        };
+        plans.push(plan);
+    }
+
+    plans.sort_by(|a, b| a.name.cmp(&b.name));
+    trace!("Planned configuration files: {:?}", &plans);
+    Ok(plans)
+}
+
+/// Returns a unified diff from `old` to `new`, both of which are the contents of the file at the
+/// given path; returns an empty string if they're the same.
+fn unified_diff(old: &str, new: &str, path: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+
+    // We split the lines ourselves, rather than using diff::lines, so that a trailing newline isn't
+    // shown as an extra empty line.
+    let old_lines: Vec<_> = old.lines().collect();
+    let new_lines: Vec<_> = new.lines().collect();
+    let lines = diff::slice(&old_lines, &new_lines);
+
+    // Find the position of each line in the old and new files, so we can number the hunks.  The
+    // extra entry at the end holds the lengths of the files.
+    let mut positions = Vec::with_capacity(lines.len() + 1);
+    let (mut old_line, mut new_line) = (0, 0);
+    for line in &lines {
+        positions.push((old_line, new_line));
+        match line {
+            diff::Result::Left(_) => old_line += 1,
+            diff::Result::Right(_) => new_line += 1,
+            diff::Result::Both(_, _) => {
+                old_line += 1;
+                new_line += 1;
+            }
+        }
+    }
+    positions.push((old_line, new_line));
+
+    // Group the changed lines into hunks, joining hunks whose context would overlap.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let diff::Result::Both(_, _) = line {
+            continue;
+        }
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + DIFF_CONTEXT + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = format!("--- {}\n+++ {}\n", path, path);
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_end - old_start),
+            hunk_range(new_start, new_end - new_start)
+        ));
+        for line in &lines[start..end] {
+            let (prefix, text) = match line {
+                diff::Result::Left(text) => ('-', text),
+                diff::Result::Right(text) => ('+', text),
+                diff::Result::Both(text, _) => (' ', text),
+            };
+            output.push(prefix);
+            output.push_str(text);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// Formats the range of a hunk for its header, given the zero-based index of its first line and
+/// its number of lines.  Lines are numbered from one, except that an empty range is numbered by
+/// the line before it.
+fn hunk_range(start: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_file_has_no_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "/etc/x"), "");
+    }
+
+    #[test]
+    fn changed_line() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        let expected =
+            "--- /etc/x\n+++ /etc/x\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n";
+        assert_eq!(unified_diff(old, new, "/etc/x"), expected);
+    }
+
+    #[test]
+    fn separate_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n";
+        let expected = "--- /etc/x\n+++ /etc/x\n\
+            @@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n\
+            @@ -7,4 +7,4 @@\n 7\n 8\n 9\n-10\n+ten\n";
+        assert_eq!(unified_diff(old, new, "/etc/x"), expected);
+    }
+
+    #[test]
+    fn new_file() {
+        let expected = "--- /etc/x\n+++ /etc/x\n@@ -0,0 +1,2 @@\n+a\n+b\n";
+        assert_eq!(unified_diff("", "a\nb\n", "/etc/x"), expected);
+    }
+}
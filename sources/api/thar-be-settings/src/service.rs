@@ -18,6 +18,9 @@ pub struct Service {
     /// The specific list of settings that changed and are relevant to this service.  Will be None
     /// if the program is running for *all* services, like at startup.
     pub changed_settings: Option<HashSet<String>>,
+    /// The subset of `changed_settings` that was removed rather than set.  Will be None if the
+    /// program is running for *all* services, like at startup.
+    pub removed_settings: Option<HashSet<String>>,
     /// The model's representation of this service.
     pub model: model::Service,
 }
@@ -36,12 +39,24 @@ impl Services {
                 name,
                 Service {
                     changed_settings,
+                    removed_settings: None,
                     model,
                 },
             );
         }
         Self(output)
     }
+
+    /// Record which of each service's changed settings were removed, given the full list of
+    /// removed settings.
+    pub fn set_removed_settings(&mut self, removed: &HashSet<String>) {
+        for service in self.0.values_mut() {
+            service.removed_settings = service
+                .changed_settings
+                .as_ref()
+                .map(|changed| changed.intersection(removed).cloned().collect());
+        }
+    }
 }
 
 /// Returns a `Services` reflecting the set of services affected by the given changed settings in
@@ -174,6 +189,11 @@ impl ServiceRestart for Service {
                     process_command.env("CHANGED_SETTINGS", join(changed_settings, " "));
                 }
             }
+            if let Some(ref removed_settings) = self.removed_settings {
+                if !removed_settings.is_empty() {
+                    process_command.env("REMOVED_SETTINGS", join(removed_settings, " "));
+                }
+            }
             let result = process_command
                 .output()
                 .context(error::CommandExecutionFailureSnafu {
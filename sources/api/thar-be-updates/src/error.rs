@@ -109,6 +109,41 @@ pub enum Error {
     #[snafu(display("Failed to start signpost: {}", source))]
     Signpost { source: std::io::Error },
 
+    #[snafu(display("Failed to roll back with signpost"))]
+    Rollback,
+
+    #[snafu(display("Failed to cancel the rollback with signpost"))]
+    CancelRollback,
+
+    #[snafu(display("No inactive partition set to roll back to"))]
+    RollbackNoInactive,
+
+    #[snafu(display("The inactive partition set has never booted successfully"))]
+    RollbackNotSuccessful,
+
+    #[snafu(display(
+        "Unable to read release info of the inactive partition set: {}",
+        source
+    ))]
+    RollbackRelease { source: bottlerocket_release::Error },
+
+    #[snafu(display(
+        "The inactive partition set holds variant '{}', but '{}' is running",
+        inactive,
+        active
+    ))]
+    RollbackVariant { inactive: String, active: String },
+
+    #[snafu(display(
+        "The inactive partition set holds version {}, which is not older than the running version {}",
+        inactive,
+        active
+    ))]
+    RollbackVersion {
+        inactive: semver::Version,
+        active: semver::Version,
+    },
+
     #[snafu(display("Failed to get setting '{}': {}", setting, source))]
     GetSetting {
         setting: String,
@@ -147,6 +182,43 @@ pub enum Error {
 
     #[snafu(display("Logger setup error: {}", source))]
     Logger { source: log::SetLoggerError },
+
+    #[snafu(display("Unable to use update source '{}': {}", path.display(), source))]
+    UpdateSource {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Update source '{}' must be an absolute path", path.display()))]
+    UpdateSourceRelative { path: PathBuf },
+
+    #[snafu(display("Failed to inspect update source '{}': {}", path.display(), source))]
+    UpdateSourceDevice {
+        path: PathBuf,
+        source: block_party::Error,
+    },
+
+    #[snafu(display(
+        "Update source '{}' is on the OS disk '{}'",
+        path.display(),
+        disk.display()
+    ))]
+    UpdateSourceOsDisk { path: PathBuf, disk: PathBuf },
+
+    #[snafu(display("Failed to create mount point: {}", source))]
+    MountDir { source: std::io::Error },
+
+    #[snafu(display("Failed to start mount for '{}': {}", path.display(), source))]
+    Mount {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to mount '{}': {}", path.display(), status))]
+    MountStatus {
+        path: PathBuf,
+        status: std::process::ExitStatus,
+    },
 }
 
 /// Map errors to specific exit codes to return to caller
@@ -157,4 +229,6 @@ pub enum TbuErrorStatus {
     DisallowCommand = 65,
     UpdateDoesNotExist = 66,
     NoStagedImage = 67,
+    InvalidSource = 68,
+    InvalidRollback = 69,
 }
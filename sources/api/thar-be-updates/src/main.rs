@@ -14,36 +14,69 @@ This allows the caller to synchronously call thar-be-updates without having to w
 
 thar-be-updates uses a lockfile to control read/write access to the disks and the update status file.
 
+While preparing an update, thar-be-updates copies updog's download progress into the update status file every second, so the progress can be seen while the command holds the lock.
+updog continues an interrupted download where it stopped, so preparing the update again after a failure doesn't start from the beginning.
+
+The `refresh` and `prepare` commands can use a local update repository instead of the configured URLs, for hosts without access to them.
+Give `--source` with the path of a directory holding the repository, with `metadata` and `targets` subdirectories, or of a block device whose filesystem holds it.
+A block device is mounted read-only, with `nosuid`, `nodev`, and `noexec`, while the command runs.
+Devices on the OS disk, like its partitions, are refused.
+The repository's metadata is verified against the same trusted root as with the configured URLs.
+
+If updog's config file gives maintenance windows, updog refuses to activate an update outside of them; give `--force` to `activate` to do it anyway.
+
+After booting a new version, updog can check its health before marking the boot successful, rolling back to the previous version if the check fails.
+The outcome updog records is included in the update status, as `boot_health`.
+
+The `rollback` command marks the inactive partition set for boot, to go back to the previous version without waiting for a health check to fail.
+It's only allowed if the inactive partition set has booted successfully, and its release info, read from its root filesystem, shows an older version of the same variant.
+Like an activated update, the rollback takes effect on the next reboot, and can be reverted with `deactivate` until then; since there's no staged update to activate, that returns the update state to `Idle` rather than `Staged`.
+Reverting a rollback runs `signpost cancel-rollback`, which keeps the previous version as the fallback if the running version fails to boot.
+
 */
 
+use block_party::BlockDevice;
+use bottlerocket_release::BottlerocketRelease;
 use fs2::FileExt;
 use log::{debug, warn};
 use nix::unistd::{fork, ForkResult};
 use num_traits::cast::ToPrimitive;
+use signpost::State;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::ensure;
 use snafu::{OptionExt, ResultExt};
+use std::ffi::OsStr;
 use std::fs::File;
+use std::os::unix::fs::FileTypeExt;
 use std::path::{Path, PathBuf};
 use std::process::{exit, Command};
 use std::str::FromStr;
-use std::{env, process};
-use tempfile::NamedTempFile;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::{env, fs, process};
+use tempfile::{NamedTempFile, TempDir};
 use thar_be_updates::error;
 use thar_be_updates::error::{Error, Result, TbuErrorStatus};
 use thar_be_updates::status::{
-    get_update_status, UpdateCommand, UpdateState, UpdateStatus, UPDATE_LOCKFILE,
-    UPDATE_STATUS_FILE,
+    get_update_status, DownloadProgress, UpdateCommand, UpdateState, UpdateStatus, UPDATE_LOCKFILE,
+    UPDATE_PROGRESS_FILE, UPDATE_STATUS_FILE,
 };
 
 const UPDATE_STATUS_DIR: &str = "/run/cache/thar-be-updates";
 const DEFAULT_CONFIG_FILE: &str = "/etc/thar-be-updates.toml";
+/// The release info of a version, relative to the root of its filesystem
+const RELEASE_FILE: &str = "usr/lib/os-release";
+/// How often we copy updog's download progress into the update status file
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Stores the command line arguments
 struct Args {
     subcommand: UpdateCommand,
     log_level: LevelFilter,
     config_path: PathBuf,
+    source: Option<PathBuf>,
+    force: bool,
 }
 
 /// Prints an usage message
@@ -58,6 +91,17 @@ fn usage() -> ! {
                             inactive partition
                 activate    Marks the inactive partition for boot
                 deactivate  Reverts update activation by marking current active partition for boot
+                rollback    Marks the inactive partition for boot, if it holds an older version
+                            that has booted successfully
+
+            Options for refresh and prepare:
+                    [ --source PATH ]         local update repository directory, or block
+                                              device holding one, to use instead of the
+                                              configured repository
+
+            Options for activate:
+                    [ --force ]               mark the update for boot even outside of
+                                              updog's maintenance windows
 
             Global options:
                     [ --config-path PATH ]    configuration file (default {})
@@ -78,6 +122,8 @@ fn parse_args(args: std::env::Args) -> Args {
     let mut subcommand = None;
     let mut log_level = None;
     let mut config_path = None;
+    let mut source = None;
+    let mut force = false;
 
     let mut iter = args.skip(1).peekable();
     while let Some(arg) = iter.next() {
@@ -97,6 +143,13 @@ fn parse_args(args: std::env::Args) -> Args {
                         usage_msg("Did not give argument to --config-path")
                     })))
             }
+            "--source" => {
+                source =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --source")
+                    })))
+            }
+            "--force" => force = true,
             // Assume any arguments not prefixed with '-' is a subcommand
             s if !s.starts_with('-') => {
                 if subcommand.is_some() {
@@ -109,10 +162,20 @@ fn parse_args(args: std::env::Args) -> Args {
         }
     }
 
+    let subcommand = subcommand.unwrap_or_else(|| usage());
+    if source.is_some() && !matches!(subcommand, UpdateCommand::Refresh | UpdateCommand::Prepare) {
+        usage_msg("--source can only be given to refresh and prepare");
+    }
+    if force && !matches!(subcommand, UpdateCommand::Activate) {
+        usage_msg("--force can only be given to activate");
+    }
+
     Args {
-        subcommand: subcommand.unwrap_or_else(|| usage()),
+        subcommand,
         log_level: log_level.unwrap_or(LevelFilter::Info),
         config_path: config_path.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE)),
+        source,
+        force,
     }
 }
 
@@ -159,6 +222,117 @@ fn write_update_status(update_status: &UpdateStatus) -> Result<()> {
     Ok(())
 }
 
+/// ReadOnlyMount is a block device mounted read-only in a temporary directory until it's dropped.
+/// Nothing on it can be run, and device nodes and setuid bits on it are ignored.
+struct ReadOnlyMount {
+    dir: TempDir,
+}
+
+impl ReadOnlyMount {
+    fn new(device: &Path) -> Result<Self> {
+        let dir = TempDir::new_in(UPDATE_STATUS_DIR).context(error::MountDirSnafu)?;
+        debug!(
+            "Mounting '{}' at '{}'",
+            device.display(),
+            dir.path().display()
+        );
+        let status = Command::new("mount")
+            .args(["-o", "ro,nosuid,nodev,noexec"])
+            .arg(device)
+            .arg(dir.path())
+            .status()
+            .context(error::MountSnafu { path: device })?;
+        ensure!(
+            status.success(),
+            error::MountStatusSnafu {
+                path: device,
+                status
+            }
+        );
+        Ok(Self { dir })
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for ReadOnlyMount {
+    fn drop(&mut self) {
+        debug!("Unmounting '{}'", self.path().display());
+        if let Err(e) = Command::new("umount").arg(self.path()).status() {
+            warn!("Failed to unmount '{}': {}", self.path().display(), e);
+        }
+    }
+}
+
+/// LocalRepository is a local update repository given with --source, for updog's --repo-dir.  If
+/// the source is a block device, it's mounted read-only until the LocalRepository is dropped; it
+/// can't be the OS disk, or part of it.
+enum LocalRepository {
+    Directory(PathBuf),
+    Device(ReadOnlyMount),
+}
+
+impl LocalRepository {
+    fn open(source: &Path) -> Result<Self> {
+        ensure!(
+            source.is_absolute(),
+            error::UpdateSourceRelativeSnafu { path: source }
+        );
+        let metadata = fs::metadata(source).context(error::UpdateSourceSnafu { path: source })?;
+        if metadata.file_type().is_block_device() {
+            let state = State::load().context(error::PartitionTableReadSnafu)?;
+            let device = BlockDevice::from_device_node(source)
+                .context(error::UpdateSourceDeviceSnafu { path: source })?;
+            ensure!(
+                !on_disk(&device, state.os_disk(), source)?,
+                error::UpdateSourceOsDiskSnafu {
+                    path: source,
+                    disk: state.os_disk(),
+                }
+            );
+            ReadOnlyMount::new(source).map(Self::Device)
+        } else {
+            Ok(Self::Directory(source.to_path_buf()))
+        }
+    }
+
+    /// Returns the arguments that tell updog to use this repository.
+    fn updog_args(&self) -> [&OsStr; 2] {
+        let repo_dir = match self {
+            Self::Directory(path) => path.as_path(),
+            Self::Device(mount) => mount.path(),
+        };
+        ["--repo-dir".as_ref(), repo_dir.as_os_str()]
+    }
+}
+
+/// Returns whether the given block device is the given disk, one of its partitions, or built on
+/// them, like the dm-verity device of the root filesystem.  `source` is the path given for the
+/// device, for errors.
+fn on_disk(device: &BlockDevice, disk: &Path, source: &Path) -> Result<bool> {
+    if let Some(parent) = device
+        .disk()
+        .context(error::UpdateSourceDeviceSnafu { path: source })?
+    {
+        return Ok(parent.path() == disk);
+    }
+    if device.path() == disk {
+        return Ok(true);
+    }
+    for lower in device
+        .lower_devices()
+        .context(error::UpdateSourceDeviceSnafu { path: source })?
+    {
+        let lower = lower.context(error::UpdateSourceDeviceSnafu { path: source })?;
+        if on_disk(&lower, disk, source)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 /// This macros encapsulates the boilerplate code for dispatching the update command in a forked process
 macro_rules! fork_and_return {
     ($child_process:block) => {
@@ -180,14 +354,17 @@ macro_rules! fork_and_return {
 
 /// Spawns updog process to get list of updates and check if any of them can be updated to.
 /// Returns true if there is an available update, returns false otherwise.
-fn refresh<P>(status: &mut UpdateStatus, config_path: P) -> Result<bool>
+fn refresh<P>(status: &mut UpdateStatus, config_path: P, source: Option<&Path>) -> Result<bool>
 where
     P: AsRef<Path>,
 {
+    // Check the source before forking, so the caller hears about problems with it.
+    let repo = source.map(LocalRepository::open).transpose()?;
     fork_and_return!({
         debug!("Spawning 'updog whats'");
         let output = Command::new("updog")
             .args(["whats", "--all", "--json"])
+            .args(repo.iter().flat_map(LocalRepository::updog_args))
             .output()
             .context(error::UpdogSnafu)?;
         status.set_recent_command_info(UpdateCommand::Refresh, &output);
@@ -202,17 +379,27 @@ where
 }
 
 /// Prepares the update by downloading and writing the update to the staging partition
-fn prepare(status: &mut UpdateStatus) -> Result<()> {
+fn prepare(status: &mut UpdateStatus, source: Option<&Path>) -> Result<()> {
+    // Check the source before forking, so the caller hears about problems with it.
+    let repo = source.map(LocalRepository::open).transpose()?;
     fork_and_return!({
         debug!("Spawning 'updog update-image'");
         let chosen_update = status
             .chosen_update()
             .context(error::UpdateDoesNotExistSnafu)?
             .clone();
+        // Don't report progress from an earlier attempt
+        status.set_download_progress(None);
+        let _ = fs::remove_file(UPDATE_PROGRESS_FILE);
+        let (stop, stopped) = mpsc::channel();
+        let reporter = report_progress(status.clone(), stopped);
         let output = Command::new("updog")
-            .arg("update-image")
-            .output()
-            .context(error::UpdogSnafu)?;
+            .args(["update-image", "--progress-file", UPDATE_PROGRESS_FILE])
+            .args(repo.iter().flat_map(LocalRepository::updog_args))
+            .output();
+        let _ = stop.send(());
+        status.set_download_progress(reporter.join().unwrap_or_default());
+        let output = output.context(error::UpdogSnafu)?;
         status.set_recent_command_info(UpdateCommand::Prepare, &output);
         if !output.status.success() {
             warn!("Failed to prepare the update with updog");
@@ -223,14 +410,41 @@ fn prepare(status: &mut UpdateStatus) -> Result<()> {
     })
 }
 
-/// "Activates" the staged update by letting updog set up the appropriate boot flags
-fn activate(status: &mut UpdateStatus) -> Result<()> {
+/// Copies updog's download progress into the update status file until told to stop, and returns the
+/// last progress updog wrote.  Failing to write the status isn't fatal; the progress is only
+/// informational, and the status is written again when the command finishes.
+fn report_progress(
+    mut status: UpdateStatus,
+    stopped: Receiver<()>,
+) -> JoinHandle<Option<DownloadProgress>> {
+    thread::spawn(move || {
+        let mut last_progress = None;
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(PROGRESS_INTERVAL) {
+            let progress = DownloadProgress::load(UPDATE_PROGRESS_FILE);
+            if progress.is_none() || progress == last_progress {
+                continue;
+            }
+            status.set_download_progress(progress.clone());
+            if let Err(e) = write_update_status(&status) {
+                warn!("Failed to write download progress: {}", e);
+            }
+            last_progress = progress;
+        }
+        DownloadProgress::load(UPDATE_PROGRESS_FILE).or(last_progress)
+    })
+}
+
+/// "Activates" the staged update by letting updog set up the appropriate boot flags.  updog refuses
+/// outside of its maintenance windows unless forced.
+fn activate(status: &mut UpdateStatus, force: bool) -> Result<()> {
     fork_and_return!({
         debug!("Spawning 'updog update-apply'");
-        let output = Command::new("updog")
-            .arg("update-apply")
-            .output()
-            .context(error::UpdogSnafu)?;
+        let mut command = Command::new("updog");
+        command.arg("update-apply");
+        if force {
+            command.arg("--force");
+        }
+        let output = command.output().context(error::UpdogSnafu)?;
         status.set_recent_command_info(UpdateCommand::Activate, &output);
         if !output.status.success() {
             warn!("Failed to activate the update with updog");
@@ -257,11 +471,87 @@ fn deactivate(status: &mut UpdateStatus) -> Result<()> {
     })
 }
 
+/// Reverts a rollback by letting signpost mark the active partition set for boot again.  Unlike
+/// `deactivate`, this leaves the inactive partition set bootable, as the previous version to fall
+/// back to.
+fn cancel_rollback(status: &mut UpdateStatus) -> Result<()> {
+    fork_and_return!({
+        debug!("Spawning 'signpost cancel-rollback'");
+        let output = Command::new("signpost")
+            .arg("cancel-rollback")
+            .output()
+            .context(error::SignpostSnafu)?;
+        status.set_recent_command_info(UpdateCommand::Deactivate, &output);
+        if !output.status.success() {
+            warn!("Failed to cancel the rollback with signpost");
+            return error::CancelRollbackSnafu.fail();
+        }
+        status.unmark_staging_partition_next_to_boot()
+    })
+}
+
+/// Checks that the inactive partition set holds a version we can roll back to: an older version of
+/// the same variant that has booted successfully.  Returns its release info, read from the
+/// partition set's root filesystem.
+fn check_rollback() -> Result<BottlerocketRelease> {
+    let gpt_state = State::load().context(error::PartitionTableReadSnafu)?;
+    let inactive_set = gpt_state
+        .inactive_set()
+        .context(error::RollbackNoInactiveSnafu)?;
+    ensure!(
+        gpt_state.inactive_boot_succeeded(),
+        error::RollbackNotSuccessfulSnafu
+    );
+
+    let active = BottlerocketRelease::new().context(error::ReleaseVersionSnafu)?;
+    let root = ReadOnlyMount::new(&inactive_set.root)?;
+    let inactive = BottlerocketRelease::from_file(root.path().join(RELEASE_FILE))
+        .context(error::RollbackReleaseSnafu)?;
+    ensure!(
+        inactive.variant_id == active.variant_id,
+        error::RollbackVariantSnafu {
+            inactive: inactive.variant_id,
+            active: active.variant_id,
+        }
+    );
+    ensure!(
+        inactive.version_id < active.version_id,
+        error::RollbackVersionSnafu {
+            inactive: inactive.version_id,
+            active: active.version_id,
+        }
+    );
+    Ok(inactive)
+}
+
+/// Rolls back to the version in the inactive partition set by letting signpost mark it for boot
+fn rollback(status: &mut UpdateStatus) -> Result<()> {
+    // Check the inactive partition set before forking, so the caller hears why it can't be used.
+    let inactive = check_rollback()?;
+    fork_and_return!({
+        debug!("Spawning 'signpost rollback-to-inactive'");
+        let output = Command::new("signpost")
+            .arg("rollback-to-inactive")
+            .output()
+            .context(error::SignpostSnafu)?;
+        status.set_recent_command_info(UpdateCommand::Rollback, &output);
+        if !output.status.success() {
+            warn!("Failed to roll back with signpost");
+            return error::RollbackSnafu.fail();
+        }
+        status.set_inactive_partition_image_info(inactive);
+        status.set_rollback(true);
+        status.mark_staging_partition_next_to_boot()
+    })
+}
+
 /// Given the update command, this drives the update state machine.
 fn drive_state_machine<P>(
     update_status: &mut UpdateStatus,
     operation: &UpdateCommand,
     config_path: P,
+    source: Option<&Path>,
+    force: bool,
 ) -> Result<()>
 where
     P: AsRef<Path>,
@@ -269,7 +559,7 @@ where
     let new_state = match (operation, update_status.update_state()) {
         (UpdateCommand::Refresh, UpdateState::Idle)
         | (UpdateCommand::Refresh, UpdateState::Available) => {
-            if refresh(update_status, config_path)? {
+            if refresh(update_status, config_path, source)? {
                 // Transitions state to `Available` if there is an available update
                 UpdateState::Available
             } else {
@@ -279,7 +569,7 @@ where
         }
         // Refreshing the list of updates is allowed under every update state
         (UpdateCommand::Refresh, _) => {
-            refresh(update_status, config_path)?;
+            refresh(update_status, config_path, source)?;
             // No need to transition state here as we're already beyond `Available`
             update_status.update_state().to_owned()
         }
@@ -291,7 +581,7 @@ where
                 update_status.chosen_update().is_some(),
                 error::UpdateDoesNotExistSnafu
             );
-            prepare(update_status)?;
+            prepare(update_status, source)?;
             // If we succeed in preparing the update, we transition to `Staged`
             UpdateState::Staged
         }
@@ -302,7 +592,7 @@ where
                 update_status.staging_partition().is_some(),
                 error::StagingPartitionSnafu
             );
-            activate(update_status)?;
+            activate(update_status, force)?;
             // If we succeed in activating the update, we transition to `Ready`
             UpdateState::Ready
         }
@@ -313,9 +603,26 @@ where
                 update_status.staging_partition().is_some(),
                 error::StagingPartitionSnafu
             );
-            deactivate(update_status)?;
-            // If we succeed in deactivating the update, we transition to `Staged`
-            UpdateState::Staged
+            if update_status.is_rollback() {
+                cancel_rollback(update_status)?;
+                // The staging partition holds the previous version rather than a staged update,
+                // so there's nothing to activate; go back to where we were before the rollback
+                update_status.set_rollback(false);
+                UpdateState::Idle
+            } else {
+                deactivate(update_status)?;
+                // If we succeed in deactivating the update, we transition to `Staged`
+                UpdateState::Staged
+            }
+        }
+        // Rolling back is allowed when there's no update written to the inactive partition set,
+        // which would have replaced the previous version
+        (UpdateCommand::Rollback, UpdateState::Idle)
+        | (UpdateCommand::Rollback, UpdateState::Available) => {
+            rollback(update_status)?;
+            // The previous version is marked for boot, like an activated update, so the rollback
+            // can be reverted with `deactivate`, which then returns to `Idle`
+            UpdateState::Ready
         }
         // Everything else is disallowed
         _ => {
@@ -354,7 +661,13 @@ fn run() -> Result<()> {
     // The commands inside drive_state_machine update the update_status object (hence &mut) to
     // reflect success or failure, and we want to reflect that in our status file regardless of
     // success, so we store the result rather than returning early here.
-    let result = drive_state_machine(&mut update_status, &args.subcommand, &args.config_path);
+    let result = drive_state_machine(
+        &mut update_status,
+        &args.subcommand,
+        &args.config_path,
+        args.source.as_deref(),
+        args.force,
+    );
     write_update_status(&update_status)?;
     result
 }
@@ -365,6 +678,14 @@ fn match_error_to_exit_status(err: Error) -> i32 {
         Error::DisallowCommand { .. } => TbuErrorStatus::DisallowCommand,
         Error::UpdateDoesNotExist { .. } => TbuErrorStatus::UpdateDoesNotExist,
         Error::StagingPartition { .. } => TbuErrorStatus::NoStagedImage,
+        Error::UpdateSource { .. } | Error::UpdateSourceRelative { .. } => {
+            TbuErrorStatus::InvalidSource
+        }
+        Error::RollbackNoInactive
+        | Error::RollbackNotSuccessful
+        | Error::RollbackRelease { .. }
+        | Error::RollbackVariant { .. }
+        | Error::RollbackVersion { .. } => TbuErrorStatus::InvalidRollback,
         _ => TbuErrorStatus::OtherError,
     }
     .to_i32()
@@ -15,6 +15,11 @@ use std::process::Output;
 
 pub const UPDATE_LOCKFILE: &str = "/run/lock/thar-be-updates.lock";
 pub const UPDATE_STATUS_FILE: &str = "/run/cache/thar-be-updates/status.json";
+/// updog writes its download progress here while preparing an update.
+pub const UPDATE_PROGRESS_FILE: &str = "/run/cache/thar-be-updates/progress.json";
+/// updog records the outcome of the health check after booting a new version here.  It's kept
+/// across reboots, so the outcome can be seen after rolling back.
+pub const BOOT_HEALTH_FILE: &str = "/var/lib/updog/boot-health.json";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
@@ -74,6 +79,7 @@ pub enum UpdateCommand {
     Prepare,
     Activate,
     Deactivate,
+    Rollback,
 }
 
 /// CommandResult represents the result of an issued command
@@ -86,7 +92,52 @@ struct CommandResult {
     stderr: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// DownloadProgress represents how much of the chosen update's images updog has downloaded, as
+/// written to the progress file.  Bytes are counted across all of the update's images.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DownloadProgress {
+    target: String,
+    bytes_downloaded: u64,
+    bytes_total: u64,
+}
+
+impl DownloadProgress {
+    /// Loads the progress updog last wrote to the given file, if it has written any.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let progress = fs::read(path).ok()?;
+        serde_json::from_slice(&progress).ok()
+    }
+}
+
+/// BootHealthOutcome is the result of the health check after booting a new version
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootHealthOutcome {
+    Pending,
+    Passed,
+    RolledBack,
+    Failed,
+}
+
+/// BootHealth represents the outcome of the health check updog ran after booting a new version,
+/// as written to the boot health file.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BootHealth {
+    version: semver::Version,
+    outcome: BootHealthOutcome,
+    reason: Option<String>,
+    timestamp: DateTime<Utc>,
+}
+
+impl BootHealth {
+    /// Loads the outcome updog last recorded in the given file, if it has recorded one.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let boot_health = fs::read(path).ok()?;
+        serde_json::from_slice(&boot_health).ok()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UpdateStatus {
     update_state: UpdateState,
     available_updates: Vec<semver::Version>,
@@ -94,6 +145,16 @@ pub struct UpdateStatus {
     active_partition: Option<StagedImage>,
     staging_partition: Option<StagedImage>,
     most_recent_command: Option<CommandResult>,
+    /// Progress of the most recent download, updated while the update is being prepared.
+    #[serde(default)]
+    download_progress: Option<DownloadProgress>,
+    /// Outcome of the health check after the most recent boot of a new version.
+    #[serde(default)]
+    boot_health: Option<BootHealth>,
+    /// Whether the staging partition holds the previous version, marked for boot by a rollback,
+    /// rather than a staged update.
+    #[serde(default)]
+    rollback: bool,
 }
 
 impl Default for UpdateStatus {
@@ -106,12 +167,27 @@ impl Default for UpdateStatus {
 /// This takes the update lock file as an parameter to signal to caller that the update
 /// lock needs to be obtained before calling this.
 pub fn get_update_status(_lockfile: &File) -> Result<UpdateStatus> {
+    read_update_status()
+}
+
+/// Loads and returns the update status from disk while an update command holds the update lock,
+/// so callers can see the progress of a running command.  This is safe without the lock because
+/// the status file is always replaced atomically, but the status may change at any time.
+pub fn get_update_status_in_progress() -> Result<UpdateStatus> {
+    read_update_status()
+}
+
+fn read_update_status() -> Result<UpdateStatus> {
     let status_file = File::open(UPDATE_STATUS_FILE).context(error::NoStatusFileSnafu {
         path: UPDATE_STATUS_FILE,
     })?;
-    serde_json::from_reader(status_file).context(error::StatusParseSnafu {
-        path: UPDATE_STATUS_FILE,
-    })
+    let mut status: UpdateStatus =
+        serde_json::from_reader(status_file).context(error::StatusParseSnafu {
+            path: UPDATE_STATUS_FILE,
+        })?;
+    // updog records the boot health outside of the status file, during boot.
+    status.boot_health = BootHealth::load(BOOT_HEALTH_FILE);
+    Ok(status)
 }
 
 /// Retrieves settings from the configuration file.
@@ -140,6 +216,9 @@ impl UpdateStatus {
             active_partition: None,
             staging_partition: None,
             most_recent_command: None,
+            download_progress: None,
+            boot_health: None,
+            rollback: false,
         }
     }
 
@@ -158,6 +237,16 @@ impl UpdateStatus {
         }
     }
 
+    /// Returns whether the staging partition holds the previous version, marked for boot by a
+    /// rollback, rather than a staged update.
+    pub fn is_rollback(&self) -> bool {
+        self.rollback
+    }
+
+    pub fn set_rollback(&mut self, rollback: bool) {
+        self.rollback = rollback;
+    }
+
     pub fn staging_partition(&self) -> Option<&StagedImage> {
         match &self.staging_partition {
             Some(partition_info) => Some(partition_info),
@@ -165,6 +254,11 @@ impl UpdateStatus {
         }
     }
 
+    /// Sets the progress of the most recent download
+    pub fn set_download_progress(&mut self, progress: Option<DownloadProgress>) {
+        self.download_progress = progress;
+    }
+
     /// Updates the active partition set information
     pub fn update_active_partition_info(&mut self) -> Result<()> {
         // Get current OS release info to determine active partition image information
@@ -187,6 +281,15 @@ impl UpdateStatus {
         Ok(())
     }
 
+    /// Sets the image information of the inactive partition set, as read from its release info
+    pub fn set_inactive_partition_image_info(&mut self, os_info: BottlerocketRelease) {
+        self.set_staging_partition_image_info(UpdateImage {
+            arch: os_info.arch,
+            version: os_info.version_id,
+            variant: os_info.variant_id,
+        });
+    }
+
     /// Sets the staging partition image information
     pub fn set_staging_partition_image_info(&mut self, image: UpdateImage) {
         self.staging_partition = Some(StagedImage {
@@ -1,7 +1,8 @@
 //! The 'exec' module holds types used to communicate between client and server for
-//! 'apiclient exec'.
+//! 'apiclient exec', and for running commands to completion with POST /exec/run.
 use libc::winsize as WinSize;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -10,6 +11,9 @@ use std::ffi::OsString;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
     Capacity(Capacity),
+    Exit(Exit),
+    FileInfo(FileInfo),
+    CopyResult(CopyResult),
 }
 
 /// A capacity update; this tells the client how many writes the server has completed so the client
@@ -23,6 +27,28 @@ pub struct Capacity {
     pub messages_written: u64,
 }
 
+/// Tells the client how the process ended; sent just before the server closes the connection, if
+/// the client asked for it in Initialize.  Copies always end with one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exit {
+    /// The exit code of the process, if it exited on its own.
+    pub code: Option<i32>,
+    /// The number of the signal that killed the process, if one did.
+    pub signal: Option<i32>,
+}
+
+/// Tells the client what the server transferred in a copy; sent just before Exit, so the client
+/// can check that it has the same data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyResult {
+    /// The number of bytes of file data the server sent or received.
+    pub size: u64,
+    /// The SHA-256 digest of the file data, in hex.
+    pub sha256: String,
+    /// Any error output from the copy, since it's kept apart from file data.
+    pub stderr: String,
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 /// Client messages to server.
@@ -31,8 +57,12 @@ pub enum ClientMessage {
     // It'd be nice to include initialization parameters in the initial HTTP request body, but not
     // all WebSocket clients support data there.
     Initialize(Initialize),
+    /// Sent instead of Initialize to copy a file to or from a container.
+    Copy(Copy),
     ContentComplete,
     Winch(Size),
+    /// Asks the server to send the named signal, like "SIGINT", to the process.
+    Signal(String),
 }
 
 /// Tells the server how to initialize the command the user is requesting.
@@ -44,6 +74,42 @@ pub struct Initialize {
     pub target: String,
     /// Whether the user wants a TTY.
     pub tty: Option<TtyInit>,
+    /// Whether the client understands Exit messages.  Older clients don't, and fail on messages
+    /// they don't know, so they only get a return code in the Close message.
+    #[serde(default)]
+    pub exit_status: bool,
+}
+
+/// Tells the server to copy a file to or from a container, rather than running a command.  File
+/// data is sent through Binary messages, the same way as process input and output, and is never
+/// mixed with error output.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Copy {
+    /// What container (task) has the file.
+    pub target: String,
+    /// The path of the file in the container.
+    pub path: OsString,
+    /// Which way to copy the file.
+    pub direction: CopyDirection,
+}
+
+/// The direction of a copy.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum CopyDirection {
+    /// The client sends the file, described here, and then ContentComplete.  The file is written
+    /// to the container only if it's received in full.
+    ToContainer(FileInfo),
+    /// The server sends FileInfo, and then the file.
+    FromContainer,
+}
+
+/// Describes a file being copied.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FileInfo {
+    /// The permission bits of the file, like 0o644.
+    pub mode: u32,
+    /// The size of the file in bytes.
+    pub size: u64,
 }
 
 /// If the user wants a TTY, these are the initial parameters the TTY should be set up with.
@@ -53,6 +119,40 @@ pub struct TtyInit {
     pub size: Option<Size>,
 }
 
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+// Non-interactive runs
+
+/// Asks the server to run a command in a container and wait for it to finish, for POST /exec/run.
+/// The command gets no input, and never gets a TTY.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunRequest {
+    /// What container (task) to run in.
+    pub target: String,
+    /// The command to run, and its arguments.
+    pub command: Vec<String>,
+    /// Variables to add to the command's environment.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// How many seconds to let the command run before stopping it; the server has a default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+/// The outcome of a RunRequest.  Output is decoded as UTF-8, replacing invalid sequences, and is
+/// cut off at a size limit set by the server.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunResult {
+    /// How the command exited.  If it timed out, this shows how it ended after being stopped.
+    pub exit: Exit,
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether stdout or stderr went over the size limit, and was cut off.
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    /// Whether the command was stopped because it ran past its timeout.
+    pub timed_out: bool,
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 // Helper types
 
@@ -24,6 +24,12 @@ pub mod exec;
 // Types used to communicate between client and server for 'apiclient ephemeral-storage'.
 pub mod ephemeral_storage;
 
+// Types used to communicate between client and server for 'apiclient watch'.
+pub mod watch;
+
+// Types used to communicate between client, server, and config applier for 'apiclient set --dry-run'.
+pub mod plan;
+
 use bottlerocket_release::BottlerocketRelease;
 use bottlerocket_settings_models::model_derive::model;
 use bottlerocket_settings_plugin::BottlerocketSettings;
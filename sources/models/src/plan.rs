@@ -0,0 +1,45 @@
+//! The 'plan' module holds types used to communicate between client, server, and config applier
+//! for 'apiclient set --dry-run' and 'apiclient apply --dry-run', which show what committing a
+//! transaction would change without changing anything.
+use crate::{ConfigurationFiles, Services};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Server request to the config applier, asking it to render configuration files and compare them
+/// to the files on disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderRequest {
+    /// The configuration files to render.
+    pub configuration_files: ConfigurationFiles,
+    /// The data to render templates with, holding the "settings" and "os" that would be live
+    /// after the commit.
+    pub template_data: serde_json::Value,
+}
+
+/// The result of rendering one configuration file against pending settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigurationFilePlan {
+    /// The name of the configuration file, as in the 'configuration-files' section of the model.
+    pub name: String,
+    /// Where the file is written on disk.
+    pub path: String,
+    /// A unified diff from the file on disk to the rendered file; empty if they're the same, or
+    /// if the file couldn't be rendered.
+    pub diff: String,
+    /// Why the file couldn't be rendered, if it couldn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Server response to client, describing what committing and applying a transaction would do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionPlan {
+    /// The name of the transaction that was planned.
+    pub transaction: String,
+    /// The names of the keys the commit would change or remove.
+    pub changed_keys: BTreeSet<String>,
+    /// The services affected by the changed keys, which would be restarted.
+    pub services: Services,
+    /// The configuration files of the affected services, which would be rewritten.
+    pub configuration_files: Vec<ConfigurationFilePlan>,
+}
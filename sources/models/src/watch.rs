@@ -0,0 +1,15 @@
+//! The 'watch' module holds types used to communicate between client and server for
+//! 'apiclient watch'.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Server messages to client; one is sent each time a commit changes settings the client is
+/// watching.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsChange {
+    /// The name of the transaction whose commit changed the settings.
+    pub transaction: String,
+    /// Maps the name of each changed key (e.g. "settings.motd") to its new value, or None if the
+    /// key is no longer set.
+    pub settings: HashMap<String, Option<serde_json::Value>>,
+}
@@ -18,9 +18,23 @@ Examples:
 * `"in 2 weeks"`
 * `"1 hour"`
 * `"7 days"`
+
+It also parses recurring weekly time windows, like maintenance windows, from a string with the form
+`"[<days>] <start>-<end>"`.
+
+* `<days>` is a comma-separated list of days of the week, or ranges of them, like `Mon-Fri` or
+  `Sat,Sun`; if it's left out, the window applies every day
+* `<start>` and `<end>` are UTC times of day in the form `HH:MM`; if `<end>` isn't later than
+  `<start>`, the window ends on the following day
+
+Examples:
+
+* `"02:00-04:00"`
+* `"Sat,Sun 01:00-05:00"`
+* `"Mon-Fri 22:00-02:00"`
 */
 
-use chrono::{DateTime, Duration, FixedOffset, Utc};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveTime, Utc, Weekday};
 use snafu::{ensure, OptionExt, ResultExt};
 
 mod error {
@@ -41,6 +55,9 @@ mod error {
             input: String,
             source: std::num::ParseIntError,
         },
+
+        #[snafu(display("Time window '{}' is invalid: {}", input, msg))]
+        WindowInvalid { input: String, msg: &'static str },
     }
 }
 pub use error::Error;
@@ -122,6 +139,121 @@ pub fn parse_offset(input: &str) -> Result<Duration> {
     Ok(duration)
 }
 
+/// A window of time that recurs every week on the given days, from a start time to an end time in
+/// UTC.  If the end time isn't later than the start time, the window ends on the following day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    /// Returns whether the given time falls inside the window.
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        let day = time.weekday();
+        let time = time.time();
+        if self.start < self.end {
+            self.days.contains(&day) && self.start <= time && time < self.end
+        } else {
+            // The window crosses midnight, so it may have started today or yesterday.
+            (self.days.contains(&day) && self.start <= time)
+                || (self.days.contains(&day.pred()) && time < self.end)
+        }
+    }
+}
+
+/// Parses a user-specified weekly time window in the form "[<days>] <start>-<end>", like
+/// "Mon-Fri 22:00-02:00".
+pub fn parse_window(input: &str) -> Result<Window> {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+    let (days, times) = match parts.as_slice() {
+        [times] => (None, *times),
+        [days, times] => (Some(*days), *times),
+        _ => {
+            return error::WindowInvalidSnafu {
+                input,
+                msg: "expected something like 'Mon-Fri 22:00-02:00'",
+            }
+            .fail()
+        }
+    };
+
+    let (start, end) = times.split_once('-').context(error::WindowInvalidSnafu {
+        input,
+        msg: "expected a start and end time like '22:00-02:00'",
+    })?;
+    let parse_time = |time: &str| {
+        NaiveTime::parse_from_str(time, "%H:%M")
+            .ok()
+            .context(error::WindowInvalidSnafu {
+                input,
+                msg: "times must be in the form HH:MM",
+            })
+    };
+    let start = parse_time(start)?;
+    let end = parse_time(end)?;
+    ensure!(
+        start != end,
+        error::WindowInvalidSnafu {
+            input,
+            msg: "start and end times must differ",
+        }
+    );
+
+    let days = match days {
+        Some(days) => parse_days(input, days)?,
+        None => ALL_DAYS.to_vec(),
+    };
+
+    Ok(Window { days, start, end })
+}
+
+const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Parses a comma-separated list of days of the week, or ranges of them, like "Mon-Wed,Sat".
+fn parse_days(input: &str, days: &str) -> Result<Vec<Weekday>> {
+    let parse_day = |day: &str| {
+        day.parse::<Weekday>()
+            .ok()
+            .context(error::WindowInvalidSnafu {
+                input,
+                msg: "days must be days of the week like 'Mon' or 'Monday'",
+            })
+    };
+
+    let mut parsed = Vec::new();
+    for item in days.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => {
+                let day = parse_day(item)?;
+                (day, day)
+            }
+        };
+        // Ranges can wrap around the end of the week, like "Fri-Mon".
+        let mut day = first;
+        loop {
+            if !parsed.contains(&day) {
+                parsed.push(day);
+            }
+            if day == last {
+                break;
+            }
+            day = day.succ();
+        }
+    }
+    Ok(parsed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -156,4 +288,57 @@ mod tests {
             assert!(parse_datetime(input).is_err())
         }
     }
+
+    fn at(input: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(input).unwrap().into()
+    }
+
+    #[test]
+    fn test_window() {
+        // 2024-06-01 is a Saturday.
+        let weekend = parse_window("Sat,Sun 01:00-05:00").unwrap();
+        assert!(weekend.contains(at("2024-06-01T01:00:00Z")));
+        assert!(weekend.contains(at("2024-06-02T04:59:59Z")));
+        assert!(!weekend.contains(at("2024-06-02T05:00:00Z")));
+        assert!(!weekend.contains(at("2024-06-03T02:00:00Z")));
+
+        let daily = parse_window("02:00-04:00").unwrap();
+        for day in 1..=7 {
+            assert!(daily.contains(at(&format!("2024-06-0{day}T03:00:00Z"))));
+        }
+        assert!(!daily.contains(at("2024-06-01T04:00:00Z")));
+    }
+
+    #[test]
+    fn test_window_past_midnight() {
+        // Starts on weeknights, so it runs into Saturday morning but not Monday morning.
+        let nights = parse_window("Mon-Fri 22:00-02:00").unwrap();
+        assert!(nights.contains(at("2024-05-31T23:00:00Z")));
+        assert!(nights.contains(at("2024-06-01T01:00:00Z")));
+        assert!(!nights.contains(at("2024-06-01T22:00:00Z")));
+        assert!(!nights.contains(at("2024-06-03T01:00:00Z")));
+        assert!(nights.contains(at("2024-06-04T01:00:00Z")));
+
+        let wrapped = parse_window("Fri-Mon 10:00-11:00").unwrap();
+        assert!(wrapped.contains(at("2024-06-03T10:30:00Z")));
+        assert!(!wrapped.contains(at("2024-06-04T10:30:00Z")));
+    }
+
+    #[test]
+    fn test_unacceptable_windows() {
+        let inputs = vec![
+            "",
+            "Mon",
+            "02:00",
+            "2:00-25:00",
+            "02:00-02:00",
+            "Someday 02:00-04:00",
+            "Mon- 02:00-04:00",
+            "Mon 02:00-04:00 UTC",
+        ];
+
+        for input in inputs {
+            assert!(parse_window(input).is_err(), "{input}")
+        }
+    }
 }
@@ -13,6 +13,7 @@ enum Command {
     UpgradeToInactive,
     CancelUpgrade,
     RollbackToInactive,
+    CancelRollback,
     HasBootEverSucceeded,
     RewriteTable,
 }
@@ -30,6 +31,7 @@ SUBCOMMANDS:
     upgrade-to-inactive     Sets the inactive partitions as new upgrade partitions if marked valid
     cancel-upgrade          Reverse upgrade-to-inactive
     rollback-to-inactive    Deprioritizes the inactive partitions
+    cancel-rollback         Reverse rollback-to-inactive
     has-boot-ever-succeeded Checks whether boot has ever succeeded
     rewrite-table           Rewrite the partition table with no changes to disk (used for testing this code)");
     std::process::exit(1)
@@ -66,6 +68,10 @@ fn main() {
                 state.rollback_to_inactive()?;
                 state.write()?;
             }
+            Command::CancelRollback => {
+                state.cancel_rollback()?;
+                state.write()?;
+            }
             Command::HasBootEverSucceeded => {
                 if state.has_boot_succeeded() {
                     println!("true");
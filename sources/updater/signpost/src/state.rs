@@ -174,7 +174,7 @@ impl State {
         })
     }
 
-    pub(crate) fn os_disk(&self) -> &Path {
+    pub fn os_disk(&self) -> &Path {
         &self.os_disk
     }
 
@@ -374,6 +374,42 @@ impl State {
         Ok(())
     }
 
+    /// Reverts rollback_to_inactive(), but **does not write to the disk**.
+    ///
+    /// * Restores the inactive partition's priority to 1, so it's still there to fall back to
+    /// * Restores the active partition's priority to 2
+    ///
+    /// Unlike cancel_upgrade(), this leaves the inactive partition bootable, since it holds the
+    /// previous version rather than an untried update.
+    pub fn cancel_rollback(&mut self) -> Result<(), Error> {
+        let inactive = self.inactive().context(error::InactiveNotAvailableSnafu {
+            inactive: &self.os_disk,
+        })?;
+
+        let mut inactive_flags = self.gptprio(inactive);
+        inactive_flags.set_priority(1);
+        self.set_gptprio(inactive, inactive_flags);
+
+        let mut active_flags = self.gptprio(self.active());
+        active_flags.set_priority(2);
+        self.set_gptprio(self.active(), active_flags);
+
+        Ok(())
+    }
+
+    /// Returns whether the active partition set has been marked as successfully booted.  After an
+    /// update, it isn't marked until the first boot of the new version is found to be successful.
+    pub fn active_boot_succeeded(&self) -> bool {
+        self.gptprio(self.active()).successful()
+    }
+
+    /// Returns whether the inactive partition set has been marked as successfully booted, meaning it
+    /// holds a version that has booted successfully before.
+    pub fn inactive_boot_succeeded(&self) -> bool {
+        self.inactive()
+            .is_some_and(|inactive| self.gptprio(inactive).successful())
+    }
+
     /// Returns whether boot has ever succeeded or not
     pub fn has_boot_succeeded(&mut self) -> bool {
         let private_flags = GptPrio::from(self.gpt_attributes(self.private_partition_num));
@@ -424,3 +460,66 @@ impl fmt::Display for State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Returns the state of a dual partition set disk with set A active, as on a host that has
+    /// booted successfully and cleared set B for an update.
+    fn dual_state() -> State {
+        let mut disk = Cursor::new(vec![0; 1024 * 1024]);
+        let table = GPT::new_from(&mut disk, 512, [1; 16]).unwrap();
+        let set = |n: u32| PartitionSet {
+            boot: PathBuf::from(format!("/dev/boot{n}")),
+            root: PathBuf::from(format!("/dev/root{n}")),
+            hash: PathBuf::from(format!("/dev/hash{n}")),
+        };
+        let mut state = State {
+            os_disk: PathBuf::from("/dev/disk"),
+            private_partition_num: 3,
+            sets: PartitionSets::Dual(DualPartitionSet {
+                sets: [set(1), set(2)],
+                active: SetSelect::A,
+            }),
+            boot_partition_nums: vec![1, 2],
+            table,
+        };
+        let mut flags = GptPrio::from(0);
+        flags.set_priority(2);
+        flags.set_successful(true);
+        state.set_gptprio(SetSelect::A, flags);
+        state
+    }
+
+    /// Switches the active partition set, as booting the next one would.
+    fn reboot(state: &mut State) {
+        let next = state.next().unwrap();
+        if let PartitionSets::Dual(sets) = &mut state.sets {
+            sets.active = next;
+        }
+    }
+
+    #[test]
+    fn cancel_rollback_keeps_fallback() {
+        let mut state = dual_state();
+        state.clear_inactive().unwrap();
+        state.mark_inactive_valid().unwrap();
+        state.upgrade_to_inactive().unwrap();
+        reboot(&mut state);
+        assert_eq!(state.active(), SetSelect::B);
+        state.mark_successful_boot();
+
+        state.rollback_to_inactive().unwrap();
+        assert_eq!(state.next(), Some(SetSelect::A));
+
+        state.cancel_rollback().unwrap();
+        assert_eq!(state.next(), Some(SetSelect::B));
+        // The previous version is still there to fall back to.
+        let previous = state.gptprio(SetSelect::A);
+        assert_eq!(previous.priority(), 1);
+        assert!(previous.will_boot());
+        assert_eq!(state.gptprio(SetSelect::B).priority(), 2);
+    }
+}
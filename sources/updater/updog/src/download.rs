@@ -0,0 +1,571 @@
+//! Downloads update images into a staging directory before anything is written to disk.  A
+//! download that's interrupted, whether the connection drops or updog is stopped, continues from
+//! what was already staged the next time, using an HTTP range request.  Images in a local
+//! repository, given with `--repo-dir`, are copied to the staging directory the same way.  Staged
+//! images are checked against the length and digest in the signed TUF metadata before they're used.
+//!
+//! Progress is counted in bytes of the compressed images, across all of an update's images, and
+//! written to a progress file as JSON for thar-be-updates to report.
+
+use crate::transport::HttpQueryTransport;
+use aws_lc_rs::digest::{Context, SHA256};
+use log::{debug, info, warn};
+use reqwest::{Response, StatusCode};
+use serde::Serialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fmt::Write as _;
+use std::io::{Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+use updog::error::{self, Error, Result};
+use url::Url;
+
+/// How many times we try to download a target, continuing each time from what we've staged,
+/// before giving up until the next update attempt.
+const DOWNLOAD_TRIES: u32 = 5;
+
+/// How long we wait before trying again after a failed download.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// How long we wait for more data before treating the connection as dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often we write progress to the progress file.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `StagedTarget` is a target to download, described by its signed metadata.
+#[derive(Debug, Clone)]
+pub(crate) struct StagedTarget {
+    name: String,
+    url: Url,
+    length: u64,
+    sha256: Vec<u8>,
+    path: PathBuf,
+}
+
+impl StagedTarget {
+    /// Describes the named target, staged in `staging_dir`.  With consistent snapshots, target
+    /// files are named with their digest in the repository, as tough expects.
+    pub(crate) fn new(
+        name: &str,
+        targets_base_url: &Url,
+        length: u64,
+        sha256: Vec<u8>,
+        consistent_snapshot: bool,
+        staging_dir: &Path,
+    ) -> Result<Self> {
+        let digest = hex(&sha256);
+        let file = if consistent_snapshot {
+            format!("{digest}.{name}")
+        } else {
+            name.to_string()
+        };
+        let mut url = targets_base_url.clone();
+        url.path_segments_mut()
+            .ok()
+            .context(error::TargetUrlSnafu {
+                url: targets_base_url.as_str(),
+            })?
+            .pop_if_empty()
+            .push(&file);
+        Ok(Self {
+            name: name.to_string(),
+            url,
+            length,
+            sha256,
+            // Staged files are named by digest, so we never continue a different image.
+            path: staging_dir.join(digest),
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// The path of the staged, still compressed, image.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Progress is what we write to the progress file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Progress {
+    /// The target being downloaded.
+    target: String,
+    /// How many bytes of the update's targets are staged, including any staged by earlier attempts.
+    bytes_downloaded: u64,
+    /// The total size of the update's targets.
+    bytes_total: u64,
+}
+
+/// `ProgressReporter` tracks progress across an update's targets, and writes it to the progress
+/// file, if we were given one.
+#[derive(Debug)]
+pub(crate) struct ProgressReporter {
+    path: Option<PathBuf>,
+    progress: Progress,
+    /// Bytes of targets that are completely staged.
+    finished: u64,
+    last_write: Option<Instant>,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(path: Option<PathBuf>, bytes_total: u64) -> Self {
+        Self {
+            path,
+            progress: Progress {
+                bytes_total,
+                ..Progress::default()
+            },
+            finished: 0,
+            last_write: None,
+        }
+    }
+
+    /// Starts reporting on the given target, of which `staged` bytes are already staged.
+    fn start(&mut self, target: &str, staged: u64) {
+        self.progress.target = target.to_string();
+        self.progress.bytes_downloaded = self.finished + staged;
+        self.write(true);
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.progress.bytes_downloaded += bytes;
+        self.write(false);
+    }
+
+    fn finish(&mut self, length: u64) {
+        self.finished += length;
+        self.progress.bytes_downloaded = self.finished;
+        self.write(true);
+    }
+
+    /// Writes progress to the progress file, unless we wrote it recently and aren't forced to.
+    /// Failures are logged rather than returned, since progress is only informational.
+    fn write(&mut self, force: bool) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !force
+            && self
+                .last_write
+                .is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last_write = Some(Instant::now());
+
+        // Write atomically so readers never see part of the file.
+        let temp_path = path.with_extension("tmp");
+        let result = serde_json::to_vec(&self.progress)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&temp_path, json))
+            .and_then(|()| std::fs::rename(&temp_path, path));
+        if let Err(e) = result {
+            warn!("Unable to write progress to '{}': {}", path.display(), e);
+        }
+    }
+}
+
+/// Removes anything in the staging directory that isn't one of the given targets, like images of
+/// an update we're no longer installing, then makes sure the directory exists.
+pub(crate) async fn clean_staging_dir(staging_dir: &Path, targets: &[StagedTarget]) -> Result<()> {
+    if let Ok(mut entries) = fs::read_dir(staging_dir).await {
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(error::StagedFileSnafu { path: staging_dir })?
+        {
+            let path = entry.path();
+            if !targets.iter().any(|t| t.path == path) {
+                debug!("Removing stale staged file '{}'", path.display());
+                fs::remove_file(&path)
+                    .await
+                    .context(error::StagedFileSnafu { path: &path })?;
+            }
+        }
+    }
+    fs::create_dir_all(staging_dir)
+        .await
+        .context(error::DirCreateSnafu { path: staging_dir })
+}
+
+/// Checks that the staging directory has room for the rest of the targets, beyond what's already
+/// staged, so we fail before downloading anything rather than when the disk fills up.
+pub(crate) async fn check_staging_space(
+    staging_dir: &Path,
+    targets: &[StagedTarget],
+) -> Result<()> {
+    let needed = space_needed(targets).await;
+    let available = fs2::available_space(staging_dir)
+        .context(error::StagingSpaceSnafu { path: staging_dir })?;
+    debug!(
+        "Staging needs {} bytes, {} bytes are available",
+        needed, available
+    );
+    ensure!(
+        needed <= available,
+        error::InsufficientSpaceSnafu {
+            path: staging_dir,
+            needed,
+            available,
+        }
+    );
+    Ok(())
+}
+
+/// Returns how many bytes the targets still need in the staging directory.
+async fn space_needed(targets: &[StagedTarget]) -> u64 {
+    let mut needed = 0;
+    for target in targets {
+        let staged = match fs::metadata(&target.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        needed += target.length.saturating_sub(staged);
+    }
+    needed
+}
+
+/// Downloads the target to its staging file, continuing from anything already staged, and checks
+/// it against the signed metadata.  Failed downloads are tried again a few times.
+pub(crate) async fn stage_target(
+    transport: &HttpQueryTransport,
+    target: &StagedTarget,
+    progress: &mut ProgressReporter,
+) -> Result<()> {
+    let mut tries = 1;
+    loop {
+        match download(transport, target, progress).await {
+            Ok(()) => break,
+            Err(e) if tries < DOWNLOAD_TRIES && retryable(&e) => {
+                warn!(
+                    "Download of {} failed, trying again in {:?}: {}",
+                    target.name, RETRY_DELAY, e
+                );
+                tries += 1;
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Run blocking IO without blocking the scheduler.
+    let staged = target.clone();
+    let rt = Handle::current();
+    let verified = rt.spawn_blocking(move || verify(&staged));
+    let verified = verified.await.context(error::TargetVerifySnafu {
+        target: &target.name,
+    })?;
+    if let Err(e) = verified {
+        // Start over next time, rather than continuing a bad download.
+        let _ = fs::remove_file(&target.path).await;
+        return Err(e);
+    }
+    progress.finish(target.length);
+    Ok(())
+}
+
+/// Returns whether a failed download is worth trying again, like when the connection dropped.
+fn retryable(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Download { .. } | Error::DownloadIncomplete { .. } | Error::DownloadTimeout { .. }
+    )
+}
+
+/// Makes one attempt to download the rest of the target to its staging file.
+async fn download(
+    transport: &HttpQueryTransport,
+    target: &StagedTarget,
+    progress: &mut ProgressReporter,
+) -> Result<()> {
+    let path = &target.path;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .context(error::StagedFileSnafu { path })?;
+    let mut staged = file
+        .metadata()
+        .await
+        .context(error::StagedFileSnafu { path })?
+        .len();
+    if staged > target.length {
+        warn!("Staged {} is too long, starting over", target.name);
+        file.set_len(0)
+            .await
+            .context(error::StagedFileSnafu { path })?;
+        staged = 0;
+    }
+    progress.start(&target.name, staged);
+    if staged == target.length {
+        debug!("{} is already staged", target.name);
+        return Ok(());
+    }
+
+    if staged > 0 {
+        info!(
+            "Continuing download of {} from byte {} of {}",
+            target.name, staged, target.length
+        );
+    }
+    let staged = if target.url.scheme() == "file" {
+        copy_local(&mut file, target, staged, progress).await?
+    } else {
+        fetch(transport, &mut file, target, staged, progress).await?
+    };
+    // Make sure what we staged survives a reboot, so we can continue from it.
+    file.sync_all()
+        .await
+        .context(error::StagedFileSnafu { path })?;
+
+    ensure!(
+        staged == target.length,
+        error::DownloadIncompleteSnafu {
+            target: &target.name,
+            staged,
+            length: target.length,
+        }
+    );
+    Ok(())
+}
+
+/// Requests the rest of the target from the server and appends it to the staging file, which
+/// already has `staged` bytes.  Returns how many bytes the staging file has after.
+async fn fetch(
+    transport: &HttpQueryTransport,
+    file: &mut fs::File,
+    target: &StagedTarget,
+    mut staged: u64,
+    progress: &mut ProgressReporter,
+) -> Result<u64> {
+    let response = transport.fetch_range(target.url.clone(), staged).await?;
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => {}
+        StatusCode::OK => {
+            // The server sent the whole file, so we start over.
+            if staged > 0 {
+                info!("Server doesn't support continuing downloads, starting over");
+                file.set_len(0)
+                    .await
+                    .context(error::StagedFileSnafu { path: &target.path })?;
+                staged = 0;
+                progress.start(&target.name, staged);
+            }
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // What we staged doesn't match the server's file; start over on the next try.
+            file.set_len(0)
+                .await
+                .context(error::StagedFileSnafu { path: &target.path })?;
+            return error::DownloadIncompleteSnafu {
+                target: &target.name,
+                staged: 0u64,
+                length: target.length,
+            }
+            .fail();
+        }
+        status => {
+            return error::DownloadStatusSnafu {
+                url: target.url.as_str(),
+                status,
+            }
+            .fail()
+        }
+    }
+
+    write_response(response, file, target, staged, progress).await
+}
+
+/// Copies the rest of the target from a local repository to the staging file, which already has
+/// `staged` bytes.  Returns how many bytes the staging file has after.
+async fn copy_local(
+    file: &mut fs::File,
+    target: &StagedTarget,
+    mut staged: u64,
+    progress: &mut ProgressReporter,
+) -> Result<u64> {
+    let path = target
+        .url
+        .to_file_path()
+        .ok()
+        .context(error::TargetUrlSnafu {
+            url: target.url.as_str(),
+        })?;
+    let mut source = fs::File::open(&path)
+        .await
+        .context(error::RepoTargetSnafu { path: &path })?;
+    source
+        .seek(SeekFrom::Start(staged))
+        .await
+        .context(error::RepoTargetSnafu { path: &path })?;
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let n = source
+            .read(&mut buf)
+            .await
+            .context(error::RepoTargetSnafu { path: &path })?;
+        if n == 0 {
+            return Ok(staged);
+        }
+        staged += n as u64;
+        ensure!(
+            staged <= target.length,
+            error::TargetLengthSnafu {
+                target: &target.name,
+                length: target.length,
+            }
+        );
+        file.write_all(&buf[..n])
+            .await
+            .context(error::StagedFileSnafu { path: &target.path })?;
+        progress.advance(n as u64);
+    }
+}
+
+/// Appends the body of the response to the staging file, which already has `staged` bytes, and
+/// returns how many bytes it has after.
+async fn write_response(
+    mut response: Response,
+    file: &mut fs::File,
+    target: &StagedTarget,
+    mut staged: u64,
+    progress: &mut ProgressReporter,
+) -> Result<u64> {
+    loop {
+        let chunk = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+            .await
+            .ok()
+            .context(error::DownloadTimeoutSnafu {
+                target: &target.name,
+            })?
+            .context(error::DownloadSnafu {
+                url: target.url.as_str(),
+            })?;
+        let Some(chunk) = chunk else {
+            return Ok(staged);
+        };
+        staged += chunk.len() as u64;
+        ensure!(
+            staged <= target.length,
+            error::TargetLengthSnafu {
+                target: &target.name,
+                length: target.length,
+            }
+        );
+        file.write_all(&chunk)
+            .await
+            .context(error::StagedFileSnafu { path: &target.path })?;
+        progress.advance(chunk.len() as u64);
+    }
+}
+
+/// Checks the staged target's digest against the signed metadata.
+fn verify(target: &StagedTarget) -> Result<()> {
+    let path = &target.path;
+    let mut file = std::fs::File::open(path).context(error::StagedFileSnafu { path })?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .context(error::StagedFileSnafu { path })?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    ensure!(
+        context.finish().as_ref() == target.sha256.as_slice(),
+        error::TargetDigestSnafu {
+            target: &target.name,
+        }
+    );
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staged_target_url() {
+        let base = Url::parse("https://updates.example.com/targets/").unwrap();
+        let staging_dir = Path::new("/var/lib/updog/staging");
+        let target = StagedTarget::new(
+            "root.ext4.lz4",
+            &base,
+            3,
+            vec![0xab, 0x01],
+            true,
+            staging_dir,
+        )
+        .unwrap();
+        assert_eq!(
+            target.url.as_str(),
+            "https://updates.example.com/targets/ab01.root.ext4.lz4"
+        );
+        assert_eq!(target.path(), staging_dir.join("ab01"));
+
+        let base = Url::parse("https://updates.example.com/targets").unwrap();
+        let target =
+            StagedTarget::new("root.ext4.lz4", &base, 3, vec![0xab], false, staging_dir).unwrap();
+        assert_eq!(
+            target.url.as_str(),
+            "https://updates.example.com/targets/root.ext4.lz4"
+        );
+    }
+
+    #[tokio::test]
+    async fn staging_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = Url::parse("https://updates.example.com/targets/").unwrap();
+        let root = StagedTarget::new("root", &base, 30, vec![0x01], true, dir.path()).unwrap();
+        let boot = StagedTarget::new("boot", &base, 20, vec![0x02], true, dir.path()).unwrap();
+        std::fs::write(root.path(), [0; 10]).unwrap();
+        let targets = [root, boot];
+        assert_eq!(space_needed(&targets).await, 40);
+        check_staging_space(dir.path(), &targets).await.unwrap();
+
+        let huge =
+            StagedTarget::new("huge", &base, u64::MAX, vec![0x03], true, dir.path()).unwrap();
+        assert!(matches!(
+            check_staging_space(dir.path(), &[huge]).await,
+            Err(Error::InsufficientSpace { .. })
+        ));
+    }
+
+    #[test]
+    fn progress_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.json");
+        let mut progress = ProgressReporter::new(Some(path.clone()), 30);
+        progress.start("root", 5);
+        progress.advance(5);
+        progress.finish(20);
+        progress.start("boot", 0);
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({"target": "boot", "bytes_downloaded": 20, "bytes_total": 30})
+        );
+    }
+}
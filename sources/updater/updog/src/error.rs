@@ -47,6 +47,16 @@ pub enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display(
+        "Invalid version or version range '{}' in deny list: {}",
+        input,
+        source
+    ))]
+    DeniedVersion {
+        input: String,
+        source: semver::Error,
+    },
+
     #[snafu(display("Failed to create directory: {:?}", path))]
     DirCreate {
         backtrace: Backtrace,
@@ -54,6 +64,52 @@ pub enum Error {
         path: PathBuf,
     },
 
+    #[snafu(display("Unable to make a URL from directory '{}'", path.display()))]
+    DirectoryUrl { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Failed to download '{}': {}", url, source))]
+    Download {
+        url: String,
+        source: reqwest::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Download of {} stopped after {} of {} bytes", target, staged, length))]
+    DownloadIncomplete {
+        target: String,
+        staged: u64,
+        length: u64,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to download '{}': server returned {}", url, status))]
+    DownloadStatus {
+        url: String,
+        status: reqwest::StatusCode,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Download of {} timed out waiting for data", target))]
+    DownloadTimeout {
+        target: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Health check failed, and the previous version can't be booted: {}",
+        reason
+    ))]
+    HealthCheckFailed {
+        reason: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to create HTTP client: {}", source))]
+    HttpClient {
+        source: reqwest::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Logger setup error: {}", source))]
     Logger { source: log::SetLoggerError },
 
@@ -77,6 +133,9 @@ pub enum Error {
         source: Box<tough::error::Error>,
     },
 
+    #[snafu(display("Invalid maintenance window: {}", source))]
+    MaintenanceWindow { source: parse_datetime::Error },
+
     #[snafu(display("Manifest load error: {}", source))]
     ManifestLoad {
         #[snafu(source(from(tough::error::Error, Box::new)))]
@@ -122,6 +181,11 @@ pub enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display(
+        "Refusing to update outside of the configured maintenance windows; use --force to override"
+    ))]
+    OutsideMaintenanceWindow { backtrace: Backtrace },
+
     #[snafu(display("Failed to read OS disk partition table: {}", source))]
     PartitionTableRead {
         // signpost::Error triggers clippy::large_enum_variant
@@ -162,12 +226,66 @@ pub enum Error {
     #[snafu(display("Unable to get OS version: {}", source))]
     ReleaseVersion { source: bottlerocket_release::Error },
 
+    #[snafu(display("Failed to access staged update file '{}': {}", path.display(), source))]
+    StagedFile {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Unable to check free space in '{}': {}", path.display(), source))]
+    StagingSpace {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Not enough space in '{}' to stage the update: need {} bytes, {} available",
+        path.display(),
+        needed,
+        available
+    ))]
+    InsufficientSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Downloaded {} does not match the digest in signed metadata", target))]
+    TargetDigest {
+        target: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Downloaded {} is longer than the {} bytes in signed metadata",
+        target,
+        length
+    ))]
+    TargetLength {
+        target: String,
+        length: u64,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Target not found: {}", target))]
     TargetNotFound {
         target: String,
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Unable to build target URL from base '{}'", url))]
+    TargetUrl { url: String, backtrace: Backtrace },
+
+    #[snafu(display("Failed to check downloaded {}: {}", target, source))]
+    TargetVerify {
+        target: String,
+        source: tokio::task::JoinError,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to create tmpfile for root mount"))]
     TmpFileCreate {
         backtrace: Backtrace,
@@ -183,6 +301,9 @@ pub enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Refusing to update because the update mode is 'disabled'"))]
+    UpdatesDisabled { backtrace: Backtrace },
+
     #[snafu(display("--wave-file <path> required to add waves to update"))]
     WaveFileArg { backtrace: Backtrace },
 
@@ -203,6 +324,24 @@ pub enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Unable to use repository directory '{}': {}", path.display(), source))]
+    RepoDir {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Failed to read target '{}' from repository directory: {}",
+        path.display(),
+        source
+    ))]
+    RepoTarget {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to store manifest and migrations: {}", source))]
     RepoCacheMigrations {
         #[snafu(source(from(tough::error::Error, Box::new)))]
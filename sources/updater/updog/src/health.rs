@@ -0,0 +1,233 @@
+//! The health check decides whether the first boot of a new version is healthy.  It waits for the
+//! required systemd units to be active and runs the check commands, trying again until they pass
+//! or the timeout expires.  The boot is only marked successful once the checks pass; otherwise we
+//! roll back to the previous version.
+//!
+//! The outcome is written to a file as JSON for thar-be-updates to report.
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+use tokio::process::Command;
+use tokio::time::{self, Instant};
+
+const SYSTEMCTL: &str = "/usr/bin/systemctl";
+
+/// How long we wait before trying the checks again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the checks can take if no timeout is configured, so a stuck command can't hold up boot
+/// forever.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// `HealthCheck` is the set of checks that must pass for the first boot of a new version to be
+/// successful.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub(crate) struct HealthCheck {
+    /// systemd units that must be active.
+    #[serde(default)]
+    required_units: Vec<String>,
+    /// Commands, each given as the program and its arguments, that must exit successfully.
+    #[serde(default)]
+    commands: Vec<Vec<String>>,
+    /// How long to keep trying the checks before giving up; without it, they're tried once, and
+    /// stopped after DEFAULT_TIMEOUT.
+    timeout_seconds: Option<u64>,
+}
+
+impl HealthCheck {
+    /// Returns whether there are any checks to run.
+    pub(crate) fn is_configured(&self) -> bool {
+        !self.required_units.is_empty() || !self.commands.is_empty()
+    }
+
+    /// Runs the checks until they pass or the timeout expires.  Returns why the checks failed, if
+    /// they did.
+    pub(crate) async fn run(&self) -> Option<String> {
+        self.run_with_default_timeout(DEFAULT_TIMEOUT).await
+    }
+
+    /// Like `run`, but stops the checks after `default_timeout` if no timeout is configured.
+    async fn run_with_default_timeout(&self, default_timeout: Duration) -> Option<String> {
+        let timeout = self.timeout_seconds.map(Duration::from_secs);
+        let deadline = Instant::now() + timeout.unwrap_or(default_timeout);
+        loop {
+            let failure = self.check(deadline).await?;
+            if self.timeout_seconds.is_some() && Instant::now() + RETRY_INTERVAL < deadline {
+                debug!("Health check failed, trying again: {failure}");
+                time::sleep(RETRY_INTERVAL).await;
+            } else {
+                return Some(failure);
+            }
+        }
+    }
+
+    /// Runs each check once, stopping at the first that fails.
+    async fn check(&self, deadline: Instant) -> Option<String> {
+        for unit in &self.required_units {
+            if let Some(failure) = check_unit(unit).await {
+                return Some(failure);
+            }
+        }
+        for command in &self.commands {
+            if let Some(failure) = check_command(command, deadline).await {
+                return Some(failure);
+            }
+        }
+        None
+    }
+}
+
+async fn check_unit(unit: &str) -> Option<String> {
+    let output = Command::new(SYSTEMCTL)
+        .args(["is-active", "--", unit])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => Some(format!(
+            "unit '{}' is {}",
+            unit,
+            String::from_utf8_lossy(&output.stdout).trim()
+        )),
+        Err(e) => Some(format!("unable to check unit '{unit}': {e}")),
+    }
+}
+
+/// Runs a check command, stopping it if it's still running at the deadline.
+async fn check_command(command: &[String], deadline: Instant) -> Option<String> {
+    let Some((program, args)) = command.split_first() else {
+        return Some("check command is empty".to_string());
+    };
+    let command = command.join(" ");
+    let status = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .status();
+    let status = match time::timeout_at(deadline, status).await {
+        Ok(status) => status,
+        Err(_) => return Some(format!("'{command}' timed out")),
+    };
+    match status {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("'{command}' failed: {status}")),
+        Err(e) => Some(format!("unable to run '{command}': {e}")),
+    }
+}
+
+/// `Outcome` is the result of the health check for a version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Outcome {
+    /// The health check will run later in boot.
+    Pending,
+    /// The checks passed, so the boot was marked successful.
+    Passed,
+    /// The checks failed, so we rolled back to the previous version.
+    RolledBack,
+    /// The checks failed, but the previous version couldn't be booted, so we kept this one.
+    Failed,
+}
+
+/// `BootHealth` is the record of the health check written for thar-be-updates.
+#[derive(Debug, Serialize)]
+pub(crate) struct BootHealth {
+    version: Version,
+    outcome: Outcome,
+    reason: Option<String>,
+    timestamp: DateTime<Utc>,
+}
+
+impl BootHealth {
+    pub(crate) fn new(version: &Version, outcome: Outcome, reason: Option<String>) -> Self {
+        Self {
+            version: version.clone(),
+            outcome,
+            reason,
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Writes the record to the given path, replacing the last one.  The record is only for
+    /// reporting, so failures are logged rather than stopping the health check.
+    pub(crate) async fn write(&self, path: &Path) {
+        let record = match serde_json::to_vec(self) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Unable to serialize boot health: {e}");
+                return;
+            }
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir).await {
+                warn!("Unable to create '{}': {}", dir.display(), e);
+                return;
+            }
+        }
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp, record).await {
+            warn!("Unable to write boot health to '{}': {}", tmp.display(), e);
+            return;
+        }
+        if let Err(e) = fs::rename(&tmp, path).await {
+            warn!("Unable to write boot health to '{}': {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health_check(commands: &[&[&str]], timeout_seconds: Option<u64>) -> HealthCheck {
+        HealthCheck {
+            required_units: vec![],
+            commands: commands
+                .iter()
+                .map(|command| command.iter().map(ToString::to_string).collect())
+                .collect(),
+            timeout_seconds,
+        }
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        assert!(!HealthCheck::default().is_configured());
+        assert_eq!(health_check(&[&["true"]], None).run().await, None);
+        assert_eq!(
+            health_check(&[&["true"], &["false"]], None).run().await,
+            Some("'false' failed: exit status: 1".to_string())
+        );
+        assert_eq!(
+            health_check(&[&["sleep", "5"]], Some(1)).run().await,
+            Some("'sleep 5' timed out".to_string())
+        );
+        // Without a timeout, commands are still stopped at the default deadline.
+        assert_eq!(
+            health_check(&[&["sleep", "5"]], None)
+                .run_with_default_timeout(Duration::from_millis(200))
+                .await,
+            Some("'sleep 5' timed out".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("updog").join("boot-health.json");
+        BootHealth::new(&Version::new(1, 20, 0), Outcome::RolledBack, None)
+            .write(&path)
+            .await;
+        let record: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(record["version"], "1.20.0");
+        assert_eq!(record["outcome"], "rolled-back");
+    }
+}
@@ -1,27 +1,33 @@
 #![warn(clippy::pedantic)]
 
+mod download;
+mod health;
+mod policy;
 mod transport;
 
+use crate::download::{ProgressReporter, StagedTarget};
+use crate::health::{BootHealth, HealthCheck, Outcome};
+use crate::policy::{DeniedVersions, MaintenanceWindow, UpdateMode};
 use crate::transport::{reader_from_stream, HttpQueryTransport, QueryParams};
 use bottlerocket_modeled_types::FriendlyVersion;
 use bottlerocket_release::BottlerocketRelease;
 use chrono::Utc;
-use log::debug;
+use log::{debug, info, warn};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use signal_hook::consts::SIGTERM;
 use signal_hook::iterator::Signals;
 use signpost::State;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use snafu::{ErrorCompat, OptionExt, ResultExt};
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt};
 use std::convert::{TryFrom, TryInto};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::str::FromStr;
 use std::thread;
 use tokio::runtime::Handle;
 use tokio::{fs, process};
-use tough::{Repository, RepositoryLoader};
+use tough::{FilesystemTransport, Repository, RepositoryLoader, TargetName};
 use update_metadata::{find_migrations, Manifest, Update};
 use updog::error::{self, Result};
 use url::Url;
@@ -40,6 +46,14 @@ const MIGRATION_PATH: &str = "/var/lib/bottlerocket-migrations";
 /// This is where we store the TUF metadata used by migrator after reboot.
 const METADATA_PATH: &str = "/var/cache/bottlerocket-metadata";
 
+/// This is where we download update images before writing them to disk.  It's persistent, so an
+/// interrupted download can continue after a reboot.
+const STAGING_PATH: &str = "/var/lib/updog/staging";
+
+/// This is where we record the outcome of the health check after booting a new version, for
+/// thar-be-updates to report.
+const BOOT_HEALTH_PATH: &str = "/var/lib/updog/boot-health.json";
+
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 enum Command {
@@ -50,6 +64,8 @@ enum Command {
     UpdateImage,
     UpdateApply,
     UpdateRevert,
+    MarkSuccessfulBoot,
+    CheckHealth,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,9 +77,18 @@ struct Config {
     ignore_waves: bool,
     https_proxy: Option<String>,
     no_proxy: Option<Vec<String>>,
-    // TODO API sourced configuration, eg.
-    // blacklist: Option<Vec<Version>>,
-    // mode: Option<{Automatic, Managed, Disabled}>
+    /// Versions, or ranges of versions, that we won't update to.
+    #[serde(default)]
+    deny_list: Vec<DeniedVersions>,
+    #[serde(default)]
+    mode: UpdateMode,
+    /// Windows of time in which updates can be applied; if there are none, they can be applied at
+    /// any time.
+    #[serde(default)]
+    maintenance_windows: Vec<MaintenanceWindow>,
+    /// Checks that must pass for the first boot of a new version to be marked successful.
+    #[serde(default)]
+    health_check: HealthCheck,
 }
 
 /// Prints a more specific message before exiting through `usage()`.
@@ -90,19 +115,32 @@ SUBCOMMANDS:
         [ -i | --image version ]      Update to a specific image version
         [ -n | --now ]                Update immediately, ignoring any release schedule
         [ -r | --reboot ]             Reboot into new update on success
+        [ -f | --force ]              Update even outside of the maintenance windows
+        [ --progress-file path ]      Write download progress to this file as JSON
 
     update-image            Download & write an update but do not update flags
         [ -i | --image version ]      Update to a specific image version
         [ -n | --now ]                Update immediately, ignoring wave limits
         [ -t | --timestamp time ]     The timestamp to execute an update from
+        [ --progress-file path ]      Write download progress to this file as JSON
 
     update-apply            Update boot flags (after having called update-image)
         [ -r | --reboot ]             Reboot after updating boot flags
+        [ -f | --force ]              Update boot flags even outside of the maintenance windows
 
     update-revert           Revert actions done by 'update-apply'
 
+    mark-successful-boot    Mark the boot successful, unless the health check will decide
+                            after booting a new version
+
+    check-health            Run the health check after booting a new version, and mark the
+                            boot successful if it passes or roll back and reboot if it fails
+
 GLOBAL OPTIONS:
     [ -j | --json ]               JSON-formatted output
+    [ --repo-dir path ]           Load the update repository from this directory, with
+                                  'metadata' and 'targets' subdirectories, instead of
+                                  the URLs in the config file
     [ --log-level trace|debug|info|warn|error ]  Set logging verbosity");
     std::process::exit(1)
 }
@@ -116,7 +154,57 @@ async fn load_config() -> Result<Config> {
     Ok(config)
 }
 
-async fn load_repository(transport: HttpQueryTransport, config: &Config) -> Result<Repository> {
+/// `RepositoryUrls` are where we load the repository's metadata and targets from.
+struct RepositoryUrls {
+    metadata_base_url: Url,
+    targets_base_url: Url,
+    /// Whether the URLs are for a local repository directory rather than an HTTP server.
+    local: bool,
+}
+
+impl RepositoryUrls {
+    /// Returns the URLs from the config file.
+    fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            metadata_base_url: Url::parse(&config.metadata_base_url).context(
+                error::UrlParseSnafu {
+                    url: &config.metadata_base_url,
+                },
+            )?,
+            targets_base_url: Url::parse(&config.targets_base_url).context(
+                error::UrlParseSnafu {
+                    url: &config.targets_base_url,
+                },
+            )?,
+            local: false,
+        })
+    }
+
+    /// Returns the URLs for a repository directory laid out as tuftool writes it, with the
+    /// metadata in a "metadata" subdirectory and the targets in a "targets" subdirectory.
+    fn from_repo_dir(repo_dir: &Path) -> Result<Self> {
+        let repo_dir =
+            std::fs::canonicalize(repo_dir).context(error::RepoDirSnafu { path: repo_dir })?;
+        let dir_url = |name: &str| {
+            let path = repo_dir.join(name);
+            Url::from_directory_path(&path)
+                .ok()
+                .context(error::DirectoryUrlSnafu { path })
+        };
+        Ok(Self {
+            metadata_base_url: dir_url("metadata")?,
+            targets_base_url: dir_url("targets")?,
+            local: true,
+        })
+    }
+}
+
+/// Loads the repository, verifying its metadata against our trusted root.  Local repositories are
+/// read from disk; otherwise, the transport makes HTTP requests with our query parameters.
+async fn load_repository(
+    transport: HttpQueryTransport,
+    urls: &RepositoryUrls,
+) -> Result<Repository> {
     fs::create_dir_all(METADATA_PATH)
         .await
         .context(error::CreateMetadataCacheSnafu {
@@ -128,19 +216,17 @@ async fn load_repository(transport: HttpQueryTransport, config: &Config) -> Resu
             path: TRUSTED_ROOT_PATH,
         })?;
 
-    RepositoryLoader::new(
+    let loader = RepositoryLoader::new(
         &root_bytes,
-        Url::parse(&config.metadata_base_url).context(error::UrlParseSnafu {
-            url: &config.metadata_base_url,
-        })?,
-        Url::parse(&config.targets_base_url).context(error::UrlParseSnafu {
-            url: &config.targets_base_url,
-        })?,
-    )
-    .transport(transport)
-    .load()
-    .await
-    .context(error::MetadataSnafu)
+        urls.metadata_base_url.clone(),
+        urls.targets_base_url.clone(),
+    );
+    let loader = if urls.local {
+        loader.transport(FilesystemTransport)
+    } else {
+        loader.transport(transport)
+    };
+    loader.load().await.context(error::MetadataSnafu)
 }
 
 fn applicable_updates<'a>(
@@ -148,6 +234,7 @@ fn applicable_updates<'a>(
     variant: &str,
     ignore_waves: bool,
     seed: u32,
+    deny_list: &[DeniedVersions],
 ) -> Vec<&'a Update> {
     let mut updates: Vec<&Update> = manifest
         .updates
@@ -157,6 +244,7 @@ fn applicable_updates<'a>(
                 && u.arch == TARGET_ARCH
                 && u.version <= u.max_version
                 && (ignore_waves || u.update_ready(seed, Utc::now()))
+                && !deny_list.iter().any(|denied| denied.matches(&u.version))
         })
         .collect();
     // sort descending
@@ -164,22 +252,18 @@ fn applicable_updates<'a>(
     updates
 }
 
-// TODO use config if there is api-sourced configuration that could affect this
-// TODO updog.toml may include settings that cause us to ignore/delay
-// certain/any updates;
-//  Ignore Specific Target Version
-//  Ignore Any Target
-//  ...
+#[allow(clippy::too_many_arguments)]
 fn update_required<'a>(
     manifest: &'a Manifest,
     version: &Version,
     variant: &str,
     ignore_waves: bool,
     seed: u32,
+    deny_list: &[DeniedVersions],
     version_lock: &str,
     force_version: Option<Version>,
 ) -> Result<Option<&'a Update>> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = applicable_updates(manifest, variant, ignore_waves, seed, deny_list);
 
     if let Some(forced_version) = force_version {
         return Ok(updates.into_iter().find(|u| u.version == forced_version));
@@ -218,32 +302,46 @@ fn update_required<'a>(
     Ok(None)
 }
 
-async fn write_target_to_disk<P: AsRef<Path>>(
+/// Describes the named image target from the signed metadata, so it can be downloaded to the
+/// staging directory.
+fn staged_target(
     repository: &Repository,
+    targets_base_url: &Url,
     target: &str,
-    disk_path: P,
-) -> Result<()> {
-    let target = target
+) -> Result<StagedTarget> {
+    let target_name: TargetName = target
         .try_into()
         .context(error::TargetNameSnafu { target })?;
-    let stream = repository
-        .read_target(&target)
-        .await
-        .context(error::MetadataSnafu)?
-        .context(error::TargetNotFoundSnafu {
-            target: target.raw(),
-        })?;
-    let reader = reader_from_stream(stream);
+    let info = repository
+        .targets()
+        .signed
+        .targets
+        .get(&target_name)
+        .context(error::TargetNotFoundSnafu { target })?;
+    StagedTarget::new(
+        target_name.resolved(),
+        targets_base_url,
+        info.length,
+        info.hashes.sha256.to_vec(),
+        repository.root().signed.consistent_snapshot,
+        Path::new(STAGING_PATH),
+    )
+}
+
+async fn write_target_to_disk<P: AsRef<Path>>(target: &StagedTarget, disk_path: P) -> Result<()> {
+    let name = target.name().to_string();
+    let staged_path = target.path().to_path_buf();
 
     // Run blocking IO without blocking the scheduler.
     let disk_path = disk_path.as_ref().to_path_buf();
     let rt = Handle::current();
     let task = rt.spawn_blocking(move || {
+        let staged = std::fs::File::open(&staged_path)
+            .context(error::StagedFileSnafu { path: &staged_path })?;
         // Note: the file extension for the compression type we're using should be removed in
         // retrieve_migrations below.
-        let mut reader = lz4::Decoder::new(reader).context(error::Lz4DecodeSnafu {
-            target: target.raw(),
-        })?;
+        let mut reader =
+            lz4::Decoder::new(staged).context(error::Lz4DecodeSnafu { target: &name })?;
         let mut f = std::fs::OpenOptions::new()
             .write(true)
             .create(true)
@@ -293,7 +391,35 @@ async fn retrieve_migrations(
     Ok(())
 }
 
-async fn update_image(update: &Update, repository: &Repository) -> Result<()> {
+async fn update_image(
+    update: &Update,
+    repository: &Repository,
+    transport: &HttpQueryTransport,
+    targets_base_url: &Url,
+    progress_file: Option<PathBuf>,
+) -> Result<()> {
+    let targets = [
+        &update.images.root,
+        &update.images.boot,
+        &update.images.hash,
+    ]
+    .into_iter()
+    .map(|target| staged_target(repository, targets_base_url, target))
+    .collect::<Result<Vec<_>>>()?;
+
+    // Download all of the images before touching the inactive partitions, so a failed download
+    // leaves them as they were.  What's already staged for these images is kept, so we continue
+    // where an earlier attempt stopped.
+    download::clean_staging_dir(Path::new(STAGING_PATH), &targets).await?;
+    download::check_staging_space(Path::new(STAGING_PATH), &targets).await?;
+    let mut progress = ProgressReporter::new(
+        progress_file,
+        targets.iter().map(StagedTarget::length).sum(),
+    );
+    for target in &targets {
+        download::stage_target(transport, target, &mut progress).await?;
+    }
+
     let mut gpt_state = State::load().context(error::PartitionTableReadSnafu)?;
     gpt_state
         .clear_inactive()
@@ -309,15 +435,24 @@ async fn update_image(update: &Update, repository: &Repository) -> Result<()> {
         .context(error::InactivePartitionMissingSnafu)?;
 
     // TODO Do we want to recover the inactive side on an error?
-    write_target_to_disk(repository, &update.images.root, &inactive.root).await?;
-    write_target_to_disk(repository, &update.images.boot, &inactive.boot).await?;
-    write_target_to_disk(repository, &update.images.hash, &inactive.hash).await?;
+    write_target_to_disk(&targets[0], &inactive.root).await?;
+    write_target_to_disk(&targets[1], &inactive.boot).await?;
+    write_target_to_disk(&targets[2], &inactive.hash).await?;
 
     gpt_state
         .mark_inactive_valid()
         .ok()
         .context(error::InactivePartitionMissingSnafu)?;
     gpt_state.write().context(error::PartitionTableWriteSnafu)?;
+
+    // The images are on disk now, so we don't need to keep them staged.
+    for target in &targets {
+        fs::remove_file(target.path())
+            .await
+            .context(error::StagedFileSnafu {
+                path: target.path(),
+            })?;
+    }
     Ok(())
 }
 
@@ -356,8 +491,9 @@ fn list_updates(
     json: bool,
     ignore_waves: bool,
     seed: u32,
+    deny_list: &[DeniedVersions],
 ) -> Result<()> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = applicable_updates(manifest, variant, ignore_waves, seed, deny_list);
     if json {
         println!(
             "{}",
@@ -381,7 +517,10 @@ struct Arguments {
     force_version: Option<Version>,
     all: bool,
     reboot: bool,
+    force: bool,
     variant: Option<String>,
+    progress_file: Option<PathBuf>,
+    repo_dir: Option<PathBuf>,
 }
 
 /// Parse the command line arguments to get the user-specified values
@@ -393,7 +532,10 @@ fn parse_args(args: std::env::Args) -> Arguments {
     let mut json = false;
     let mut all = false;
     let mut reboot = false;
+    let mut force = false;
     let mut variant = None;
+    let mut progress_file = None;
+    let mut repo_dir = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -420,6 +562,18 @@ fn parse_args(args: std::env::Args) -> Arguments {
                         .unwrap_or_else(|| usage_msg("Did not give argument to --variant")),
                 );
             }
+            "--progress-file" => {
+                progress_file =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --progress-file")
+                    })));
+            }
+            "--repo-dir" => {
+                repo_dir =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --repo-dir")
+                    })));
+            }
             "-n" | "--now" | "--ignore-waves" => {
                 ignore_waves = true;
             }
@@ -429,6 +583,9 @@ fn parse_args(args: std::env::Args) -> Arguments {
             "-r" | "--reboot" => {
                 reboot = true;
             }
+            "-f" | "--force" => {
+                force = true;
+            }
             "-a" | "--all" => {
                 all = true;
             }
@@ -451,7 +608,10 @@ fn parse_args(args: std::env::Args) -> Arguments {
         force_version: update_version,
         all,
         reboot,
+        force,
         variant,
+        progress_file,
+        repo_dir,
     }
 }
 
@@ -495,6 +655,82 @@ async fn initiate_reboot() -> Result<()> {
     Ok(())
 }
 
+/// Marks the boot successful, as `signpost mark-successful-boot` does, unless this is the first boot
+/// of a new version and there's a health check to decide whether it's healthy.  The host can't boot
+/// this version again until its boot is marked successful, so problems loading the config file
+/// only mean that we skip the health check.
+async fn mark_successful_boot() -> Result<()> {
+    let mut gpt_state = State::load().context(error::PartitionTableReadSnafu)?;
+    if !gpt_state.active_boot_succeeded() {
+        match (load_config().await, BottlerocketRelease::new()) {
+            (Ok(config), Ok(release)) if config.health_check.is_configured() => {
+                info!("Leaving the boot to be marked successful by the health check");
+                BootHealth::new(&release.version_id, Outcome::Pending, None)
+                    .write(Path::new(BOOT_HEALTH_PATH))
+                    .await;
+                return Ok(());
+            }
+            (Err(e), _) => warn!("Unable to load config, skipping health check: {e}"),
+            (_, Err(e)) => warn!("Unable to get OS version, skipping health check: {e}"),
+            _ => {}
+        }
+    }
+    gpt_state.mark_successful_boot();
+    gpt_state.write().context(error::PartitionTableWriteSnafu)?;
+    Ok(())
+}
+
+/// Runs the health check on the first boot of a new version.  If it passes, the boot is marked
+/// successful.  If it fails, we roll back to the previous version and reboot, unless the previous
+/// version can't be booted, in which case we keep this one.
+async fn check_health() -> Result<()> {
+    let config = load_config().await?;
+    let current_release = BottlerocketRelease::new().context(error::ReleaseVersionSnafu)?;
+    let version = &current_release.version_id;
+    let boot_health_path = Path::new(BOOT_HEALTH_PATH);
+    if !config.health_check.is_configured()
+        || State::load()
+            .context(error::PartitionTableReadSnafu)?
+            .active_boot_succeeded()
+    {
+        debug!("No health check needed");
+        return Ok(());
+    }
+
+    info!("Checking the health of version {version}");
+    let failure = config.health_check.run().await;
+    // Load the partition table again, since the checks can take a while.
+    let mut gpt_state = State::load().context(error::PartitionTableReadSnafu)?;
+    let Some(reason) = failure else {
+        info!("Health check passed");
+        gpt_state.mark_successful_boot();
+        gpt_state.write().context(error::PartitionTableWriteSnafu)?;
+        BootHealth::new(version, Outcome::Passed, None)
+            .write(boot_health_path)
+            .await;
+        return Ok(());
+    };
+
+    warn!("Health check failed: {reason}");
+    if let Err(e) = gpt_state.rollback_to_inactive() {
+        // The previous version can't be booted, so marking this boot successful is the only way
+        // to keep the host bootable.
+        warn!("Unable to roll back, keeping version {version}: {e}");
+        gpt_state.mark_successful_boot();
+        gpt_state.write().context(error::PartitionTableWriteSnafu)?;
+        BootHealth::new(version, Outcome::Failed, Some(reason.clone()))
+            .write(boot_health_path)
+            .await;
+        return error::HealthCheckFailedSnafu { reason }.fail();
+    }
+    gpt_state.write().context(error::PartitionTableWriteSnafu)?;
+    BootHealth::new(version, Outcome::RolledBack, Some(reason))
+        .write(boot_health_path)
+        .await;
+    info!("Rolled back to the previous version, rebooting");
+    initiate_reboot().await
+}
+
 /// Our underlying HTTP client, reqwest, supports proxies by reading the `HTTPS_PROXY` and `NO_PROXY`
 /// environment variables. Bottlerocket services can source proxy.env before running, but updog is
 /// not a service, so we read these values from the config file and add them to the environment
@@ -519,6 +755,27 @@ fn set_https_proxy_environment_variables(
     }
 }
 
+/// Refuses commands that change the boot partitions if the update mode is disabled, and refuses to
+/// change which partitions we boot from outside of the maintenance windows unless forced.
+fn check_update_policy(config: &Config, command: &Command, force: bool) -> Result<()> {
+    if matches!(
+        command,
+        Command::Update | Command::UpdateImage | Command::UpdateApply
+    ) {
+        ensure!(
+            config.mode != UpdateMode::Disabled,
+            error::UpdatesDisabledSnafu
+        );
+    }
+    if matches!(command, Command::Update | Command::UpdateApply) {
+        ensure!(
+            force || policy::in_maintenance_window(&config.maintenance_windows, Utc::now()),
+            error::OutsideMaintenanceWindowSnafu
+        );
+    }
+    Ok(())
+}
+
 #[allow(clippy::too_many_lines)]
 async fn main_inner() -> Result<()> {
     // Parse and store the arguments passed to the program
@@ -530,7 +787,15 @@ async fn main_inner() -> Result<()> {
     let command =
         serde_plain::from_str::<Command>(&arguments.subcommand).unwrap_or_else(|_| usage());
 
+    // These run during boot, and don't need the update repository.
+    match command {
+        Command::MarkSuccessfulBoot => return mark_successful_boot().await,
+        Command::CheckHealth => return check_health().await,
+        _ => {}
+    }
+
     let config = load_config().await?;
+    check_update_policy(&config, &command, arguments.force)?;
     set_https_proxy_environment_variables(&config.https_proxy, &config.no_proxy);
     let current_release = BottlerocketRelease::new().context(error::ReleaseVersionSnafu)?;
     let variant = arguments.variant.unwrap_or(current_release.variant_id);
@@ -539,9 +804,14 @@ async fn main_inner() -> Result<()> {
     // the transport's HTTP calls.
     let mut query_params = transport.query_params();
     set_common_query_params(&mut query_params, &current_release.version_id, &config);
-    let repository = load_repository(transport, &config).await?;
+    let urls = match &arguments.repo_dir {
+        Some(repo_dir) => RepositoryUrls::from_repo_dir(repo_dir)?,
+        None => RepositoryUrls::from_config(&config)?,
+    };
+    let repository = load_repository(transport.clone(), &urls).await?;
     let manifest = load_manifest(&repository).await?;
-    let ignore_waves = arguments.ignore_waves || config.ignore_waves;
+    let ignore_waves =
+        arguments.ignore_waves || config.ignore_waves || config.mode == UpdateMode::Managed;
     match command {
         Command::CheckUpdate | Command::Whats => {
             if arguments.all {
@@ -551,6 +821,7 @@ async fn main_inner() -> Result<()> {
                     arguments.json,
                     ignore_waves,
                     config.seed,
+                    &config.deny_list,
                 );
             }
 
@@ -560,6 +831,7 @@ async fn main_inner() -> Result<()> {
                 &variant,
                 ignore_waves,
                 config.seed,
+                &config.deny_list,
                 &config.version_lock,
                 arguments.force_version,
             )?
@@ -574,6 +846,7 @@ async fn main_inner() -> Result<()> {
                 &variant,
                 ignore_waves,
                 config.seed,
+                &config.deny_list,
                 &config.version_lock,
                 arguments.force_version,
             )? {
@@ -587,7 +860,14 @@ async fn main_inner() -> Result<()> {
                     &current_release.version_id,
                 )
                 .await?;
-                update_image(u, &repository).await?;
+                update_image(
+                    u,
+                    &repository,
+                    &transport,
+                    &urls.targets_base_url,
+                    arguments.progress_file,
+                )
+                .await?;
                 if command == Command::Update {
                     update_flags()?;
                     if arguments.reboot {
@@ -615,6 +895,8 @@ async fn main_inner() -> Result<()> {
         Command::Prepare => {
             // TODO unimplemented
         }
+        // Handled before loading the repository.
+        Command::MarkSuccessfulBoot | Command::CheckHealth => {}
     }
 
     Ok(())
@@ -719,6 +1001,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            deny_list: vec![],
+            mode: UpdateMode::Automatic,
+            maintenance_windows: vec![],
+            health_check: HealthCheck::default(),
         };
         let version = Version::parse("1.18.0").unwrap();
         let variant = String::from("bottlerocket-aws-eks");
@@ -730,6 +1016,7 @@ mod tests {
                 &variant,
                 config.ignore_waves,
                 config.seed,
+                &config.deny_list,
                 &config.version_lock,
                 None
             )
@@ -754,6 +1041,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            deny_list: vec![],
+            mode: UpdateMode::Automatic,
+            maintenance_windows: vec![],
+            health_check: HealthCheck::default(),
         };
 
         let version = Version::parse("0.1.3").unwrap();
@@ -764,6 +1055,7 @@ mod tests {
             &variant,
             config.ignore_waves,
             config.seed,
+            &config.deny_list,
             &config.version_lock,
             None,
         )
@@ -793,6 +1085,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            deny_list: vec![],
+            mode: UpdateMode::Automatic,
+            maintenance_windows: vec![],
+            health_check: HealthCheck::default(),
         };
 
         let version = Version::parse("1.10.0").unwrap();
@@ -803,6 +1099,7 @@ mod tests {
             &variant,
             config.ignore_waves,
             config.seed,
+            &config.deny_list,
             &config.version_lock,
             None,
         )
@@ -836,6 +1133,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            deny_list: vec![],
+            mode: UpdateMode::Automatic,
+            maintenance_windows: vec![],
+            health_check: HealthCheck::default(),
         };
 
         let version = Version::parse("1.10.0").unwrap();
@@ -847,6 +1148,7 @@ mod tests {
             &variant,
             config.ignore_waves,
             config.seed,
+            &config.deny_list,
             &config.version_lock,
             Some(forced),
         )
@@ -863,6 +1165,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn deny_listed_version() {
+        // The same manifest as test_multiple, but 1.15.0 is denied in the config file, so we
+        // update to 1.13.0 instead.
+        let path = format!("tests/data/multiple_{TARGET_ARCH}.json");
+        let manifest: Manifest =
+            serde_json::from_reader(std::fs::File::open(path).unwrap()).unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 123
+            version_lock = "latest"
+            ignore_waves = false
+            deny_list = ["1.14.0", ">=1.15.0, <1.16.0"]
+            mode = "managed"
+            maintenance_windows = ["Sat,Sun 01:00-05:00"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, UpdateMode::Managed);
+        assert_eq!(config.maintenance_windows.len(), 1);
+
+        let version = Version::parse("1.10.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let result = update_required(
+            &manifest,
+            &version,
+            &variant,
+            config.ignore_waves,
+            config.seed,
+            &config.deny_list,
+            &config.version_lock,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.map(|u| &u.version),
+            Some(&Version::parse("1.13.0").unwrap()),
+            "Updog didn't skip the denied version"
+        );
+    }
+
     #[test]
     fn bad_bound() {
         // This manifest has an invalid key for one of the update's waves
@@ -920,6 +1266,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            deny_list: vec![],
+            mode: UpdateMode::Automatic,
+            maintenance_windows: vec![],
+            health_check: HealthCheck::default(),
         };
 
         // Two waves; the 1st wave that starts immediately, and the final wave which starts in one hour
@@ -940,6 +1290,7 @@ mod tests {
                 &variant,
                 config.ignore_waves,
                 config.seed,
+                &config.deny_list,
                 &config.version_lock,
                 None,
             )
@@ -955,6 +1306,7 @@ mod tests {
                 &variant,
                 config.ignore_waves,
                 2000,
+                &config.deny_list,
                 &config.version_lock,
                 None,
             )
@@ -963,4 +1315,20 @@ mod tests {
             "Later wave incorrectly sees update"
         );
     }
+
+    #[test]
+    fn test_repo_dir_urls() {
+        // A local repository has metadata and targets subdirectories.
+        let repo_dir = tempfile::tempdir().unwrap();
+        let urls = RepositoryUrls::from_repo_dir(repo_dir.path()).unwrap();
+        let repo_dir = std::fs::canonicalize(repo_dir.path()).unwrap();
+        assert!(urls.local);
+        assert_eq!(
+            urls.metadata_base_url.to_file_path().unwrap(),
+            repo_dir.join("metadata")
+        );
+        assert!(urls.targets_base_url.as_str().ends_with("/targets/"));
+
+        assert!(RepositoryUrls::from_repo_dir(Path::new("/does/not/exist")).is_err());
+    }
 }
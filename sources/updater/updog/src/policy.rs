@@ -0,0 +1,126 @@
+//! Update policy from the config file: the update mode, versions we won't update to, and the
+//! maintenance windows that updates can be applied in.
+
+use chrono::{DateTime, Utc};
+use parse_datetime::Window;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::str::FromStr;
+use updog::error::{self, Error, Result};
+
+/// `UpdateMode` controls whether updates are applied, and who decides when.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum UpdateMode {
+    /// Updates are applied when the release's wave schedule reaches this host.
+    #[default]
+    Automatic,
+    /// Something else, like an update operator, decides when to update, so the wave schedule is
+    /// ignored.
+    Managed,
+    /// Updates aren't applied.
+    Disabled,
+}
+
+/// `DeniedVersions` is a version, like "1.20.1", or a range of versions, like ">=1.20.0, <1.20.3",
+/// that we won't update to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct DeniedVersions(VersionReq);
+
+impl DeniedVersions {
+    pub(crate) fn matches(&self, version: &Version) -> bool {
+        self.0.matches(version)
+    }
+}
+
+impl FromStr for DeniedVersions {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        // semver would take a bare version to mean every compatible version, like "^1.20.1", but
+        // a bare version in the deny list means just that version.
+        let trimmed = input.trim();
+        let req = match Version::parse(trimmed.strip_prefix('v').unwrap_or(trimmed)) {
+            Ok(version) => VersionReq::parse(&format!("={version}")),
+            Err(_) => VersionReq::parse(trimmed),
+        }
+        .context(error::DeniedVersionSnafu { input })?;
+        Ok(Self(req))
+    }
+}
+
+impl TryFrom<String> for DeniedVersions {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<Self> {
+        input.parse()
+    }
+}
+
+/// `MaintenanceWindow` is a weekly window of time, like "Sat,Sun 01:00-05:00", in which updates
+/// can be applied.  See the `parse-datetime` crate for the format.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct MaintenanceWindow(Window);
+
+impl FromStr for MaintenanceWindow {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        parse_datetime::parse_window(input)
+            .map(Self)
+            .context(error::MaintenanceWindowSnafu)
+    }
+}
+
+impl TryFrom<String> for MaintenanceWindow {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<Self> {
+        input.parse()
+    }
+}
+
+/// Returns whether an update can be applied at the given time.  Updates can be applied at any
+/// time if there are no maintenance windows.
+pub(crate) fn in_maintenance_window(windows: &[MaintenanceWindow], time: DateTime<Utc>) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.0.contains(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denied_versions() {
+        let exact: DeniedVersions = "1.20.1".parse().unwrap();
+        assert!(exact.matches(&Version::new(1, 20, 1)));
+        assert!(!exact.matches(&Version::new(1, 20, 2)));
+        let friendly: DeniedVersions = "v1.20.1".parse().unwrap();
+        assert_eq!(exact, friendly);
+
+        let range: DeniedVersions = ">=1.20.0, <1.20.3".parse().unwrap();
+        assert!(range.matches(&Version::new(1, 20, 2)));
+        assert!(!range.matches(&Version::new(1, 20, 3)));
+
+        assert!("latest".parse::<DeniedVersions>().is_err());
+    }
+
+    #[test]
+    fn maintenance_windows() {
+        let windows: Vec<MaintenanceWindow> = ["Sat,Sun 01:00-05:00", "Wed 12:00-13:00"]
+            .iter()
+            .map(|w| w.parse().unwrap())
+            .collect();
+        // 2024-06-01 is a Saturday.
+        let saturday = DateTime::parse_from_rfc3339("2024-06-01T02:00:00Z").unwrap();
+        let monday = DateTime::parse_from_rfc3339("2024-06-03T02:00:00Z").unwrap();
+        assert!(in_maintenance_window(&windows, saturday.into()));
+        assert!(!in_maintenance_window(&windows, monday.into()));
+        assert!(in_maintenance_window(&[], monday.into()));
+
+        assert!("Sat".parse::<MaintenanceWindow>().is_err());
+    }
+}
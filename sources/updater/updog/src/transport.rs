@@ -4,14 +4,22 @@ use futures::StreamExt;
 use futures::TryStreamExt;
 use futures_core::Stream;
 use log::error;
+use reqwest::header::RANGE;
+use reqwest::Response;
+use snafu::ResultExt;
 use std::io::{ErrorKind, Read};
 use std::pin::Pin;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 use tokio_util::io::SyncIoBridge;
 use tough::{HttpTransport, Transport, TransportError};
+use updog::error::{self, Result};
 use url::Url;
 
+/// How long we wait to connect for range requests, as tough's `HttpTransport` does by default.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// A shared pointer to a list of query params that the transport will add to HTTP calls.
 #[derive(Debug, Clone, Default)]
 pub(crate) struct QueryParams(Arc<RwLock<Vec<(String, String)>>>);
@@ -73,6 +81,26 @@ impl HttpQueryTransport {
     pub fn query_params(&self) -> QueryParams {
         QueryParams(Arc::clone(&self.parameters.0))
     }
+
+    /// Send a GET request to the URL, with the same query params as `fetch`, asking for the bytes
+    /// from `offset` onward if it's not zero.  The caller checks the status of the response to see
+    /// whether the server honored the range; tough's `Transport` can't, so this uses reqwest
+    /// directly.
+    pub(crate) async fn fetch_range(&self, url: Url, offset: u64) -> Result<Response> {
+        let url = self.parameters.add_params_to_url(url);
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .context(error::HttpClientSnafu)?;
+        let mut request = client.get(url.clone());
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+        request
+            .send()
+            .await
+            .context(error::DownloadSnafu { url: url.as_str() })
+    }
 }
 
 pub(crate) type TransportStream = Pin<Box<dyn Stream<Item = Result<Bytes, TransportError>> + Send>>;